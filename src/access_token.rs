@@ -1,16 +1,142 @@
-use crate::AppError;
+use actix_web::{dev::Payload, http::header::Header, FromRequest, HttpRequest};
+use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::future::{ready, Ready};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
+use crate::{settings::Settings, AppError, SessionID};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub type TokenHash = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct AccessToken(pub String);
 
 impl AccessToken {
     pub fn from_string(s: &str) -> Result<AccessToken, AppError> {
-        if s.len() < 10 {
-            Err(AppError::BadAccessToken)
-        } else if s.len() > 100 {
+        if s.len() < 10 || s.len() > 100 {
             Err(AppError::BadAccessToken)
         } else {
             Ok(AccessToken(s.to_string()))
         }
     }
+
+    pub fn new_random() -> AccessToken {
+        let token_length = 32;
+        let mut rng = OsRng;
+        AccessToken(
+            (0..token_length)
+                .map(|_| rng.sample(rand::distributions::Alphanumeric) as char)
+                .collect(),
+        )
+    }
+
+    /// Compares the tokens in constant time, so that the time does not tell how much of a
+    /// guessed token is right. Only the length may be leaked.
+    pub fn constant_time_eq(&self, other: &AccessToken) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+
+    /// Derives the token from the session id and issue time. Such a token can be validated
+    /// without knowing the stored token, e.g. after the server has been restarted.
+    /// It looks like `<issue-time-ms>.<hex-signature>`.
+    pub fn new_signed(
+        secret: &[u8],
+        session_id: &SessionID,
+        issued_at: DateTime<Utc>,
+    ) -> AccessToken {
+        let issued_at_ms = issued_at.timestamp_millis();
+        let signature: String = sign(secret, session_id, issued_at_ms)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        AccessToken(format!("{}.{}", issued_at_ms, signature))
+    }
+
+    /// Returns when the token has been issued if it has been derived for this session with
+    /// the given secret and is not older than `max_age`.
+    pub fn signed_issue_time(
+        &self,
+        secret: &[u8],
+        session_id: &SessionID,
+        now: DateTime<Utc>,
+        max_age: std::time::Duration,
+    ) -> Option<DateTime<Utc>> {
+        let (issued_at_ms, signature) = self.0.split_once('.')?;
+        let issued_at_ms: i64 = issued_at_ms.parse().ok()?;
+        let signature = decode_hex(signature)?;
+        sign(secret, session_id, issued_at_ms)
+            .verify_slice(&signature)
+            .ok()?;
+        let issued_at = DateTime::from_timestamp_millis(issued_at_ms)?;
+        if now - issued_at > chrono::Duration::from_std(max_age).ok()? {
+            return None;
+        }
+        Some(issued_at)
+    }
+
+    /// Used as key to find the sessions of a token without keeping the token itself around
+    /// a second time, see [`crate::State::sessions_by_token`].
+    pub fn hash(&self) -> TokenHash {
+        Sha256::digest(self.0.as_bytes()).into()
+    }
+
+    /// Same as [`AccessToken::signed_issue_time`] with the secret and timeout of the server.
+    pub fn signed_issue_time_for(
+        &self,
+        settings: &Settings,
+        session_id: &SessionID,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        self.signed_issue_time(
+            &settings.token_secret,
+            session_id,
+            now,
+            settings.token_timeout,
+        )
+    }
+}
+
+/// The issue time has a fixed size, so the concatenation with the session id is unambiguous.
+fn sign(secret: &[u8], session_id: &SessionID, issued_at_ms: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(session_id.0.as_bytes());
+    mac.update(&issued_at_ms.to_be_bytes());
+    mac
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Extracts the token from the `Authorization: Bearer <token>` header. A missing or
+/// malformed header results in the same error as an invalid token.
+impl FromRequest for AccessToken {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match Authorization::<Bearer>::parse(req) {
+            Err(_) => Err(AppError::BadAccessToken),
+            Ok(auth) => AccessToken::from_string(auth.as_ref().token()),
+        })
+    }
+}
+
+/// See [`AccessToken::constant_time_eq`]. It's also used for tokens that are not session
+/// tokens, like the admin token.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
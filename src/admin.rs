@@ -0,0 +1,34 @@
+use actix_web::{dev::Payload, http::header::Header, web, FromRequest, HttpRequest};
+use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
+use std::future::{ready, Ready};
+
+use crate::{access_token::constant_time_eq, AppError, SharedState};
+
+/// Extractor that only succeeds when the request has the admin token configured at
+/// startup. Admin routes are disabled entirely when there is no admin token.
+pub struct AdminAuth;
+
+impl FromRequest for AdminAuth {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(check_admin_token(req))
+    }
+}
+
+fn check_admin_token(req: &HttpRequest) -> Result<AdminAuth, AppError> {
+    let Some(shared_state) = req.app_data::<web::Data<SharedState>>() else {
+        return Err(AppError::ServerError);
+    };
+    let Some(admin_token) = &shared_state.settings.admin_token else {
+        return Err(AppError::AdminDisabled);
+    };
+    let Ok(auth) = Authorization::<Bearer>::parse(req) else {
+        return Err(AppError::BadAccessToken);
+    };
+    if !constant_time_eq(auth.as_ref().token().as_bytes(), admin_token.as_bytes()) {
+        return Err(AppError::BadAccessToken);
+    }
+    Ok(AdminAuth)
+}
@@ -0,0 +1,118 @@
+use actix_web::HttpRequest;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{clock::Clock, rate_limit, AppError, Settings};
+
+/// Clients beyond this are not tracked, so that made-up client ids can't use arbitrary
+/// amounts of memory.
+pub const MAX_AUDIENCE_CLIENTS: usize = 10_000;
+
+/// Longer client ids are rejected.
+pub const MAX_CLIENT_ID_LENGTH: usize = 64;
+
+/// Clients can send a random id, so that several people behind the same ip count
+/// separately. Otherwise, the ip is used.
+pub fn client_key(
+    req: &HttpRequest,
+    settings: &Settings,
+    client: Option<&str>,
+) -> Result<String, AppError> {
+    match client {
+        Some(client) if client.is_empty() || client.len() > MAX_CLIENT_ID_LENGTH => {
+            Err(AppError::BadQueryParameters {
+                parameter: Some("client".to_string()),
+            })
+        }
+        Some(client) => Ok(format!("client:{client}")),
+        None => Ok(match rate_limit::client_ip(req, settings) {
+            Some(ip) => format!("ip:{ip}"),
+            None => "ip:unknown".to_string(),
+        }),
+    }
+}
+
+/// Approximates how many people follow a session. A client counts while it long-polls
+/// `/wait_for_new_page` and for [`Settings::audience_timeout`] after its last long-poll
+/// or ping, so that clients that vanish without closing their connection drop out.
+#[derive(Default)]
+pub struct Audience {
+    clients: HashMap<String, AudienceClient>,
+}
+
+struct AudienceClient {
+    /// Long-polls of the client that are in flight.
+    waiting: usize,
+    last_seen: Instant,
+}
+
+impl Audience {
+    /// Counts the client as present until the timeout has passed.
+    pub fn seen(&mut self, client: &str, now: Instant, timeout: Duration) {
+        self.touch(client, now, timeout, 0);
+    }
+
+    pub fn count(&self, now: Instant, timeout: Duration) -> usize {
+        self.clients
+            .values()
+            .filter(|client| client.is_present(now, timeout))
+            .count()
+    }
+
+    fn touch(&mut self, client: &str, now: Instant, timeout: Duration, waiting: isize) {
+        if !self.clients.contains_key(client) {
+            self.clients
+                .retain(|_, client| client.is_present(now, timeout));
+            if self.clients.len() >= MAX_AUDIENCE_CLIENTS {
+                return;
+            }
+        }
+        let entry = self
+            .clients
+            .entry(client.to_string())
+            .or_insert(AudienceClient {
+                waiting: 0,
+                last_seen: now,
+            });
+        entry.waiting = entry.waiting.saturating_add_signed(waiting);
+        entry.last_seen = entry.last_seen.max(now);
+    }
+}
+
+impl AudienceClient {
+    fn is_present(&self, now: Instant, timeout: Duration) -> bool {
+        self.waiting > 0 || now.saturating_duration_since(self.last_seen) < timeout
+    }
+}
+
+/// Counts the client as waiting until it is dropped, and as seen at that time afterwards.
+pub struct AudienceGuard {
+    audience: Arc<Mutex<Audience>>,
+    client: String,
+    clock: Arc<dyn Clock>,
+    timeout: Duration,
+}
+
+impl AudienceGuard {
+    pub fn acquire(settings: &Settings, audience: Arc<Mutex<Audience>>, client: String) -> Self {
+        audience
+            .lock()
+            .touch(&client, settings.instant(), settings.audience_timeout, 1);
+        AudienceGuard {
+            audience,
+            client,
+            clock: settings.clock.clone(),
+            timeout: settings.audience_timeout,
+        }
+    }
+}
+
+impl Drop for AudienceGuard {
+    fn drop(&mut self) {
+        self.audience
+            .lock()
+            .touch(&self.client, self.clock.instant(), self.timeout, -1);
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+
+use crate::{SessionID, Settings};
+
+/// Recent changes of sessions, so that an admin can find out what happened when a
+/// presenter reports that the page was overwritten. It only contains metadata, never
+/// pages or tokens. At most [`Settings::audit_log_len`] events are kept.
+#[derive(Default)]
+pub struct AuditLog {
+    /// Oldest first.
+    events: VecDeque<AuditEvent>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct AuditEvent {
+    pub session: SessionID,
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: AuditEventKind,
+}
+
+#[derive(Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEventKind {
+    SessionCreated {
+        ip: Option<IpAddr>,
+    },
+    PageUpdated {
+        ip: Option<IpAddr>,
+        access: PageAccess,
+    },
+    TokenRotated {
+        ip: Option<IpAddr>,
+    },
+    SessionRemoved {
+        reason: RemovalReason,
+    },
+}
+
+/// Why a page update was allowed.
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageAccess {
+    TokenMatched,
+    /// A newer signed token replaced the token of the session.
+    NewerSignedToken,
+    /// The session has not been used for longer than the token timeout, so someone else
+    /// started over with it.
+    Takeover,
+    /// A scheduled page became due.
+    Schedule,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    /// By the presenter or an admin.
+    Deleted,
+    Expired,
+    /// Too many sessions or too much memory.
+    Evicted,
+}
+
+impl AuditLog {
+    /// Drops the oldest events when the log is full.
+    pub fn record(
+        &mut self,
+        settings: &Settings,
+        session_id: &SessionID,
+        now: DateTime<Utc>,
+        kind: AuditEventKind,
+    ) {
+        if settings.audit_log_len == 0 {
+            return;
+        }
+        while self.events.len() >= settings.audit_log_len {
+            self.events.pop_front();
+        }
+        self.events.push_back(AuditEvent {
+            session: session_id.clone(),
+            time: now,
+            kind,
+        });
+    }
+
+    /// Events of the session, oldest first.
+    pub fn session_events(&self, session_id: &SessionID) -> Vec<AuditEvent> {
+        self.events
+            .iter()
+            .filter(|event| event.session == *session_id)
+            .cloned()
+            .collect()
+    }
+}
@@ -1,73 +1,536 @@
 use byte_unit::Byte;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
 use parking_lot::Mutex;
+use std::cell::{RefCell, RefMut};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::ops::DerefMut;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::{SessionState, Settings, State, UserResponse};
+use crate::{
+    audit_log::RemovalReason, rate_limit::RateLimiter, statistics, verify, SessionID, SessionState,
+    Settings, State, UserID, UserResponse,
+};
 
-pub async fn do_periodic_cleanup(settings: Settings, state: Arc<Mutex<State>>) {
+/// Sessions that still have to be checked for expiry in the current cleanup pass. Only a
+/// limited number of sessions is checked per tick, so that the state is not locked for
+/// too long when there are very many sessions.
+#[derive(Default)]
+pub struct CleanupCursor {
+    remaining: Vec<SessionID>,
+    scanned_in_pass: usize,
+    pass_duration: Duration,
+}
+
+#[derive(Default, Clone, Debug, serde::Serialize)]
+pub struct CleanupMetrics {
+    pub last_tick_duration: Duration,
+    pub last_pass_duration: Duration,
+    pub last_pass_sessions_scanned: usize,
+    pub completed_passes: usize,
+    pub evicted_sessions: usize,
+    /// Sessions that have been removed because the memory limit has been reached.
+    pub evicted_sessions_for_memory: usize,
+    /// Number of internal inconsistencies found by the periodic verification.
+    pub invariant_violations: usize,
+    /// Estimated memory usage in bytes as of the last cleanup tick.
+    pub memory_usage: u64,
+    /// The estimate multiplied by the safety factor. This is compared to the limit.
+    pub memory_usage_with_safety_buffer: u64,
+}
+
+/// Access to the state during the cleanup. When the state is shared with request
+/// handlers, the lock is released between batches.
+pub trait CleanupState {
+    fn lock(&self) -> impl DerefMut<Target = State> + '_;
+    /// Gives others the chance to lock the state.
+    fn release(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl CleanupState for Mutex<State> {
+    fn lock(&self) -> impl DerefMut<Target = State> + '_ {
+        Mutex::lock(self)
+    }
+
+    fn release(&self) -> impl Future<Output = ()> + Send {
+        tokio::task::yield_now()
+    }
+}
+
+/// State that is only used by the cleanup, so it never has to wait.
+struct ExclusiveState<'a>(RefCell<&'a mut State>);
+
+impl CleanupState for ExclusiveState<'_> {
+    fn lock(&self) -> impl DerefMut<Target = State> + '_ {
+        RefMut::map(self.0.borrow_mut(), |state| &mut **state)
+    }
+
+    fn release(&self) -> impl Future<Output = ()> + Send {
+        std::future::ready(())
+    }
+}
+
+pub async fn do_periodic_cleanup(
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+    rate_limiter: Arc<RateLimiter>,
+) {
     let mut interval = tokio::time::interval(settings.cleanup_interval);
+    let mut cursor = CleanupCursor::default();
+    let mut last_verification = Instant::now();
+    let mut last_memory_recount = Instant::now();
     loop {
         interval.tick().await;
-        let mut state = state.lock();
-        let now = Utc::now();
-
-        // Delete old sessions.
-        state.sessions.retain(|_, session| {
-            return session.last_request + settings.session_keep_alive_duration > now;
-        });
-
-        // Count used memory with a safety buffer in case more drastic measures to free
-        // memory have to be taken.
-        let used_bytes = get_memory_usage_with_safety_buffer(&state);
-        if used_bytes < settings.max_memory_usage {
-            // Enough memory is available. No need to do anything else.
-            continue;
+        rate_limiter.remove_full_buckets(Instant::now());
+
+        // Look for bugs that result in inconsistent state once in a while.
+        if last_verification.elapsed() >= settings.verify_interval {
+            last_verification = Instant::now();
+            verify_sessions_in_batches(&settings, &state).await;
+        }
+
+        // Correct the drift of the incrementally tracked memory usage.
+        if last_memory_recount.elapsed() >= settings.memory_recount_interval {
+            last_memory_recount = Instant::now();
+            recount_memory_usage_in_batches(&settings, &state).await;
+        }
+
+        cleanup_tick(&settings, &*state, &mut cursor, settings.now()).await;
+    }
+}
+
+/// Runs the whole cleanup at once while the caller holds the lock. This does the same as
+/// [`cleanup_tick`], which is used by the periodic cleanup.
+pub fn cleanup_once(settings: &Settings, state: &mut State, now: DateTime<Utc>) {
+    let state = ExclusiveState(RefCell::new(state));
+    cleanup_tick(settings, &state, &mut CleanupCursor::default(), now)
+        .now_or_never()
+        .expect("The cleanup does not wait for exclusive state");
+}
+
+/// The state is only locked for a batch of sessions at a time, so that requests don't
+/// have to wait for long even if there are very many sessions.
+pub async fn cleanup_tick(
+    settings: &Settings,
+    state: &impl CleanupState,
+    cursor: &mut CleanupCursor,
+    now: DateTime<Utc>,
+) {
+    // Delete old sessions.
+    loop {
+        let pass_done = expire_sessions_incrementally(settings, &mut state.lock(), cursor, now);
+        if pass_done {
+            break;
         }
+        state.release().await;
+    }
 
-        // Free responses that should have been received by all interested parties already.
-        for (_, session) in &mut state.sessions {
-            session.responses.retain(|_, user_response| {
-                return user_response.was_received
-                    && user_response.time + Duration::from_secs(30) > now;
+    // Having very many small sessions is a problem even if they don't use much memory.
+    evict_least_recently_used_sessions(settings, state, now).await;
+
+    // Count used memory with a safety buffer in case more drastic measures to free
+    // memory have to be taken.
+    let used_bytes = get_memory_usage_with_safety_buffer(settings, &mut state.lock());
+    if used_bytes < settings.tunables().max_memory_usage {
+        // Enough memory is available. No need to do anything else.
+        return;
+    }
+
+    // Free responses that should have been received by all interested parties already.
+    // Responses that have not been received yet are kept.
+    let session_ids = get_session_ids(state);
+    let instant = settings.instant();
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        if let Some(session) = state.sessions.get_mut(session_id) {
+            let mut freed_bytes = 0;
+            let mut dropped = 0;
+            session.responses.retain(|user_id, user_response| {
+                let keep = !user_response.was_received
+                    || instant.saturating_duration_since(user_response.arrived)
+                        < settings.received_response_retention;
+                if !keep {
+                    freed_bytes += count_response_memory_usage(user_id, user_response);
+                    dropped += 1;
+                }
+                keep
             });
+            state.track_memory_usage(freed_bytes, 0);
+            statistics::add(&state.statistics.responses_dropped, dropped);
         }
+    })
+    .await;
 
-        let used_bytes = get_memory_usage_with_safety_buffer(&state);
-        if used_bytes < settings.max_memory_usage {
-            // Looks like nothing else has to be freed.
-            continue;
+    let used_bytes = get_memory_usage_with_safety_buffer(settings, &mut state.lock());
+    if used_bytes < settings.tunables().max_memory_usage {
+        // Looks like nothing else has to be freed.
+        return;
+    }
+
+    // Previous pages are only kept so that the presenter can go back to them, which is
+    // less important than keeping the sessions.
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        if let Some(session) = state.sessions.get_mut(session_id) {
+            let freed_bytes = count_page_history_memory_usage(session);
+            session.page_history = VecDeque::new();
+            state.track_memory_usage(freed_bytes, 0);
         }
+    })
+    .await;
 
-        // If all above did not help, it's likely that there is some kind of attack.
-        // It's not really something we can protect against at this level. Best we
-        // can do is to just free everything that wasn't used a few seconds ago.
-        // Valid users should use this system in real-time and should have received
-        // responses in less than a few seconds already.
-        state.sessions.retain(|_, session| {
-            return session.last_request + Duration::from_secs(5) > now;
-        });
-        state.sessions.shrink_to_fit();
-        for (_, session) in &mut state.sessions {
+    let used_bytes = get_memory_usage_with_safety_buffer(settings, &mut state.lock());
+    if used_bytes < settings.tunables().max_memory_usage {
+        return;
+    }
+
+    // Pages that reference assets still work without them, just with missing images.
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        if let Some(session) = state.sessions.get_mut(session_id) {
+            let freed_bytes = count_assets_memory_usage(session);
+            session.assets = HashMap::new();
+            state.track_memory_usage(freed_bytes, 0);
+        }
+    })
+    .await;
+
+    let used_bytes = get_memory_usage_with_safety_buffer(settings, &mut state.lock());
+    if used_bytes < settings.tunables().max_memory_usage {
+        return;
+    }
+
+    // If all above did not help, it's likely that there is some kind of attack.
+    // It's not really something we can protect against at this level. Best we
+    // can do is to free the sessions that have not been used for the longest time.
+    // Valid users should use this system in real-time, so their sessions are kept.
+    evict_sessions_for_memory(settings, state, now).await;
+    let session_ids = get_session_ids(state);
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        if let Some(session) = state.sessions.get_mut(session_id) {
+            let old_bytes = count_responses_capacity(&session.responses);
             session.responses.shrink_to_fit();
+            let new_bytes = count_responses_capacity(&session.responses);
+            state.track_memory_usage(old_bytes, new_bytes);
+        }
+    })
+    .await;
+    state.lock().sessions.shrink_to_fit();
+}
+
+/// Removes the least recently used sessions until the memory usage is below the limit.
+/// Sessions that have been used within the emergency retention are never removed,
+/// because they likely belong to an ongoing presentation.
+pub async fn evict_sessions_for_memory(
+    settings: &Settings,
+    state: &impl CleanupState,
+    now: DateTime<Utc>,
+) {
+    let mut sessions_by_age = get_sessions_by_age(settings, state).await;
+    let instant = settings.instant();
+    sessions_by_age.retain(|(last_used, _)| {
+        instant.saturating_duration_since(*last_used) >= settings.emergency_retention
+    });
+    sessions_by_age.sort_unstable_by_key(|(last_used, _)| *last_used);
+
+    let mut is_below_limit = false;
+    let session_ids: Vec<SessionID> = sessions_by_age
+        .iter()
+        .map(|(_, session_id)| session_id.clone())
+        .collect();
+    let mut last_used: HashMap<SessionID, Instant> = sessions_by_age
+        .into_iter()
+        .map(|(last_used, session_id)| (session_id, last_used))
+        .collect();
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        is_below_limit = is_below_limit || is_below_memory_limit_after_shrink(settings, state);
+        if is_below_limit {
+            return;
+        }
+        // Sessions that have been used in the mean-time are kept.
+        let previously_used = last_used.remove(session_id);
+        let is_unchanged = state
+            .sessions
+            .get(session_id)
+            .is_some_and(|session| Some(session.last_used) == previously_used);
+        if is_unchanged {
+            state.expire_session(settings, session_id, now, RemovalReason::Evicted);
+            state.cleanup_metrics.evicted_sessions_for_memory += 1;
+            statistics::add(&state.statistics.sessions_evicted_for_memory, 1);
+            println!(
+                "Evicted session {} because the memory limit has been reached",
+                session_id.0
+            );
+        }
+    })
+    .await;
+}
+
+pub(crate) fn get_session_ids(state: &impl CleanupState) -> Vec<SessionID> {
+    state.lock().sessions.keys().cloned().collect()
+}
+
+/// Calls the function for every given session id. The lock is released between batches.
+pub(crate) async fn process_in_batches(
+    settings: &Settings,
+    state: &impl CleanupState,
+    session_ids: &[SessionID],
+    mut f: impl FnMut(&mut State, &SessionID),
+) {
+    for batch in session_ids.chunks(settings.cleanup_batch_size.max(1)) {
+        {
+            let mut state = state.lock();
+            for session_id in batch {
+                f(&mut state, session_id);
+            }
         }
+        state.release().await;
     }
 }
 
-fn get_memory_usage_with_safety_buffer(state: &State) -> Byte {
-    count_user_memory_usage(state).multiply(2).unwrap()
+async fn verify_sessions_in_batches(settings: &Settings, state: &Mutex<State>) {
+    let now = settings.now();
+    let session_ids = get_session_ids(state);
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        let Some(session) = state.sessions.get_mut(session_id) else {
+            return;
+        };
+        let violations = verify::verify_session(session_id, session, now, false);
+        for violation in &violations {
+            println!("Invariant violation: {:?}", violation);
+        }
+        state.cleanup_metrics.invariant_violations += violations.len();
+    })
+    .await;
+}
+
+/// Checks the next batch of sessions for expiry. A new pass over all sessions starts
+/// when the previous one is done. Returns true when the pass has been completed.
+pub fn expire_sessions_incrementally(
+    settings: &Settings,
+    state: &mut State,
+    cursor: &mut CleanupCursor,
+    now: DateTime<Utc>,
+) -> bool {
+    let tick_start = Instant::now();
+    if cursor.remaining.is_empty() {
+        cursor.remaining = state.sessions.keys().cloned().collect();
+    }
+
+    let mut scanned = 0;
+    // At least one session is checked, so that every pass completes eventually.
+    while scanned < settings.cleanup_batch_size.max(1)
+        && (scanned == 0 || tick_start.elapsed() < settings.cleanup_time_budget)
+    {
+        let Some(session_id) = cursor.remaining.pop() else {
+            break;
+        };
+        scanned += 1;
+        // The session may have been removed in the mean-time already.
+        let Some(session) = state.sessions.get_mut(&session_id) else {
+            continue;
+        };
+        if session.unused_for(settings) >= session.keep_alive_duration(settings) {
+            state.expire_session(settings, &session_id, now, RemovalReason::Expired);
+            statistics::add(&state.statistics.sessions_expired, 1);
+            continue;
+        }
+        if session.current_message(settings, now).is_none() {
+            if let Some(message) = session.message.take() {
+                state.track_memory_usage(message.text.len() as u64, 0);
+            }
+        }
+    }
+
+    let tick_duration = tick_start.elapsed();
+    cursor.scanned_in_pass += scanned;
+    cursor.pass_duration += tick_duration;
+
+    let metrics = &mut state.cleanup_metrics;
+    metrics.last_tick_duration = tick_duration;
+    if !cursor.remaining.is_empty() {
+        return false;
+    }
+    metrics.last_pass_duration = cursor.pass_duration;
+    metrics.last_pass_sessions_scanned = cursor.scanned_in_pass;
+    metrics.completed_passes += 1;
+    cursor.scanned_in_pass = 0;
+    cursor.pass_duration = Duration::ZERO;
+    true
 }
 
-fn count_user_memory_usage(state: &State) -> Byte {
-    let mut used_bytes: usize = 0;
-    for (session_id, session) in &state.sessions {
-        used_bytes += session_id.0.len() + session.page.len() + session.access_token.0.len();
-        for (user_id, user_response) in &session.responses {
-            used_bytes += user_id.0.len() + user_response.data.len();
+/// Enforces the maximum number of sessions by removing the sessions that have not been
+/// used for the longest time.
+pub async fn evict_least_recently_used_sessions(
+    settings: &Settings,
+    state: &impl CleanupState,
+    now: DateTime<Utc>,
+) {
+    let excess = state
+        .lock()
+        .sessions
+        .len()
+        .saturating_sub(settings.tunables().max_sessions);
+    if excess == 0 {
+        return;
+    }
+    let mut sessions_by_age = get_sessions_by_age(settings, state).await;
+    let excess = excess.min(sessions_by_age.len());
+    if excess == 0 {
+        return;
+    }
+    sessions_by_age.select_nth_unstable_by_key(excess - 1, |(last_used, _)| *last_used);
+    sessions_by_age.truncate(excess);
+
+    let mut last_used: HashMap<SessionID, Instant> = sessions_by_age
+        .into_iter()
+        .map(|(last_used, session_id)| (session_id, last_used))
+        .collect();
+    let session_ids: Vec<SessionID> = last_used.keys().cloned().collect();
+    process_in_batches(settings, state, &session_ids, |state, session_id| {
+        // Sessions that have been used in the mean-time are kept.
+        let previously_used = last_used.remove(session_id);
+        let is_unchanged = state
+            .sessions
+            .get(session_id)
+            .is_some_and(|session| Some(session.last_used) == previously_used);
+        if is_unchanged {
+            state.expire_session(settings, session_id, now, RemovalReason::Evicted);
+            state.cleanup_metrics.evicted_sessions += 1;
+            statistics::add(&state.statistics.sessions_evicted, 1);
         }
-        used_bytes += size_of::<UserResponse>() * session.responses.capacity();
+    })
+    .await;
+}
+
+/// Uses the monotonic time of the last usage, see [`SessionState::last_used`].
+async fn get_sessions_by_age(
+    settings: &Settings,
+    state: &impl CleanupState,
+) -> Vec<(Instant, SessionID)> {
+    let mut sessions_by_age = vec![];
+    process_in_batches(
+        settings,
+        state,
+        &get_session_ids(state),
+        |state, session_id| {
+            if let Some(session) = state.sessions.get(session_id) {
+                sessions_by_age.push((session.last_used, session_id.clone()));
+            }
+        },
+    )
+    .await;
+    sessions_by_age
+}
+
+/// Uses the incrementally tracked memory usage, so that not all sessions have to be
+/// counted on every tick.
+fn get_memory_usage_with_safety_buffer(settings: &Settings, state: &mut State) -> Byte {
+    let used_bytes = state
+        .approx_bytes
+        .saturating_add(bytes_of::<SessionState>(state.sessions.capacity()));
+    let used_bytes_with_buffer = apply_safety_factor(used_bytes, settings.memory_safety_factor);
+    state.cleanup_metrics.memory_usage = used_bytes;
+    state.cleanup_metrics.memory_usage_with_safety_buffer = used_bytes_with_buffer.as_u64();
+    if used_bytes_with_buffer >= settings.tunables().max_memory_usage {
+        println!(
+            "Memory usage of {} bytes ({} with safety buffer) exceeds the limit of {}",
+            used_bytes,
+            used_bytes_with_buffer,
+            settings.tunables().max_memory_usage
+        );
     }
-    used_bytes += size_of::<SessionState>() * state.sessions.capacity();
-    Byte::from_u64(used_bytes as u64)
+    used_bytes_with_buffer
+}
+
+/// Counts sessions batch by batch. Changes of sessions that have been counted already
+/// are lost when they happen during the recount, but that is corrected by the next one.
+pub async fn recount_memory_usage_in_batches(settings: &Settings, state: &Mutex<State>) {
+    let mut used_bytes: u64 = 0;
+    process_in_batches(
+        settings,
+        state,
+        &get_session_ids(state),
+        |state, session_id| {
+            if let Some(session) = state.sessions.get(session_id) {
+                used_bytes =
+                    used_bytes.saturating_add(count_session_memory_usage(session_id, session));
+            }
+        },
+    )
+    .await;
+    state.lock().approx_bytes = used_bytes;
+}
+
+/// The sessions map is shrunk after sessions have been evicted, so only the sessions that
+/// are left are taken into account.
+fn is_below_memory_limit_after_shrink(settings: &Settings, state: &State) -> bool {
+    let used_bytes = state
+        .approx_bytes
+        .saturating_add(bytes_of::<SessionState>(state.sessions.len()));
+    apply_safety_factor(used_bytes, settings.memory_safety_factor)
+        < settings.tunables().max_memory_usage
+}
+
+/// Saturates instead of overflowing, so that a huge value still triggers the cleanup.
+pub fn apply_safety_factor(used_bytes: u64, factor: f64) -> Byte {
+    // Float to int casts saturate.
+    Byte::from_u64((used_bytes as f64 * factor.max(0.0)) as u64)
+}
+
+fn bytes_of<T>(count: usize) -> u64 {
+    (size_of::<T>() as u64).saturating_mul(count as u64)
+}
+
+pub fn count_session_memory_usage(session_id: &SessionID, session: &SessionState) -> u64 {
+    let mut used_bytes =
+        (session_id.0.len() + session.page.stored_len() + session.access_token.0.len()) as u64;
+    if let Some(message) = &session.message {
+        used_bytes = used_bytes.saturating_add(message.text.len() as u64);
+    }
+    used_bytes = used_bytes.saturating_add(count_page_history_memory_usage(session));
+    used_bytes = used_bytes.saturating_add(count_assets_memory_usage(session));
+    if let Some(staged_page) = &session.staged_page {
+        used_bytes = used_bytes.saturating_add(staged_page.len() as u64);
+    }
+    if let Some(scheduled_page) = &session.scheduled_page {
+        used_bytes = used_bytes.saturating_add(scheduled_page.page.len() as u64);
+    }
+    if let Some(viewer_token) = &session.viewer_token {
+        used_bytes = used_bytes.saturating_add(viewer_token.0.len() as u64);
+    }
+    for user_id in session.issued_user_ids.iter().chain(&session.banned_users) {
+        used_bytes = used_bytes.saturating_add(user_id.0.len() as u64);
+    }
+    for (user_id, user_response) in &session.responses {
+        used_bytes = used_bytes.saturating_add(count_response_memory_usage(user_id, user_response));
+    }
+    used_bytes.saturating_add(count_responses_capacity(&session.responses))
+}
+
+pub fn count_page_history_memory_usage(session: &SessionState) -> u64 {
+    session.page_history.iter().fold(0, |total: u64, entry| {
+        total.saturating_add(entry.page.stored_len() as u64)
+    })
+}
+
+pub fn count_assets_memory_usage(session: &SessionState) -> u64 {
+    session.assets.iter().fold(0, |total: u64, (name, asset)| {
+        total.saturating_add((name.len() + asset.data.len() + asset.content_type.len()) as u64)
+    })
+}
+
+pub fn count_response_memory_usage(user_id: &UserID, user_response: &UserResponse) -> u64 {
+    let user_agent = user_response
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.user_agent.as_ref());
+    (user_id.0.len()
+        + user_response.data.len()
+        + user_response.content_type.len()
+        + user_agent.map_or(0, |user_agent| user_agent.len())) as u64
+}
+
+/// Memory of the hash map itself, excluding the data the responses point to.
+pub fn count_responses_capacity(responses: &HashMap<UserID, UserResponse>) -> u64 {
+    bytes_of::<UserResponse>(responses.capacity())
 }
@@ -0,0 +1,580 @@
+use byte_unit::{Byte, Unit};
+use clap::{Args, Parser, Subcommand};
+use parking_lot::Mutex;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    cleanup, commands, config, digest, page, persist, push, response_filter::FilterMode,
+    response_webhook, scheduled_page, settings, start_server, tls, CorsPolicy, FrameOptions,
+    Listener, MemoryStorage, RateLimiter, RedisStorage, ResponseThrottle, SessionIDStyle, Settings,
+    State, Storage,
+};
+
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Options of `serve`, so that the server starts without a subcommand.
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the server. This is the default.
+    Serve(Box<ServeArgs>),
+
+    /// Print a token for the session, like `/new` would create it. The server accepts it
+    /// when it is started with the same `--token-secret`.
+    GenerateToken {
+        #[arg(long)]
+        session: String,
+
+        #[arg(long)]
+        token_secret: String,
+    },
+
+    /// Check if the page would be accepted and where the polli.live script would be injected.
+    CheckPage {
+        file: PathBuf,
+
+        #[arg(long, default_value = "1024")]
+        page_size_limit_kb: usize,
+
+        #[arg(long)]
+        inline_injection: bool,
+    },
+
+    /// Set the page of a session to the file, e.g. `push --server https://polli.live
+    /// --session 123456 --token <token> talk.html`.
+    Push {
+        #[arg(long)]
+        server: String,
+
+        #[arg(long)]
+        session: String,
+
+        #[arg(long)]
+        token: String,
+
+        file: PathBuf,
+
+        /// Push the file again whenever it changes.
+        #[arg(long)]
+        watch: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Can be passed multiple times or as comma separated list, e.g. `0.0.0.0,::`.
+    #[arg(long, default_value = "0.0.0.0", value_delimiter = ',')]
+    host: Vec<String>,
+
+    #[arg(long, default_value = "9000")]
+    pub port: u16,
+
+    /// Also listen on this unix socket, e.g. for a reverse proxy on the same host.
+    #[cfg(unix)]
+    #[arg(long)]
+    unix_socket: Option<PathBuf>,
+
+    /// Permissions of the unix socket in octal, e.g. `660`.
+    #[cfg(unix)]
+    #[arg(long, requires = "unix_socket", value_parser = parse_socket_mode)]
+    socket_mode: Option<u32>,
+
+    /// Url under which the server is reachable, used e.g. for links. With `auto`, it is
+    /// derived from the `Host` header of each request.
+    #[arg(long)]
+    root_url: Option<String>,
+
+    /// Path below which all routes are served, e.g. `/polli` when a reverse proxy forwards
+    /// `https://example.com/polli/` to this server. The root url should not contain it.
+    #[arg(long, default_value = "")]
+    base_path: String,
+
+    /// Serve https with the certificate chain in this pem file. Requires `--tls-key`.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key in pem format that belongs to `--tls-cert`.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    #[arg(long, default_value = "1024")]
+    page_size_limit_kb: usize,
+
+    /// Maximum size of files that pages can reference with `/asset`.
+    #[arg(long, default_value_t = 256)]
+    asset_size_limit_kb: usize,
+
+    /// Maximum number of files per session for `/asset`.
+    #[arg(long, default_value_t = 16)]
+    max_assets_per_session: usize,
+
+    #[arg(long, default_value = "4")]
+    response_size_limit_kb: usize,
+
+    #[arg(long, default_value = "50000")]
+    max_sessions: usize,
+
+    /// No new sessions are created when there are that many. Defaults to twice
+    /// `--max-sessions`, because sessions beyond that are only evicted periodically.
+    #[arg(long)]
+    max_sessions_total: Option<usize>,
+
+    /// Only allow creating sessions with `/new` instead of implicitly when a page is set.
+    #[arg(long)]
+    no_implicit_sessions: bool,
+
+    /// Only changing the page and responses keep sessions alive, but not fetching the page.
+    #[arg(long)]
+    no_touch_on_read: bool,
+
+    /// Put the polli.live script into every page instead of referencing it.
+    #[arg(long)]
+    inline_injection: bool,
+
+    /// Keep pages compressed in memory, so that more sessions fit.
+    #[arg(long)]
+    compress_stored_pages: bool,
+
+    /// Directory with files that replace the embedded static files, e.g. `index.html`.
+    #[arg(long)]
+    static_dir: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "digits")]
+    session_id_style: SessionIDStyle,
+
+    /// Token required for the `/admin` routes. They are disabled without it.
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// Require the session token or a viewer token for reading responses.
+    #[arg(long)]
+    responses_require_auth: bool,
+
+    /// Media type that responses may have, e.g. `application/json`. Can be passed multiple
+    /// times. By default, `text/plain` and `application/json` are allowed.
+    #[arg(long)]
+    allowed_response_content_type: Vec<String>,
+
+    /// File with words that are not allowed in free-text responses, one per line. Lines like
+    /// `/regex/` are regexes. It is read again on `SIGHUP`.
+    #[arg(long)]
+    response_filter: Option<PathBuf>,
+
+    /// What happens to responses that contain filtered words.
+    #[arg(long, value_enum, default_value = "reject")]
+    response_filter_mode: FilterMode,
+
+    /// Only accept responses from user ids that have been issued by `/join`.
+    #[arg(long, conflicts_with = "redis_url")]
+    require_server_user_ids: bool,
+
+    /// Allow webhooks and digests to post to private networks and to the server itself.
+    #[arg(long)]
+    allow_private_webhook_urls: bool,
+
+    /// Only mark responses as received when the presenter calls `/ack`. Otherwise, a later
+    /// call of `/responses` marks the earlier responses.
+    #[arg(long)]
+    require_explicit_ack: bool,
+
+    /// Store the page version and the User-Agent with responses for `/export_session`,
+    /// e.g. to measure how long the audience takes to answer.
+    #[arg(long, conflicts_with = "redis_url")]
+    collect_response_metadata: bool,
+
+    /// Key for signing session tokens. Presenters can keep using their tokens after a
+    /// restart if it stays the same. A random key is used if it is not set.
+    #[arg(long)]
+    token_secret: Option<String>,
+
+    /// Directory where sessions are stored, so that they are restored after a restart.
+    #[arg(long)]
+    persist_path: Option<PathBuf>,
+
+    /// How often sessions are written to the persist path in seconds.
+    #[arg(long, default_value_t = 30)]
+    persist_interval: u64,
+
+    /// Store sessions in Redis, so that multiple instances can serve the same sessions.
+    /// Only `/page`, `/respond` and `/responses` use it so far.
+    #[arg(long)]
+    redis_url: Option<String>,
+
+    /// Prefix of all Redis keys used by this server.
+    #[arg(long, default_value = "polli:")]
+    redis_key_prefix: String,
+
+    /// Use the client ip from the `X-Forwarded-For` header for rate limiting. Only set
+    /// this when the server runs behind a proxy.
+    #[arg(long)]
+    trusted_proxy: bool,
+
+    /// Only allow websites with this origin to use the api from the browser, e.g.
+    /// `https://talks.example.com`. Can be passed multiple times.
+    #[arg(long, conflicts_with = "cors_permissive")]
+    allowed_origin: Vec<String>,
+
+    /// Allow all websites to use the api from the browser. This is the default.
+    #[arg(long)]
+    cors_permissive: bool,
+
+    /// `Content-Security-Policy` header for html responses, e.g. `default-src 'self'`.
+    #[arg(long)]
+    csp: Option<String>,
+
+    /// `X-Frame-Options` header for html responses. Pages can be embedded anywhere without it.
+    #[arg(long, value_enum)]
+    frame_options: Option<FrameOptions>,
+
+    /// Don't add any security headers to html responses.
+    #[arg(long, conflicts_with_all = ["csp", "frame_options"])]
+    no_security_headers: bool,
+
+    /// Upper bound for the `limit` of `/responses`.
+    #[arg(long, default_value_t = 1000)]
+    max_responses_per_request: usize,
+
+    /// Upper bound for the `timeout_ms` of long-polling routes in seconds.
+    #[arg(long, default_value_t = 60)]
+    max_long_poll_duration: u64,
+
+    /// Maximum number of long-polls that wait for the same session at the same time.
+    #[arg(long, default_value_t = 2000)]
+    max_long_polls_per_session: usize,
+
+    /// Maximum number of long-polls that wait at the same time.
+    #[arg(long, default_value_t = 20_000)]
+    max_long_polls_total: usize,
+
+    /// Maximum size of messages for the audience in bytes.
+    #[arg(long, default_value_t = 500)]
+    max_message_size: usize,
+
+    /// Messages for the audience are removed after that many seconds.
+    #[arg(long, default_value_t = 300)]
+    message_lifetime: u64,
+
+    /// Number of previous pages per session that can be restored with `/page_rollback`.
+    #[arg(long, default_value_t = 3)]
+    page_history_len: usize,
+
+    /// Page updates within that many milliseconds after the audience has been told to
+    /// reload are announced together, e.g. when the page changes many times per second.
+    #[arg(long, default_value_t = 0)]
+    page_notify_debounce_ms: u64,
+
+    /// Number of session events that admins can see with `/admin/audit`.
+    #[arg(long, default_value_t = 1000)]
+    audit_log_len: usize,
+
+    /// Minimum time between two responses of the same user in milliseconds.
+    #[arg(long, default_value_t = 200)]
+    min_response_interval_ms: u64,
+
+    /// What happens to responses that come faster than the minimum interval.
+    #[arg(long, value_enum, default_value = "coalesce")]
+    response_throttle: ResponseThrottle,
+
+    /// The estimated memory usage is multiplied by this before comparing it with the limit.
+    #[arg(long, default_value_t = 2.0)]
+    memory_safety_factor: f64,
+
+    /// Received responses are kept at least that many seconds when memory is low.
+    #[arg(long, default_value_t = 30)]
+    received_response_retention: u64,
+
+    /// Sessions that have been used within that many seconds are kept when memory is low.
+    #[arg(long, default_value_t = 30)]
+    emergency_retention: u64,
+
+    /// Toml file with settings that can be changed at runtime, e.g. `max_response_size`.
+    /// It overrides the command line and is read again on `SIGHUP`.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+pub fn run(cli: Cli) -> std::io::Result<()> {
+    match cli.command.unwrap_or(Command::Serve(Box::new(cli.serve))) {
+        Command::Serve(args) => serve(*args),
+        Command::GenerateToken {
+            session,
+            token_secret,
+        } => {
+            match commands::generate_token(&token_secret, &session, chrono::Utc::now()) {
+                Ok(token) => println!("{}", token.0),
+                Err(err) => {
+                    eprintln!("Cannot generate token: {}", err);
+                    std::process::exit(2);
+                }
+            }
+            Ok(())
+        }
+        Command::CheckPage {
+            file,
+            page_size_limit_kb,
+            inline_injection,
+        } => {
+            let page = std::fs::read_to_string(&file)?;
+            let mut settings = Settings::default("http://127.0.0.1:9000".to_string());
+            settings.max_page_size =
+                Byte::from_u64_with_unit(page_size_limit_kb as u64, Unit::KB).unwrap();
+            settings.inline_injection = inline_injection;
+            let check = page::check_page(&settings, &page);
+            println!(
+                "{}",
+                commands::describe_page_check(&file.display().to_string(), &check)
+            );
+            if check.result.is_err() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Push {
+            server,
+            session,
+            token,
+            file,
+            watch,
+        } => {
+            let target = push::PushTarget {
+                server_url: server,
+                session,
+                token,
+            };
+            push(target, file, watch)
+        }
+    }
+}
+
+#[tokio::main]
+async fn push(target: push::PushTarget, file: PathBuf, watch: bool) -> std::io::Result<()> {
+    let client = reqwest::Client::new();
+    let result = match watch {
+        true => push::watch_and_push(&client, &target, &file).await,
+        false => push::push_file(&client, &target, &file)
+            .await
+            .map(|status| push::report_push(&file, Ok(status))),
+    };
+    if let Err(err) = result {
+        eprintln!("Cannot push {}: {}", file.display(), err);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[actix_web::main]
+async fn serve(args: ServeArgs) -> std::io::Result<()> {
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(
+            tls::load_server_config(cert, key)
+                .unwrap_or_else(|err| panic!("Cannot set up TLS: {}", err)),
+        ),
+        _ => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    let tcp_listeners: Vec<TcpListener> = args
+        .host
+        .iter()
+        .map(|host| {
+            // Ipv6 addresses may be given in brackets like in urls.
+            let ip = host.trim_start_matches('[').trim_end_matches(']');
+            TcpListener::bind((ip, args.port))
+                .unwrap_or_else(|err| panic!("Cannot bind to {} port {}: {}", host, args.port, err))
+        })
+        .collect();
+    // With port 0, every listener gets a different port. The first one is used for the
+    // root url.
+    let actual_port = tcp_listeners[0].local_addr().unwrap().port();
+
+    for listener in &tcp_listeners {
+        let addr = listener.local_addr().unwrap();
+        println!(
+            "Start server on {}://{}{}",
+            scheme,
+            addr,
+            settings::normalize_base_path(&args.base_path)
+        );
+    }
+    #[allow(unused_mut)]
+    let mut listeners: Vec<Listener> = tcp_listeners.into_iter().map(Listener::Tcp).collect();
+    #[cfg(unix)]
+    if let Some(path) = &args.unix_socket {
+        let listener = start_server::bind_unix_socket(path, args.socket_mode)
+            .unwrap_or_else(|err| panic!("Cannot bind to {}: {}", path.display(), err));
+        println!("Start server on unix socket {}", path.display());
+        listeners.push(Listener::Unix(listener));
+    }
+
+    // With `auto`, the default is only used when the request has no usable `Host` header.
+    let auto_root_url = args.root_url.as_deref() == Some("auto");
+    let root_url = args
+        .root_url
+        .filter(|_| !auto_root_url)
+        .unwrap_or_else(|| format!("{}://127.0.0.1:{}", scheme, actual_port));
+
+    let mut settings = Settings::default(root_url);
+    settings.auto_root_url = auto_root_url;
+    settings.base_path = settings::normalize_base_path(&args.base_path);
+    settings.max_page_size =
+        Byte::from_u64_with_unit(args.page_size_limit_kb as u64, Unit::KB).unwrap();
+    settings.max_asset_size =
+        Byte::from_u64_with_unit(args.asset_size_limit_kb as u64, Unit::KB).unwrap();
+    settings.max_assets_per_session = args.max_assets_per_session;
+    {
+        let mut tunables = settings.tunables.write();
+        tunables.max_response_size =
+            Byte::from_u64_with_unit(args.response_size_limit_kb as u64, Unit::KB).unwrap();
+        tunables.max_sessions = args.max_sessions;
+        tunables.max_sessions_total = args
+            .max_sessions_total
+            .unwrap_or(args.max_sessions.saturating_mul(2));
+        tunables.min_response_interval = Duration::from_millis(args.min_response_interval_ms);
+    }
+    // Settings that are removed from the config file fall back to these when reloading.
+    let default_tunables = settings.tunables();
+    if let Some(path) = &args.config {
+        *settings.tunables.write() = config::load_tunables(path, &default_tunables)
+            .unwrap_or_else(|err| panic!("Cannot load config: {}", err));
+    }
+    settings.allow_implicit_session_creation = !args.no_implicit_sessions;
+    settings.touch_on_read = !args.no_touch_on_read;
+    settings.inline_injection = args.inline_injection;
+    settings.compress_stored_pages = args.compress_stored_pages;
+    settings.static_dir = args.static_dir;
+    settings.admin_token = args.admin_token;
+    settings.session_id_style = args.session_id_style;
+    settings.max_responses_per_request = args.max_responses_per_request;
+    settings.max_long_poll_duration = Duration::from_secs(args.max_long_poll_duration);
+    settings.max_long_polls_per_session = args.max_long_polls_per_session;
+    settings.max_long_polls_total = args.max_long_polls_total;
+    settings.max_message_size = args.max_message_size;
+    settings.message_lifetime = Duration::from_secs(args.message_lifetime);
+    settings.page_history_len = args.page_history_len;
+    settings.audit_log_len = args.audit_log_len;
+    settings.page_notify_debounce = Duration::from_millis(args.page_notify_debounce_ms);
+    settings.responses_require_auth = args.responses_require_auth;
+    settings.require_explicit_ack = args.require_explicit_ack;
+    settings.collect_response_metadata = args.collect_response_metadata;
+    settings.require_server_user_ids = args.require_server_user_ids;
+    settings.allow_private_webhook_urls = args.allow_private_webhook_urls;
+    settings.response_filter_path = args.response_filter;
+    settings.response_filter_mode = args.response_filter_mode;
+    config::reload_response_filter(&settings)
+        .unwrap_or_else(|err| panic!("Cannot load response filter: {}", err));
+    if !args.allowed_response_content_type.is_empty() {
+        settings.allowed_response_content_types = args.allowed_response_content_type;
+    }
+    if let Some(token_secret) = args.token_secret {
+        settings.token_secret = token_secret.into_bytes();
+    }
+    settings.persist_path = args.persist_path;
+    settings.persist_interval = Duration::from_secs(args.persist_interval);
+    settings.redis_key_prefix = args.redis_key_prefix;
+    settings.trust_forwarded_for = args.trusted_proxy;
+    if !args.allowed_origin.is_empty() {
+        settings.cors = CorsPolicy::AllowedOrigins(args.allowed_origin);
+    }
+    settings.security_headers.enabled = !args.no_security_headers;
+    settings.security_headers.content_security_policy = args.csp;
+    settings.security_headers.frame_options = args.frame_options;
+    settings.response_throttle = args.response_throttle;
+    settings.memory_safety_factor = args.memory_safety_factor;
+    settings.received_response_retention = Duration::from_secs(args.received_response_retention);
+    settings.emergency_retention = Duration::from_secs(args.emergency_retention);
+
+    let mut state = match &settings.persist_path {
+        Some(dir) => persist::load_state(dir),
+        None => State::default(),
+    };
+    persist::restore_instants(&settings, &mut state);
+    // Restored sessions may have expired while the server was stopped.
+    cleanup::cleanup_once(&settings, &mut state, settings.now());
+    let state = Arc::new(Mutex::new(state));
+
+    let rate_limiter = Arc::new(RateLimiter::default());
+
+    let settings_clone = settings.clone();
+    let state_clone = state.clone();
+    let rate_limiter_clone = rate_limiter.clone();
+    tokio::spawn(async move {
+        cleanup::do_periodic_cleanup(settings_clone, state_clone, rate_limiter_clone).await;
+    });
+
+    let settings_clone = settings.clone();
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        digest::do_periodic_digest_delivery(settings_clone, state_clone).await;
+    });
+
+    let settings_clone = settings.clone();
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        response_webhook::do_periodic_webhook_delivery(settings_clone, state_clone).await;
+    });
+
+    let settings_clone = settings.clone();
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        scheduled_page::do_periodic_page_switches(settings_clone, state_clone).await;
+    });
+
+    let settings_clone = settings.clone();
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        persist::do_periodic_persist(settings_clone, state_clone).await;
+    });
+
+    #[cfg(unix)]
+    if args.config.is_some() || settings.response_filter_path.is_some() {
+        tokio::spawn(config::reload_on_sighup(
+            settings.clone(),
+            args.config.clone(),
+            default_tunables,
+        ));
+    }
+
+    let storage: Arc<dyn Storage> =
+        match &args.redis_url {
+            None => Arc::new(MemoryStorage::new(settings.clone(), state.clone())),
+            Some(url) => Arc::new(RedisStorage::connect(url, settings.clone()).await.map_err(
+                |err| std::io::Error::other(format!("Cannot connect to Redis: {}", err)),
+            )?),
+        };
+
+    let result = start_server::start_server(
+        listeners,
+        settings.clone(),
+        state.clone(),
+        storage,
+        rate_limiter,
+        tls,
+    )
+    .await;
+
+    // The server stops gracefully on e.g. ctrl+c, so store the latest state.
+    if let Some(dir) = &settings.persist_path {
+        if let Err(err) = persist::save_state(&settings, dir, &state).await {
+            println!("Cannot write snapshot: {}", err);
+        }
+    }
+    result
+}
+
+#[cfg(unix)]
+fn parse_socket_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("{} is not an octal file mode like 660", mode))
+}
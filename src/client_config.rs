@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+
+use crate::{SessionState, Settings};
+
+/// Limits and state that audience pages can use to validate responses before sending
+/// them. It takes per-session overrides into account.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct ClientConfig {
+    pub max_response_size: u64,
+    pub max_user_id_length: usize,
+    pub accepting_responses: bool,
+    pub lock_first_response: bool,
+    /// Voting closes at this time, unless the page changes before.
+    #[serde(default)]
+    pub voting_deadline: Option<DateTime<Utc>>,
+}
+
+impl ClientConfig {
+    pub fn new(settings: &Settings, session: &SessionState) -> Self {
+        ClientConfig {
+            max_response_size: session.max_response_size(settings).as_u64(),
+            max_user_id_length: settings.max_user_id_length,
+            accepting_responses: session.accepting_responses(settings.now()),
+            lock_first_response: session.lock_first_response,
+            voting_deadline: session.voting_deadline,
+        }
+    }
+
+    /// The same information as http headers, so that it is available with the page.
+    pub fn headers(&self) -> [(&'static str, String); 4] {
+        [
+            (
+                "X-Polli-Max-Response-Size",
+                self.max_response_size.to_string(),
+            ),
+            (
+                "X-Polli-Max-User-ID-Length",
+                self.max_user_id_length.to_string(),
+            ),
+            (
+                "X-Polli-Accepting-Responses",
+                self.accepting_responses.to_string(),
+            ),
+            (
+                "X-Polli-Lock-First-Response",
+                self.lock_first_response.to_string(),
+            ),
+        ]
+    }
+}
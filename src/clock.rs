@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use std::time::Instant;
+
+/// Source of the current time. It can be replaced, so that time dependent behavior like
+/// session expiry can be tested without waiting.
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, which is shown to clients and stored. It may jump, e.g. when the
+    /// time of the host is synchronized.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Monotonic time, which is used to decide whether sessions are still in use.
+    fn instant(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Monotonic time that corresponds to a wall-clock time in the past, assuming that the
+/// wall clock has not jumped since. It's only used for times that were stored without
+/// a monotonic time, e.g. in snapshots, because monotonic times don't survive restarts.
+/// Times in the future map to now.
+pub fn instant_at(clock: &dyn Clock, time: DateTime<Utc>) -> Instant {
+    let now = clock.instant();
+    let age = (clock.now() - time).to_std().unwrap_or_default();
+    now.checked_sub(age).unwrap_or(now)
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+
+use crate::{page::PageCheck, AccessToken, AppError, SessionID};
+
+/// Same token as `/new` creates for the session. The server accepts it if it uses the same
+/// `--token-secret`, so scripts can create the credentials before the session exists.
+pub fn generate_token(
+    token_secret: &str,
+    session: &str,
+    now: DateTime<Utc>,
+) -> Result<AccessToken, AppError> {
+    let session_id = SessionID::from_string(session)?;
+    Ok(AccessToken::new_signed(
+        token_secret.as_bytes(),
+        &session_id,
+        now,
+    ))
+}
+
+/// Human readable result of `check-page`.
+pub fn describe_page_check(file_name: &str, check: &PageCheck) -> String {
+    let verdict = match &check.result {
+        Ok(()) => format!("{} would be accepted ({} bytes).", file_name, check.size),
+        Err(err) => format!("{} would be rejected: {}", file_name, err),
+    };
+    format!(
+        "{}\nThe script would be injected {} (line {}, column {}).",
+        verdict, check.injection_point, check.line, check.column
+    )
+}
@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+
+use crate::{request_id, response_filter::ResponseFilter, settings::Tunables, Settings};
+
+/// The config file contains the settings that can be changed at runtime, e.g.
+/// `max_response_size = 8000`. Settings that are missing in the file keep the values from
+/// the command line.
+pub fn load_tunables(path: &Path, defaults: &Tunables) -> Result<Tunables, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let file_values: toml::Table = toml::from_str(&content)
+        .map_err(|err| format!("Cannot parse {}: {}", path.display(), err))?;
+    let mut values = serde_json::to_value(defaults).unwrap();
+    for (setting, value) in file_values {
+        values[setting] = serde_json::to_value(value).unwrap();
+    }
+    serde_json::from_value(values).map_err(|err| format!("Invalid {}: {}", path.display(), err))
+}
+
+/// Applies the config file again. The current settings are kept if it's invalid.
+pub fn reload(settings: &Settings, path: &Path, defaults: &Tunables) -> Result<(), String> {
+    let new_tunables = load_tunables(path, defaults)?;
+    let mut tunables = settings.tunables.write();
+    let changes = tunables.describe_changes(&new_tunables);
+    if changes.is_empty() {
+        request_id::log(format!("Reloaded {} without changes", path.display()));
+    }
+    for change in changes {
+        request_id::log(format!("Reloaded {}: changed {}", path.display(), change));
+    }
+    *tunables = new_tunables;
+    Ok(())
+}
+
+/// Loads the file of [`Settings::response_filter_path`] again. The current filter is kept if
+/// the file is invalid.
+pub fn reload_response_filter(settings: &Settings) -> Result<(), String> {
+    let Some(path) = &settings.response_filter_path else {
+        return Ok(());
+    };
+    let filter = ResponseFilter::load(path, settings.response_filter_mode)?;
+    request_id::log(format!(
+        "Reloaded {} with {} filtered words",
+        path.display(),
+        filter.len()
+    ));
+    *settings.response_filter.write() = Some(filter);
+    Ok(())
+}
+
+/// E.g. `systemctl reload` sends `SIGHUP`. The config file is optional, the response
+/// filter is reloaded as well.
+#[cfg(unix)]
+pub async fn reload_on_sighup(settings: Settings, path: Option<PathBuf>, defaults: Tunables) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup()).expect("Cannot listen for SIGHUP");
+    while hangups.recv().await.is_some() {
+        if let Some(path) = &path {
+            if let Err(err) = reload(&settings, path, &defaults) {
+                request_id::log(format!("Keeping previous settings: {}", err));
+            }
+        }
+        if let Err(err) = reload_response_filter(&settings) {
+            request_id::log(format!("Keeping previous response filter: {}", err));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{webhooks, Settings, State, UserID};
+
+pub const MIN_DIGEST_INTERVAL: Duration = Duration::from_secs(60);
+pub const MAX_DIGEST_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Periodically sends all new responses of a session to a webhook, so that presenters
+/// don't have to keep a long-poll loop running for sessions with few responses.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Digest {
+    pub url: String,
+    pub interval: Duration,
+    /// It's monotonic, so snapshots don't contain it and restored digests are delivered
+    /// right away.
+    #[serde(skip, default = "Instant::now")]
+    pub next_delivery: Instant,
+    /// Id of the first response that has not been delivered yet.
+    pub start: usize,
+}
+
+/// Responses with ids in the range `start..end`. The `end` can be used as `start` when
+/// fetching more responses from `/responses`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct DigestPayload {
+    pub session: String,
+    pub start: usize,
+    pub end: usize,
+    pub responses_by_user: HashMap<UserID, String>,
+}
+
+pub struct DigestDelivery {
+    pub url: String,
+    pub payload: DigestPayload,
+}
+
+impl Digest {
+    pub fn new(url: String, interval: Duration, now: Instant, start: usize) -> Self {
+        Digest {
+            url,
+            interval,
+            next_delivery: now + interval,
+            start,
+        }
+    }
+}
+
+/// Parses intervals like `90s`, `15m` or `2h`.
+pub fn parse_interval(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return None,
+    };
+    let interval = Duration::from_secs(number.checked_mul(unit_seconds)?);
+    if interval < MIN_DIGEST_INTERVAL || interval > MAX_DIGEST_INTERVAL {
+        return None;
+    }
+    Some(interval)
+}
+
+/// Gathers the digests that are due. Sessions without new responses are skipped but
+/// their next delivery is still scheduled.
+pub fn collect_due_digests(state: &mut State, now: Instant) -> Vec<DigestDelivery> {
+    let mut deliveries = vec![];
+    for (session_id, session) in state.sessions.iter_mut() {
+        let Some(digest) = &mut session.digest else {
+            continue;
+        };
+        if digest.next_delivery > now {
+            continue;
+        }
+        digest.next_delivery = now + digest.interval;
+
+        let start = digest.start;
+        let end = session.next_response_id;
+        digest.start = end;
+        let responses_by_user: HashMap<UserID, String> = session
+            .responses
+            .iter()
+            .filter(|(_, user_response)| user_response.id >= start && user_response.id < end)
+            .map(|(user_id, user_response)| (user_id.clone(), user_response.data.clone()))
+            .collect();
+        if responses_by_user.is_empty() {
+            continue;
+        }
+        deliveries.push(DigestDelivery {
+            url: digest.url.clone(),
+            payload: DigestPayload {
+                session: session_id.0.clone(),
+                start,
+                end,
+                responses_by_user,
+            },
+        });
+    }
+    deliveries
+}
+
+pub async fn do_periodic_digest_delivery(settings: Settings, state: Arc<Mutex<State>>) {
+    let mut interval = tokio::time::interval(settings.digest_check_interval);
+    loop {
+        interval.tick().await;
+        let deliveries = collect_due_digests(&mut state.lock(), settings.instant());
+        for delivery in deliveries {
+            let settings = settings.clone();
+            tokio::spawn(async move {
+                webhooks::deliver_with_retry(&settings, &delivery.url, &delivery.payload).await;
+            });
+        }
+    }
+}
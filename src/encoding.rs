@@ -0,0 +1,87 @@
+use actix_web::{http::header, HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::AppError;
+
+/// Formats of the responses api besides json, for embedded clients that can't parse json
+/// well. The same structs are used for all of them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// Expects the media type without parameters.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type.trim().to_ascii_lowercase().as_str() {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Encoding::MessagePack)
+            }
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    /// The first supported media type in the `Accept` header. Everything else gets json.
+    pub fn negotiate(req: &HttpRequest) -> Self {
+        req.headers()
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value.split(',').find_map(|media_type| {
+                    Encoding::from_media_type(media_type.split(';').next().unwrap_or_default())
+                })
+            })
+            .unwrap_or(Encoding::Json)
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// Structs are encoded as maps with field names, so that they look like the json.
+    pub fn encode(self, value: &impl Serialize) -> Result<Vec<u8>, AppError> {
+        let result = match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            Encoding::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+            Encoding::Cbor => {
+                let mut data = vec![];
+                ciborium::into_writer(value, &mut data)
+                    .map(|_| data)
+                    .map_err(|err| err.to_string())
+            }
+        };
+        result.map_err(|err| {
+            println!("Cannot encode response as {}: {}", self.media_type(), err);
+            AppError::ServerError
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(data).map_err(|err| err.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(data).map_err(|err| err.to_string()),
+            Encoding::Cbor => ciborium::from_reader(data).map_err(|err| err.to_string()),
+        }
+    }
+
+    pub fn respond(
+        self,
+        builder: &mut HttpResponseBuilder,
+        value: &impl Serialize,
+    ) -> Result<HttpResponse, AppError> {
+        builder.insert_header((header::VARY, "Accept"));
+        if self == Encoding::Json {
+            return Ok(builder.json(value));
+        }
+        let data = self.encode(value)?;
+        Ok(builder.content_type(self.media_type()).body(data))
+    }
+}
@@ -1,23 +1,310 @@
 use actix_web::{
-    http::{header::ContentType, StatusCode},
-    HttpResponse,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::QueryPayloadError,
+    http::{
+        header::{self, ContentType},
+        StatusCode,
+    },
+    middleware::Next,
+    web, HttpRequest, HttpResponse, HttpResponseBuilder,
 };
 use derive_more::derive::{Display, Error};
+use std::time::Duration;
+
+use crate::{links, request_id::RequestId, static_files, SessionID, Settings, SharedState, UserID};
 
 #[derive(Debug, Display, Error)]
 pub enum AppError {
     BadUserID,
     BadSessionID,
     BadAccessToken,
+    #[display("BadQueryParameters: {}", parameter.as_deref().unwrap_or("unknown"))]
+    BadQueryParameters {
+        parameter: Option<String>,
+    },
     SessionIDDoesNotExist,
-    PageTooLarge,
+    /// Same as [`AppError::SessionIDDoesNotExist`], but with a more helpful page for the
+    /// audience.
+    #[display("EmptySession: {}", session.0)]
+    EmptySession {
+        session: SessionID,
+    },
+    SessionExpired,
+    SessionIDTaken,
+    /// The session has a join code and it is missing or wrong. The html version asks the
+    /// audience for the code.
+    #[display("JoinCodeRequired: {}", session.0)]
+    JoinCodeRequired {
+        session: SessionID,
+    },
+    #[display("PageTooLarge: {size} bytes with the injected script, at most {max_size} allowed")]
+    PageTooLarge {
+        size: u64,
+        max_size: u64,
+    },
     ResponseTooLarge,
+    #[display("MessageTooLarge: at most {max_size} bytes allowed")]
+    MessageTooLarge {
+        max_size: usize,
+    },
+    #[display("AssetTooLarge: at most {max_size} bytes allowed")]
+    AssetTooLarge {
+        max_size: u64,
+    },
+    /// The session has [`Settings::max_assets_per_session`] already.
+    TooManyAssets,
+    AssetNotFound,
+    /// The user has not responded to the current page yet.
+    ResponseNotFound,
+    /// The page version is not in the history of the session (anymore).
+    PageVersionNotFound,
+    /// There is no staged page that could be committed.
+    StagedPageNotFound,
+    #[display("ResponseLocked: {response}")]
+    ResponseLocked {
+        response: String,
+    },
+    /// The `Content-Type` of a response is not in
+    /// [`Settings::allowed_response_content_types`].
+    #[display("UnsupportedMediaType: {content_type}")]
+    UnsupportedMediaType {
+        content_type: String,
+    },
+    /// The presenter closed the voting or its deadline has passed.
+    VotingClosed,
+    /// The presenter has not made the results of the session public.
+    ResultsNotPublic,
+    /// The json schema for responses of a session can't be used.
+    #[display("InvalidResponseSchema: {message}")]
+    InvalidResponseSchema {
+        message: String,
+    },
+    /// The words or regexes of a response filter can't be compiled.
+    #[display("InvalidResponseFilter: {message}")]
+    InvalidResponseFilter {
+        message: String,
+    },
+    /// A session archive can't be imported.
+    #[display("InvalidArchive: {message}")]
+    InvalidArchive {
+        message: String,
+    },
+    /// A page patch can't be parsed or applied.
+    #[display("InvalidPatch: {message}")]
+    InvalidPatch {
+        message: String,
+    },
+    /// A page patch is based on an older version of the page.
+    #[display("PageVersionConflict: current version is {current_version}")]
+    PageVersionConflict {
+        current_version: usize,
+    },
+    /// The response does not match the json schema of the session.
+    #[display("InvalidResponse: {message}")]
+    InvalidResponse {
+        message: String,
+    },
+    /// The `If-Match` id does not match the stored response of the user.
+    #[display("ResponseConflict: current id is {}", current_id.map_or("none".to_string(), |id| id.to_string()))]
+    ResponseConflict {
+        current_id: Option<usize>,
+    },
+    AdminDisabled,
+    #[display("InvalidSetting: {}", setting.as_deref().unwrap_or("unknown"))]
+    InvalidSetting {
+        setting: Option<String>,
+    },
+    FileNotFound,
+    /// There is no route for the requested path.
+    NotFound,
+    #[display("TooManyRequests: retry after {}s", retry_after_seconds(*retry_after))]
+    TooManyRequests {
+        retry_after: Duration,
+    },
+    #[display("SessionLimitReached: {limit}")]
+    SessionLimitReached {
+        limit: &'static str,
+    },
+    TooManyUsers,
+    #[display("LongPollLimitReached: {limit}")]
+    LongPollLimitReached {
+        limit: &'static str,
+    },
+    /// The presenter banned the user from the session.
+    UserBanned,
+    /// The user id has not been issued by `/join`, see
+    /// [`Settings::require_server_user_ids`].
+    UnknownUserID,
+    /// The option only works with the in-memory storage, but the server uses Redis.
+    #[display("NotSupported: {option} is not supported with Redis")]
+    NotSupported {
+        option: &'static str,
+    },
     ServerError,
 }
 
+/// Body of an error response when the client asked for json.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    /// Same as the `X-Request-Id` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl AppError {
+    /// Stable identifier of the error kind that clients can branch on.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            AppError::BadUserID => "bad_user_id",
+            AppError::BadSessionID => "bad_session_id",
+            AppError::BadAccessToken => "bad_access_token",
+            AppError::BadQueryParameters { .. } => "bad_query_parameters",
+            AppError::SessionIDDoesNotExist => "session_not_found",
+            AppError::EmptySession { .. } => "session_not_found",
+            AppError::SessionExpired => "session_expired",
+            AppError::SessionIDTaken => "session_taken",
+            AppError::JoinCodeRequired { .. } => "join_code_required",
+            AppError::PageTooLarge { .. } => "page_too_large",
+            AppError::ResponseTooLarge => "response_too_large",
+            AppError::MessageTooLarge { .. } => "message_too_large",
+            AppError::AssetTooLarge { .. } => "asset_too_large",
+            AppError::TooManyAssets => "too_many_assets",
+            AppError::AssetNotFound => "asset_not_found",
+            AppError::ResponseNotFound => "response_not_found",
+            AppError::PageVersionNotFound => "page_version_not_found",
+            AppError::StagedPageNotFound => "staged_page_not_found",
+            AppError::ResponseLocked { .. } => "response_locked",
+            AppError::ResponseConflict { .. } => "response_conflict",
+            AppError::UnsupportedMediaType { .. } => "unsupported_media_type",
+            AppError::VotingClosed => "voting_closed",
+            AppError::ResultsNotPublic => "results_not_public",
+            AppError::InvalidResponseSchema { .. } => "invalid_response_schema",
+            AppError::InvalidResponseFilter { .. } => "invalid_response_filter",
+            AppError::InvalidArchive { .. } => "invalid_archive",
+            AppError::InvalidPatch { .. } => "invalid_patch",
+            AppError::PageVersionConflict { .. } => "page_version_conflict",
+            AppError::InvalidResponse { .. } => "invalid_response",
+            AppError::AdminDisabled => "admin_disabled",
+            AppError::InvalidSetting { .. } => "invalid_setting",
+            AppError::FileNotFound => "file_not_found",
+            AppError::NotFound => "not_found",
+            AppError::TooManyRequests { .. } => "too_many_requests",
+            AppError::SessionLimitReached { .. } => "session_limit_reached",
+            AppError::TooManyUsers => "too_many_users",
+            AppError::LongPollLimitReached { .. } => "long_poll_limit_reached",
+            AppError::UserBanned => "user_banned",
+            AppError::UnknownUserID => "unknown_user_id",
+            AppError::NotSupported { .. } => "not_supported",
+            AppError::ServerError => "server_error",
+        }
+    }
+
+    /// Additional machine-readable information about the error.
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            AppError::BadQueryParameters {
+                parameter: Some(parameter),
+            } => Some(serde_json::json!({ "parameter": parameter })),
+            AppError::InvalidSetting {
+                setting: Some(setting),
+            } => Some(serde_json::json!({ "setting": setting })),
+            AppError::ResponseLocked { response } => {
+                Some(serde_json::json!({ "response": response }))
+            }
+            AppError::UnsupportedMediaType { content_type } => {
+                Some(serde_json::json!({ "content_type": content_type }))
+            }
+            AppError::ResponseConflict { current_id } => {
+                Some(serde_json::json!({ "current_id": current_id }))
+            }
+            AppError::PageVersionConflict { current_version } => {
+                Some(serde_json::json!({ "current_version": current_version }))
+            }
+            AppError::PageTooLarge { size, max_size } => {
+                Some(serde_json::json!({ "size": size, "max_size": max_size }))
+            }
+            AppError::MessageTooLarge { max_size } => {
+                Some(serde_json::json!({ "max_size": max_size }))
+            }
+            AppError::AssetTooLarge { max_size } => {
+                Some(serde_json::json!({ "max_size": max_size }))
+            }
+            AppError::SessionLimitReached { limit } => Some(serde_json::json!({ "limit": limit })),
+            AppError::LongPollLimitReached { limit } => Some(serde_json::json!({ "limit": limit })),
+            AppError::NotSupported { option } => Some(serde_json::json!({ "option": option })),
+            AppError::TooManyRequests { retry_after } => Some(
+                serde_json::json!({ "retry_after_seconds": retry_after_seconds(*retry_after) }),
+            ),
+            _ => None,
+        }
+    }
+
+    /// The base url is used for links in the page.
+    fn html_body(&self, settings: &Settings, base_url: &str) -> String {
+        match self {
+            AppError::EmptySession { session } => static_files::render(
+                settings,
+                "empty_session_page.html",
+                &[("session", &session.0)],
+            )
+            .unwrap_or_else(|_| self.to_string()),
+            AppError::JoinCodeRequired { session } => {
+                static_files::render(settings, "join_code_page.html", &[("session", &session.0)])
+                    .unwrap_or_else(|_| self.to_string())
+            }
+            AppError::SessionExpired => {
+                static_files::get(settings, "expired_session_page.html").into_owned()
+            }
+            AppError::NotFound => {
+                static_files::render(settings, "not_found_page.html", &[("root_url", base_url)])
+                    .unwrap_or_else(|_| self.to_string())
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    fn html_response(&self, settings: &Settings, base_url: &str) -> HttpResponse {
+        let mut builder = self.response_builder();
+        for header in settings.security_headers.headers() {
+            builder.insert_header(header);
+        }
+        builder
+            .insert_header(ContentType::html())
+            .body(self.html_body(settings, base_url))
+    }
+
+    fn response_builder(&self) -> HttpResponseBuilder {
+        let mut builder = HttpResponse::build(actix_web::ResponseError::status_code(self));
+        if let AppError::TooManyRequests { retry_after } = self {
+            builder.insert_header((header::RETRY_AFTER, retry_after_seconds(*retry_after)));
+        }
+        builder
+    }
+
+    /// Also used for errors of single entries in otherwise successful responses.
+    pub fn body(&self, request_id: Option<RequestId>) -> ErrorBody {
+        ErrorBody {
+            error: self.code().to_string(),
+            message: self.to_string(),
+            details: self.details(),
+            request_id: request_id.map(|id| id.0),
+        }
+    }
+
+    fn json_response(&self, request_id: Option<RequestId>) -> HttpResponse {
+        self.response_builder().json(self.body(request_id))
+    }
+}
+
 impl actix_web::error::ResponseError for AppError {
+    /// Replaced by [`negotiate_error_format`], which knows the settings.
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
+        self.response_builder()
             .insert_header(ContentType::html())
             .body(self.to_string())
     }
@@ -27,10 +314,117 @@ impl actix_web::error::ResponseError for AppError {
             AppError::BadUserID => StatusCode::BAD_REQUEST,
             AppError::BadSessionID => StatusCode::BAD_REQUEST,
             AppError::SessionIDDoesNotExist => StatusCode::NOT_FOUND,
+            AppError::EmptySession { .. } => StatusCode::NOT_FOUND,
+            AppError::SessionExpired => StatusCode::GONE,
+            AppError::SessionIDTaken => StatusCode::CONFLICT,
+            AppError::JoinCodeRequired { .. } => StatusCode::FORBIDDEN,
             AppError::BadAccessToken => StatusCode::UNAUTHORIZED,
-            AppError::PageTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::BadQueryParameters { .. } => StatusCode::BAD_REQUEST,
+            AppError::PageTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
             AppError::ResponseTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::MessageTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::AssetTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::TooManyAssets => StatusCode::FORBIDDEN,
+            AppError::AssetNotFound => StatusCode::NOT_FOUND,
+            AppError::ResponseNotFound => StatusCode::NOT_FOUND,
+            AppError::PageVersionNotFound => StatusCode::NOT_FOUND,
+            AppError::StagedPageNotFound => StatusCode::NOT_FOUND,
+            AppError::ResponseLocked { .. } => StatusCode::CONFLICT,
+            AppError::ResponseConflict { .. } => StatusCode::CONFLICT,
+            AppError::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::VotingClosed => StatusCode::FORBIDDEN,
+            AppError::ResultsNotPublic => StatusCode::FORBIDDEN,
+            AppError::InvalidResponseSchema { .. } => StatusCode::BAD_REQUEST,
+            AppError::InvalidResponseFilter { .. } => StatusCode::BAD_REQUEST,
+            AppError::InvalidArchive { .. } => StatusCode::BAD_REQUEST,
+            AppError::InvalidPatch { .. } => StatusCode::BAD_REQUEST,
+            AppError::PageVersionConflict { .. } => StatusCode::CONFLICT,
+            AppError::InvalidResponse { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::AdminDisabled => StatusCode::FORBIDDEN,
+            AppError::InvalidSetting { .. } => StatusCode::BAD_REQUEST,
+            AppError::FileNotFound => StatusCode::NOT_FOUND,
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::SessionLimitReached { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::TooManyUsers => StatusCode::FORBIDDEN,
+            AppError::LongPollLimitReached { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UserBanned => StatusCode::FORBIDDEN,
+            AppError::UnknownUserID => StatusCode::FORBIDDEN,
+            AppError::NotSupported { .. } => StatusCode::NOT_IMPLEMENTED,
             AppError::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
+
+/// `Retry-After` only supports whole seconds, so round up to avoid retrying too early.
+fn retry_after_seconds(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64
+}
+
+/// Used as error handler for the query extractor so that malformed query strings result
+/// in the same kind of errors as everything else. The original deserialization message
+/// is not passed on, only the name of the offending parameter if it is known.
+pub fn query_error_handler(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    if let Some(err) = find_bad_id_in_query(req.query_string()) {
+        return err.into();
+    }
+    let QueryPayloadError::Deserialize(err) = &err else {
+        return AppError::BadQueryParameters { parameter: None }.into();
+    };
+    // Serde formats e.g. missing fields as "missing field `start`".
+    let message = err.to_string();
+    let parameter = message
+        .split('`')
+        .nth(1)
+        .map(|parameter| parameter.to_string());
+    AppError::BadQueryParameters { parameter }.into()
+}
+
+/// Ids have more specific errors than other parameters, but serde only passes on the
+/// error message. So the query is checked again to find the actual error.
+fn find_bad_id_in_query(query: &str) -> Option<AppError> {
+    let params = web::Query::<Vec<(String, String)>>::from_query(query).ok()?;
+    params.iter().find_map(|(key, value)| match key.as_str() {
+        "session" => SessionID::from_string(value).err(),
+        "user" => UserID::from_string(value).err(),
+        _ => None,
+    })
+}
+
+pub fn accepts_json(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"))
+}
+
+/// Middleware that turns errors into json when the client asked for it. Browsers keep
+/// getting html, which may use the static files of the settings.
+pub async fn negotiate_error_format(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let wants_json = accepts_json(req.request());
+    let request_id = RequestId::of(req.request());
+    let shared_state = req.app_data::<web::Data<SharedState>>().cloned();
+    let base_url = shared_state
+        .as_ref()
+        .map(|shared_state| links::base_url(&shared_state.settings, req.request()));
+    let res = next.call(req).await?.map_into_boxed_body();
+    let error_response = res
+        .response()
+        .error()
+        .and_then(|err| err.as_error::<AppError>())
+        .and_then(|err| match (wants_json, &shared_state) {
+            (true, _) => Some(err.json_response(request_id)),
+            (false, Some(shared_state)) => Some(err.html_response(
+                &shared_state.settings,
+                base_url.as_deref().unwrap_or_default(),
+            )),
+            (false, None) => None,
+        });
+    match error_response {
+        None => Ok(res),
+        Some(error_response) => Ok(res.into_response(error_response)),
+    }
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{SessionID, Settings};
+
+/// Remembers recently removed sessions, so that clients can be told that their session
+/// expired instead of that it never existed. Old entries are dropped, so that this does
+/// not grow without bounds.
+#[derive(Default)]
+pub struct ExpiredSessions {
+    expired_at: HashMap<SessionID, DateTime<Utc>>,
+    /// Oldest first.
+    order: VecDeque<(SessionID, DateTime<Utc>)>,
+}
+
+impl ExpiredSessions {
+    pub fn insert(&mut self, settings: &Settings, session_id: SessionID, now: DateTime<Utc>) {
+        if self.expired_at.insert(session_id.clone(), now).is_some() {
+            // The same id has been used again and expired again. This is rare, so it's ok
+            // that this is slow.
+            self.order
+                .retain(|(expired_id, _)| *expired_id != session_id);
+        }
+        self.order.push_back((session_id, now));
+        while let Some((session_id, expired_at)) = self.order.front() {
+            let is_too_old = *expired_at + settings.expired_session_retention <= now;
+            if !is_too_old && self.order.len() <= settings.max_expired_sessions {
+                break;
+            }
+            self.expired_at.remove(session_id);
+            self.order.pop_front();
+        }
+    }
+
+    pub fn contains(
+        &self,
+        settings: &Settings,
+        session_id: &SessionID,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.expired_at
+            .get(session_id)
+            .is_some_and(|expired_at| *expired_at + settings.expired_session_retention > now)
+    }
+
+    #[cfg(feature = "test-util")]
+    pub fn count(&self) -> usize {
+        self.expired_at.len()
+    }
+}
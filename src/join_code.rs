@@ -0,0 +1,66 @@
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    HttpRequest,
+};
+use rand::Rng;
+
+use crate::{access_token, AppError, SessionID, Settings};
+
+/// Codes are typed by the audience, so they should be short anyway.
+const MAX_JOIN_CODE_LENGTH: usize = 64;
+
+/// Six digits that are easy to read out loud.
+pub fn new_random() -> String {
+    let mut rng = rand::thread_rng();
+    (0..6).map(|_| rng.gen_range(0..10).to_string()).collect()
+}
+
+/// The `parameter` is reported in the error, e.g. `join_code`.
+pub fn validate(code: &str, parameter: &str) -> Result<(), AppError> {
+    let is_valid = !code.is_empty()
+        && code.len() <= MAX_JOIN_CODE_LENGTH
+        && code.chars().all(|c| c.is_ascii_graphic());
+    match is_valid {
+        true => Ok(()),
+        false => Err(AppError::BadQueryParameters {
+            parameter: Some(parameter.to_string()),
+        }),
+    }
+}
+
+/// The code can be passed as `code` query parameter or with the cookie that is set when the
+/// page has been opened with the code.
+pub fn check(
+    req: &HttpRequest,
+    session_id: &SessionID,
+    expected: Option<&str>,
+    code: Option<&str>,
+) -> Result<(), AppError> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let cookie = req.cookie(&cookie_name(session_id));
+    let code = code.or(cookie.as_ref().map(|cookie| cookie.value()));
+    match code
+        .is_some_and(|code| access_token::constant_time_eq(code.as_bytes(), expected.as_bytes()))
+    {
+        true => Ok(()),
+        false => Err(AppError::JoinCodeRequired {
+            session: session_id.clone(),
+        }),
+    }
+}
+
+/// Remembers the entered code, so that the page can be reloaded and responses can be sent
+/// without passing it again.
+pub fn cookie(settings: &Settings, session_id: &SessionID, code: &str) -> Cookie<'static> {
+    Cookie::build(cookie_name(session_id), code.to_string())
+        .path(settings.cookie_path())
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+fn cookie_name(session_id: &SessionID) -> String {
+    format!("polli_code_{}", session_id.0)
+}
@@ -0,0 +1,60 @@
+//! The polli.live server as a library, so that it can be embedded into other applications,
+//! e.g. a presentation tool that starts a local server. See [`start_server::start_server`].
+
+pub mod access_token;
+pub mod admin;
+pub mod audience;
+pub mod audit_log;
+pub mod cleanup;
+pub mod cli;
+pub mod client_config;
+pub mod clock;
+pub mod commands;
+pub mod config;
+pub mod digest;
+pub mod encoding;
+pub mod errors;
+pub mod expired_sessions;
+pub mod join_code;
+pub mod links;
+pub mod long_poll;
+pub mod openapi;
+pub mod page;
+pub mod page_content;
+pub mod page_patch;
+pub mod persist;
+pub mod push;
+pub mod rate_limit;
+pub mod request_id;
+pub mod response_filter;
+pub mod response_schema;
+pub mod response_webhook;
+pub mod routes;
+pub mod scheduled_page;
+pub mod security_headers;
+pub mod session_archive;
+pub mod session_assets;
+pub mod session_id;
+pub mod settings;
+pub mod start_server;
+pub mod state;
+pub mod static_files;
+pub mod statistics;
+pub mod storage;
+pub mod tls;
+pub mod user_id;
+pub mod verify;
+pub mod webhooks;
+pub mod words;
+
+pub use access_token::AccessToken;
+pub use errors::AppError;
+pub use rate_limit::RateLimiter;
+pub use routes::RetrievedResponses;
+pub use security_headers::FrameOptions;
+pub use session_id::SessionID;
+pub use settings::{CorsPolicy, ResponseThrottle, SessionIDStyle, Settings};
+pub use start_server::{start_server, Listener};
+pub use state::{SessionState, SharedState, State, UserResponse};
+pub use storage::{MemoryStorage, RedisStorage, Storage};
+pub use user_id::UserID;
@@ -0,0 +1,94 @@
+use actix_web::http::header::{self, HeaderName};
+use actix_web::HttpRequest;
+
+use crate::{SessionID, Settings};
+
+/// Builds a `Link` header (RFC 8288) that points clients at related endpoints. Urls in
+/// curly braces like `{user}` are placeholders the client has to fill in.
+pub struct Links {
+    base_url: String,
+    entries: Vec<String>,
+}
+
+impl Links {
+    pub fn new(settings: &Settings, req: &HttpRequest) -> Self {
+        Links {
+            base_url: base_url(settings, req),
+            entries: vec![],
+        }
+    }
+
+    /// Adds a link to a path relative to the root url.
+    pub fn add(mut self, rel: &str, path: &str) -> Self {
+        self.entries
+            .push(format!("<{}{}>; rel=\"{}\"", self.base_url, path, rel));
+        self
+    }
+
+    pub fn header(&self) -> (HeaderName, String) {
+        (header::LINK, self.entries.join(", "))
+    }
+}
+
+/// Links that are useful for everyone who has access to the page of a session.
+pub fn session_page_links(settings: &Settings, req: &HttpRequest, session_id: &SessionID) -> Links {
+    Links::new(settings, req)
+        .add("page", &format!("/page?session={}", session_id.0))
+        .add(
+            "respond",
+            &format!("/respond?session={}&user={{user}}", session_id.0),
+        )
+        .add(
+            "wait_for_new_page",
+            &format!("/wait_for_new_page?session={}", session_id.0),
+        )
+        .add(
+            "client_config",
+            &format!("/client_config?session={}", session_id.0),
+        )
+}
+
+/// Url that urls in the response to the request start with. With `--root-url auto`, it's
+/// derived from the request, so that it works for every host the server is reachable at.
+pub fn base_url(settings: &Settings, req: &HttpRequest) -> String {
+    if !settings.auto_root_url {
+        return settings.base_url();
+    }
+    let header_value = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|value| value.trim())
+    };
+    let forwarded = |name: &str| {
+        settings
+            .trust_forwarded_for
+            .then(|| header_value(name))
+            .flatten()
+    };
+    let scheme = match forwarded("X-Forwarded-Proto") {
+        Some(scheme @ ("http" | "https")) => scheme,
+        _ if req.app_config().secure() => "https",
+        _ => "http",
+    };
+    let host = forwarded("X-Forwarded-Host")
+        .or_else(|| header_value("Host"))
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()));
+    match host {
+        Some(host) if is_valid_host(host) => {
+            format!("{}://{}{}", scheme, host, settings.base_path)
+        }
+        _ => settings.base_url(),
+    }
+}
+
+/// The host ends up in html and headers, so only characters of host names, ip addresses
+/// and ports are allowed.
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 255
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{AppError, SessionID, Settings, SharedState};
+
+/// Counts a long-poll as in flight until it is dropped. The count stays correct however
+/// the request ends, e.g. also when the request future is dropped while waiting.
+pub struct LongPollGuard {
+    counters: Vec<Arc<AtomicUsize>>,
+}
+
+impl LongPollGuard {
+    /// Fails when the server or one of the sessions has too many long-polls in flight
+    /// already, so that a hostile page can't open arbitrarily many. Sessions that are not
+    /// in memory, e.g. with Redis, only count towards the total.
+    pub fn acquire(
+        settings: &Settings,
+        total: &Arc<AtomicUsize>,
+        sessions: impl IntoIterator<Item = Arc<AtomicUsize>>,
+    ) -> Result<Self, AppError> {
+        let mut guard = LongPollGuard { counters: vec![] };
+        guard.count(total.clone(), settings.max_long_polls_total, "total")?;
+        for session in sessions {
+            guard.count(session, settings.max_long_polls_per_session, "per_session")?;
+        }
+        Ok(guard)
+    }
+
+    /// The counter is decremented again when the guard is dropped, even if it fails.
+    fn count(
+        &mut self,
+        counter: Arc<AtomicUsize>,
+        max: usize,
+        limit: &'static str,
+    ) -> Result<(), AppError> {
+        let previous = counter.fetch_add(1, Ordering::Relaxed);
+        self.counters.push(counter);
+        if previous >= max {
+            return Err(AppError::LongPollLimitReached { limit });
+        }
+        Ok(())
+    }
+}
+
+/// Has to be called before waiting. The guard has to be kept until the long-poll is done.
+pub fn start(
+    shared_state: &SharedState,
+    session_ids: &[&SessionID],
+) -> Result<LongPollGuard, AppError> {
+    let sessions: Vec<Arc<AtomicUsize>> = {
+        let state = shared_state.state.lock();
+        session_ids
+            .iter()
+            .filter_map(|session_id| state.sessions.get(*session_id))
+            .map(|session| session.long_polls.clone())
+            .collect()
+    };
+    LongPollGuard::acquire(&shared_state.settings, &shared_state.long_polls, sessions)
+}
+
+impl Drop for LongPollGuard {
+    fn drop(&mut self) {
+        for counter in &self.counters {
+            counter.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,1132 @@
+use serde_json::{json, Map, Value};
+
+/// Stable codes of [`crate::AppError`] that clients can get in json errors.
+const ERROR_CODES: &[&str] = &[
+    "bad_user_id",
+    "bad_session_id",
+    "bad_access_token",
+    "bad_query_parameters",
+    "session_not_found",
+    "session_expired",
+    "session_taken",
+    "join_code_required",
+    "page_too_large",
+    "response_too_large",
+    "message_too_large",
+    "asset_too_large",
+    "too_many_assets",
+    "asset_not_found",
+    "response_not_found",
+    "page_version_not_found",
+    "staged_page_not_found",
+    "response_locked",
+    "response_conflict",
+    "unsupported_media_type",
+    "voting_closed",
+    "results_not_public",
+    "invalid_response_schema",
+    "invalid_response_filter",
+    "invalid_archive",
+    "invalid_patch",
+    "page_version_conflict",
+    "invalid_response",
+    "admin_disabled",
+    "invalid_setting",
+    "file_not_found",
+    "not_found",
+    "too_many_requests",
+    "session_limit_reached",
+    "too_many_users",
+    "long_poll_limit_reached",
+    "user_banned",
+    "unknown_user_id",
+    "not_supported",
+    "server_error",
+];
+
+/// Who may call a route.
+#[derive(Clone, Copy)]
+enum Auth {
+    None,
+    /// The token of the session.
+    Session,
+    /// Depends on `--responses-require-auth`, or the token only adds information.
+    Optional,
+    Admin,
+}
+
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    auth: Auth,
+    parameters: Vec<Value>,
+    request_body: Option<Value>,
+    /// Content type and schema of a successful response.
+    response: (&'static str, Value),
+}
+
+/// OpenAPI 3 description of all routes. It is maintained by hand, so it has to be updated
+/// together with the routes.
+pub fn document(base_url: &str) -> Value {
+    let mut paths = Map::new();
+    for operation in operations() {
+        let path = paths
+            .entry(operation.path)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .unwrap();
+        path.insert(operation.method.to_string(), operation.to_json());
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "polli.live",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": base_url }],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "sessionToken": { "type": "http", "scheme": "bearer" },
+                "adminToken": { "type": "http", "scheme": "bearer" },
+            },
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["error", "message"],
+                    "properties": {
+                        "error": { "type": "string", "enum": ERROR_CODES },
+                        "message": { "type": "string" },
+                        "details": { "type": "object" },
+                        "request_id": { "type": "string" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+impl Operation {
+    fn to_json(&self) -> Value {
+        let (content_type, schema) = &self.response;
+        let mut operation = json!({
+            "summary": self.summary,
+            "parameters": self.parameters,
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": { *content_type: { "schema": schema } },
+                },
+                "default": {
+                    "description": "Error, as json when the request accepts `application/json`.",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" },
+                        },
+                    },
+                },
+            },
+        });
+        let security = match self.auth {
+            Auth::None => None,
+            Auth::Session => Some(json!([{ "sessionToken": [] }])),
+            Auth::Optional => Some(json!([{}, { "sessionToken": [] }])),
+            Auth::Admin => Some(json!([{ "adminToken": [] }])),
+        };
+        if let Some(security) = security {
+            operation["security"] = security;
+        }
+        if let Some(request_body) = &self.request_body {
+            operation["requestBody"] = request_body.clone();
+        }
+        operation
+    }
+}
+
+fn parameter(location: &str, name: &str, schema: Value, required: bool) -> Value {
+    json!({ "in": location, "name": name, "required": required, "schema": schema })
+}
+
+fn session_param() -> Value {
+    parameter("query", "session", string(), true)
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn binary() -> Value {
+    json!({ "type": "string", "format": "binary" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn body(content_type: &str, schema: Value) -> Value {
+    json!({ "required": true, "content": { content_type: { "schema": schema } } })
+}
+
+fn object(properties: Value) -> Value {
+    json!({ "type": "object", "properties": properties })
+}
+
+fn token_response() -> (&'static str, Value) {
+    ("application/json", object(json!({ "token": string() })))
+}
+
+fn message_object() -> Value {
+    object(json!({
+        "id": integer(),
+        "text": string(),
+        "time": { "type": "string", "format": "date-time" },
+    }))
+}
+
+fn operations() -> Vec<Operation> {
+    let text = || ("text/plain", string());
+    let html = || ("text/html", string());
+    vec![
+        Operation {
+            method: "get",
+            path: "/",
+            summary: "Home page for entering a session id.",
+            auth: Auth::None,
+            parameters: vec![],
+            request_body: None,
+            response: html(),
+        },
+        Operation {
+            method: "post",
+            path: "/new",
+            summary: "Create a new session or reuse a previous one.",
+            auth: Auth::None,
+            parameters: vec![],
+            request_body: Some(body(
+                "application/json",
+                object(json!({
+                    "session": string(),
+                    "token": string(),
+                    "strict": boolean(),
+                    "style": { "type": "string", "enum": ["digits", "words"] },
+                    "page": string(),
+                    "ttl_seconds": integer(),
+                    "response_schema": { "type": "object" },
+                    "anonymous": boolean(),
+                    "join_code": string(),
+                })),
+            )),
+            response: (
+                "application/json",
+                object(json!({ "session": string(), "token": string() })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/page",
+            summary: "Page of the session with the injected script.",
+            auth: Auth::None,
+            parameters: vec![session_param(), parameter("query", "code", string(), false)],
+            request_body: None,
+            response: html(),
+        },
+        Operation {
+            method: "post",
+            path: "/page",
+            summary: "Set the page of the session.",
+            auth: Auth::Session,
+            parameters: vec![
+                session_param(),
+                parameter("query", "notify", boolean(), false),
+                parameter("query", "lock_first_response", boolean(), false),
+                parameter("query", "lock_sticky", boolean(), false),
+                parameter("query", "max_response_size", integer(), false),
+                parameter("query", "keep_response_schema", boolean(), false),
+                parameter("query", "choices", string(), false),
+                parameter("query", "anonymous", boolean(), false),
+                parameter(
+                    "query",
+                    "deadline",
+                    json!({ "type": "string", "format": "date-time" }),
+                    false,
+                ),
+                parameter("query", "duration_ms", integer(), false),
+                parameter("query", "force", boolean(), false),
+            ],
+            request_body: Some(body("text/html", string())),
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/page/stage",
+            summary: "Store a page that replaces the current one when it is committed.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: Some(body("text/html", string())),
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/page/commit",
+            summary: "Replace the page with the staged one. The audience reloads.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/page/schedule",
+            summary: "Store a page that replaces the current one at the given time.",
+            auth: Auth::Session,
+            parameters: vec![
+                session_param(),
+                parameter(
+                    "query",
+                    "at",
+                    json!({ "type": "string", "format": "date-time" }),
+                    true,
+                ),
+                parameter(
+                    "query",
+                    "deadline",
+                    json!({ "type": "string", "format": "date-time" }),
+                    false,
+                ),
+                parameter("query", "duration_ms", integer(), false),
+            ],
+            request_body: Some(body("text/html", string())),
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/page/schedule",
+            summary: "Remove the scheduled page.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/page_history",
+            summary: "Versions of the current and previous pages, newest first.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "current_version": integer(),
+                    "versions": {
+                        "type": "array",
+                        "items": object(json!({
+                            "version": integer(),
+                            "time": { "type": "string", "format": "date-time" },
+                            "bytes": integer(),
+                        })),
+                    },
+                })),
+            ),
+        },
+        Operation {
+            method: "post",
+            path: "/page/patch",
+            summary: "Replace byte ranges of the current page. Offsets refer to the page as it was sent, without the injected script. Edits must not overlap. The audience reloads. Patches of older versions are rejected with `page_version_conflict`.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: Some(body(
+                "application/json",
+                object(json!({
+                    "base_version": integer(),
+                    "edits": {
+                        "type": "array",
+                        "items": object(json!({
+                            "start": integer(),
+                            "end": integer(),
+                            "text": string(),
+                        })),
+                    },
+                })),
+            )),
+            response: ("application/json", object(json!({ "version": integer() }))),
+        },
+        Operation {
+            method: "post",
+            path: "/page_rollback",
+            summary: "Set a previous page again. The audience reloads.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "version", integer(), true)],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/respond",
+            summary: "Send the response of an audience member. The `ETag` header contains the id of the stored response. The body may also be `application/json`, or `application/msgpack` and `application/cbor`, which are stored as json.",
+            auth: Auth::None,
+            parameters: vec![
+                session_param(),
+                parameter("query", "user", string(), true),
+                parameter("query", "prev_id", integer(), false),
+                parameter("query", "page_version", integer(), false),
+                parameter("query", "code", string(), false),
+                parameter("header", "If-Match", string(), false),
+                parameter("header", "Idempotency-Key", string(), false),
+            ],
+            request_body: Some(body("text/plain", string())),
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/join",
+            summary: "Issue a user id for the session. It is also stored in a cookie, so joining again returns the same id.",
+            auth: Auth::None,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: ("application/json", object(json!({ "user": string() }))),
+        },
+        Operation {
+            method: "get",
+            path: "/respond",
+            summary: "Current response of the user, e.g. to restore the page after a reload.",
+            auth: Auth::None,
+            parameters: vec![session_param(), parameter("query", "user", string(), true)],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "data": string(),
+                    "content_type": string(),
+                    "id": integer(),
+                    "time": { "type": "string", "format": "date-time" },
+                })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/responses",
+            summary: "Responses starting at the given id. Long-polls if there are none. With `Accept: application/msgpack` or `application/cbor`, the result is encoded that way.",
+            auth: Auth::Optional,
+            parameters: vec![
+                session_param(),
+                parameter("query", "start", integer(), true),
+                parameter("query", "limit", integer(), false),
+                parameter("query", "timeout_ms", integer(), false),
+                parameter("query", "min_count", integer(), false),
+                parameter(
+                    "query",
+                    "format",
+                    json!({ "type": "string", "enum": ["map", "list"] }),
+                    false,
+                ),
+                parameter("query", "verbose", boolean(), false),
+            ],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "next_start": integer(),
+                    "responses_by_user": {
+                        "type": "object",
+                        "description": "Only with `format=map`. With `verbose=true`, the values are objects with `data`, `content_type`, `id` and `revision`.",
+                        "additionalProperties": string(),
+                    },
+                    "responses": {
+                        "type": "array",
+                        "description": "Only with `format=list`, sorted by id.",
+                        "items": object(json!({
+                            "user": string(),
+                            "data": string(),
+                            "content_type": string(),
+                            "id": integer(),
+                            "time": { "type": "string", "format": "date-time" },
+                            "revision": integer(),
+                        })),
+                    },
+                    "session": string(),
+                    "total_responses": integer(),
+                    "server_time": { "type": "string", "format": "date-time" },
+                })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/responses/batch",
+            summary:
+                "Responses of multiple sessions. Long-polls until any of them has new responses.",
+            auth: Auth::Optional,
+            parameters: vec![
+                parameter("query", "sessions", string(), true),
+                parameter(
+                    "query",
+                    "start",
+                    json!({ "type": "array", "items": integer() }),
+                    false,
+                ),
+                parameter("query", "timeout_ms", integer(), false),
+            ],
+            request_body: None,
+            response: (
+                "application/json",
+                json!({
+                    "type": "object",
+                    "description": "By session id. Entries of sessions that can't be read contain an error instead.",
+                    "additionalProperties": object(json!({
+                        "next_start": integer(),
+                        "responses_by_user": {
+                            "type": "object",
+                            "additionalProperties": string(),
+                        },
+                        "session": string(),
+                        "total_responses": integer(),
+                        "server_time": { "type": "string", "format": "date-time" },
+                    })),
+                }),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/responses/stream",
+            summary: "Responses starting at the given id as newline-delimited json, one `{user, data, id}` per line. The last line is `{next_start, total_responses}`. With `format=csv`, a csv table instead. Does not long-poll.",
+            auth: Auth::Optional,
+            parameters: vec![
+                session_param(),
+                parameter("query", "start", integer(), true),
+                parameter("query", "limit", integer(), false),
+                parameter(
+                    "query",
+                    "format",
+                    json!({ "type": "string", "enum": ["ndjson", "csv"] }),
+                    false,
+                ),
+            ],
+            request_body: None,
+            response: ("application/x-ndjson", string()),
+        },
+        Operation {
+            method: "get",
+            path: "/responses/events",
+            summary: "Server-sent `response` events with `{user, data, id}` for every new or changed response. The event id is the response id, so `Last-Event-ID` resumes after it.",
+            auth: Auth::Optional,
+            parameters: vec![
+                session_param(),
+                parameter("header", "Last-Event-ID", integer(), false),
+            ],
+            request_body: None,
+            response: ("text/event-stream", string()),
+        },
+        Operation {
+            method: "get",
+            path: "/responses/aggregate",
+            summary: "Number of users per response, including the unchosen choices of the page.",
+            auth: Auth::Optional,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "session": string(),
+                    "total_responses": integer(),
+                    "revised_count": integer(),
+                    "counts": {
+                        "type": "array",
+                        "items": object(json!({ "response": string(), "count": integer() })),
+                    },
+                })),
+            ),
+        },
+        Operation {
+            method: "post",
+            path: "/ack",
+            summary: "Mark the responses with smaller ids as received.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "upto", integer(), true)],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/ping",
+            summary: "Keep the session alive while the page is open. Responds with `session_not_found` when the session has ended.",
+            auth: Auth::None,
+            parameters: vec![
+                session_param(),
+                parameter("query", "client", string(), false),
+            ],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/asset",
+            summary: "Store a file that pages of the session can reference, e.g. an image. The content type is derived from the extension of the name. Responds with the hash of the content.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "name", string(), true)],
+            request_body: Some(body("application/octet-stream", binary())),
+            response: ("application/json", object(json!({ "hash": string() }))),
+        },
+        Operation {
+            method: "get",
+            path: "/asset",
+            summary: "Get a file of the session. With the hash of the content as `v`, it is cached forever. The `ETag` is the hash.",
+            auth: Auth::None,
+            parameters: vec![
+                session_param(),
+                parameter("query", "name", string(), true),
+                parameter("query", "v", string(), false),
+                parameter("header", "If-None-Match", string(), false),
+            ],
+            request_body: None,
+            response: ("application/octet-stream", binary()),
+        },
+        Operation {
+            method: "get",
+            path: "/wait_for_new_page",
+            summary: "Long-polls until the page changes. Responds with `reload` or `wait`.",
+            auth: Auth::None,
+            parameters: vec![
+                session_param(),
+                parameter("query", "timeout_ms", integer(), false),
+                parameter("query", "client", string(), false),
+            ],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/results",
+            summary: "Read-only page with the aggregated responses if they are public.",
+            auth: Auth::None,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: html(),
+        },
+        Operation {
+            method: "get",
+            path: "/qr",
+            summary: "QR code with the url of the session page as svg.",
+            auth: Auth::None,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: ("image/svg+xml", string()),
+        },
+        Operation {
+            method: "post",
+            path: "/results/public",
+            summary: "Show the aggregated responses on /results without the token.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/results/public",
+            summary: "Make the results private again.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/export_session",
+            summary: "Archive of the session that can be imported again.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: ("application/json", object(json!({ "version": integer() }))),
+        },
+        Operation {
+            method: "post",
+            path: "/import_session",
+            summary: "Restore a session from an archive under the token of the request.",
+            auth: Auth::Session,
+            parameters: vec![],
+            request_body: Some(body("application/json", json!({ "type": "object" }))),
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/session_info",
+            summary: "Overview of the session. The token of the session adds more details.",
+            auth: Auth::Optional,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "session": string(),
+                    "created": { "type": "string", "format": "date-time" },
+                    "last_request": { "type": "string", "format": "date-time" },
+                    "responses": integer(),
+                    "page_bytes": integer(),
+                    "audience": integer(),
+                    "expires_at": { "type": "string", "format": "date-time" },
+                    "expires_in_seconds": integer(),
+                    "server_time": { "type": "string", "format": "date-time" },
+                    "presenter": object(json!({
+                        "approx_bytes": integer(),
+                        "banned_users": integer(),
+                        "anonymous": boolean(),
+                        "has_join_code": boolean(),
+                        "results_public": boolean(),
+                        "scheduled_page_at": { "type": "string", "format": "date-time", "nullable": true },
+                    })),
+                })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/audience_count",
+            summary: "Approximate number of audience members that have the page open.",
+            auth: Auth::None,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: ("application/json", object(json!({ "audience": integer() }))),
+        },
+        Operation {
+            method: "get",
+            path: "/session_time",
+            summary: "Server time and the time until the voting deadline, for countdowns.",
+            auth: Auth::None,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "server_time": { "type": "string", "format": "date-time" },
+                    "deadline": { "type": "string", "format": "date-time", "nullable": true },
+                    "remaining_ms": { "type": "integer", "minimum": 0, "nullable": true },
+                    "accepting_responses": boolean(),
+                })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/my_sessions",
+            summary: "Sessions of the token, most recently used first.",
+            auth: Auth::Session,
+            parameters: vec![],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "sessions": {
+                        "type": "array",
+                        "items": object(json!({
+                            "session": string(),
+                            "last_request": { "type": "string", "format": "date-time" },
+                            "responses": integer(),
+                        })),
+                    },
+                })),
+            ),
+        },
+        Operation {
+            method: "delete",
+            path: "/my_sessions",
+            summary: "Delete all sessions of the token.",
+            auth: Auth::Session,
+            parameters: vec![],
+            request_body: None,
+            response: ("application/json", object(json!({ "deleted": integer() }))),
+        },
+        Operation {
+            method: "get",
+            path: "/dashboard",
+            summary: "Page for the presenter with the join url and live results.",
+            auth: Auth::None,
+            parameters: vec![session_param(), parameter("query", "token", string(), true)],
+            request_body: None,
+            response: html(),
+        },
+        Operation {
+            method: "get",
+            path: "/client_config",
+            summary: "Limits that audience pages can use to validate responses.",
+            auth: Auth::None,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "max_response_size": integer(),
+                    "max_user_id_length": integer(),
+                    "accepting_responses": boolean(),
+                    "lock_first_response": boolean(),
+                    "voting_deadline": { "type": "string", "format": "date-time", "nullable": true },
+                })),
+            ),
+        },
+        Operation {
+            method: "post",
+            path: "/rotate_token",
+            summary: "Replace the session token.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: token_response(),
+        },
+        Operation {
+            method: "post",
+            path: "/viewer_token",
+            summary: "Create a token that can only read responses.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: token_response(),
+        },
+        Operation {
+            method: "post",
+            path: "/join_code",
+            summary: "Set or rotate the code that the audience needs to join.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "code", string(), false)],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({ "join_code": string() })),
+            ),
+        },
+        Operation {
+            method: "delete",
+            path: "/join_code",
+            summary: "Remove the join code.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/message",
+            summary: "Show a short message to the audience without changing the page.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: Some(body("text/plain", string())),
+            response: ("application/json", message_object()),
+        },
+        Operation {
+            method: "get",
+            path: "/message",
+            summary: "Long-poll for a message newer than `since`. Responds with 204 if there is none.",
+            auth: Auth::None,
+            parameters: vec![
+                session_param(),
+                parameter("query", "since", integer(), false),
+                parameter("query", "timeout_ms", integer(), false),
+            ],
+            request_body: None,
+            response: ("application/json", message_object()),
+        },
+        Operation {
+            method: "post",
+            path: "/ban",
+            summary: "Remove the response of the user and reject later ones.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "user", string(), true)],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/ban",
+            summary: "Accept responses of the user again.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "user", string(), true)],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/ban",
+            summary: "Banned users of the session.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({ "users": { "type": "array", "items": string() } })),
+            ),
+        },
+        Operation {
+            method: "post",
+            path: "/digest",
+            summary: "Periodically post new responses to the url.",
+            auth: Auth::Session,
+            parameters: vec![
+                session_param(),
+                parameter("query", "interval", string(), true),
+                parameter("query", "url", string(), true),
+            ],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/digest",
+            summary: "Stop posting digests.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/webhook",
+            summary: "Post new responses to the url shortly after they arrive.",
+            auth: Auth::Session,
+            parameters: vec![session_param(), parameter("query", "url", string(), true)],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({ "secret": string() })),
+            ),
+        },
+        Operation {
+            method: "delete",
+            path: "/webhook",
+            summary: "Stop posting new responses.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/response_schema",
+            summary: "Reject responses that don't match the json schema until the page changes.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: Some(body("application/json", json!({ "type": "object" }))),
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/response_schema",
+            summary: "Accept all responses again.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/response_filter",
+            summary: "Reject or mask responses that contain the words in the body, one per line.",
+            auth: Auth::Session,
+            parameters: vec![
+                session_param(),
+                parameter(
+                    "query",
+                    "mode",
+                    json!({ "type": "string", "enum": ["reject", "mask"] }),
+                    false,
+                ),
+            ],
+            request_body: Some(body("text/plain", string())),
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/response_filter",
+            summary: "Remove the response filter of the session.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/voting/close",
+            summary: "Reject responses until voting is opened again or the page changes.",
+            auth: Auth::Session,
+            parameters: vec![
+                session_param(),
+                parameter(
+                    "query",
+                    "deadline",
+                    json!({ "type": "string", "format": "date-time" }),
+                    false,
+                ),
+                parameter("query", "after_seconds", integer(), false),
+            ],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "post",
+            path: "/voting/open",
+            summary: "Accept responses again and remove the deadline.",
+            auth: Auth::Session,
+            parameters: vec![session_param()],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "get",
+            path: "/polli_live.js",
+            summary: "Script that is injected into pages.",
+            auth: Auth::None,
+            parameters: vec![],
+            request_body: None,
+            response: ("text/javascript", string()),
+        },
+        Operation {
+            method: "get",
+            path: "/static/{filename}",
+            summary: "Static file, e.g. a stylesheet.",
+            auth: Auth::None,
+            parameters: vec![parameter("path", "filename", string(), true)],
+            request_body: None,
+            response: ("application/octet-stream", string()),
+        },
+        Operation {
+            method: "get",
+            path: "/openapi.json",
+            summary: "This document.",
+            auth: Auth::None,
+            parameters: vec![],
+            request_body: None,
+            response: ("application/json", json!({ "type": "object" })),
+        },
+        Operation {
+            method: "get",
+            path: "/stats",
+            summary: "Counters since the server started and the estimated memory usage.",
+            auth: Auth::None,
+            parameters: vec![],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "sessions_created": integer(),
+                    "sessions_expired": integer(),
+                    "sessions_evicted": integer(),
+                    "sessions_evicted_for_memory": integer(),
+                    "responses_received": integer(),
+                    "responses_dropped": integer(),
+                    "memory_usage": integer(),
+                })),
+            ),
+        },
+        Operation {
+            method: "post",
+            path: "/admin/verify",
+            summary: "Check internal invariants of all sessions or only the given one.",
+            auth: Auth::Admin,
+            parameters: vec![
+                parameter("query", "session", string(), false),
+                parameter("query", "repair", boolean(), false),
+            ],
+            request_body: None,
+            response: (
+                "application/json",
+                object(
+                    json!({ "violations": { "type": "array", "items": object(json!({
+                    "session": string(),
+                    "invariant": string(),
+                    "message": string(),
+                    "repaired": boolean(),
+                })) } }),
+                ),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/admin/sessions",
+            summary: "Sessions that use the most memory.",
+            auth: Auth::Admin,
+            parameters: vec![
+                parameter("query", "limit", integer(), false),
+                parameter("query", "start", integer(), false),
+            ],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "total": integer(),
+                    "next_start": integer(),
+                    "sessions": { "type": "array", "items": object(json!({
+                        "session": string(),
+                        "responses": integer(),
+                        "page_bytes": integer(),
+                        "last_request": { "type": "string", "format": "date-time" },
+                        "approx_bytes": integer(),
+                        "banned_users": { "type": "array", "items": string() },
+                    })) },
+                })),
+            ),
+        },
+        Operation {
+            method: "delete",
+            path: "/admin/sessions/{session}",
+            summary: "Delete a session.",
+            auth: Auth::Admin,
+            parameters: vec![parameter("path", "session", string(), true)],
+            request_body: None,
+            response: text(),
+        },
+        Operation {
+            method: "delete",
+            path: "/admin/user_data",
+            summary: "Delete everything about a user in all sessions.",
+            auth: Auth::Admin,
+            parameters: vec![parameter("query", "user", string(), true)],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({ "removed": integer(), "sessions": integer() })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/admin/audit",
+            summary: "Recent events of a session, oldest first.",
+            auth: Auth::Admin,
+            parameters: vec![parameter("query", "session", string(), true)],
+            request_body: None,
+            response: (
+                "application/json",
+                object(json!({
+                    "events": {
+                        "type": "array",
+                        "items": object(json!({
+                            "session": string(),
+                            "time": string(),
+                            "event": string(),
+                            "ip": string(),
+                            "access": string(),
+                            "reason": string(),
+                        })),
+                    },
+                })),
+            ),
+        },
+        Operation {
+            method: "get",
+            path: "/admin/settings",
+            summary: "Settings that can be changed at runtime.",
+            auth: Auth::Admin,
+            parameters: vec![],
+            request_body: None,
+            response: ("application/json", json!({ "type": "object" })),
+        },
+        Operation {
+            method: "patch",
+            path: "/admin/settings",
+            summary: "Change some of the settings. Sizes are in bytes, durations in seconds.",
+            auth: Auth::Admin,
+            parameters: vec![],
+            request_body: Some(body("application/json", json!({ "type": "object" }))),
+            response: ("application/json", json!({ "type": "object" })),
+        },
+    ]
+}
@@ -0,0 +1,146 @@
+use byte_unit::Byte;
+use derive_more::derive::Display;
+
+use crate::{errors::AppError, static_files, Settings};
+
+/// Pages can contain this to control where the polli.live script is injected.
+pub const INJECTION_PLACEHOLDER: &str = "<!-- polli-live -->";
+
+/// Injects the polli.live script into the page. The size limit applies to the page with
+/// the script, so that stored pages never exceed it. The size of the request body is
+/// limited already, so the injection into too large pages is not a problem.
+pub fn prepare_page(settings: &Settings, page: String) -> Result<String, AppError> {
+    let page = inject_script(page, &injection_snippet(settings));
+    check_size(settings, page.len())?;
+    Ok(page)
+}
+
+fn check_size(settings: &Settings, size: usize) -> Result<(), AppError> {
+    if Byte::from_u64(size as u64) > settings.max_page_size {
+        return Err(AppError::PageTooLarge {
+            size: size as u64,
+            max_size: settings.max_page_size.as_u64(),
+        });
+    }
+    Ok(())
+}
+
+/// What [`prepare_page`] would do with a page, without storing it anywhere.
+pub struct PageCheck {
+    pub injection_point: InjectionPoint,
+    /// Line and column in the original page, both starting at 1.
+    pub line: usize,
+    pub column: usize,
+    /// Size of the page with the script.
+    pub size: u64,
+    pub result: Result<(), AppError>,
+}
+
+pub fn check_page(settings: &Settings, page: &str) -> PageCheck {
+    let (offset, injection_point) = find_injection_point(page);
+    let before = &page[..offset];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let size = inject_script(page.to_string(), &injection_snippet(settings)).len();
+    PageCheck {
+        injection_point,
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+        size: size as u64,
+        result: check_size(settings, size),
+    }
+}
+
+/// By default, the script is only referenced, so that browsers can cache it.
+pub fn injection_snippet(settings: &Settings) -> String {
+    if settings.inline_injection {
+        return format!(
+            "<script>\n{}</script>\n",
+            static_files::get(settings, "polli_live.js")
+        );
+    }
+    // Pages are stored once but may be fetched through different hosts.
+    let base_url = match settings.auto_root_url {
+        true => settings.base_path.clone(),
+        false => settings.base_url(),
+    };
+    format!(
+        "<script src=\"{}/polli_live.js?v={}\"></script>\n",
+        base_url,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum InjectionPoint {
+    #[display("at the placeholder")]
+    Placeholder,
+    #[display("at the end of the head")]
+    EndOfHead,
+    #[display("at the start of the body")]
+    StartOfBody,
+    #[display("after the doctype")]
+    AfterDoctype,
+    #[display("at the start of the document")]
+    StartOfDocument,
+}
+
+/// The script is put where the placeholder is, at the end of the head, at the start of
+/// the body or at the start of the document, whatever is found first.
+pub fn inject_script(mut page: String, script: &str) -> String {
+    match find_injection_point(&page) {
+        (idx, InjectionPoint::Placeholder) => {
+            page.replace_range(idx..idx + INJECTION_PLACEHOLDER.len(), script)
+        }
+        (idx, _) => page.insert_str(idx, script),
+    }
+    page
+}
+
+/// Reverts [`inject_script`] with the current settings. The script is replaced by the
+/// placeholder, so that it's injected at the same position again.
+pub fn remove_script(settings: &Settings, page: &str) -> String {
+    page.replacen(&injection_snippet(settings), INJECTION_PLACEHOLDER, 1)
+}
+
+/// The page like it was sent, which is what the offsets of a
+/// [`crate::page_patch::PagePatch`] refer to. Pages that had no placeholder get it back
+/// from [`remove_script`] otherwise.
+pub fn original_page(settings: &Settings, page: &str) -> String {
+    let snippet = injection_snippet(settings);
+    let without_script = page.replacen(&snippet, "", 1);
+    if inject_script(without_script.clone(), &snippet) == page {
+        return without_script;
+    }
+    remove_script(settings, page)
+}
+
+/// Byte offset where the script is inserted.
+fn find_injection_point(page: &str) -> (usize, InjectionPoint) {
+    if let Some(idx) = page.find(INJECTION_PLACEHOLDER) {
+        return (idx, InjectionPoint::Placeholder);
+    }
+    // Lowercasing ascii characters does not change the byte offsets.
+    let lowercase_page = page.to_ascii_lowercase();
+    if let Some(idx) = lowercase_page.find("</head>") {
+        return (idx, InjectionPoint::EndOfHead);
+    }
+    if let Some(idx) = find_tag_end(&lowercase_page, "<body") {
+        return (idx, InjectionPoint::StartOfBody);
+    }
+    if let Some(idx) = find_tag_end(&lowercase_page, "<!doctype") {
+        return (idx, InjectionPoint::AfterDoctype);
+    }
+    (0, InjectionPoint::StartOfDocument)
+}
+
+/// Position right after the opening tag with the given prefix.
+fn find_tag_end(page: &str, tag_start: &str) -> Option<usize> {
+    let start = page.find(tag_start)?;
+    let after_name = page.as_bytes().get(start + tag_start.len())?;
+    // Don't match e.g. `<bodyguard>`.
+    if !matches!(after_name, b'>' | b' ' | b'\t' | b'\n' | b'\r' | b'/') {
+        return None;
+    }
+    let end = page[start..].find('>')?;
+    Some(start + end + 1)
+}
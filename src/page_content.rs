@@ -0,0 +1,99 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use crate::Settings;
+
+/// A page like it is kept in a session, i.e. with the injected script. With
+/// [`Settings::compress_stored_pages`], it is gzip-compressed, because pages are by far
+/// the largest part of sessions and html compresses well. Snapshots contain the plain
+/// page, so restored pages are only compressed again when they are replaced.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PageContent {
+    Plain(String),
+    Gzip {
+        data: Vec<u8>,
+        /// Length of the uncompressed page in bytes.
+        len: usize,
+    },
+}
+
+impl PageContent {
+    pub fn new(settings: &Settings, page: String) -> Self {
+        if !settings.compress_stored_pages {
+            return PageContent::Plain(page);
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(page.as_bytes())
+            .expect("Writing to a vector does not fail");
+        let data = encoder.finish().expect("Writing to a vector does not fail");
+        // Very small pages become larger.
+        if data.len() >= page.len() {
+            return PageContent::Plain(page);
+        }
+        PageContent::Gzip {
+            data,
+            len: page.len(),
+        }
+    }
+
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            PageContent::Plain(page) => Cow::Borrowed(page),
+            PageContent::Gzip { data, len } => {
+                let mut page = String::with_capacity(*len);
+                GzDecoder::new(data.as_slice())
+                    .read_to_string(&mut page)
+                    .expect("Compressed pages have been valid utf-8");
+                Cow::Owned(page)
+            }
+        }
+    }
+
+    /// Length of the uncompressed page in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            PageContent::Plain(page) => page.len(),
+            PageContent::Gzip { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes that are actually used to keep the page.
+    pub fn stored_len(&self) -> usize {
+        match self {
+            PageContent::Plain(page) => page.len(),
+            PageContent::Gzip { data, .. } => data.len(),
+        }
+    }
+
+    /// Can be sent as is to clients that accept `Content-Encoding: gzip`.
+    pub fn gzip(&self) -> Option<&[u8]> {
+        match self {
+            PageContent::Plain(_) => None,
+            PageContent::Gzip { data, .. } => Some(data),
+        }
+    }
+}
+
+impl From<String> for PageContent {
+    fn from(page: String) -> Self {
+        PageContent::Plain(page)
+    }
+}
+
+impl serde::Serialize for PageContent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PageContent {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(PageContent::Plain)
+    }
+}
@@ -0,0 +1,59 @@
+use crate::AppError;
+
+/// Changes to the current page, so that a small change of a large page does not require
+/// sending the whole page again. Offsets are in bytes and refer to the page like it was
+/// sent, i.e. without the injected script.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PagePatch {
+    /// Version of the page that the patch is based on, see
+    /// [`crate::SessionState::page_version`].
+    pub base_version: usize,
+    pub edits: Vec<PageEdit>,
+}
+
+/// Replaces the bytes from `start` to `end` (exclusive) with the text. Insertions have the
+/// same start and end.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PageEdit {
+    pub start: usize,
+    pub end: usize,
+    #[serde(default)]
+    pub text: String,
+}
+
+/// The edits may be in any order, but must not overlap. Edits that touch, e.g. an insertion
+/// at the end of a replaced range, are fine. Nothing is applied if any edit is invalid.
+pub fn apply_edits(page: &str, edits: &[PageEdit]) -> Result<String, AppError> {
+    let invalid = |message: String| AppError::InvalidPatch { message };
+    let mut edits: Vec<&PageEdit> = edits.iter().collect();
+    edits.sort_by_key(|edit| (edit.start, edit.end));
+    let mut result = String::with_capacity(page.len());
+    let mut position = 0;
+    for edit in edits {
+        if edit.start > edit.end || edit.end > page.len() {
+            return Err(invalid(format!(
+                "Range {}..{} is not within the page of {} bytes.",
+                edit.start,
+                edit.end,
+                page.len()
+            )));
+        }
+        if edit.start < position {
+            return Err(invalid(format!(
+                "Range {}..{} overlaps with another edit.",
+                edit.start, edit.end
+            )));
+        }
+        if !page.is_char_boundary(edit.start) || !page.is_char_boundary(edit.end) {
+            return Err(invalid(format!(
+                "Range {}..{} splits a character.",
+                edit.start, edit.end
+            )));
+        }
+        result.push_str(&page[position..edit.start]);
+        result.push_str(&edit.text);
+        position = edit.end;
+    }
+    result.push_str(&page[position..]);
+    Ok(result)
+}
@@ -0,0 +1,191 @@
+use derive_more::derive::{Display, Error, From};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    audit_log::RemovalReason, clock, request_id, SessionID, SessionState, Settings, State,
+};
+
+/// Has to be increased when the serialized format of the state changes in an incompatible
+/// way. Snapshots of other versions are ignored.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const SNAPSHOT_FILE_NAME: &str = "sessions.json";
+
+#[derive(Debug, Display, Error, From)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    #[display("Version mismatch: expected {SNAPSHOT_VERSION}, found {found}")]
+    #[from(ignore)]
+    VersionMismatch {
+        found: u32,
+    },
+}
+
+#[derive(serde::Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    sessions: &'a HashMap<SessionID, SessionState>,
+}
+
+/// The version has been checked with [`SnapshotHeader`] already.
+#[derive(serde::Deserialize)]
+struct Snapshot {
+    sessions: HashMap<SessionID, SessionState>,
+}
+
+/// Only used to check the version before the rest of the snapshot is parsed.
+#[derive(serde::Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+pub fn snapshot_file_path(dir: &Path) -> PathBuf {
+    dir.join(SNAPSHOT_FILE_NAME)
+}
+
+pub fn state_to_snapshot(state: &State) -> Vec<u8> {
+    serde_json::to_vec(&SnapshotRef {
+        version: SNAPSHOT_VERSION,
+        sessions: &state.sessions,
+    })
+    .expect("state can always be serialized")
+}
+
+pub fn state_from_snapshot(data: &[u8]) -> Result<State, SnapshotError> {
+    let header: SnapshotHeader = serde_json::from_slice(data)?;
+    if header.version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::VersionMismatch {
+            found: header.version,
+        });
+    }
+    let snapshot: Snapshot = serde_json::from_slice(data)?;
+    let mut state = State {
+        sessions: snapshot.sessions,
+        ..Default::default()
+    };
+    state.recount_sessions_per_ip();
+    state.reindex_tokens();
+    state.recount_memory_usage();
+    Ok(state)
+}
+
+/// Monotonic times are not part of snapshots, so they are derived from the wall-clock
+/// times after loading. Sessions that expired while the server was stopped are removed
+/// here already, because their monotonic time may not be representable.
+pub fn restore_instants(settings: &Settings, state: &mut State) {
+    let now = settings.now();
+    let expired: Vec<SessionID> = state
+        .sessions
+        .iter()
+        .filter(|(_, session)| {
+            (now - session.last_request)
+                .to_std()
+                .is_ok_and(|age| age >= session.keep_alive_duration(settings))
+        })
+        .map(|(session_id, _)| session_id.clone())
+        .collect();
+    for session_id in expired {
+        state.expire_session(settings, &session_id, now, RemovalReason::Expired);
+    }
+    for session in state.sessions.values_mut() {
+        session.last_used = clock::instant_at(&*settings.clock, session.last_request);
+        for response in session.responses.values_mut() {
+            response.arrived = clock::instant_at(&*settings.clock, response.time);
+        }
+        // Responses that have not been delivered before the restart are delivered soon.
+        if let Some(webhook) = &mut session.webhook {
+            if webhook.start < session.next_response_id {
+                webhook.due = Some(settings.instant());
+            }
+        }
+    }
+}
+
+/// Same format as [`state_to_snapshot`], but the sessions are serialized batch by batch, so
+/// that the state is only locked briefly. Sessions are stored as they were when their batch
+/// was serialized, and sessions that are created in the meantime are left out.
+pub async fn state_to_snapshot_in_batches(settings: &Settings, state: &Mutex<State>) -> Vec<u8> {
+    let session_ids: Vec<SessionID> = state.lock().sessions.keys().cloned().collect();
+    let mut data = format!("{{\"version\":{},\"sessions\":{{", SNAPSHOT_VERSION).into_bytes();
+    let mut is_first = true;
+    for batch in session_ids.chunks(settings.cleanup_batch_size.max(1)) {
+        {
+            let state = state.lock();
+            for session_id in batch {
+                let Some(session) = state.sessions.get(session_id) else {
+                    continue;
+                };
+                if !is_first {
+                    data.push(b',');
+                }
+                is_first = false;
+                serde_json::to_writer(&mut data, session_id).expect("ids can be serialized");
+                data.push(b':');
+                serde_json::to_writer(&mut data, session).expect("state can always be serialized");
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+    data.extend_from_slice(b"}}");
+    data
+}
+
+/// Writes to a temporary file first, so that a crash while writing does not corrupt the
+/// previous snapshot.
+pub async fn save_state(
+    settings: &Settings,
+    dir: &Path,
+    state: &Mutex<State>,
+) -> Result<(), SnapshotError> {
+    let data = state_to_snapshot_in_batches(settings, state).await;
+    tokio::fs::create_dir_all(dir).await?;
+    let path = snapshot_file_path(dir);
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(())
+}
+
+/// Starts with an empty state if there is no usable snapshot.
+pub fn load_state(dir: &Path) -> State {
+    let path = snapshot_file_path(dir);
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return State::default(),
+        Err(err) => {
+            request_id::log(format!("Cannot read snapshot {}: {}", path.display(), err));
+            return State::default();
+        }
+    };
+    match state_from_snapshot(&data) {
+        Ok(state) => {
+            request_id::log(format!(
+                "Restored {} sessions from {}",
+                state.sessions.len(),
+                path.display()
+            ));
+            state
+        }
+        Err(err) => {
+            request_id::log(format!("Ignoring snapshot {}: {}", path.display(), err));
+            State::default()
+        }
+    }
+}
+
+pub async fn do_periodic_persist(settings: Settings, state: Arc<Mutex<State>>) {
+    let Some(dir) = settings.persist_path.clone() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(settings.persist_interval);
+    loop {
+        interval.tick().await;
+        if let Err(err) = save_state(&settings, &dir, &state).await {
+            request_id::log(format!("Cannot write snapshot: {}", err));
+        }
+    }
+}
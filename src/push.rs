@@ -0,0 +1,122 @@
+use notify::{EventKind, RecursiveMode, Watcher};
+use reqwest::header;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::errors::ErrorBody;
+
+/// Editors often write a file in multiple steps, so changes are only pushed after the file
+/// has not changed for this long.
+const DEBOUNCE_DURATION: Duration = Duration::from_millis(300);
+
+/// Session on a server whose page is replaced.
+pub struct PushTarget {
+    pub server_url: String,
+    pub session: String,
+    pub token: String,
+}
+
+/// Sends the file to `/page` of the session. The error is a message for the presenter.
+pub async fn push_file(
+    client: &reqwest::Client,
+    target: &PushTarget,
+    path: &Path,
+) -> Result<reqwest::StatusCode, String> {
+    let page = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let res = client
+        .post(format!("{}/page", target.server_url.trim_end_matches('/')))
+        .query(&[("session", &target.session)])
+        .bearer_auth(&target.token)
+        .header(header::ACCEPT, "application/json")
+        .body(page)
+        .send()
+        .await
+        .map_err(|err| format!("Cannot reach {}: {}", target.server_url, err))?;
+    let status = res.status();
+    if status.is_success() {
+        return Ok(status);
+    }
+    let body = res.text().await.unwrap_or_default();
+    Err(describe_error(status, &body))
+}
+
+/// Turns the json error of the server into something that tells the presenter what to do.
+pub fn describe_error(status: reqwest::StatusCode, body: &str) -> String {
+    let Ok(error) = serde_json::from_str::<ErrorBody>(body) else {
+        return format!("Server responded with {}", status);
+    };
+    let detail = |name: &str| {
+        error
+            .details
+            .as_ref()
+            .and_then(|details| details.get(name))
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "?".to_string())
+    };
+    let message = match error.error.as_str() {
+        "bad_access_token" => "The token does not belong to this session.".to_string(),
+        "bad_session_id" => "The session id contains invalid characters.".to_string(),
+        "session_not_found" => {
+            "The session does not exist. Create it with `/new` first.".to_string()
+        }
+        "session_expired" => "The session has expired. Create a new one.".to_string(),
+        "page_too_large" => format!(
+            "The page is too large: {} bytes with the injected script, at most {} allowed.",
+            detail("size"),
+            detail("max_size")
+        ),
+        "too_many_requests" => format!(
+            "Too many pushes, retry in {} seconds.",
+            detail("retry_after_seconds")
+        ),
+        _ => error.message,
+    };
+    format!("{} ({})", message, status)
+}
+
+/// Pushes the file once and then again whenever it changes, until the watcher fails.
+pub async fn watch_and_push(
+    client: &reqwest::Client,
+    target: &PushTarget,
+    path: &Path,
+) -> Result<(), String> {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .map_err(|err| format!("Cannot watch {}: {}", path.display(), err))?;
+    // Editors may replace the file instead of changing it, so the directory is watched.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|err| format!("Cannot watch {}: {}", dir.display(), err))?;
+
+    report_push(path, push_file(client, target, path).await);
+    let file_name = path.file_name();
+    loop {
+        let event: notify::Event = match receiver.recv().await {
+            Some(event) => {
+                event.map_err(|err| format!("Cannot watch {}: {}", path.display(), err))?
+            }
+            None => return Ok(()),
+        };
+        let is_change = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+        if !is_change || !event.paths.iter().any(|p| p.file_name() == file_name) {
+            continue;
+        }
+        while let Ok(Some(_)) = tokio::time::timeout(DEBOUNCE_DURATION, receiver.recv()).await {}
+        report_push(path, push_file(client, target, path).await);
+    }
+}
+
+pub fn report_push(path: &Path, result: Result<reqwest::StatusCode, String>) {
+    match result {
+        Ok(status) => println!("Pushed {} ({})", path.display(), status),
+        Err(err) => println!("Cannot push {}: {}", path.display(), err),
+    }
+}
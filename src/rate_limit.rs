@@ -0,0 +1,129 @@
+use actix_web::HttpRequest;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::{AppError, Settings};
+
+/// Token bucket parameters. Every request takes one token and the bucket refills
+/// continuously up to the burst size.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Requests are limited separately per kind, so that e.g. a presenter that updates the
+/// page often can still create new sessions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RateLimitKind {
+    Respond,
+    NewSession,
+    SetPage,
+    Ping,
+}
+
+impl RateLimitKind {
+    fn limit(self, settings: &Settings) -> RateLimit {
+        match self {
+            RateLimitKind::Respond => settings.respond_rate_limit,
+            RateLimitKind::NewSession => settings.new_session_rate_limit,
+            RateLimitKind::SetPage => settings.set_page_rate_limit,
+            RateLimitKind::Ping => settings.ping_rate_limit,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.last_refill = self.last_refill.max(now);
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(RateLimitKind, IpAddr), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of the client or returns how long it has to wait.
+    pub fn check(
+        &self,
+        kind: RateLimitKind,
+        limit: RateLimit,
+        ip: IpAddr,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.entry((kind, ip)).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            last_refill: now,
+            limit,
+        });
+        bucket.refill(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let missing = 1.0 - bucket.tokens;
+        Err(Duration::try_from_secs_f64(missing / limit.per_second).unwrap_or(Duration::MAX))
+    }
+
+    /// Full buckets behave the same as buckets that don't exist, so they can be removed.
+    pub fn remove_full_buckets(&self, now: Instant) {
+        self.buckets.lock().retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.limit.burst as f64
+        });
+    }
+
+    #[cfg(feature = "test-util")]
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.lock().len()
+    }
+}
+
+/// The peer address is the proxy when running behind one. Only then the forwarded
+/// header can be trusted, because clients could set it to anything otherwise.
+pub fn client_ip(req: &HttpRequest, settings: &Settings) -> Option<IpAddr> {
+    if settings.trust_forwarded_for {
+        // The proxy appends the ip it got the request from, everything before that comes
+        // from the client and can be made up to get around the limits.
+        let forwarded_ip = req
+            .headers()
+            .get_all("X-Forwarded-For")
+            .last()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        if forwarded_ip.is_some() {
+            return forwarded_ip;
+        }
+    }
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+pub fn check_rate_limit(
+    req: &HttpRequest,
+    settings: &Settings,
+    rate_limiter: &RateLimiter,
+    kind: RateLimitKind,
+) -> Result<(), AppError> {
+    let Some(ip) = client_ip(req, settings) else {
+        return Ok(());
+    };
+    rate_limiter
+        .check(kind, kind.limit(settings), ip, Instant::now())
+        .map_err(|retry_after| AppError::TooManyRequests { retry_after })
+}
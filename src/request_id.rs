@@ -0,0 +1,79 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    HttpMessage, HttpRequest,
+};
+use rand::Rng;
+use std::fmt::Display;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// Identifies a request in logs and error responses, so that reports of failed requests
+/// can be correlated with what the server logged.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn new_random() -> Self {
+        RequestId(format!("{:016x}", rand::thread_rng().gen::<u64>()))
+    }
+
+    /// Ids from a reverse proxy are reused so that they match the logs of the proxy.
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let is_valid = !value.is_empty()
+            && value.len() <= 64
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        is_valid.then(|| RequestId(value.to_string()))
+    }
+
+    pub fn of(req: &HttpRequest) -> Option<Self> {
+        req.extensions().get::<RequestId>().cloned()
+    }
+}
+
+/// Prints the message with the id of the request that is currently handled, if any.
+pub fn log(message: impl Display) {
+    match CURRENT_REQUEST_ID.try_with(|id| id.0.clone()) {
+        Ok(id) => println!("[{}] {}", id, message),
+        Err(_) => println!("{}", message),
+    }
+}
+
+/// Middleware that assigns the request id and adds it to the response.
+pub async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::new_random);
+    req.extensions_mut().insert(request_id.clone());
+    let path = req.path().to_string();
+    let mut res = CURRENT_REQUEST_ID
+        .scope(request_id.clone(), next.call(req))
+        .await?;
+    if res.status().is_server_error() {
+        println!(
+            "[{}] {} responded with {}",
+            request_id.0,
+            path,
+            res.status()
+        );
+    }
+    res.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&request_id.0).unwrap(),
+    );
+    Ok(res)
+}
@@ -0,0 +1,125 @@
+use regex::{Regex, RegexSet};
+use std::path::Path;
+
+use crate::AppError;
+
+/// What happens to responses that contain filtered words.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    /// Reject with `422 Unprocessable Entity`.
+    #[default]
+    Reject,
+    /// Replace every matched character with `*`.
+    Mask,
+}
+
+/// How a filter is serialized, e.g. in snapshots.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct FilterSource {
+    pub patterns: String,
+    pub mode: FilterMode,
+}
+
+/// Denylist for free-text responses. Every line of the source is a word that is matched
+/// case-insensitively as a whole word, or a regex if it is enclosed in slashes like
+/// `/sp[a4]m/`. Empty lines and lines starting with `#` are ignored. The patterns are only
+/// compiled once, when the filter is created.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "FilterSource", into = "FilterSource")]
+pub struct ResponseFilter {
+    source: String,
+    mode: FilterMode,
+    set: RegexSet,
+    /// All patterns combined, to find the matches that are masked.
+    combined: Regex,
+}
+
+impl ResponseFilter {
+    pub fn new(source: &str, mode: FilterMode) -> Result<Self, String> {
+        let patterns: Vec<String> = source
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(
+                |line| match line.strip_prefix('/').and_then(|l| l.strip_suffix('/')) {
+                    Some(regex) if !regex.is_empty() => regex.to_string(),
+                    _ => format!(r"(?i)\b{}\b", regex::escape(line)),
+                },
+            )
+            .collect();
+        let set = RegexSet::new(&patterns).map_err(|err| err.to_string())?;
+        let combined = patterns
+            .iter()
+            .map(|pattern| format!("(?:{})", pattern))
+            .collect::<Vec<_>>()
+            .join("|");
+        let combined = Regex::new(&combined).map_err(|err| err.to_string())?;
+        Ok(ResponseFilter {
+            source: source.to_string(),
+            mode,
+            set,
+            combined,
+        })
+    }
+
+    pub fn load(path: &Path, mode: FilterMode) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        Self::new(&source, mode).map_err(|err| format!("Invalid {}: {}", path.display(), err))
+    }
+
+    /// Number of words and regexes in the filter.
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// Returns the response with masked matches, or an error if matches are rejected.
+    pub fn apply(&self, data: String) -> Result<String, AppError> {
+        if !self.set.is_match(&data) {
+            return Ok(data);
+        }
+        match self.mode {
+            FilterMode::Reject => Err(AppError::InvalidResponse {
+                message: "Response contains words that are not allowed.".to_string(),
+            }),
+            FilterMode::Mask => Ok(self
+                .combined
+                .replace_all(&data, |captures: &regex::Captures| {
+                    "*".repeat(captures[0].chars().count())
+                })
+                .into_owned()),
+        }
+    }
+}
+
+impl TryFrom<FilterSource> for ResponseFilter {
+    type Error = String;
+
+    fn try_from(source: FilterSource) -> Result<Self, Self::Error> {
+        ResponseFilter::new(&source.patterns, source.mode)
+    }
+}
+
+impl From<ResponseFilter> for FilterSource {
+    fn from(filter: ResponseFilter) -> Self {
+        FilterSource {
+            patterns: filter.source,
+            mode: filter.mode,
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::AppError;
+
+/// Json schema that responses of a session have to match, so that the presenter does not
+/// have to deal with malformed responses. Only the schema itself is serialized, the
+/// validator is created again when it is loaded.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "serde_json::Value", into = "serde_json::Value")]
+pub struct ResponseSchema {
+    schema: serde_json::Value,
+    validator: Arc<jsonschema::Validator>,
+}
+
+impl ResponseSchema {
+    pub fn new(schema: serde_json::Value) -> Result<Self, AppError> {
+        let validator =
+            jsonschema::validator_for(&schema).map_err(|err| AppError::InvalidResponseSchema {
+                message: err.to_string(),
+            })?;
+        Ok(ResponseSchema {
+            schema,
+            validator: Arc::new(validator),
+        })
+    }
+
+    pub fn schema(&self) -> &serde_json::Value {
+        &self.schema
+    }
+
+    /// The error describes the first violation.
+    pub fn validate(&self, data: &str) -> Result<(), AppError> {
+        let instance: serde_json::Value =
+            serde_json::from_str(data).map_err(|err| AppError::InvalidResponse {
+                message: format!("Response is not valid json: {}", err),
+            })?;
+        self.validator
+            .validate(&instance)
+            .map_err(|err| AppError::InvalidResponse {
+                message: match err.instance_path().as_str() {
+                    "" => err.to_string(),
+                    path => format!("{} at {}", err, path),
+                },
+            })
+    }
+}
+
+impl FromStr for ResponseSchema {
+    type Err = AppError;
+
+    fn from_str(schema: &str) -> Result<Self, Self::Err> {
+        let schema =
+            serde_json::from_str(schema).map_err(|err| AppError::InvalidResponseSchema {
+                message: err.to_string(),
+            })?;
+        Self::new(schema)
+    }
+}
+
+impl TryFrom<serde_json::Value> for ResponseSchema {
+    type Error = String;
+
+    fn try_from(schema: serde_json::Value) -> Result<Self, Self::Error> {
+        ResponseSchema::new(schema).map_err(|err| err.to_string())
+    }
+}
+
+impl From<ResponseSchema> for serde_json::Value {
+    fn from(schema: ResponseSchema) -> Self {
+        schema.schema
+    }
+}
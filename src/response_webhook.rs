@@ -0,0 +1,156 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{request_id, webhooks, RetrievedResponses, SessionID, Settings, State, UserID};
+
+/// Posts new responses of a session to a url shortly after they arrive, so that external
+/// services don't have to long-poll `/responses`. Unlike digests, every response is sent
+/// and failed deliveries are sent again with the next one.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ResponseWebhook {
+    pub url: String,
+    /// Used to sign the posted json, see [`webhooks::SIGNATURE_HEADER`].
+    pub secret: String,
+    /// Id of the first response that has not been delivered yet.
+    pub start: usize,
+    /// Set when a response arrived that has not been delivered yet. It's monotonic, so
+    /// snapshots don't contain it, see [`crate::persist::restore_instants`].
+    #[serde(skip)]
+    pub due: Option<Instant>,
+    /// Only one delivery is in progress at a time, so that they arrive in order.
+    #[serde(skip)]
+    pub delivering: bool,
+    pub consecutive_failures: usize,
+}
+
+pub struct WebhookDelivery {
+    pub session_id: SessionID,
+    pub url: String,
+    pub secret: String,
+    pub payload: RetrievedResponses,
+}
+
+impl ResponseWebhook {
+    pub fn new(url: String, secret: String, start: usize) -> Self {
+        ResponseWebhook {
+            url,
+            secret,
+            start,
+            due: None,
+            delivering: false,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Responses that arrive before the delivery is due are delivered together with this one.
+    pub fn response_arrived(&mut self, now: Instant, debounce: Duration) {
+        if self.due.is_none() {
+            self.due = Some(now + debounce);
+        }
+    }
+}
+
+/// Gathers the webhooks that are due. The start of a webhook is only moved forward once the
+/// delivery succeeded, see [`finish_delivery`].
+pub fn collect_due_webhooks(state: &mut State, settings: &Settings) -> Vec<WebhookDelivery> {
+    let now = settings.instant();
+    let mut deliveries = vec![];
+    for (session_id, session) in state.sessions.iter_mut() {
+        let Some(webhook) = &mut session.webhook else {
+            continue;
+        };
+        if webhook.delivering || webhook.due.is_none_or(|due| due > now) {
+            continue;
+        }
+        webhook.due = None;
+
+        let start = webhook.start;
+        let end = session.next_response_id;
+        let responses_by_user: HashMap<UserID, String> = session
+            .responses
+            .iter()
+            .filter(|(_, user_response)| user_response.id >= start && user_response.id < end)
+            .map(|(user_id, user_response)| (user_id.clone(), user_response.data.clone()))
+            .collect();
+        if responses_by_user.is_empty() {
+            // The page changed before the responses were delivered.
+            webhook.start = end;
+            continue;
+        }
+        webhook.delivering = true;
+        deliveries.push(WebhookDelivery {
+            session_id: session_id.clone(),
+            url: webhook.url.clone(),
+            secret: webhook.secret.clone(),
+            payload: RetrievedResponses {
+                next_start: end,
+                responses_by_user,
+                session: session_id.0.clone(),
+                total_responses: session.responses.len(),
+                server_time: settings.now(),
+            },
+        });
+    }
+    deliveries
+}
+
+/// Failed deliveries are retried after the debounce duration, until the webhook is
+/// disabled because too many failed in a row.
+pub fn finish_delivery(
+    state: &mut State,
+    settings: &Settings,
+    delivery: &WebhookDelivery,
+    success: bool,
+) {
+    let Some(session) = state.sessions.get_mut(&delivery.session_id) else {
+        return;
+    };
+    // The webhook may have been replaced while it was delivered.
+    let Some(webhook) = session
+        .webhook
+        .as_mut()
+        .filter(|webhook| webhook.secret == delivery.secret)
+    else {
+        return;
+    };
+    webhook.delivering = false;
+    if success {
+        webhook.start = webhook.start.max(delivery.payload.next_start);
+        webhook.consecutive_failures = 0;
+        return;
+    }
+    webhook.consecutive_failures += 1;
+    if webhook.consecutive_failures >= settings.webhook_max_failures {
+        request_id::log(format!(
+            "Webhook {} of session {} disabled after {} failed deliveries",
+            webhook.url, delivery.session_id.0, webhook.consecutive_failures
+        ));
+        session.webhook = None;
+        return;
+    }
+    webhook.response_arrived(settings.instant(), settings.webhook_debounce);
+}
+
+pub async fn do_periodic_webhook_delivery(settings: Settings, state: Arc<Mutex<State>>) {
+    let mut interval = tokio::time::interval(settings.webhook_check_interval);
+    loop {
+        interval.tick().await;
+        let deliveries = collect_due_webhooks(&mut state.lock(), &settings);
+        for delivery in deliveries {
+            let settings = settings.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                let success = webhooks::deliver_signed_with_retry(
+                    &settings,
+                    &delivery.url,
+                    &delivery.payload,
+                    &delivery.secret,
+                )
+                .await;
+                finish_delivery(&mut state.lock(), &settings, &delivery, success);
+            });
+        }
+    }
+}
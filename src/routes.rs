@@ -1,18 +1,204 @@
+use actix_web::web;
+
+mod admin_audit;
+mod admin_sessions;
+mod admin_settings;
+mod admin_user_data;
+mod get_asset;
+mod get_audience_count;
+mod get_client_config;
+mod get_dashboard;
+mod get_export_session;
 mod get_index;
+mod get_join;
+mod get_my_sessions;
+mod get_openapi;
 mod get_page;
+mod get_page_history;
+mod get_qr;
+mod get_respond;
 mod get_responses;
+mod get_responses_aggregate;
+mod get_responses_batch;
+mod get_responses_events;
+mod get_responses_stream;
+mod get_results;
+mod get_script;
+mod get_session_info;
+mod get_session_time;
+mod get_static;
+mod get_stats;
 mod get_wait_for_page;
+mod not_found;
+mod post_ack;
+mod post_admin_verify;
+mod post_asset;
+mod post_ban;
+mod post_digest;
+mod post_import_session;
 mod post_init_session;
+mod post_join_code;
+mod post_message;
 mod post_page;
+mod post_page_patch;
+mod post_page_rollback;
+mod post_page_schedule;
+mod post_page_stage;
+mod post_ping;
 mod post_respond;
+mod post_response_filter;
+mod post_response_schema;
+mod post_results_public;
+mod post_rotate_token;
+mod post_viewer_token;
+mod post_voting;
+mod post_webhook;
 
+pub use admin_audit::get_admin_audit_route;
+pub use admin_sessions::{delete_admin_session_route, get_admin_sessions_route};
+pub use admin_settings::{get_admin_settings_route, patch_admin_settings_route};
+pub use admin_user_data::delete_admin_user_data_route;
+pub use get_asset::get_asset_route;
+pub use get_audience_count::{get_audience_count_route, AudienceCount};
+pub use get_client_config::get_client_config_route;
+pub use get_dashboard::get_dashboard_route;
+pub use get_export_session::get_export_session_route;
 pub use get_index::get_index_route;
+pub use get_join::get_join_route;
+pub use get_my_sessions::{delete_my_sessions_route, get_my_sessions_route};
+pub use get_openapi::get_openapi_route;
 pub use get_page::get_page_route;
+pub use get_page_history::get_page_history_route;
+pub use get_qr::get_qr_route;
+pub use get_respond::get_respond_route;
 pub use get_responses::get_responses_route;
+pub use get_responses_aggregate::get_responses_aggregate_route;
+pub use get_responses_batch::get_responses_batch_route;
+pub use get_responses_events::get_responses_events_route;
+pub use get_responses_stream::get_responses_stream_route;
+pub use get_results::get_results_route;
+pub use get_script::get_script_route;
+pub use get_session_info::get_session_info_route;
+pub use get_session_time::get_session_time_route;
+pub use get_static::get_static_route;
+pub use get_stats::get_stats_route;
 pub use get_wait_for_page::get_wait_for_page_route;
+pub use not_found::not_found_route;
+pub use post_ack::post_ack_route;
+pub use post_admin_verify::post_admin_verify_route;
+pub use post_asset::{post_asset_route, StoredAsset};
+pub use post_ban::{delete_ban_route, get_ban_route, post_ban_route};
+pub use post_digest::{delete_digest_route, post_digest_route};
+pub use post_import_session::post_import_session_route;
 pub use post_init_session::post_init_session_route;
+pub use post_join_code::{delete_join_code_route, post_join_code_route};
+pub use post_message::{get_message_route, post_message_route};
 pub use post_page::post_page_route;
+pub use post_page_patch::{post_page_patch_route, PatchedPage};
+pub use post_page_rollback::post_page_rollback_route;
+pub use post_page_schedule::{delete_page_schedule_route, post_page_schedule_route};
+pub use post_page_stage::{post_page_commit_route, post_page_stage_route};
+pub use post_ping::post_ping_route;
 pub use post_respond::post_respond_route;
+pub use post_response_filter::{delete_response_filter_route, post_response_filter_route};
+pub use post_response_schema::{delete_response_schema_route, post_response_schema_route};
+pub use post_results_public::{delete_results_public_route, post_results_public_route};
+pub use post_rotate_token::post_rotate_token_route;
+pub use post_viewer_token::post_viewer_token_route;
+pub use post_voting::{post_voting_close_route, post_voting_open_route};
+pub use post_webhook::{delete_webhook_route, post_webhook_route};
+
+pub use admin_audit::AuditEvents;
+pub use admin_sessions::SessionList;
+pub use admin_user_data::ErasedUserData;
+pub use get_join::JoinedUser;
+pub use get_my_sessions::{DeletedSessions, OwnedSession, OwnedSessions};
+pub use get_page_history::{PageHistory, PageVersion};
+pub use get_respond::OwnResponse;
+pub use get_responses::{
+    RetrievedResponseList, RetrievedResponses, VerboseResponse, VerboseRetrievedResponses,
+};
+pub use get_responses_aggregate::{AggregatedResponses, ResponseCount};
+pub use get_responses_batch::BatchEntry;
+pub use get_responses_stream::{StreamSummary, StreamedLine, StreamedResponse};
+pub use get_session_info::{PresenterSessionInfo, SessionInfo};
+pub use get_session_time::SessionTime;
+pub use get_stats::Stats;
+pub use post_admin_verify::VerifyResult;
+pub use post_ban::BannedUsers;
+pub use post_join_code::JoinCode;
+pub use post_rotate_token::RotatedToken;
+pub use post_viewer_token::ViewerToken;
+pub use post_webhook::WebhookSecret;
 
-#[cfg(test)]
-pub use get_responses::RetrievedResponses;
+/// Adds all routes, e.g. to a scope of an app that embeds the server. The app has to provide
+/// the [`crate::SharedState`] as `web::Data`.
+pub fn register_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_index_route)
+        .service(get_openapi_route)
+        .service(get_stats_route)
+        .service(get_page_route)
+        .service(get_script_route)
+        .service(get_static_route)
+        .service(post_page_route)
+        .service(post_page_stage_route)
+        .service(post_page_commit_route)
+        .service(post_page_schedule_route)
+        .service(delete_page_schedule_route)
+        .service(get_page_history_route)
+        .service(post_page_patch_route)
+        .service(post_page_rollback_route)
+        .service(get_responses_route)
+        .service(get_responses_batch_route)
+        .service(get_responses_aggregate_route)
+        .service(get_responses_stream_route)
+        .service(get_responses_events_route)
+        .service(get_results_route)
+        .service(get_qr_route)
+        .service(post_results_public_route)
+        .service(delete_results_public_route)
+        .service(post_respond_route)
+        .service(get_respond_route)
+        .service(get_join_route)
+        .service(post_join_code_route)
+        .service(delete_join_code_route)
+        .service(post_ban_route)
+        .service(delete_ban_route)
+        .service(get_ban_route)
+        .service(post_response_filter_route)
+        .service(delete_response_filter_route)
+        .service(post_message_route)
+        .service(get_message_route)
+        .service(post_ack_route)
+        .service(post_ping_route)
+        .service(post_asset_route)
+        .service(get_asset_route)
+        .service(post_init_session_route)
+        .service(get_export_session_route)
+        .service(post_import_session_route)
+        .service(get_wait_for_page_route)
+        .service(get_client_config_route)
+        .service(get_session_info_route)
+        .service(get_session_time_route)
+        .service(get_audience_count_route)
+        .service(get_my_sessions_route)
+        .service(delete_my_sessions_route)
+        .service(get_dashboard_route)
+        .service(post_rotate_token_route)
+        .service(post_viewer_token_route)
+        .service(post_digest_route)
+        .service(delete_digest_route)
+        .service(post_webhook_route)
+        .service(delete_webhook_route)
+        .service(post_response_schema_route)
+        .service(delete_response_schema_route)
+        .service(post_voting_open_route)
+        .service(post_voting_close_route)
+        .service(post_admin_verify_route)
+        .service(get_admin_sessions_route)
+        .service(delete_admin_session_route)
+        .service(delete_admin_user_data_route)
+        .service(get_admin_audit_route)
+        .service(get_admin_settings_route)
+        .service(patch_admin_settings_route);
+}
@@ -0,0 +1,30 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{admin::AdminAuth, audit_log::AuditEvent, errors::AppError, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct AuditParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AuditEvents {
+    /// Oldest first. Events of removed sessions are kept, until newer ones replace them.
+    pub events: Vec<AuditEvent>,
+}
+
+/// What happened to a session recently, e.g. when a presenter reports that someone else
+/// changed the page.
+#[get("/admin/audit")]
+async fn get_admin_audit_route(
+    query: web::Query<AuditParams>,
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    let events = shared_state
+        .state
+        .lock()
+        .audit_log
+        .session_events(&query.session);
+    Ok(HttpResponse::Ok().json(AuditEvents { events }))
+}
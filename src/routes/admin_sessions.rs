@@ -0,0 +1,97 @@
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+
+use crate::{admin::AdminAuth, cleanup, errors::AppError, SessionID, SharedState, UserID};
+
+#[derive(serde::Deserialize)]
+struct ListSessionsParams {
+    limit: Option<usize>,
+    start: Option<usize>,
+}
+
+/// Overview of a session without anything that grants access to it.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SessionSummary {
+    pub session: SessionID,
+    pub responses: usize,
+    pub page_bytes: usize,
+    pub last_request: DateTime<Utc>,
+    /// Same estimate that the cleanup uses to stay below the memory limit.
+    pub approx_bytes: u64,
+    pub banned_users: Vec<UserID>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SessionList {
+    pub total: usize,
+    pub sessions: Vec<SessionSummary>,
+    /// Pass this as `start` to get the next page. It's missing on the last page.
+    pub next_start: Option<usize>,
+}
+
+/// Sessions that use the most memory come first. The memory usage is counted in batches like
+/// in the cleanup, so that other requests are not blocked when there are many sessions.
+#[get("/admin/sessions")]
+async fn get_admin_sessions_route(
+    query: web::Query<ListSessionsParams>,
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    let state = &*shared_state.state;
+    let session_ids = cleanup::get_session_ids(state);
+    let mut sizes = Vec::with_capacity(session_ids.len());
+    cleanup::process_in_batches(
+        &shared_state.settings,
+        state,
+        &session_ids,
+        |state, session_id| {
+            if let Some(session) = state.sessions.get(session_id) {
+                let approx_bytes = cleanup::count_session_memory_usage(session_id, session);
+                sizes.push((approx_bytes, session_id.clone()));
+            }
+        },
+    )
+    .await;
+    let total = sizes.len();
+    sizes.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1 .0.cmp(&b.1 .0)));
+
+    let start = query.start.unwrap_or(0).min(total);
+    let end = start.saturating_add(query.limit.unwrap_or(100)).min(total);
+    let sessions = {
+        let state = state.lock();
+        sizes[start..end]
+            .iter()
+            .filter_map(|(approx_bytes, session_id)| {
+                let session = state.sessions.get(session_id)?;
+                Some(SessionSummary {
+                    session: session_id.clone(),
+                    responses: session.responses.len(),
+                    page_bytes: session.page.len(),
+                    last_request: session.last_request,
+                    approx_bytes: *approx_bytes,
+                    banned_users: session.banned_users.iter().cloned().collect(),
+                })
+            })
+            .collect()
+    };
+    let next_start = (end < total).then_some(end);
+    Ok(HttpResponse::Ok().json(SessionList {
+        total,
+        sessions,
+        next_start,
+    }))
+}
+
+#[delete("/admin/sessions/{session}")]
+async fn delete_admin_session_route(
+    path: web::Path<String>,
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    let session_id = SessionID::from_string(&path)?;
+    let mut state = shared_state.state.lock();
+    match state.delete_session(&shared_state.settings, &session_id) {
+        true => Ok("Session deleted."),
+        false => Err(AppError::SessionIDDoesNotExist),
+    }
+}
@@ -0,0 +1,48 @@
+use actix_web::{get, patch, web, HttpResponse, Responder};
+use serde_json::{Map, Value};
+
+use crate::{admin::AdminAuth, errors::AppError, request_id, settings::Tunables, SharedState};
+
+#[get("/admin/settings")]
+async fn get_admin_settings_route(
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    Ok(HttpResponse::Ok().json(shared_state.settings.tunables()))
+}
+
+/// The body contains only the settings that should change, e.g. `{"max_response_size": 8000}`.
+/// Nothing changes if any of them is invalid.
+#[patch("/admin/settings")]
+async fn patch_admin_settings_route(
+    req_body: String,
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    let changes: Map<String, Value> =
+        serde_json::from_str(&req_body).map_err(|_| AppError::InvalidSetting { setting: None })?;
+
+    let mut tunables = shared_state.settings.tunables.write();
+    let Value::Object(mut values) = serde_json::to_value(*tunables).unwrap() else {
+        return Err(AppError::ServerError);
+    };
+    let mut new_tunables = *tunables;
+    for (setting, value) in &changes {
+        let invalid_setting = || AppError::InvalidSetting {
+            setting: Some(setting.clone()),
+        };
+        if !values.contains_key(setting) {
+            return Err(invalid_setting());
+        }
+        values.insert(setting.clone(), value.clone());
+        // Deserialized after every change to know which setting is invalid.
+        new_tunables = serde_json::from_value::<Tunables>(Value::Object(values.clone()))
+            .map_err(|_| invalid_setting())?;
+    }
+
+    for change in tunables.describe_changes(&new_tunables) {
+        request_id::log(format!("Admin changed {}", change));
+    }
+    *tunables = new_tunables;
+    Ok(HttpResponse::Ok().json(new_tunables))
+}
@@ -0,0 +1,30 @@
+use actix_web::{delete, web, HttpResponse, Responder};
+
+use crate::{admin::AdminAuth, errors::AppError, request_id, SharedState, UserID};
+
+#[derive(serde::Deserialize)]
+struct UserDataParams {
+    user: UserID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ErasedUserData {
+    /// Responses, idempotency keys, bans and issued ids.
+    pub removed: usize,
+    pub sessions: usize,
+}
+
+/// Deletes everything about a user in all sessions, e.g. when a participant asks for it.
+#[delete("/admin/user_data")]
+async fn delete_admin_user_data_route(
+    query: web::Query<UserDataParams>,
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    let (removed, sessions) = shared_state.storage.erase_user_data(&query.user).await?;
+    request_id::log(format!(
+        "Erased {} items of a user in {} sessions",
+        removed, sessions
+    ));
+    Ok(HttpResponse::Ok().json(ErasedUserData { removed, sessions }))
+}
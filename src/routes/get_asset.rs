@@ -0,0 +1,61 @@
+use actix_web::{
+    get,
+    http::header::{self, CacheControl, CacheDirective, EntityTag},
+    web, HttpRequest, HttpResponse, Responder,
+};
+
+use crate::{errors::AppError, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct GetAssetParams {
+    session: SessionID,
+    name: String,
+    /// Hash of the content as returned when the asset was stored.
+    v: Option<String>,
+}
+
+/// Files that have been stored with `POST /asset`. Urls with the hash of the content can
+/// be cached forever, because a changed asset gets a new hash. Otherwise, browsers have
+/// to revalidate with the `ETag`.
+#[get("/asset")]
+async fn get_asset_route(
+    query: web::Query<GetAssetParams>,
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(state.session_not_found(&shared_state.settings, &query.session));
+    };
+    let Some(asset) = session.assets.get(&query.name) else {
+        return Err(AppError::AssetNotFound);
+    };
+    let etag = EntityTag::new_strong(asset.hash.clone());
+    let cache_control = match query.v.as_deref() == Some(asset.hash.as_str()) {
+        true => CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(365 * 24 * 60 * 60),
+            CacheDirective::Extension("immutable".to_string(), None),
+        ]),
+        false => CacheControl(vec![CacheDirective::NoCache]),
+    };
+    let is_cached = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<EntityTag>().ok())
+        .is_some_and(|cached| cached.strong_eq(&etag));
+    let mut response = match is_cached {
+        true => HttpResponse::NotModified(),
+        false => HttpResponse::Ok(),
+    };
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(cache_control);
+    Ok(match is_cached {
+        true => response.finish(),
+        false => response
+            .content_type(asset.content_type.clone())
+            .body(asset.data.clone()),
+    })
+}
@@ -0,0 +1,30 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct AudienceCountParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AudienceCount {
+    pub audience: usize,
+}
+
+/// Approximate number of audience members, see [`crate::audience`]. It's cheap, so that
+/// the presenter can poll it. Does not count as usage of the session.
+#[get("/audience_count")]
+async fn get_audience_count_route(
+    query: web::Query<AudienceCountParams>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    Ok(HttpResponse::Ok().json(AudienceCount {
+        audience: session.audience_count(settings),
+    }))
+}
@@ -0,0 +1,22 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{client_config::ClientConfig, errors::AppError, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct Params {
+    session: SessionID,
+}
+
+#[get("/client_config")]
+async fn get_client_config_route(
+    query: web::Query<Params>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let state = shared_state.state.lock();
+    match state.sessions.get(&query.session) {
+        None => Err(AppError::SessionIDDoesNotExist),
+        Some(session) => {
+            Ok(HttpResponse::Ok().json(ClientConfig::new(&shared_state.settings, session)))
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpRequest, HttpResponse, Responder,
+};
+
+use crate::{errors::AppError, links, page, static_files, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct DashboardParams {
+    session: SessionID,
+    /// The token is part of the url, so that the dashboard can be opened in a browser
+    /// directly. The viewer token works as well.
+    token: String,
+}
+
+/// Page for presenters that shows the join url and the aggregated responses, so that they
+/// don't have to build their own results page.
+#[get("/dashboard")]
+async fn get_dashboard_route(
+    query: web::Query<DashboardParams>,
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let access_token = AccessToken::from_string(&query.token)?;
+    let storage = &shared_state.storage;
+    storage
+        .check_read_access(&query.session, &access_token)
+        .await?;
+    let stored = storage.get_responses(&query.session, 0, 0).await?;
+
+    let dashboard = static_files::render(
+        settings,
+        "dashboard.html",
+        &[
+            ("root_url", &links::base_url(settings, &req)),
+            ("session", &query.session.0),
+            ("response_count", &stored.total_responses.to_string()),
+        ],
+    )?;
+    let dashboard = page::inject_script(dashboard, &page::injection_snippet(settings));
+    let mut response = HttpResponse::Ok();
+    for header in settings.security_headers.headers() {
+        response.insert_header(header);
+    }
+    // The url contains the token.
+    response.insert_header(CacheControl(vec![CacheDirective::NoStore]));
+    Ok(response.content_type("text/html").body(dashboard))
+}
@@ -0,0 +1,40 @@
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse, Responder,
+};
+
+use crate::{
+    errors::AppError, session_archive::SessionArchive, AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct ExportSessionParams {
+    session: SessionID,
+}
+
+/// Archive of the session that can be restored with `/import_session`, e.g. after a
+/// restart of the server.
+#[get("/export_session")]
+async fn get_export_session_route(
+    query: web::Query<ExportSessionParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(state.session_not_found(&shared_state.settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    let archive = SessionArchive::new(&shared_state.settings, &query.session, session);
+    drop(state);
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "session-{}.json",
+                query.session.0
+            ))],
+        })
+        .json(archive))
+}
@@ -1,10 +1,20 @@
-use actix_web::{get, HttpResponse, Responder};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 
-use crate::{errors::AppError, static_files};
+use crate::{errors::AppError, links, static_files, SharedState};
 
 #[get("/")]
-async fn get_index_route() -> Result<impl Responder, AppError> {
-    Ok(HttpResponse::Ok()
-        .content_type("text/html")
-        .body(static_files::get("index.html")))
+async fn get_index_route(
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let page = static_files::render(
+        &shared_state.settings,
+        "index.html",
+        &[("root_url", &links::base_url(&shared_state.settings, &req))],
+    )?;
+    let mut response = HttpResponse::Ok();
+    for header in shared_state.settings.security_headers.headers() {
+        response.insert_header(header);
+    }
+    Ok(response.content_type("text/html").body(page))
 }
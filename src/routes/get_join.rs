@@ -0,0 +1,64 @@
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    get, web, HttpRequest, HttpResponse, Responder,
+};
+
+use crate::{errors::AppError, SessionID, SharedState, UserID};
+
+#[derive(serde::Deserialize)]
+struct JoinParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct JoinedUser {
+    pub user: UserID,
+}
+
+/// Issues a user id for the session, see [`crate::Settings::require_server_user_ids`]. The
+/// id is also stored in a cookie, so that joining again returns the same id.
+#[get("/join")]
+async fn get_join_route(
+    query: web::Query<JoinParams>,
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let cookie_name = user_cookie_name(&query.session);
+    let previous_id = req
+        .cookie(&cookie_name)
+        .and_then(|cookie| UserID::from_string(cookie.value()).ok());
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    let user_id = match previous_id {
+        Some(user_id) if session.issued_user_ids.contains(&user_id) => user_id,
+        _ => {
+            if session.issued_user_ids.len()
+                >= shared_state.settings.tunables().max_users_per_session
+            {
+                return Err(AppError::TooManyUsers);
+            }
+            let user_id = UserID::new_random();
+            session.issued_user_ids.insert(user_id.clone());
+            state.track_memory_usage(0, user_id.0.len() as u64);
+            user_id
+        }
+    };
+    drop(state);
+
+    let cookie = Cookie::build(cookie_name, user_id.0.clone())
+        .path(shared_state.settings.cookie_path())
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+    Ok(HttpResponse::Ok()
+        .cookie(cookie)
+        .json(JoinedUser { user: user_id }))
+}
+
+/// One cookie per session, so that the audience can join multiple sessions.
+fn user_cookie_name(session_id: &SessionID) -> String {
+    format!("polli_user_{}", session_id.0)
+}
@@ -0,0 +1,66 @@
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use std::time::Instant;
+
+use crate::{errors::AppError, AccessToken, SessionID, SharedState};
+
+/// Session that belongs to the token of the request.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct OwnedSession {
+    pub session: SessionID,
+    pub last_request: DateTime<Utc>,
+    pub responses: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct OwnedSessions {
+    pub sessions: Vec<OwnedSession>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct DeletedSessions {
+    pub deleted: usize,
+}
+
+/// Sessions whose main token is the one of the request, most recently used first. It does
+/// not count as usage of the sessions.
+#[get("/my_sessions")]
+async fn get_my_sessions_route(
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let state = shared_state.state.lock();
+    let mut sessions: Vec<(Instant, OwnedSession)> = state
+        .sessions_of_token(&access_token)
+        .into_iter()
+        .map(|session_id| {
+            let session = &state.sessions[&session_id];
+            let owned = OwnedSession {
+                last_request: session.last_request,
+                responses: session.responses.len(),
+                session: session_id,
+            };
+            (session.last_used, owned)
+        })
+        .collect();
+    drop(state);
+    sessions.sort_by_key(|(last_used, _)| std::cmp::Reverse(*last_used));
+    let sessions = sessions.into_iter().map(|(_, owned)| owned).collect();
+    Ok(HttpResponse::Ok().json(OwnedSessions { sessions }))
+}
+
+/// Deletes all sessions of the token, e.g. after a conference.
+#[delete("/my_sessions")]
+async fn delete_my_sessions_route(
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let session_ids = state.sessions_of_token(&access_token);
+    for session_id in &session_ids {
+        state.delete_session(&shared_state.settings, session_id);
+    }
+    Ok(HttpResponse::Ok().json(DeletedSessions {
+        deleted: session_ids.len(),
+    }))
+}
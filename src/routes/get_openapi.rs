@@ -0,0 +1,12 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{errors::AppError, links, openapi, SharedState};
+
+#[get("/openapi.json")]
+async fn get_openapi_route(
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let base_url = links::base_url(&shared_state.settings, &req);
+    Ok(HttpResponse::Ok().json(openapi::document(&base_url)))
+}
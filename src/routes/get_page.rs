@@ -1,21 +1,83 @@
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{
+    get,
+    http::header::{self, ContentEncoding, ContentType},
+    web, HttpRequest, HttpResponse, Responder,
+};
 
-use crate::{errors::AppError, static_files, SessionID, SharedState};
+use crate::{errors::AppError, join_code, links, SessionID, SharedState};
 
 #[derive(serde::Deserialize)]
 struct Params {
-    session: String,
+    session: SessionID,
+    /// Join code of the session, if it has one and the cookie is not set.
+    code: Option<String>,
 }
 
 #[get("/page")]
 async fn get_page_route(
     query: web::Query<Params>,
     shared_state: web::Data<SharedState>,
+    req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
-    let session_id = SessionID::from_string(&query.session)?;
-    let state = shared_state.state.lock();
-    match state.sessions.get(&session_id) {
-        None => Ok(HttpResponse::NotFound().body(static_files::get("empty_session_page.html"))),
-        Some(session) => Ok(HttpResponse::Ok().body(session.page.clone())),
+    let expected_code = shared_state
+        .state
+        .lock()
+        .sessions
+        .get(&query.session)
+        .and_then(|session| session.join_code.clone());
+    join_code::check(
+        &req,
+        &query.session,
+        expected_code.as_deref(),
+        query.code.as_deref(),
+    )?;
+    let stored_page = match shared_state.storage.get_page(&query.session).await {
+        Err(AppError::SessionIDDoesNotExist) => {
+            return Err(AppError::EmptySession {
+                session: query.session.clone(),
+            })
+        }
+        result => result?,
+    };
+    let mut response = HttpResponse::Ok();
+    if let Some(code) = &expected_code {
+        response.cookie(join_code::cookie(
+            &shared_state.settings,
+            &query.session,
+            code,
+        ));
     }
+    response.insert_header(
+        links::session_page_links(&shared_state.settings, &req, &query.session).header(),
+    );
+    for header in stored_page.client_config.headers() {
+        response.insert_header(header);
+    }
+    for header in shared_state.settings.security_headers.headers() {
+        response.insert_header(header);
+    }
+    response
+        .content_type(ContentType::html())
+        .insert_header((header::VARY, "Accept-Encoding"));
+    // Compressed pages are sent as they are stored, so they don't have to be decompressed.
+    if let Some(data) = stored_page.page.gzip().filter(|_| accepts_gzip(&req)) {
+        return Ok(response
+            .insert_header(ContentEncoding::Gzip)
+            .body(data.to_vec()));
+    }
+    Ok(response.body(stored_page.page.text().into_owned()))
+}
+
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    let Some(value) = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    value.split(',').any(|encoding| {
+        let mut parts = encoding.split(';').map(str::trim);
+        parts.next() == Some("gzip") && parts.all(|param| param != "q=0" && param != "q=0.0")
+    })
 }
@@ -0,0 +1,57 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+
+use crate::{errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct PageHistoryParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PageHistory {
+    pub current_version: usize,
+    /// Newest first, starting with the current page.
+    pub versions: Vec<PageVersion>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PageVersion {
+    pub version: usize,
+    /// Time when the page was set.
+    pub time: DateTime<Utc>,
+    /// Size including the injected script.
+    pub bytes: usize,
+}
+
+/// Previous pages that can be restored with `/page_rollback`. The pages themselves are not
+/// sent, because they can be large.
+#[get("/page_history")]
+async fn get_page_history_route(
+    query: web::Query<PageHistoryParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    session.session_used(settings);
+
+    let current = PageVersion {
+        version: session.page_version,
+        time: session.page_time,
+        bytes: session.page.len(),
+    };
+    let previous = session.page_history.iter().rev().map(|entry| PageVersion {
+        version: entry.version,
+        time: entry.time,
+        bytes: entry.page.len(),
+    });
+    Ok(HttpResponse::Ok().json(PageHistory {
+        current_version: session.page_version,
+        versions: std::iter::once(current).chain(previous).collect(),
+    }))
+}
@@ -0,0 +1,27 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use qrcode::{render::svg, QrCode};
+
+use crate::{errors::AppError, links, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct QrParams {
+    session: SessionID,
+}
+
+/// QR code with the url that the audience opens to join the session. It only contains the
+/// session id, so it does not grant more access than the id itself.
+#[get("/qr")]
+async fn get_qr_route(
+    query: web::Query<QrParams>,
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let url = format!(
+        "{}/page?session={}",
+        links::base_url(&shared_state.settings, &req),
+        query.session.0
+    );
+    let code = QrCode::new(url.as_bytes()).map_err(|_| AppError::ServerError)?;
+    let image = code.render::<svg::Color>().min_dimensions(256, 256).build();
+    Ok(HttpResponse::Ok().content_type("image/svg+xml").body(image))
+}
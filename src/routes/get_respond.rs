@@ -0,0 +1,42 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, SessionID, SharedState, UserID};
+
+#[derive(serde::Deserialize)]
+struct GetRespondQueryParams {
+    session: SessionID,
+    user: UserID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct OwnResponse {
+    pub data: String,
+    pub content_type: String,
+    pub id: usize,
+    pub time: chrono::DateTime<chrono::Utc>,
+}
+
+/// Lets a reloaded page restore what the user selected before. User ids are chosen by the
+/// clients anyway, so this does not need a token. Only the response of the given user is
+/// returned.
+#[get("/respond")]
+async fn get_respond_route(
+    query: web::Query<GetRespondQueryParams>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let user_id = match shared_state.state.lock().sessions.get(&query.session) {
+        Some(session) => session.stored_user_id(&query.user),
+        None => query.user.clone(),
+    };
+    let response = shared_state
+        .storage
+        .get_user_response(&query.session, &user_id)
+        .await?
+        .ok_or(AppError::ResponseNotFound)?;
+    Ok(HttpResponse::Ok().json(OwnResponse {
+        data: response.data,
+        content_type: response.content_type,
+        id: response.id,
+        time: response.time,
+    }))
+}
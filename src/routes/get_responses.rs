@@ -1,64 +1,202 @@
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 
-use crate::{errors::AppError, SessionID, SharedState, UserID};
+use crate::{
+    encoding::Encoding,
+    errors::AppError,
+    links::Links,
+    long_poll,
+    storage::{ListedResponse, StoredResponses},
+    AccessToken, SessionID, SharedState, UserID,
+};
 
 #[derive(serde::Deserialize)]
 struct GetResponsesParams {
-    session: String,
+    session: SessionID,
     start: usize,
+    /// Maximum number of responses. The server may return fewer.
+    limit: Option<usize>,
+    /// Overrides the default long-poll duration. With `0`, it responds immediately.
+    timeout_ms: Option<u64>,
+    /// Keep waiting until there are at least this many responses since `start`, e.g. to
+    /// only show a chart once enough people voted.
+    #[serde(default = "default_min_count")]
+    min_count: usize,
+    #[serde(default)]
+    format: ResponsesFormat,
+    /// Only used by the map format.
+    #[serde(default)]
+    verbose: bool,
+}
+
+fn default_min_count() -> usize {
+    1
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ResponsesFormat {
+    #[default]
+    Map,
+    List,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct RetrievedResponses {
     pub next_start: usize,
     pub responses_by_user: HashMap<UserID, String>,
+    /// The session that was asked for.
+    pub session: String,
+    /// Number of users that have responded to the current page, including the responses
+    /// before `start`.
+    pub total_responses: usize,
+    pub server_time: DateTime<Utc>,
+}
+
+impl RetrievedResponses {
+    pub fn new(
+        session_id: &SessionID,
+        stored: StoredResponses,
+        server_time: DateTime<Utc>,
+    ) -> Self {
+        RetrievedResponses {
+            next_start: stored.next_start,
+            responses_by_user: stored
+                .responses
+                .into_iter()
+                .map(|response| (response.user, response.data))
+                .collect(),
+            session: session_id.0.clone(),
+            total_responses: stored.total_responses,
+            server_time,
+        }
+    }
+}
+
+/// With `verbose=true`, the map contains the ids as well.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct VerboseRetrievedResponses {
+    pub next_start: usize,
+    pub responses_by_user: HashMap<UserID, VerboseResponse>,
+    pub session: String,
+    pub total_responses: usize,
+    pub server_time: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct VerboseResponse {
+    pub data: String,
+    pub content_type: String,
+    pub id: usize,
+    /// See [`crate::UserResponse::revision`].
+    #[serde(default)]
+    pub revision: u32,
+}
+
+/// With `format=list`, the responses are in the order in which they arrived.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RetrievedResponseList {
+    pub next_start: usize,
+    pub responses: Vec<ListedResponse>,
+    pub session: String,
+    pub total_responses: usize,
+    pub server_time: DateTime<Utc>,
 }
 
 #[get("/responses")]
 async fn get_responses_route(
     query: web::Query<GetResponsesParams>,
     shared_state: web::Data<SharedState>,
+    access_token: Option<AccessToken>,
+    req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
-    let session_id = SessionID::from_string(&query.session)?;
+    let storage = &shared_state.storage;
+    if shared_state.settings.responses_require_auth {
+        let access_token = access_token.ok_or(AppError::BadAccessToken)?;
+        storage
+            .check_read_access(&query.session, &access_token)
+            .await?;
+    }
 
-    let (notifier, next_response_id) = {
-        let state = shared_state.state.lock();
-        match state.sessions.get(&session_id) {
-            None => return Err(AppError::SessionIDDoesNotExist),
-            Some(session) => (session.response_notifier.clone(), session.next_response_id),
-        }
+    // Long-poll if there are not enough new responses available already.
+    let timeout = shared_state.settings.long_poll_duration(
+        shared_state.settings.tunables().response_long_poll_duration,
+        query.timeout_ms,
+    );
+    let _guard = match timeout.is_zero() {
+        true => None,
+        false => Some(long_poll::start(&shared_state, &[&query.session])?),
     };
-
-    // Long-poll if there are no new responses available already.
-    if next_response_id <= query.start {
-        if !shared_state.settings.response_long_poll_duration.is_zero() {
-            // Don't wait for notifier while session the mutex is locked!
-            tokio::select! {
-                _ = notifier.notified() => {},
-                _ = tokio::time::sleep(shared_state.settings.response_long_poll_duration) => {},
-            }
-        }
-    }
-    let mut state = shared_state.state.lock();
-    match state.sessions.get_mut(&session_id) {
-        None => Err(AppError::SessionIDDoesNotExist),
-        Some(session) => {
-            session.session_used();
-            let mut response = RetrievedResponses {
-                next_start: session.next_response_id,
-                responses_by_user: HashMap::new(),
-            };
-            for (user_id, user_response) in session.responses.iter_mut() {
-                if user_response.id < query.start {
-                    user_response.was_received = true;
-                    continue;
-                }
-                response
-                    .responses_by_user
-                    .insert(user_id.clone(), user_response.data.clone());
-            }
-            Ok(HttpResponse::Ok().json(response))
-        }
+    storage
+        .wait_for_responses(&query.session, query.start, query.min_count, timeout)
+        .await?;
+    let limit = query
+        .limit
+        .unwrap_or(usize::MAX)
+        .min(shared_state.settings.max_responses_per_request);
+    let stored_responses = storage
+        .get_responses(&query.session, query.start, limit)
+        .await?;
+    let next_start = stored_responses.next_start;
+    let session = &query.session.0;
+    let links = Links::new(&shared_state.settings, &req)
+        .add(
+            "next",
+            &format!("/responses?session={}&start={}", session, next_start),
+        )
+        .add("page", &format!("/page?session={}", session))
+        .add("results", &format!("/results?session={}", session))
+        .add(
+            "export_csv",
+            &format!("/responses/stream?session={}&start=0&format=csv", session),
+        )
+        .add(
+            "export_ndjson",
+            &format!("/responses/stream?session={}&start=0", session),
+        );
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(links.header());
+    let server_time = shared_state.settings.now();
+    let session = query.session.0.clone();
+    let total_responses = stored_responses.total_responses;
+    let encoding = Encoding::negotiate(&req);
+    match (&query.format, query.verbose) {
+        (ResponsesFormat::List, _) => encoding.respond(
+            &mut builder,
+            &RetrievedResponseList {
+                next_start,
+                responses: stored_responses.responses,
+                session,
+                total_responses,
+                server_time,
+            },
+        ),
+        (ResponsesFormat::Map, false) => encoding.respond(
+            &mut builder,
+            &RetrievedResponses::new(&query.session, stored_responses, server_time),
+        ),
+        (ResponsesFormat::Map, true) => encoding.respond(
+            &mut builder,
+            &VerboseRetrievedResponses {
+                next_start,
+                responses_by_user: stored_responses
+                    .responses
+                    .into_iter()
+                    .map(|response| {
+                        let verbose = VerboseResponse {
+                            data: response.data,
+                            content_type: response.content_type,
+                            id: response.id,
+                            revision: response.revision,
+                        };
+                        (response.user, verbose)
+                    })
+                    .collect(),
+                session,
+                total_responses,
+                server_time,
+            },
+        ),
     }
 }
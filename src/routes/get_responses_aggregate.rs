@@ -0,0 +1,88 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use std::collections::HashMap;
+
+use super::get_results;
+use crate::{encoding::Encoding, errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct AggregateParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct AggregatedResponses {
+    pub session: String,
+    pub total_responses: usize,
+    /// Users that changed their response to the current page at least once.
+    pub revised_count: usize,
+    /// The choices of the page come first in their order, also when nobody chose them, so
+    /// that charts have stable axes. Other responses follow, most common first.
+    pub counts: Vec<ResponseCount>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ResponseCount {
+    pub response: String,
+    pub count: usize,
+}
+
+/// Number of users per distinct response of the current page. Does not long-poll.
+#[get("/responses/aggregate")]
+async fn get_responses_aggregate_route(
+    query: web::Query<AggregateParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: Option<AccessToken>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let storage = &shared_state.storage;
+    if shared_state.settings.responses_require_auth {
+        match access_token {
+            Some(access_token) => {
+                storage
+                    .check_read_access(&query.session, &access_token)
+                    .await?
+            }
+            // Public results are shown on `/results` without the token.
+            None if get_results::is_public(&shared_state, &query.session).unwrap_or(false) => {}
+            None => return Err(AppError::BadAccessToken),
+        }
+    }
+    let stored = storage.get_responses(&query.session, 0, usize::MAX).await?;
+
+    let mut count_by_response: HashMap<String, usize> = HashMap::new();
+    let mut revised_count = 0;
+    for response in stored.responses {
+        if response.revision > 0 {
+            revised_count += 1;
+        }
+        *count_by_response.entry(response.data).or_default() += 1;
+    }
+    let mut counts = vec![];
+    for choice in stored.choices.unwrap_or_default() {
+        let count = count_by_response.remove(&choice).unwrap_or(0);
+        counts.push(ResponseCount {
+            response: choice,
+            count,
+        });
+    }
+    let mut others: Vec<ResponseCount> = count_by_response
+        .into_iter()
+        .map(|(response, count)| ResponseCount { response, count })
+        .collect();
+    others.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.response.cmp(&b.response))
+    });
+    counts.extend(others);
+
+    Encoding::negotiate(&req).respond(
+        &mut HttpResponse::Ok(),
+        &AggregatedResponses {
+            session: query.session.0.clone(),
+            total_responses: stored.total_responses,
+            revised_count,
+            counts,
+        },
+    )
+}
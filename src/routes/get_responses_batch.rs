@@ -0,0 +1,154 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use futures_util::future::{self, FutureExt};
+use std::collections::HashMap;
+
+use crate::{
+    encoding::Encoding,
+    errors::{AppError, ErrorBody},
+    long_poll,
+    routes::RetrievedResponses,
+    AccessToken, SessionID, SharedState,
+};
+
+/// Limits the work done for a single request.
+const MAX_SESSIONS_PER_BATCH: usize = 50;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum BatchEntry {
+    Responses(RetrievedResponses),
+    Error(ErrorBody),
+}
+
+/// Responses of multiple sessions, e.g. one per breakout room, so that the presenter only
+/// needs a single long-poll. The sessions are passed as `sessions=a,b,c`. The start ids
+/// are either repeated `start` parameters in the same order or a json body like
+/// `{"a": 3, "b": 5}`. Missing start ids are `0`.
+#[get("/responses/batch")]
+async fn get_responses_batch_route(
+    query: web::Query<Vec<(String, String)>>,
+    body: String,
+    shared_state: web::Data<SharedState>,
+    access_token: Option<AccessToken>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let bad_parameter = |parameter: &str| AppError::BadQueryParameters {
+        parameter: Some(parameter.to_string()),
+    };
+    let param = |name| query_values(&query, name);
+    let sessions: Vec<&str> = param("sessions")
+        .flat_map(|value| value.split(','))
+        .filter(|session| !session.is_empty())
+        .collect();
+    if sessions.is_empty() || sessions.len() > MAX_SESSIONS_PER_BATCH {
+        return Err(bad_parameter("sessions"));
+    }
+    let starts: Vec<usize> = if body.trim().is_empty() {
+        let starts = param("start")
+            .map(|start| start.parse().map_err(|_| bad_parameter("start")))
+            .collect::<Result<Vec<usize>, AppError>>()?;
+        if starts.len() > sessions.len() {
+            return Err(bad_parameter("start"));
+        }
+        (0..sessions.len())
+            .map(|i| starts.get(i).copied().unwrap_or(0))
+            .collect()
+    } else {
+        let starts: HashMap<String, usize> =
+            serde_json::from_str(&body).map_err(|_| bad_parameter("start"))?;
+        sessions
+            .iter()
+            .map(|session| starts.get(*session).copied().unwrap_or(0))
+            .collect()
+    };
+    let timeout = shared_state.settings.long_poll_duration(
+        shared_state.settings.tunables().response_long_poll_duration,
+        param("timeout_ms")
+            .next()
+            .map(|timeout| timeout.parse().map_err(|_| bad_parameter("timeout_ms")))
+            .transpose()?,
+    );
+
+    // Unknown sessions are reported in their entry, so that the other ones still work.
+    let storage = &shared_state.storage;
+    let mut entries = HashMap::new();
+    let mut valid = vec![];
+    for (session, start) in sessions.iter().zip(starts) {
+        let result = match SessionID::from_string(session) {
+            Ok(session_id) => check_access(&shared_state, &session_id, &access_token)
+                .await
+                .map(|()| session_id),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(session_id) => valid.push((session_id, start)),
+            Err(err) => {
+                entries.insert(session.to_string(), BatchEntry::Error(err.body(None)));
+            }
+        }
+    }
+
+    // Returns as soon as any of the sessions has new responses. Sessions that don't exist
+    // return an error right away. They are reported when getting the responses below.
+    {
+        let session_ids: Vec<&SessionID> = valid.iter().map(|(session_id, _)| session_id).collect();
+        let _guard = match timeout.is_zero() {
+            true => None,
+            false => Some(long_poll::start(&shared_state, &session_ids)?),
+        };
+        let mut waits: Vec<_> = valid
+            .iter()
+            .map(|(session_id, start)| {
+                storage
+                    .wait_for_responses(session_id, *start, 1, timeout)
+                    .boxed()
+            })
+            .collect();
+        while !waits.is_empty() {
+            let (result, _, remaining) = future::select_all(waits).await;
+            if result.is_ok() {
+                break;
+            }
+            waits = remaining;
+        }
+    }
+
+    let limit = shared_state.settings.max_responses_per_request;
+    for (session_id, start) in valid {
+        let entry = match storage.get_responses(&session_id, start, limit).await {
+            Ok(stored) => BatchEntry::Responses(RetrievedResponses::new(
+                &session_id,
+                stored,
+                shared_state.settings.now(),
+            )),
+            Err(err) => BatchEntry::Error(err.body(None)),
+        };
+        entries.insert(session_id.0, entry);
+    }
+    Encoding::negotiate(&req).respond(&mut HttpResponse::Ok(), &entries)
+}
+
+async fn check_access(
+    shared_state: &SharedState,
+    session_id: &SessionID,
+    access_token: &Option<AccessToken>,
+) -> Result<(), AppError> {
+    if !shared_state.settings.responses_require_auth {
+        return Ok(());
+    }
+    let access_token = access_token.as_ref().ok_or(AppError::BadAccessToken)?;
+    shared_state
+        .storage
+        .check_read_access(session_id, access_token)
+        .await
+}
+
+fn query_values<'a>(
+    query: &'a [(String, String)],
+    name: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    query
+        .iter()
+        .filter(move |(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
@@ -0,0 +1,108 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use futures_util::stream::{self, StreamExt};
+
+use super::get_responses_stream::StreamedResponse;
+use crate::{
+    errors::AppError,
+    long_poll,
+    state::{self, PublishedResponse},
+    AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct ResponseEventsParams {
+    session: SessionID,
+}
+
+/// Server-sent events with every new or changed response, e.g. for a word cloud that adds
+/// answers as they arrive. Clients that reconnect with `Last-Event-ID` first get the
+/// responses they missed. The stream ends when the session is removed. It counts as
+/// long-poll while it is open.
+#[get("/responses/events")]
+async fn get_responses_events_route(
+    query: web::Query<ResponseEventsParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: Option<AccessToken>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    if settings.responses_require_auth {
+        let access_token = access_token.ok_or(AppError::BadAccessToken)?;
+        shared_state
+            .storage
+            .check_read_access(&query.session, &access_token)
+            .await?;
+    }
+    let last_event_id = last_event_id(&req)?;
+    let guard = long_poll::start(&shared_state, &[&query.session])?;
+
+    // Subscribing while the state is locked makes sure that no response is missed or sent
+    // twice between the missed ones and the new ones.
+    let (missed, receiver) = {
+        let state = shared_state.state.lock();
+        let Some(session) = state.sessions.get(&query.session) else {
+            return Err(state.session_not_found(settings, &query.session));
+        };
+        let mut missed: Vec<PublishedResponse> = match last_event_id {
+            None => vec![],
+            Some(last_event_id) => session
+                .responses
+                .iter()
+                .filter(|(_, response)| response.id > last_event_id)
+                .map(|(user_id, response)| PublishedResponse {
+                    user: user_id.clone(),
+                    data: response.data.clone(),
+                    id: response.id,
+                })
+                .collect(),
+        };
+        missed.sort_by_key(|response| response.id);
+        (missed, session.response_sender.subscribe())
+    };
+
+    let period = settings.event_stream_heartbeat_interval;
+    let heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    let live = stream::unfold(
+        (receiver, heartbeat, guard),
+        |(mut receiver, mut heartbeat, guard)| async move {
+            let event = tokio::select! {
+                response = state::next_published_response(&mut receiver) => {
+                    response_event(&response?)
+                }
+                _ = heartbeat.tick() => ": heartbeat\n\n".to_string(),
+            };
+            Some((event, (receiver, heartbeat, guard)))
+        },
+    );
+    let events = stream::iter(missed.iter().map(response_event).collect::<Vec<_>>())
+        .chain(live)
+        .map(|event| Ok::<_, AppError>(web::Bytes::from(event)));
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+fn response_event(response: &PublishedResponse) -> String {
+    let data = serde_json::to_string(&StreamedResponse {
+        user: response.user.clone(),
+        data: response.data.clone(),
+        id: response.id,
+    })
+    .expect("Responses can be serialized");
+    format!("id: {}\nevent: response\ndata: {}\n\n", response.id, data)
+}
+
+fn last_event_id(req: &HttpRequest) -> Result<Option<usize>, AppError> {
+    let Some(value) = req.headers().get("Last-Event-ID") else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .map(Some)
+        .ok_or(AppError::BadQueryParameters {
+            parameter: Some("Last-Event-ID".to_string()),
+        })
+}
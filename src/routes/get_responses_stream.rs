@@ -0,0 +1,123 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use futures_util::stream::{self, StreamExt};
+
+use crate::{
+    errors::AppError, storage::ListedResponse, AccessToken, SessionID, SharedState, UserID,
+};
+
+#[derive(serde::Deserialize)]
+struct StreamResponsesParams {
+    session: SessionID,
+    start: usize,
+    /// Maximum number of responses. All responses after `start` by default.
+    limit: Option<usize>,
+    #[serde(default)]
+    format: StreamFormat,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum StreamFormat {
+    #[default]
+    Ndjson,
+    /// Spreadsheet friendly export with a header row and one row per response. There is no
+    /// summary, so it's only complete without `limit`.
+    Csv,
+}
+
+/// One line of the stream. The summary is always the last line.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum StreamedLine {
+    Response(StreamedResponse),
+    Summary(StreamSummary),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StreamedResponse {
+    pub user: UserID,
+    pub data: String,
+    pub id: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StreamSummary {
+    pub next_start: usize,
+    pub total_responses: usize,
+}
+
+/// Like `/responses` with `format=list`, but as newline-delimited json, so that very many
+/// responses don't have to be serialized into one large object. The responses are copied
+/// out of the session at once and only serialized while the body is sent. It does not
+/// long-poll.
+#[get("/responses/stream")]
+async fn get_responses_stream_route(
+    query: web::Query<StreamResponsesParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: Option<AccessToken>,
+) -> Result<impl Responder, AppError> {
+    let storage = &shared_state.storage;
+    if shared_state.settings.responses_require_auth {
+        let access_token = access_token.ok_or(AppError::BadAccessToken)?;
+        storage
+            .check_read_access(&query.session, &access_token)
+            .await?;
+    }
+    let stored = storage
+        .get_responses(
+            &query.session,
+            query.start,
+            query.limit.unwrap_or(usize::MAX),
+        )
+        .await?;
+    if let StreamFormat::Csv = query.format {
+        let rows = stream::once(async { CSV_HEADER.to_string() })
+            .chain(stream::iter(stored.responses).map(|response| csv_row(&response)))
+            .map(|row| Ok::<_, AppError>(web::Bytes::from(row)));
+        return Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .streaming(rows));
+    }
+    let summary = StreamedLine::Summary(StreamSummary {
+        next_start: stored.next_start,
+        total_responses: stored.total_responses,
+    });
+    let lines = stream::iter(stored.responses)
+        .map(|response: ListedResponse| {
+            StreamedLine::Response(StreamedResponse {
+                user: response.user,
+                data: response.data,
+                id: response.id,
+            })
+        })
+        .chain(stream::once(async { summary }))
+        .map(|line| {
+            let mut data = serde_json::to_vec(&line)?;
+            data.push(b'\n');
+            Ok::<_, serde_json::Error>(web::Bytes::from(data))
+        });
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(lines))
+}
+
+const CSV_HEADER: &str = "id,user,time,content_type,data\r\n";
+
+fn csv_row(response: &ListedResponse) -> String {
+    let fields = [
+        response.id.to_string(),
+        csv_field(&response.user.0),
+        response.time.to_rfc3339(),
+        csv_field(&response.content_type),
+        csv_field(&response.data),
+    ];
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Quotes the field as in RFC 4180 if necessary.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\r', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
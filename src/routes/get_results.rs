@@ -0,0 +1,37 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, page, static_files, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct ResultsParams {
+    session: SessionID,
+}
+
+/// Read-only page with the aggregated responses, e.g. for a projector. It only works when
+/// the presenter made the results public, because the audience could see them otherwise.
+#[get("/results")]
+async fn get_results_route(
+    query: web::Query<ResultsParams>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    if !is_public(&shared_state, &query.session)? {
+        return Err(AppError::ResultsNotPublic);
+    }
+    let results = static_files::render(settings, "results.html", &[("session", &query.session.0)])?;
+    let results = page::inject_script(results, &page::injection_snippet(settings));
+    let mut response = HttpResponse::Ok();
+    for header in settings.security_headers.headers() {
+        response.insert_header(header);
+    }
+    Ok(response.content_type("text/html").body(results))
+}
+
+/// Whether the results of the session can be read without the token.
+pub fn is_public(shared_state: &SharedState, session_id: &SessionID) -> Result<bool, AppError> {
+    let state = shared_state.state.lock();
+    match state.sessions.get(session_id) {
+        None => Err(state.session_not_found(&shared_state.settings, session_id)),
+        Some(session) => Ok(session.results_public),
+    }
+}
@@ -0,0 +1,22 @@
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse, Responder,
+};
+
+use crate::{errors::AppError, static_files, SharedState};
+
+/// Script that is referenced by all pages. Pages reference it with the server version,
+/// so it can be cached for long.
+#[get("/polli_live.js")]
+async fn get_script_route(
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/javascript")
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(7 * 24 * 60 * 60),
+        ]))
+        .body(static_files::get(&shared_state.settings, "polli_live.js").into_owned()))
+}
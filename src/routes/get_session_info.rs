@@ -0,0 +1,93 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+
+use crate::{cleanup, errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct SessionInfoParams {
+    session: SessionID,
+}
+
+/// Overview of a session for client tooling. It never contains the tokens.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SessionInfo {
+    pub session: String,
+    pub created: DateTime<Utc>,
+    pub last_request: DateTime<Utc>,
+    pub responses: usize,
+    pub page_bytes: usize,
+    /// Approximate number of audience members that follow the session, see
+    /// [`crate::audience`].
+    #[serde(default)]
+    pub audience: usize,
+    /// The session expires at this time unless it is used before.
+    pub expires_at: DateTime<Utc>,
+    pub expires_in_seconds: u64,
+    pub server_time: DateTime<Utc>,
+    /// Only sent to the presenter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presenter: Option<PresenterSessionInfo>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PresenterSessionInfo {
+    /// Same estimate that the cleanup uses to stay below the memory limit.
+    pub approx_bytes: u64,
+    pub banned_users: usize,
+    pub anonymous: bool,
+    pub has_join_code: bool,
+    pub results_public: bool,
+    /// Time when the scheduled page replaces the current one.
+    pub scheduled_page_at: Option<DateTime<Utc>>,
+}
+
+/// Does not count as usage of the session. With the token of the session, it contains
+/// more details. A wrong token is rejected, so that clients notice it.
+#[get("/session_info")]
+async fn get_session_info_route(
+    query: web::Query<SessionInfoParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: Option<AccessToken>,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let now = settings.now();
+    let state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    let presenter = match access_token {
+        None => None,
+        Some(access_token) => {
+            session.check_access_token(&access_token)?;
+            Some(PresenterSessionInfo {
+                approx_bytes: cleanup::count_session_memory_usage(&query.session, session),
+                banned_users: session.banned_users.len(),
+                anonymous: session.anonymous_salt.is_some(),
+                has_join_code: session.join_code.is_some(),
+                results_public: session.results_public,
+                scheduled_page_at: session
+                    .scheduled_page
+                    .as_ref()
+                    .map(|scheduled| scheduled.at),
+            })
+        }
+    };
+    // Measured with the monotonic clock like the cleanup does, so that it stays right
+    // when the wall clock jumps.
+    let expires_in = session
+        .keep_alive_duration(settings)
+        .saturating_sub(session.unused_for(settings));
+    let expires_at = now + expires_in;
+    Ok(HttpResponse::Ok().json(SessionInfo {
+        session: query.session.0.clone(),
+        created: session.created,
+        last_request: session.last_request,
+        responses: session.responses.len(),
+        page_bytes: session.page.len(),
+        audience: session.audience_count(settings),
+        expires_at,
+        expires_in_seconds: expires_in.as_secs(),
+        server_time: now,
+        presenter,
+    }))
+}
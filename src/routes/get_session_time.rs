@@ -0,0 +1,49 @@
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse, Responder,
+};
+use chrono::{DateTime, Utc};
+
+use crate::{errors::AppError, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct SessionTimeParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SessionTime {
+    pub server_time: DateTime<Utc>,
+    pub deadline: Option<DateTime<Utc>>,
+    /// Time until the deadline, zero once it has passed. Countdowns should use this instead
+    /// of the deadline, because the clock of the client may be off.
+    pub remaining_ms: Option<u64>,
+    pub accepting_responses: bool,
+}
+
+/// Small enough to be polled by every audience member. It does not count as usage of the
+/// session.
+#[get("/session_time")]
+async fn get_session_time_route(
+    query: web::Query<SessionTimeParams>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let now = settings.now();
+    let state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    let remaining_ms = session
+        .voting_deadline
+        .map(|deadline| (deadline - now).num_milliseconds().max(0) as u64);
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(SessionTime {
+            server_time: now,
+            deadline: session.voting_deadline,
+            remaining_ms,
+            accepting_responses: session.accepting_responses(now),
+        }))
+}
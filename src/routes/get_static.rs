@@ -0,0 +1,34 @@
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse, Responder,
+};
+
+use crate::{errors::AppError, static_files, SharedState};
+
+/// Assets that custom pages can reference, e.g. shared stylesheets. They are not
+/// versioned, so they are only cached for a limited time.
+#[get("/static/{filename:.*}")]
+async fn get_static_route(
+    path: web::Path<String>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let filename = path.into_inner();
+    let Some(content) = static_files::get_bytes(&shared_state.settings, &filename) else {
+        return Err(AppError::FileNotFound);
+    };
+    let content_type = static_files::content_type(&filename);
+    let mut response = HttpResponse::Ok();
+    if content_type.starts_with("text/html") {
+        for header in shared_state.settings.security_headers.headers() {
+            response.insert_header(header);
+        }
+    }
+    Ok(response
+        .content_type(content_type)
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(60 * 60),
+        ]))
+        .body(content.into_owned()))
+}
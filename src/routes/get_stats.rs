@@ -0,0 +1,32 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, statistics, SharedState};
+
+/// Totals since the server started. See [`crate::statistics::Statistics`].
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Stats {
+    pub sessions_created: u64,
+    pub sessions_expired: u64,
+    pub sessions_evicted: u64,
+    pub sessions_evicted_for_memory: u64,
+    pub responses_received: u64,
+    pub responses_dropped: u64,
+    /// Estimated memory usage of all sessions in bytes right now.
+    pub memory_usage: u64,
+}
+
+/// Public and cheap, so that monitoring can poll it often.
+#[get("/stats")]
+async fn get_stats_route(shared_state: web::Data<SharedState>) -> Result<impl Responder, AppError> {
+    let counters = &shared_state.statistics;
+    let memory_usage = shared_state.state.lock().approx_bytes;
+    Ok(HttpResponse::Ok().json(Stats {
+        sessions_created: statistics::get(&counters.sessions_created),
+        sessions_expired: statistics::get(&counters.sessions_expired),
+        sessions_evicted: statistics::get(&counters.sessions_evicted),
+        sessions_evicted_for_memory: statistics::get(&counters.sessions_evicted_for_memory),
+        responses_received: statistics::get(&counters.responses_received),
+        responses_dropped: statistics::get(&counters.responses_dropped),
+        memory_usage,
+    }))
+}
@@ -1,29 +1,60 @@
-use actix_web::{get, web, Responder};
+use actix_web::{get, web, HttpRequest, Responder};
 
-use crate::{errors::AppError, SessionID, SharedState};
+use crate::{
+    audience::{self, AudienceGuard},
+    errors::AppError,
+    long_poll::LongPollGuard,
+    SessionID, SharedState,
+};
 
 #[derive(serde::Deserialize)]
 struct QueryParams {
-    session: String,
+    session: SessionID,
+    /// Overrides the default long-poll duration. With `0`, it responds immediately.
+    timeout_ms: Option<u64>,
+    /// Random id of the client, so that it is counted in the audience, see
+    /// [`crate::audience::client_key`].
+    client: Option<String>,
 }
 
 #[get("/wait_for_new_page")]
 async fn get_wait_for_page_route(
     query: web::Query<QueryParams>,
     shared_state: web::Data<SharedState>,
+    req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
-    let session_id = SessionID::from_string(&query.session)?;
-
-    let notifier = {
-        let state = shared_state.state.lock();
-        match state.sessions.get(&session_id) {
-            None => return Err(AppError::SessionIDDoesNotExist),
-            Some(session) => session.page_notifier.clone(),
+    let settings = &shared_state.settings;
+    let client = audience::client_key(&req, settings, query.client.as_deref())?;
+    let timeout = settings.long_poll_duration(
+        settings.tunables().page_update_long_poll_duration,
+        query.timeout_ms,
+    );
+    let (notifier, long_polls, audience) = {
+        let mut state = shared_state.state.lock();
+        let Some(session) = state.sessions.get_mut(&query.session) else {
+            return Err(AppError::SessionIDDoesNotExist);
+        };
+        // The audience waits for the next page, so the session is still in use.
+        if settings.touch_on_read {
+            session.session_used(settings);
         }
+        (
+            session.page_notifier.clone(),
+            session.long_polls.clone(),
+            session.audience.clone(),
+        )
     };
 
+    if timeout.is_zero() {
+        audience
+            .lock()
+            .seen(&client, settings.instant(), settings.audience_timeout);
+        return Ok("wait");
+    }
+    let _guard = LongPollGuard::acquire(settings, &shared_state.long_polls, [long_polls])?;
+    let _audience_guard = AudienceGuard::acquire(settings, audience, client);
     tokio::select! {
         _ = notifier.notified() => Ok("reload"),
-        _ = tokio::time::sleep(shared_state.settings.page_update_long_poll_duration) => Ok("wait")
+        _ = tokio::time::sleep(timeout) => Ok("wait")
     }
 }
@@ -0,0 +1,12 @@
+use actix_web::HttpRequest;
+
+use crate::{errors::AppError, request_id};
+
+/// Used for all paths that don't match any route, so that clients get the usual error
+/// format instead of an empty response.
+pub async fn not_found_route(req: HttpRequest) -> Result<&'static str, AppError> {
+    if cfg!(debug_assertions) {
+        request_id::log(format!("Unknown route: {} {}", req.method(), req.path()));
+    }
+    Err(AppError::NotFound)
+}
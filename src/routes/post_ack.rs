@@ -0,0 +1,32 @@
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct AckParams {
+    session: SessionID,
+    upto: usize,
+}
+
+/// Marks the responses with an id smaller than `upto` as received, so that the cleanup may
+/// free them when memory is low. With [`crate::Settings::require_explicit_ack`], this is
+/// the only way responses are marked.
+#[post("/ack")]
+async fn post_ack_route(
+    query: web::Query<AckParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    for user_response in session.responses.values_mut() {
+        if user_response.id < query.upto {
+            user_response.was_received = true;
+        }
+    }
+    session.session_used(&shared_state.settings);
+    Ok(HttpResponse::Ok().body("Responses acknowledged."))
+}
@@ -0,0 +1,38 @@
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::{
+    admin::AdminAuth,
+    errors::AppError,
+    verify::{self, Violation},
+    SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct VerifyParams {
+    session: Option<SessionID>,
+    repair: Option<bool>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct VerifyResult {
+    pub violations: Vec<Violation>,
+}
+
+#[post("/admin/verify")]
+async fn post_admin_verify_route(
+    query: web::Query<VerifyParams>,
+    shared_state: web::Data<SharedState>,
+    _admin: AdminAuth,
+) -> Result<impl Responder, AppError> {
+    let repair = query.repair.unwrap_or(false);
+    let now = shared_state.settings.now();
+    let mut state = shared_state.state.lock();
+    let violations = match &query.session {
+        None => verify::verify_state(&mut state, now, repair),
+        Some(session_id) => match state.sessions.get_mut(session_id) {
+            None => return Err(AppError::SessionIDDoesNotExist),
+            Some(session) => verify::verify_session(session_id, session, now, repair),
+        },
+    };
+    Ok(HttpResponse::Ok().json(VerifyResult { violations }))
+}
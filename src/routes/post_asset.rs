@@ -0,0 +1,58 @@
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::{
+    cleanup,
+    errors::AppError,
+    session_assets::{self, SessionAsset},
+    AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct PostAssetParams {
+    session: SessionID,
+    name: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StoredAsset {
+    /// Changes whenever the content changes, so that pages can reference
+    /// `/asset?session=<id>&name=<name>&v=<hash>` and browsers cache it for long.
+    pub hash: String,
+}
+
+/// Stores a file that pages of the session can reference, e.g. an image. An asset with
+/// the same name is replaced.
+#[post("/asset")]
+async fn post_asset_route(
+    data: web::Bytes,
+    query: web::Query<PostAssetParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    session_assets::validate_name(&query.name)?;
+    if data.len() as u64 > settings.max_asset_size.as_u64() {
+        return Err(AppError::AssetTooLarge {
+            max_size: settings.max_asset_size.as_u64(),
+        });
+    }
+    let asset = SessionAsset::new(&query.name, data.to_vec());
+    let hash = asset.hash.clone();
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    if !session.assets.contains_key(&query.name)
+        && session.assets.len() >= settings.max_assets_per_session
+    {
+        return Err(AppError::TooManyAssets);
+    }
+    session.session_used(settings);
+    let old_bytes = cleanup::count_assets_memory_usage(session);
+    session.assets.insert(query.name.clone(), asset);
+    let new_bytes = cleanup::count_assets_memory_usage(session);
+    state.track_memory_usage(old_bytes, new_bytes);
+    Ok(HttpResponse::Ok().json(StoredAsset { hash }))
+}
@@ -0,0 +1,88 @@
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+
+use crate::{
+    cleanup::{count_response_memory_usage, count_responses_capacity},
+    errors::AppError,
+    AccessToken, SessionID, SharedState, UserID,
+};
+
+#[derive(serde::Deserialize)]
+struct BanParams {
+    session: SessionID,
+    /// Same id as in `/responses`, i.e. the hashed one in anonymous sessions.
+    user: UserID,
+}
+
+#[derive(serde::Deserialize)]
+struct BanListParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BannedUsers {
+    pub users: Vec<UserID>,
+}
+
+/// Rejects further responses of the user and removes the current one. Bans are kept when
+/// the page changes.
+#[post("/ban")]
+async fn post_ban_route(
+    query: web::Query<BanParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.session_used(&shared_state.settings);
+    if !session.banned_users.insert(query.user.clone()) {
+        return Ok("User is banned already.");
+    }
+    let mut old_bytes = count_responses_capacity(&session.responses);
+    let mut new_bytes = query.user.0.len() as u64;
+    if let Some(response) = session.responses.remove(&query.user) {
+        old_bytes += count_response_memory_usage(&query.user, &response);
+        // Waiting presenters fetch the responses again without the removed one.
+        session.response_notifier.notify_waiters();
+    }
+    new_bytes += count_responses_capacity(&session.responses);
+    state.track_memory_usage(old_bytes, new_bytes);
+    Ok("User banned.")
+}
+
+#[delete("/ban")]
+async fn delete_ban_route(
+    query: web::Query<BanParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.session_used(&shared_state.settings);
+    if !session.banned_users.remove(&query.user) {
+        return Ok("User is not banned.");
+    }
+    state.track_memory_usage(query.user.0.len() as u64, 0);
+    Ok("User unbanned.")
+}
+
+#[get("/ban")]
+async fn get_ban_route(
+    query: web::Query<BanListParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    let mut users: Vec<UserID> = session.banned_users.iter().cloned().collect();
+    users.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(HttpResponse::Ok().json(BannedUsers { users }))
+}
@@ -0,0 +1,67 @@
+use actix_web::{delete, post, web, Responder};
+
+use crate::{
+    digest::{self, Digest},
+    errors::AppError,
+    webhooks, AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct EnableDigestParams {
+    session: SessionID,
+    url: String,
+    interval: String,
+}
+
+#[derive(serde::Deserialize)]
+struct DisableDigestParams {
+    session: SessionID,
+}
+
+#[post("/digest")]
+async fn post_digest_route(
+    query: web::Query<EnableDigestParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let Some(interval) = digest::parse_interval(&query.interval) else {
+        return Err(AppError::BadQueryParameters {
+            parameter: Some("interval".to_string()),
+        });
+    };
+    if !webhooks::is_valid_url(&query.url) {
+        return Err(AppError::BadQueryParameters {
+            parameter: Some("url".to_string()),
+        });
+    }
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.digest = Some(Digest::new(
+        query.url.clone(),
+        interval,
+        shared_state.settings.instant(),
+        session.next_response_id,
+    ));
+    session.session_used(&shared_state.settings);
+    Ok("Digest enabled.")
+}
+
+#[delete("/digest")]
+async fn delete_digest_route(
+    query: web::Query<DisableDigestParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.digest = None;
+    session.session_used(&shared_state.settings);
+    Ok("Digest disabled.")
+}
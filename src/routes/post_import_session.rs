@@ -0,0 +1,57 @@
+use actix_web::{post, web, HttpRequest, Responder};
+
+use crate::{
+    cleanup,
+    errors::AppError,
+    page,
+    rate_limit::{self, RateLimitKind},
+    session_archive::SessionArchive,
+    state::SetPageOptions,
+    AccessToken, SharedState,
+};
+
+/// Restores a session from an archive of `/export_session` under the token of the request.
+/// A session with the same id is replaced if it belongs to the same token.
+#[post("/import_session")]
+async fn post_import_session_route(
+    req_body: String,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    rate_limit::check_rate_limit(
+        &req,
+        settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::NewSession,
+    )?;
+    let mut archive = SessionArchive::parse(settings, &req_body)?;
+    let page = page::prepare_page(settings, std::mem::take(&mut archive.page))?;
+    let session_id = archive.session.clone();
+    let creator_ip = rate_limit::client_ip(&req, settings);
+
+    let mut state = shared_state.state.lock();
+    let session = match state.set_page(
+        settings,
+        &session_id,
+        access_token,
+        page,
+        SetPageOptions {
+            allow_create: true,
+            notify: true,
+            creator_ip,
+            keep_response_schema: false,
+        },
+    ) {
+        Ok(session) => session,
+        Err(AppError::BadAccessToken) => return Err(AppError::SessionIDTaken),
+        Err(err) => return Err(err),
+    };
+    let old_bytes = cleanup::count_session_memory_usage(&session_id, session);
+    archive.restore(settings, session);
+    session.response_notifier.notify_waiters();
+    let new_bytes = cleanup::count_session_memory_usage(&session_id, session);
+    state.track_memory_usage(old_bytes, new_bytes);
+    Ok("Session imported.")
+}
@@ -1,13 +1,69 @@
-use actix_web::{post, web, HttpResponse, Responder};
-use rand::rngs::OsRng;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use rand::seq::SliceRandom;
 use rand::Rng;
+use std::net::IpAddr;
+use std::time::Duration;
 
-use crate::{errors::AppError, static_files, SharedState};
+use crate::{
+    errors::AppError,
+    join_code,
+    links::{self, Links},
+    page,
+    rate_limit::{self, RateLimitKind},
+    response_schema::ResponseSchema,
+    settings::SessionIDStyle,
+    state::SetPageOptions,
+    static_files, words, AccessToken, SessionID, SessionState, Settings, SharedState,
+};
 
-#[derive(serde::Deserialize)]
-struct DesiredSession {
-    session: String,
-    token: String,
+/// All fields are optional. Without a body, a new random session is created.
+#[derive(serde::Deserialize, Default)]
+struct NewSessionRequest {
+    /// Desired session id that is used when a token is given as well.
+    session: Option<String>,
+    token: Option<String>,
+    /// Fail instead of falling back to a random session id if the desired one is taken.
+    #[serde(default)]
+    strict: bool,
+    style: Option<SessionIDStyle>,
+    /// Page that is used instead of the placeholder page.
+    page: Option<String>,
+    /// Per-session keep-alive duration.
+    ttl_seconds: Option<u64>,
+    /// Json schema that responses have to match, see `/response_schema`.
+    response_schema: Option<serde_json::Value>,
+    /// Store hashed user ids, see [`SessionState::anonymous_salt`].
+    #[serde(default)]
+    anonymous: bool,
+    /// Code the audience has to enter, see `/join_code`.
+    join_code: Option<String>,
+}
+
+/// Settings from the request that are applied to the new or reused session.
+struct SessionOptions {
+    /// Per-session keep-alive duration.
+    keep_alive: Option<Duration>,
+    response_schema: Option<ResponseSchema>,
+    anonymous: bool,
+    join_code: Option<String>,
+}
+
+impl SessionOptions {
+    /// Options that are not given don't change a reused session.
+    fn apply(&self, session: &mut SessionState) {
+        if self.keep_alive.is_some() {
+            session.keep_alive = self.keep_alive;
+        }
+        if self.response_schema.is_some() {
+            session.response_schema = self.response_schema.clone();
+        }
+        if self.anonymous {
+            session.make_anonymous();
+        }
+        if self.join_code.is_some() {
+            session.join_code = self.join_code.clone();
+        }
+    }
 }
 
 #[derive(serde::Serialize)]
@@ -20,66 +76,219 @@ struct InitSessionResponse {
 async fn post_init_session_route(
     req_body: String,
     shared_state: web::Data<SharedState>,
+    req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
-    let mut session_id_length = 6;
-    let mut next: DesiredSession =
-        serde_json::from_str(&req_body).unwrap_or_else(|_| DesiredSession {
-            session: make_random_session_id(session_id_length),
-            token: make_random_access_token(),
-        });
-    let retries = 5;
-    let initial_page = static_files::get("initial_session_page.html");
+    rate_limit::check_rate_limit(
+        &req,
+        &shared_state.settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::NewSession,
+    )?;
+    let request: NewSessionRequest = match req_body.trim().is_empty() {
+        true => NewSessionRequest::default(),
+        false => serde_json::from_str(&req_body).map_err(|_| AppError::BadQueryParameters {
+            parameter: Some("body".to_string()),
+        })?,
+    };
+    let custom_page = request
+        .page
+        .clone()
+        .map(|page| page::prepare_page(&shared_state.settings, page))
+        .transpose()?;
+    if let Some(code) = &request.join_code {
+        join_code::validate(code, "join_code")?;
+    }
+    if request.anonymous {
+        shared_state.storage.check_supported("anonymous")?;
+    }
+    let options = SessionOptions {
+        keep_alive: request.ttl_seconds.map(Duration::from_secs),
+        response_schema: request
+            .response_schema
+            .map(ResponseSchema::new)
+            .transpose()?,
+        anonymous: request.anonymous,
+        join_code: request.join_code,
+    };
+    let creator_ip = rate_limit::client_ip(&req, &shared_state.settings);
+    let base_url = links::base_url(&shared_state.settings, &req);
 
-    for retry_i in 0..retries {
-        // Todo, safely handle root url.
-        let url = format!(
-            "{}/page?session={}&notify=false",
-            shared_state.settings.root_url, next.session
-        );
-        let client = reqwest::Client::new();
-        match client
-            .post(url)
-            .bearer_auth(&next.token)
-            .body(initial_page)
-            .send()
-            .await
-        {
-            Err(_) => {
-                return Err(AppError::ServerError);
+    if let (Some(session), Some(token)) = (&request.session, &request.token) {
+        match use_desired_session(
+            &shared_state,
+            session,
+            token,
+            custom_page.as_ref(),
+            &options,
+            creator_ip,
+            &base_url,
+        ) {
+            Ok(()) => {
+                return Ok(make_response(
+                    &shared_state,
+                    &req,
+                    session.clone(),
+                    token.clone(),
+                ))
             }
-            Ok(res) => {
-                if res.status() == reqwest::StatusCode::OK {
-                    return Ok(HttpResponse::Ok().json(InitSessionResponse {
-                        session: next.session,
-                        token: next.token,
-                    }));
+            Err(err) => {
+                if request.strict {
+                    return Err(err);
                 }
             }
         }
+    }
+
+    let style = request
+        .style
+        .unwrap_or(shared_state.settings.session_id_style);
+    let mut session_id_length = match style {
+        SessionIDStyle::Digits => 6,
+        SessionIDStyle::Words => 2,
+    };
+    let retries = 5;
+    for retry_i in 0..retries {
+        let session = make_random_session_id(style, session_id_length);
+        let session_id = SessionID::from_string(&session)?;
+        let token = AccessToken::new_signed(
+            &shared_state.settings.token_secret,
+            &session_id,
+            shared_state.settings.now(),
+        )
+        .0;
+        let mut state = shared_state.state.lock();
+        // The signed token would take over an existing session, so only free ids are used.
+        let result = match state.sessions.contains_key(&session_id) {
+            true => Err(AppError::SessionIDTaken),
+            false => state.set_page(
+                &shared_state.settings,
+                &session_id,
+                AccessToken::from_string(&token)?,
+                match &custom_page {
+                    Some(page) => page.clone(),
+                    None => make_initial_page(&shared_state.settings, &base_url, &session_id)?,
+                },
+                SetPageOptions {
+                    allow_create: true,
+                    notify: false,
+                    creator_ip,
+                    keep_response_schema: false,
+                },
+            ),
+        };
+        match result {
+            Ok(session_state) => {
+                options.apply(session_state);
+                return Ok(make_response(&shared_state, &req, session, token));
+            }
+            // Trying other session ids does not help.
+            Err(err @ AppError::SessionLimitReached { .. }) => return Err(err),
+            Err(_) => {}
+        }
 
         if retry_i > 2 {
             // Increase session id length to increase likelyness to find one that is free.
             session_id_length += 1;
         }
-
-        next.session = make_random_session_id(session_id_length);
-        next.token = make_random_access_token();
     }
 
     Err(AppError::ServerError)
 }
 
-fn make_random_session_id(length: usize) -> String {
-    let mut rng = rand::thread_rng();
-    (0..length)
-        .map(|_| rng.gen_range(0..10).to_string())
-        .collect()
+/// Reuses the desired session if it belongs to the same token already or creates it if
+/// it is free. The page of a reused session is only changed if a new page is given.
+fn use_desired_session(
+    shared_state: &SharedState,
+    session: &str,
+    token: &str,
+    page: Option<&String>,
+    options: &SessionOptions,
+    creator_ip: Option<IpAddr>,
+    base_url: &str,
+) -> Result<(), AppError> {
+    let session_id = SessionID::from_string(session)?;
+    let access_token = AccessToken::from_string(token)?;
+
+    let mut state = shared_state.state.lock();
+    if let Some(session) = state.sessions.get_mut(&session_id) {
+        if session.access_token.constant_time_eq(&access_token) && page.is_none() {
+            session.session_used(&shared_state.settings);
+            options.apply(session);
+            return Ok(());
+        }
+    }
+    let initial_page = match page {
+        Some(page) => page.clone(),
+        None => make_initial_page(&shared_state.settings, base_url, &session_id)?,
+    };
+    match state.set_page(
+        &shared_state.settings,
+        &session_id,
+        access_token,
+        initial_page,
+        SetPageOptions {
+            allow_create: true,
+            notify: page.is_some(),
+            creator_ip,
+            keep_response_schema: false,
+        },
+    ) {
+        Ok(session) => {
+            options.apply(session);
+            Ok(())
+        }
+        Err(AppError::BadAccessToken) => Err(AppError::SessionIDTaken),
+        Err(err) => Err(err),
+    }
 }
 
-fn make_random_access_token() -> String {
-    let token_length = 32;
-    let mut rng = OsRng;
-    (0..token_length)
-        .map(|_| rng.sample(rand::distributions::Alphanumeric) as char)
-        .collect()
+/// Placeholder page until the presenter sets the first page.
+fn make_initial_page(
+    settings: &Settings,
+    base_url: &str,
+    session_id: &SessionID,
+) -> Result<String, AppError> {
+    let page = static_files::render(
+        settings,
+        "initial_session_page.html",
+        &[("session", &session_id.0), ("root_url", base_url)],
+    )?;
+    page::prepare_page(settings, page)
+}
+
+fn make_response(
+    shared_state: &SharedState,
+    req: &HttpRequest,
+    session: String,
+    token: String,
+) -> HttpResponse {
+    let links = Links::new(&shared_state.settings, req)
+        .add("page", &format!("/page?session={}", session))
+        .add(
+            "responses",
+            &format!("/responses?session={}&start=0", session),
+        )
+        .add("qr", &format!("/qr?session={}", session));
+    HttpResponse::Ok()
+        .insert_header(links.header())
+        .json(InitSessionResponse { session, token })
+}
+
+/// The length is the number of digits or words.
+fn make_random_session_id(style: SessionIDStyle, length: usize) -> String {
+    let mut rng = rand::thread_rng();
+    match style {
+        SessionIDStyle::Digits => (0..length)
+            .map(|_| rng.gen_range(0..10).to_string())
+            .collect(),
+        SessionIDStyle::Words => {
+            // E.g. "blue-tiger-42" or "calm-blue-tiger-42" when more words are needed.
+            let mut parts: Vec<String> = (1..length)
+                .map(|_| words::ADJECTIVES.choose(&mut rng).unwrap().to_string())
+                .collect();
+            parts.push(words::NOUNS.choose(&mut rng).unwrap().to_string());
+            parts.push(rng.gen_range(0..100).to_string());
+            parts.join("-")
+        }
+    }
 }
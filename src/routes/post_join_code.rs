@@ -0,0 +1,62 @@
+use actix_web::{delete, post, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, join_code, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct JoinCodeParams {
+    session: SessionID,
+    /// A random code is used if none is given.
+    code: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct DeleteJoinCodeParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct JoinCode {
+    pub join_code: String,
+}
+
+/// Sets or rotates the code that the audience needs for the page and for responding.
+/// Audience members that entered the previous code have to enter the new one.
+#[post("/join_code")]
+async fn post_join_code_route(
+    query: web::Query<JoinCodeParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let code = match &query.code {
+        Some(code) => {
+            join_code::validate(code, "code")?;
+            code.clone()
+        }
+        None => join_code::new_random(),
+    };
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.join_code = Some(code.clone());
+    session.session_used(&shared_state.settings);
+    Ok(HttpResponse::Ok().json(JoinCode { join_code: code }))
+}
+
+#[delete("/join_code")]
+async fn delete_join_code_route(
+    query: web::Query<DeleteJoinCodeParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.join_code = None;
+    session.session_used(&shared_state.settings);
+    Ok("Join code removed.")
+}
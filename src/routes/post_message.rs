@@ -0,0 +1,102 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+
+use crate::{
+    errors::AppError, long_poll, state::AudienceMessage, AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct PostMessageParams {
+    session: SessionID,
+}
+
+#[derive(serde::Deserialize)]
+struct GetMessageParams {
+    session: SessionID,
+    /// Id of the last message the client has shown already.
+    #[serde(default)]
+    since: usize,
+    /// Overrides the default long-poll duration. With `0`, it responds immediately.
+    timeout_ms: Option<u64>,
+}
+
+/// Shows a short message to the audience without changing the page, e.g. to ask whether
+/// the audio works. It replaces the previous message.
+#[post("/message")]
+async fn post_message_route(
+    text: String,
+    query: web::Query<PostMessageParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let max_size = shared_state.settings.max_message_size;
+    if text.len() > max_size {
+        return Err(AppError::MessageTooLarge { max_size });
+    }
+    let now = shared_state.settings.now();
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    let old_bytes = session
+        .message
+        .as_ref()
+        .map_or(0, |message| message.text.len());
+    let new_bytes = text.len();
+    session.last_message_id += 1;
+    let message = AudienceMessage {
+        id: session.last_message_id,
+        text,
+        time: now,
+    };
+    session.message = Some(message.clone());
+    session.session_used(&shared_state.settings);
+    session.message_notifier.notify_waiters();
+    state.track_memory_usage(old_bytes as u64, new_bytes as u64);
+    Ok(HttpResponse::Ok().json(message))
+}
+
+/// Long-polls until there is a message that is newer than `since`. Responds with `204` if
+/// there is none when the time is up.
+#[get("/message")]
+async fn get_message_route(
+    query: web::Query<GetMessageParams>,
+    shared_state: web::Data<SharedState>,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let timeout = settings.long_poll_duration(
+        settings.tunables().page_update_long_poll_duration,
+        query.timeout_ms,
+    );
+    let deadline = tokio::time::Instant::now() + timeout;
+    let _guard = match timeout.is_zero() {
+        true => None,
+        false => Some(long_poll::start(&shared_state, &[&query.session])?),
+    };
+    loop {
+        let notifier;
+        let notified;
+        {
+            let state = shared_state.state.lock();
+            let Some(session) = state.sessions.get(&query.session) else {
+                return Err(AppError::SessionIDDoesNotExist);
+            };
+            if let Some(message) = session.current_message(settings, settings.now()) {
+                if message.id > query.since {
+                    return Ok(HttpResponse::Ok().json(message));
+                }
+            }
+            notifier = session.message_notifier.clone();
+            // Created before the lock is released, so that no message is missed.
+            notified = notifier.notified();
+        }
+        if timeout.is_zero() {
+            return Ok(HttpResponse::NoContent().finish());
+        }
+        tokio::select! {
+            _ = notified => {},
+            _ = tokio::time::sleep_until(deadline) => return Ok(HttpResponse::NoContent().finish()),
+        }
+    }
+}
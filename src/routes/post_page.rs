@@ -1,57 +1,122 @@
-use actix_web::{post, web, Responder};
-use actix_web_httpauth::extractors::bearer::BearerAuth;
+use actix_web::{post, web, HttpRequest, Responder};
 use byte_unit::Byte;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
 
-use crate::{errors::AppError, static_files, AccessToken, SessionID, SessionState, SharedState};
+use super::post_voting;
+
+use crate::{
+    errors::AppError,
+    page,
+    rate_limit::{self, RateLimitKind},
+    storage::{PageChange, PageUpdate},
+    AccessToken, SessionID, SharedState,
+};
 
 #[derive(serde::Deserialize)]
 struct SetPageQueryParams {
-    session: String,
+    session: SessionID,
     notify: Option<bool>,
+    lock_first_response: Option<bool>,
+    lock_sticky: Option<bool>,
+    max_response_size: Option<u64>,
+    /// Keep the response schema of the session instead of removing it with the old page.
+    #[serde(default)]
+    keep_response_schema: bool,
+    /// Either comma separated like `A,B,C` or a json array.
+    choices: Option<String>,
+    /// Responses are rejected after this time.
+    deadline: Option<DateTime<Utc>>,
+    /// Like `deadline`, but relative to now, e.g. for the countdown of a quiz question.
+    duration_ms: Option<u64>,
+    /// Store hashed user ids from now on. Can't be turned off again.
+    #[serde(default)]
+    anonymous: bool,
+    /// Update the page even if it did not change, so that the audience reloads and the
+    /// responses are removed.
+    #[serde(default)]
+    force: bool,
+}
+
+impl SetPageQueryParams {
+    /// Pages with options always replace the current page, because the options belong to
+    /// the new page.
+    fn has_page_options(&self) -> bool {
+        self.lock_first_response.is_some()
+            || self.lock_sticky.is_some()
+            || self.max_response_size.is_some()
+            || self.choices.is_some()
+            || self.deadline.is_some()
+            || self.duration_ms.is_some()
+            || self.anonymous
+    }
 }
 
 #[post("/page")]
 async fn post_page_route(
-    mut page: String,
+    page: String,
     query: web::Query<SetPageQueryParams>,
     shared_state: web::Data<SharedState>,
-    auth: BearerAuth,
+    access_token: AccessToken,
+    req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
-    let access_token = AccessToken::from_string(auth.token())?;
-    let session_id = SessionID::from_string(&query.session)?;
+    rate_limit::check_rate_limit(
+        &req,
+        &shared_state.settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::SetPage,
+    )?;
+    let page = page::prepare_page(&shared_state.settings, page)?;
+    let choices = query.choices.as_deref().map(parse_choices).transpose()?;
+    let voting_deadline = post_voting::resolve_deadline(
+        shared_state.settings.now(),
+        query.deadline,
+        query.duration_ms.map(Duration::from_millis),
+        "duration_ms",
+    )?;
 
-    if Byte::from_u64(page.len() as u64) > shared_state.settings.max_page_size {
-        return Err(AppError::PageTooLarge);
-    }
-
-    match page.find("</head>") {
-        None => {}
-        Some(idx) => {
-            page.insert_str(idx, &static_files::get("polli_live_injection.html"));
-        }
-    }
+    let change = shared_state
+        .storage
+        .set_page(
+            &query.session,
+            access_token,
+            page,
+            PageUpdate {
+                allow_create: shared_state.settings.allow_implicit_session_creation,
+                notify: query.notify.unwrap_or(true),
+                skip_unchanged: !query.force && !query.has_page_options(),
+                lock_first_response: query.lock_first_response,
+                lock_sticky: query.lock_sticky,
+                max_response_size: query.max_response_size.map(Byte::from_u64),
+                creator_ip: rate_limit::client_ip(&req, &shared_state.settings),
+                keep_response_schema: query.keep_response_schema,
+                choices,
+                voting_deadline,
+                anonymous: query.anonymous,
+            },
+        )
+        .await?;
+    Ok(match change {
+        PageChange::Updated => "Page updated.",
+        PageChange::Unchanged => "Page unchanged.",
+    })
+}
 
-    let mut state = shared_state.state.lock();
-    match state.sessions.get_mut(&session_id) {
-        None => {
-            state
-                .sessions
-                .insert(session_id, SessionState::new(access_token, page));
-        }
-        Some(session) => {
-            if session.access_token != access_token {
-                if session.last_request + shared_state.settings.token_timeout > Utc::now() {
-                    return Err(AppError::BadAccessToken);
-                }
-                *session = SessionState::new(access_token, page);
-            } else {
-                session.update(page);
-            }
-            if query.notify.unwrap_or(true) {
-                session.page_notifier.notify_waiters();
-            }
-        }
+fn parse_choices(choices: &str) -> Result<Vec<String>, AppError> {
+    let choices: Vec<String> = match choices.trim_start().starts_with('[') {
+        true => serde_json::from_str(choices).map_err(|_| AppError::BadQueryParameters {
+            parameter: Some("choices".to_string()),
+        })?,
+        false => choices
+            .split(',')
+            .map(|choice| choice.trim().to_string())
+            .filter(|choice| !choice.is_empty())
+            .collect(),
+    };
+    if choices.is_empty() {
+        return Err(AppError::BadQueryParameters {
+            parameter: Some("choices".to_string()),
+        });
     }
-    Ok("Page updated.")
+    Ok(choices)
 }
@@ -0,0 +1,79 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    errors::AppError,
+    page,
+    page_patch::{self, PagePatch},
+    rate_limit::{self, RateLimitKind},
+    state::SetPageOptions,
+    AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct PagePatchParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PatchedPage {
+    pub version: usize,
+}
+
+/// Changes parts of the current page like `POST /page` without query parameters, so that
+/// small changes of large pages don't have to send the whole page. The patch is checked
+/// against the version under the same lock that sets the page, so concurrent patches
+/// can't overwrite each other.
+#[post("/page/patch")]
+async fn post_page_patch_route(
+    req_body: String,
+    query: web::Query<PagePatchParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    rate_limit::check_rate_limit(
+        &req,
+        settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::SetPage,
+    )?;
+    let patch: PagePatch =
+        serde_json::from_str(&req_body).map_err(|err| AppError::InvalidPatch {
+            message: err.to_string(),
+        })?;
+    let creator_ip = rate_limit::client_ip(&req, settings);
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    if session.page_version != patch.base_version {
+        return Err(AppError::PageVersionConflict {
+            current_version: session.page_version,
+        });
+    }
+    let page = page_patch::apply_edits(
+        &page::original_page(settings, &session.page.text()),
+        &patch.edits,
+    )?;
+    let page = page::prepare_page(settings, page)?;
+    let session = state.set_page(
+        settings,
+        &query.session,
+        access_token,
+        page,
+        SetPageOptions {
+            allow_create: false,
+            notify: true,
+            creator_ip,
+            keep_response_schema: false,
+        },
+    )?;
+    session.choices = None;
+    session.voting_deadline = None;
+    Ok(HttpResponse::Ok().json(PatchedPage {
+        version: session.page_version,
+    }))
+}
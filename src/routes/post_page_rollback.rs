@@ -0,0 +1,62 @@
+use actix_web::{post, web, HttpRequest, Responder};
+
+use crate::{
+    errors::AppError, rate_limit, storage::PageUpdate, AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct PageRollbackParams {
+    session: SessionID,
+    version: usize,
+}
+
+/// Sets a previous page again like `POST /page` without query parameters, so the audience
+/// reloads and the responses to the current page are removed. The restored page gets a new
+/// version and the current page is added to the history, so the rollback can be undone.
+#[post("/page_rollback")]
+async fn post_page_rollback_route(
+    query: web::Query<PageRollbackParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let page = {
+        let state = shared_state.state.lock();
+        let Some(session) = state.sessions.get(&query.session) else {
+            return Err(state.session_not_found(&shared_state.settings, &query.session));
+        };
+        session.check_access_token(&access_token)?;
+        let Some(entry) = session
+            .page_history
+            .iter()
+            .find(|entry| entry.version == query.version)
+        else {
+            return Err(AppError::PageVersionNotFound);
+        };
+        // The page has been prepared already when it was set the first time.
+        entry.page.text().into_owned()
+    };
+
+    shared_state
+        .storage
+        .set_page(
+            &query.session,
+            access_token,
+            page,
+            PageUpdate {
+                allow_create: false,
+                notify: true,
+                skip_unchanged: false,
+                lock_first_response: None,
+                lock_sticky: None,
+                max_response_size: None,
+                creator_ip: rate_limit::client_ip(&req, &shared_state.settings),
+                keep_response_schema: false,
+                choices: None,
+                voting_deadline: None,
+                anonymous: false,
+            },
+        )
+        .await?;
+    Ok("Page rolled back.")
+}
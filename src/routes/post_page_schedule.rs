@@ -0,0 +1,91 @@
+use actix_web::{delete, post, web, HttpRequest, Responder};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+use super::post_voting;
+
+use crate::{
+    cleanup,
+    errors::AppError,
+    page,
+    rate_limit::{self, RateLimitKind},
+    scheduled_page::ScheduledPage,
+    AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct SchedulePageParams {
+    session: SessionID,
+    at: DateTime<Utc>,
+    /// Responses to the scheduled page are rejected after this time.
+    deadline: Option<DateTime<Utc>>,
+    /// Like `deadline`, but relative to the time of the switch.
+    duration_ms: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+struct CancelScheduleParams {
+    session: SessionID,
+}
+
+/// Sets the page at the given time for everyone at once. A time in the past switches the
+/// page right away. It replaces a previously scheduled page.
+#[post("/page/schedule")]
+async fn post_page_schedule_route(
+    page: String,
+    query: web::Query<SchedulePageParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    rate_limit::check_rate_limit(
+        &req,
+        settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::SetPage,
+    )?;
+    let page = page::prepare_page(settings, page)?;
+    let voting_deadline = post_voting::resolve_deadline(
+        query.at,
+        query.deadline,
+        query.duration_ms.map(Duration::from_millis),
+        "duration_ms",
+    )?;
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    let old_bytes = cleanup::count_session_memory_usage(&query.session, session);
+    session.scheduled_page = Some(ScheduledPage {
+        page,
+        at: query.at,
+        voting_deadline,
+    });
+    session.session_used(settings);
+    let new_bytes = cleanup::count_session_memory_usage(&query.session, session);
+    state.track_memory_usage(old_bytes, new_bytes);
+    Ok("Page scheduled.")
+}
+
+#[delete("/page/schedule")]
+async fn delete_page_schedule_route(
+    query: web::Query<CancelScheduleParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    let old_bytes = cleanup::count_session_memory_usage(&query.session, session);
+    session.scheduled_page = None;
+    session.session_used(settings);
+    let new_bytes = cleanup::count_session_memory_usage(&query.session, session);
+    state.track_memory_usage(old_bytes, new_bytes);
+    Ok("Scheduled page removed.")
+}
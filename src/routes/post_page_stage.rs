@@ -0,0 +1,82 @@
+use actix_web::{post, web, HttpRequest, Responder};
+
+use crate::{
+    cleanup,
+    errors::AppError,
+    page,
+    rate_limit::{self, RateLimitKind},
+    state::SetPageOptions,
+    AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct StagePageParams {
+    session: SessionID,
+}
+
+/// Stores the page without changing what the audience sees, e.g. while a tool uploads a
+/// presentation in several steps. It replaces a previously staged page.
+#[post("/page/stage")]
+async fn post_page_stage_route(
+    page: String,
+    query: web::Query<StagePageParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    rate_limit::check_rate_limit(
+        &req,
+        settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::SetPage,
+    )?;
+    let page = page::prepare_page(settings, page)?;
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    let old_bytes = cleanup::count_session_memory_usage(&query.session, session);
+    session.staged_page = Some(page);
+    session.session_used(settings);
+    let new_bytes = cleanup::count_session_memory_usage(&query.session, session);
+    state.track_memory_usage(old_bytes, new_bytes);
+    Ok("Page staged.")
+}
+
+/// Replaces the page with the staged one like `POST /page` without query parameters. This
+/// happens while the state is locked, so the audience either gets the old or the new page.
+#[post("/page/commit")]
+async fn post_page_commit_route(
+    query: web::Query<StagePageParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.check_access_token(&access_token)?;
+    let Some(page) = session.staged_page.take() else {
+        return Err(AppError::StagedPageNotFound);
+    };
+    // Setting the page tracks the memory of the new page.
+    state.track_memory_usage(page.len() as u64, 0);
+    state.set_page(
+        settings,
+        &query.session,
+        access_token,
+        page,
+        SetPageOptions {
+            allow_create: false,
+            notify: true,
+            creator_ip: rate_limit::client_ip(&req, settings),
+            keep_response_schema: false,
+        },
+    )?;
+    Ok("Page updated.")
+}
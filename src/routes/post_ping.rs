@@ -0,0 +1,44 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    audience,
+    errors::AppError,
+    rate_limit::{self, RateLimitKind},
+    SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct PingParams {
+    session: SessionID,
+    /// See [`crate::audience::client_key`].
+    client: Option<String>,
+}
+
+/// Keeps the session alive while the audience still has the page open, even when the
+/// presenter is not active. It does not need a token, so it only counts as usage and
+/// is rate-limited more heavily than other routes.
+#[post("/ping")]
+async fn post_ping_route(
+    query: web::Query<PingParams>,
+    shared_state: web::Data<SharedState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let settings = &shared_state.settings;
+    rate_limit::check_rate_limit(
+        &req,
+        settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::Ping,
+    )?;
+    let client = audience::client_key(&req, settings, query.client.as_deref())?;
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(state.session_not_found(settings, &query.session));
+    };
+    session.session_used(settings);
+    session
+        .audience
+        .lock()
+        .seen(&client, settings.instant(), settings.audience_timeout);
+    Ok(HttpResponse::Ok().body("Pong."))
+}
@@ -1,48 +1,216 @@
-use actix_web::{post, web, HttpResponse, Responder};
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse, Responder};
 use byte_unit::Byte;
-use chrono::Utc;
 
-use crate::{errors::AppError, SessionID, SharedState, UserID, UserResponse};
+use crate::{
+    encoding::Encoding,
+    errors::AppError,
+    join_code,
+    rate_limit::{self, RateLimitKind},
+    response_filter::ResponseFilter,
+    state::ResponseMetadata,
+    statistics,
+    storage::{ResponseConditions, DEFAULT_CONTENT_TYPE},
+    SessionID, Settings, SharedState, UserID,
+};
+
+/// Keys are generated by the clients, e.g. a uuid per submission.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+
+/// Longer user agents are cut off, so that they don't use much memory.
+const MAX_USER_AGENT_LENGTH: usize = 256;
 
 #[derive(serde::Deserialize)]
 struct RespondQueryParams {
-    session: String,
-    user: String,
+    session: SessionID,
+    user: UserID,
+    /// Same as the `If-Match` header, for clients that can't set headers.
+    prev_id: Option<usize>,
+    /// Join code of the session, if it has one and the cookie is not set.
+    code: Option<String>,
+    /// Version of the page that the user responds to, see
+    /// [`crate::Settings::collect_response_metadata`].
+    page_version: Option<usize>,
 }
 
 #[post("/respond")]
 async fn post_respond_route(
-    response_data: String,
+    body: web::Bytes,
     query: web::Query<RespondQueryParams>,
     shared_state: web::Data<SharedState>,
+    req: HttpRequest,
 ) -> Result<impl Responder, AppError> {
-    let session_id = SessionID::from_string(&query.session)?;
-    let user_id = UserID::from_string(&query.user)?;
-
-    if Byte::from_u64(response_data.len() as u64) > shared_state.settings.max_response_size {
+    rate_limit::check_rate_limit(
+        &req,
+        &shared_state.settings,
+        &shared_state.rate_limiter,
+        RateLimitKind::Respond,
+    )?;
+    if query.user.0.len() > shared_state.settings.max_user_id_length {
+        return Err(AppError::BadUserID);
+    }
+    let (response_data, content_type) = decode_response(&body, request_media_type(&req))?;
+    if Byte::from_u64(response_data.len() as u64)
+        > shared_state.settings.tunables().max_response_size
+    {
         return Err(AppError::ResponseTooLarge);
     }
-
-    let mut state = shared_state.state.lock();
-    match state.sessions.get_mut(&session_id) {
-        None => Err(AppError::SessionIDDoesNotExist),
+    check_content_type(&content_type, &shared_state.settings)?;
+    let mut filters: Vec<ResponseFilter> = shared_state
+        .settings
+        .response_filter()
+        .into_iter()
+        .collect();
+    let (schema, user_id, metadata) = match shared_state.state.lock().sessions.get(&query.session) {
         Some(session) => {
-            let response_id = session.next_response_id;
-            session.next_response_id += 1;
-
-            session.responses.insert(
-                user_id,
-                UserResponse {
-                    data: response_data,
-                    id: response_id,
-                    was_received: false,
-                    time: Utc::now(),
-                },
-            );
-            session.session_used();
-            session.response_notifier.notify_waiters();
-
-            Ok(HttpResponse::Ok().body("Response updated."))
+            join_code::check(
+                &req,
+                &query.session,
+                session.join_code.as_deref(),
+                query.code.as_deref(),
+            )?;
+            if shared_state.settings.require_server_user_ids
+                && !session.issued_user_ids.contains(&query.user)
+            {
+                return Err(AppError::UnknownUserID);
+            }
+            // Only free-text responses are filtered, the choices are set by the presenter.
+            match session.choices {
+                Some(_) => filters.clear(),
+                None => filters.extend(session.response_filter.clone()),
+            }
+            let metadata = shared_state.settings.collect_response_metadata.then(|| {
+                let page_version = query.page_version.unwrap_or(session.page_version);
+                ResponseMetadata {
+                    page_version,
+                    page_time: session.page_time_of(page_version),
+                    user_agent: user_agent(&req),
+                }
+            });
+            (
+                session.response_schema.clone(),
+                session.stored_user_id(&query.user),
+                metadata,
+            )
+        }
+        // Sessions that are not in memory don't have issued ids.
+        None if shared_state.settings.require_server_user_ids => {
+            return Err(AppError::UnknownUserID)
         }
+        None => (None, query.user.clone(), None),
+    };
+    // Filtered and validated without holding the lock.
+    let mut response_data = response_data;
+    for filter in filters {
+        response_data = filter.apply(response_data)?;
+    }
+    if let Some(schema) = schema {
+        schema.validate(&response_data)?;
+    }
+    let conditions = ResponseConditions {
+        if_match: if_match(&req)?.or(query.prev_id),
+        idempotency_key: idempotency_key(&req)?,
+        metadata,
+    };
+
+    let response_id = shared_state
+        .storage
+        .insert_response(
+            &query.session,
+            &user_id,
+            response_data,
+            content_type,
+            conditions,
+        )
+        .await?;
+    statistics::add(&shared_state.statistics.responses_received, 1);
+    Ok(HttpResponse::Ok()
+        .insert_header(header::ETag(header::EntityTag::new_strong(
+            response_id.to_string(),
+        )))
+        .body("Response updated."))
+}
+
+/// Media type of the request without parameters. Responses without `Content-Type` are
+/// plain text.
+fn request_media_type(req: &HttpRequest) -> String {
+    match req.headers().get(header::CONTENT_TYPE) {
+        None => DEFAULT_CONTENT_TYPE.to_string(),
+        Some(value) => value
+            .to_str()
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+    }
+}
+
+/// MessagePack and CBOR are stored as json, so they are accepted whenever json is. Other
+/// responses are stored as they are.
+fn decode_response(body: &[u8], media_type: String) -> Result<(String, String), AppError> {
+    match Encoding::from_media_type(&media_type) {
+        Some(encoding @ (Encoding::MessagePack | Encoding::Cbor)) => {
+            let value: serde_json::Value = encoding
+                .decode(body)
+                .map_err(|message| AppError::InvalidResponse { message })?;
+            Ok((value.to_string(), Encoding::Json.media_type().to_string()))
+        }
+        _ => match std::str::from_utf8(body) {
+            Ok(data) => Ok((data.to_string(), media_type)),
+            Err(_) => Err(AppError::InvalidResponse {
+                message: "The response is not valid utf-8.".to_string(),
+            }),
+        },
+    }
+}
+
+fn check_content_type(content_type: &str, settings: &Settings) -> Result<(), AppError> {
+    let allowed = settings
+        .allowed_response_content_types
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(content_type));
+    if !allowed {
+        return Err(AppError::UnsupportedMediaType {
+            content_type: content_type.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The id of the response the client has seen last, as returned in the `ETag` header.
+fn if_match(req: &HttpRequest) -> Result<Option<usize>, AppError> {
+    let Some(value) = req.headers().get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let bad_header = || AppError::BadQueryParameters {
+        parameter: Some("If-Match".to_string()),
+    };
+    let value = value.to_str().map_err(|_| bad_header())?.trim();
+    let value = value.strip_prefix("W/").unwrap_or(value);
+    let value = value.trim_matches('"');
+    value.parse().map(Some).map_err(|_| bad_header())
+}
+
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, AppError> {
+    let Some(value) = req.headers().get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(AppError::BadQueryParameters {
+            parameter: Some("Idempotency-Key".to_string()),
+        }),
+    }
+}
+
+fn user_agent(req: &HttpRequest) -> Option<String> {
+    let user_agent = req.headers().get(header::USER_AGENT)?.to_str().ok()?;
+    let mut end = user_agent.len().min(MAX_USER_AGENT_LENGTH);
+    while !user_agent.is_char_boundary(end) {
+        end -= 1;
     }
+    Some(user_agent[..end].to_string())
 }
@@ -0,0 +1,57 @@
+use actix_web::{delete, post, web, Responder};
+
+use crate::{
+    errors::AppError,
+    response_filter::{FilterMode, ResponseFilter},
+    AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct ResponseFilterParams {
+    session: SessionID,
+    #[serde(default)]
+    mode: FilterMode,
+}
+
+#[derive(serde::Deserialize)]
+struct DeleteResponseFilterParams {
+    session: SessionID,
+}
+
+/// The body contains the words and regexes that are filtered, one per line. The filter
+/// applies in addition to the one of the server and is kept when the page changes.
+#[post("/response_filter")]
+async fn post_response_filter_route(
+    patterns: String,
+    query: web::Query<ResponseFilterParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let filter = ResponseFilter::new(&patterns, query.mode)
+        .map_err(|message| AppError::InvalidResponseFilter { message })?;
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.response_filter = Some(filter);
+    session.session_used(&shared_state.settings);
+    Ok("Response filter set.")
+}
+
+#[delete("/response_filter")]
+async fn delete_response_filter_route(
+    query: web::Query<DeleteResponseFilterParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.response_filter = None;
+    session.session_used(&shared_state.settings);
+    Ok("Response filter removed.")
+}
@@ -0,0 +1,47 @@
+use actix_web::{delete, post, web, Responder};
+
+use crate::{
+    errors::AppError, response_schema::ResponseSchema, AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct ResponseSchemaParams {
+    session: SessionID,
+}
+
+/// The body is a json schema that all following responses have to match. It is removed
+/// when the page changes, unless the page is set with `keep_response_schema=true`.
+#[post("/response_schema")]
+async fn post_response_schema_route(
+    schema: String,
+    query: web::Query<ResponseSchemaParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let schema: ResponseSchema = schema.parse()?;
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.response_schema = Some(schema);
+    session.session_used(&shared_state.settings);
+    Ok("Response schema set.")
+}
+
+#[delete("/response_schema")]
+async fn delete_response_schema_route(
+    query: web::Query<ResponseSchemaParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.response_schema = None;
+    session.session_used(&shared_state.settings);
+    Ok("Response schema removed.")
+}
@@ -0,0 +1,45 @@
+use actix_web::{delete, post, web, Responder};
+
+use crate::{errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct ResultsPublicParams {
+    session: SessionID,
+}
+
+/// Allows everyone to see the aggregated responses on `/results`, without the token.
+#[post("/results/public")]
+async fn post_results_public_route(
+    query: web::Query<ResultsPublicParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    set_results_public(&query.session, &shared_state, &access_token, true)?;
+    Ok("Results are public.")
+}
+
+#[delete("/results/public")]
+async fn delete_results_public_route(
+    query: web::Query<ResultsPublicParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    set_results_public(&query.session, &shared_state, &access_token, false)?;
+    Ok("Results are private.")
+}
+
+fn set_results_public(
+    session_id: &SessionID,
+    shared_state: &SharedState,
+    access_token: &AccessToken,
+    public: bool,
+) -> Result<(), AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(session_id) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(access_token)?;
+    session.results_public = public;
+    session.session_used(&shared_state.settings);
+    Ok(())
+}
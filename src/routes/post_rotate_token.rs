@@ -0,0 +1,56 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    audit_log::AuditEventKind, errors::AppError, rate_limit, AccessToken, SessionID, SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct RotateTokenParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RotatedToken {
+    pub token: String,
+}
+
+/// Replaces the access token of a session, e.g. when it has been leaked. The new token is
+/// signed, so that it survives restarts of the server like the ones created by `/new`.
+#[post("/rotate_token")]
+async fn post_rotate_token_route(
+    query: web::Query<RotateTokenParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+    req: HttpRequest,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    // The new token has to be issued later than the current one, so that the current one
+    // is not accepted as signed token anymore.
+    let mut issued_at = shared_state.settings.now();
+    if let Some(previous) = session.token_issued_at {
+        issued_at = issued_at.max(previous + chrono::Duration::milliseconds(1));
+    }
+    let new_token = AccessToken::new_signed(
+        &shared_state.settings.token_secret,
+        &query.session,
+        issued_at,
+    );
+    let old_token = std::mem::replace(&mut session.access_token, new_token.clone());
+    session.token_issued_at = Some(issued_at);
+    session.session_used(&shared_state.settings);
+    state.replace_token(&query.session, &old_token, &new_token);
+    state.track_memory_usage(old_token.0.len() as u64, new_token.0.len() as u64);
+    state.audit_log.record(
+        &shared_state.settings,
+        &query.session,
+        shared_state.settings.now(),
+        AuditEventKind::TokenRotated {
+            ip: rate_limit::client_ip(&req, &shared_state.settings),
+        },
+    );
+    Ok(HttpResponse::Ok().json(RotatedToken { token: new_token.0 }))
+}
@@ -0,0 +1,39 @@
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::{errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct ViewerTokenParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ViewerToken {
+    pub token: String,
+}
+
+/// Creates a token that can only be used to read responses. A previous viewer token is
+/// replaced.
+#[post("/viewer_token")]
+async fn post_viewer_token_route(
+    query: web::Query<ViewerTokenParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    let viewer_token = AccessToken::new_random();
+    let old_bytes = session
+        .viewer_token
+        .as_ref()
+        .map_or(0, |token| token.0.len());
+    session.viewer_token = Some(viewer_token.clone());
+    session.session_used(&shared_state.settings);
+    state.track_memory_usage(old_bytes as u64, viewer_token.0.len() as u64);
+    Ok(HttpResponse::Ok().json(ViewerToken {
+        token: viewer_token.0,
+    }))
+}
@@ -0,0 +1,91 @@
+use actix_web::{post, web, Responder};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+use crate::{errors::AppError, AccessToken, SessionID, SharedState};
+
+#[derive(serde::Deserialize)]
+struct OpenVotingParams {
+    session: SessionID,
+}
+
+#[derive(serde::Deserialize)]
+struct CloseVotingParams {
+    session: SessionID,
+    /// Close at this time instead of right away.
+    deadline: Option<DateTime<Utc>>,
+    /// Close after this many seconds, e.g. to announce that voting ends in 30 seconds.
+    after_seconds: Option<u64>,
+}
+
+/// Accepts responses again, also after the deadline.
+#[post("/voting/open")]
+async fn post_voting_open_route(
+    query: web::Query<OpenVotingParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.voting_closed = false;
+    session.voting_deadline = None;
+    session.session_used(&shared_state.settings);
+    Ok("Voting opened.")
+}
+
+/// Rejects responses until voting is opened again or the page changes.
+#[post("/voting/close")]
+async fn post_voting_close_route(
+    query: web::Query<CloseVotingParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let now = shared_state.settings.now();
+    let deadline = resolve_deadline(
+        now,
+        query.deadline,
+        query.after_seconds.map(Duration::from_secs),
+        "after_seconds",
+    )?;
+
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    match deadline {
+        None => session.voting_closed = true,
+        Some(deadline) => session.voting_deadline = Some(deadline),
+    }
+    session.session_used(&shared_state.settings);
+    Ok(match deadline {
+        None => "Voting closed.",
+        Some(_) => "Voting deadline set.",
+    })
+}
+
+/// Either the absolute deadline or one that is the given duration after `start`. Passing
+/// both is rejected as an error of the parameter with the duration.
+pub fn resolve_deadline(
+    start: DateTime<Utc>,
+    deadline: Option<DateTime<Utc>>,
+    after: Option<Duration>,
+    after_parameter: &str,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    let bad_parameter = || AppError::BadQueryParameters {
+        parameter: Some(after_parameter.to_string()),
+    };
+    match (deadline, after) {
+        (Some(_), Some(_)) => Err(bad_parameter()),
+        (Some(deadline), None) => Ok(Some(deadline)),
+        (None, Some(after)) => chrono::Duration::from_std(after)
+            .ok()
+            .and_then(|after| start.checked_add_signed(after))
+            .map(Some)
+            .ok_or_else(bad_parameter),
+        (None, None) => Ok(None),
+    }
+}
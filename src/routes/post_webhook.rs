@@ -0,0 +1,73 @@
+use actix_web::{delete, post, web, HttpResponse, Responder};
+
+use crate::{
+    errors::AppError, response_webhook::ResponseWebhook, webhooks, AccessToken, SessionID,
+    SharedState,
+};
+
+#[derive(serde::Deserialize)]
+struct EnableWebhookParams {
+    session: SessionID,
+    url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct DisableWebhookParams {
+    session: SessionID,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct WebhookSecret {
+    pub secret: String,
+}
+
+/// Replaces the previous webhook of the session. Every call returns a new secret.
+#[post("/webhook")]
+async fn post_webhook_route(
+    query: web::Query<EnableWebhookParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let bad_url = || AppError::BadQueryParameters {
+        parameter: Some("url".to_string()),
+    };
+    if !webhooks::is_valid_url(&query.url) {
+        return Err(bad_url());
+    }
+    if !shared_state.settings.allow_private_webhook_urls {
+        // Checked again before every delivery, because the address of the host may change.
+        webhooks::check_public_url(&query.url)
+            .await
+            .map_err(|_| bad_url())?;
+    }
+
+    let secret = AccessToken::new_random().0;
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.webhook = Some(ResponseWebhook::new(
+        query.url.clone(),
+        secret.clone(),
+        session.next_response_id,
+    ));
+    session.session_used(&shared_state.settings);
+    Ok(HttpResponse::Ok().json(WebhookSecret { secret }))
+}
+
+#[delete("/webhook")]
+async fn delete_webhook_route(
+    query: web::Query<DisableWebhookParams>,
+    shared_state: web::Data<SharedState>,
+    access_token: AccessToken,
+) -> Result<impl Responder, AppError> {
+    let mut state = shared_state.state.lock();
+    let Some(session) = state.sessions.get_mut(&query.session) else {
+        return Err(AppError::SessionIDDoesNotExist);
+    };
+    session.check_access_token(&access_token)?;
+    session.webhook = None;
+    session.session_used(&shared_state.settings);
+    Ok("Webhook disabled.")
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use crate::{
+    audit_log::{AuditEventKind, PageAccess},
+    cleanup, Settings, State,
+};
+
+/// Page that replaces the current one at a fixed time, e.g. the next question of a timed
+/// quiz. Everyone in the audience reloads at the same time then.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ScheduledPage {
+    /// Prepared like pages of `POST /page`.
+    pub page: String,
+    pub at: DateTime<Utc>,
+    /// Replaces the voting deadline of the session when the page is switched.
+    pub voting_deadline: Option<DateTime<Utc>>,
+}
+
+/// Sets the scheduled pages that are due like `POST /page` without query parameters.
+/// Returns the number of sessions whose page has been switched.
+pub fn switch_due_pages(settings: &Settings, state: &mut State, now: DateTime<Utc>) -> usize {
+    let mut switched = 0;
+    let mut old_bytes = 0;
+    let mut new_bytes = 0;
+    for (session_id, session) in state.sessions.iter_mut() {
+        if session
+            .scheduled_page
+            .as_ref()
+            .is_none_or(|scheduled| scheduled.at > now)
+        {
+            continue;
+        }
+        old_bytes += cleanup::count_session_memory_usage(session_id, session);
+        let scheduled = session.scheduled_page.take().unwrap();
+        session.update(settings, scheduled.page, now, false);
+        session.voting_deadline = scheduled.voting_deadline;
+        session.notify_page(settings);
+        state.audit_log.record(
+            settings,
+            session_id,
+            now,
+            AuditEventKind::PageUpdated {
+                ip: None,
+                access: PageAccess::Schedule,
+            },
+        );
+        new_bytes += cleanup::count_session_memory_usage(session_id, session);
+        switched += 1;
+    }
+    state.track_memory_usage(old_bytes, new_bytes);
+    switched
+}
+
+pub async fn do_periodic_page_switches(settings: Settings, state: Arc<Mutex<State>>) {
+    let mut interval = tokio::time::interval(settings.page_schedule_check_interval);
+    loop {
+        interval.tick().await;
+        switch_due_pages(&settings, &mut state.lock(), settings.now());
+    }
+}
@@ -0,0 +1,53 @@
+/// Value of the `X-Frame-Options` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum FrameOptions {
+    Deny,
+    SameOrigin,
+}
+
+/// Headers that are added to html responses, but not to the json api. Presenter pages are
+/// arbitrary html, so the defaults don't restrict what pages can load or where they can be
+/// embedded.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    /// Some presenters rely on embedding external media, which may break with any of the
+    /// headers, so they can all be disabled.
+    pub enabled: bool,
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<FrameOptions>,
+    pub referrer_policy: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders {
+            enabled: true,
+            content_security_policy: None,
+            frame_options: None,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        if !self.enabled {
+            return vec![];
+        }
+        let mut headers = vec![
+            ("X-Content-Type-Options", "nosniff".to_string()),
+            ("Referrer-Policy", self.referrer_policy.clone()),
+        ];
+        if let Some(policy) = &self.content_security_policy {
+            headers.push(("Content-Security-Policy", policy.clone()));
+        }
+        if let Some(frame_options) = self.frame_options {
+            let value = match frame_options {
+                FrameOptions::Deny => "DENY",
+                FrameOptions::SameOrigin => "SAMEORIGIN",
+            };
+            headers.push(("X-Frame-Options", value.to_string()));
+        }
+        headers
+    }
+}
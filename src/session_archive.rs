@@ -0,0 +1,214 @@
+use byte_unit::Byte;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::{
+    clock, join_code, page, response_filter::ResponseFilter, response_schema::ResponseSchema,
+    state::ResponseMetadata, AppError, SessionID, SessionState, Settings, UserID, UserResponse,
+};
+
+/// Has to be increased when fields are changed in an incompatible way. New fields should
+/// have defaults instead, so that older archives stay readable.
+pub const SESSION_ARCHIVE_VERSION: u32 = 1;
+
+/// Everything that is needed to restore a session on another server or after a restart.
+/// Tokens and webhook secrets are not part of it, because the archive may be stored
+/// anywhere. The session is restored under the token of whoever imports it.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SessionArchive {
+    pub version: u32,
+    pub session: SessionID,
+    /// The page without the injected script, which may be different on the new server.
+    pub page: String,
+    /// Sorted by id.
+    pub responses: Vec<ArchivedResponse>,
+    pub next_response_id: usize,
+    pub created: DateTime<Utc>,
+    #[serde(default)]
+    pub lock_first_response: bool,
+    #[serde(default)]
+    pub lock_first_response_sticky: bool,
+    #[serde(default)]
+    pub max_response_size: Option<Byte>,
+    #[serde(default)]
+    pub response_schema: Option<ResponseSchema>,
+    #[serde(default)]
+    pub choices: Option<Vec<String>>,
+    #[serde(default)]
+    pub voting_closed: bool,
+    #[serde(default)]
+    pub voting_deadline: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub keep_alive: Option<Duration>,
+    /// The salt is not exported, so new responses of the same users get different ids.
+    #[serde(default)]
+    pub anonymous: bool,
+    #[serde(default)]
+    pub join_code: Option<String>,
+    #[serde(default)]
+    pub response_filter: Option<ResponseFilter>,
+    #[serde(default)]
+    pub results_public: bool,
+    #[serde(default)]
+    pub banned_users: Vec<UserID>,
+    #[serde(default)]
+    pub issued_user_ids: Vec<UserID>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ArchivedResponse {
+    pub user: UserID,
+    pub data: String,
+    pub content_type: String,
+    pub id: usize,
+    pub time: DateTime<Utc>,
+    #[serde(default)]
+    pub was_received: bool,
+    #[serde(default)]
+    pub revision: u32,
+    /// Only set when the server collects it, see [`Settings::collect_response_metadata`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ResponseMetadata>,
+}
+
+impl SessionArchive {
+    pub fn new(settings: &Settings, session_id: &SessionID, session: &SessionState) -> Self {
+        let mut responses: Vec<ArchivedResponse> = session
+            .responses
+            .iter()
+            .map(|(user_id, response)| ArchivedResponse {
+                user: user_id.clone(),
+                data: response.data.clone(),
+                content_type: response.content_type.clone(),
+                id: response.id,
+                time: response.time,
+                was_received: response.was_received,
+                revision: response.revision,
+                metadata: response.metadata.clone(),
+            })
+            .collect();
+        responses.sort_by_key(|response| response.id);
+        let mut banned_users: Vec<UserID> = session.banned_users.iter().cloned().collect();
+        banned_users.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut issued_user_ids: Vec<UserID> = session.issued_user_ids.iter().cloned().collect();
+        issued_user_ids.sort_by(|a, b| a.0.cmp(&b.0));
+        SessionArchive {
+            version: SESSION_ARCHIVE_VERSION,
+            session: session_id.clone(),
+            page: page::remove_script(settings, &session.page.text()),
+            responses,
+            next_response_id: session.next_response_id,
+            created: session.created,
+            lock_first_response: session.lock_first_response,
+            lock_first_response_sticky: session.lock_first_response_sticky,
+            max_response_size: session.max_response_size,
+            response_schema: session.response_schema.clone(),
+            choices: session.choices.clone(),
+            voting_closed: session.voting_closed,
+            voting_deadline: session.voting_deadline,
+            keep_alive: session.keep_alive,
+            anonymous: session.anonymous_salt.is_some(),
+            join_code: session.join_code.clone(),
+            response_filter: session.response_filter.clone(),
+            results_public: session.results_public,
+            banned_users,
+            issued_user_ids,
+        }
+    }
+
+    /// Checks the version before the rest of the archive is parsed, so that archives of
+    /// newer servers get a helpful error. The archive is checked completely, so that
+    /// [`SessionArchive::restore`] can't fail halfway.
+    pub fn parse(settings: &Settings, json: &str) -> Result<Self, AppError> {
+        #[derive(serde::Deserialize)]
+        struct Header {
+            version: u32,
+        }
+        let invalid = |message: String| AppError::InvalidArchive { message };
+        let header: Header = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+        if header.version > SESSION_ARCHIVE_VERSION {
+            return Err(invalid(format!(
+                "Version {} is not supported, at most {} is.",
+                header.version, SESSION_ARCHIVE_VERSION
+            )));
+        }
+        let archive: SessionArchive =
+            serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+
+        if archive.responses.len() > settings.tunables().max_users_per_session {
+            return Err(AppError::TooManyUsers);
+        }
+        if let Some(code) = &archive.join_code {
+            join_code::validate(code, "join_code")?;
+        }
+        let mut ids = HashSet::new();
+        let mut users = HashSet::new();
+        for response in &archive.responses {
+            if response.id >= archive.next_response_id || !ids.insert(response.id) {
+                return Err(invalid(format!(
+                    "Response id {} is used twice or not below the next response id.",
+                    response.id
+                )));
+            }
+            if !users.insert(&response.user) {
+                return Err(invalid(format!(
+                    "User {} has more than one response.",
+                    response.user.0
+                )));
+            }
+        }
+        Ok(archive)
+    }
+
+    /// Replaces the responses and settings of the session. The page is set separately,
+    /// because it has to be prepared.
+    pub fn restore(self, settings: &Settings, session: &mut SessionState) {
+        session.responses = self
+            .responses
+            .into_iter()
+            .map(|response| {
+                (
+                    response.user,
+                    UserResponse {
+                        data: response.data,
+                        content_type: response.content_type,
+                        id: response.id,
+                        was_received: response.was_received,
+                        time: response.time,
+                        arrived: clock::instant_at(&*settings.clock, response.time),
+                        revision: response.revision,
+                        metadata: response.metadata,
+                        coalesced: false,
+                    },
+                )
+            })
+            .collect();
+        session.next_response_id = self.next_response_id;
+        // A replaced session may have had more responses.
+        if let Some(digest) = &mut session.digest {
+            digest.start = digest.start.min(self.next_response_id);
+        }
+        if let Some(webhook) = &mut session.webhook {
+            webhook.start = webhook.start.min(self.next_response_id);
+        }
+        session.created = self.created;
+        session.lock_first_response = self.lock_first_response;
+        session.lock_first_response_sticky = self.lock_first_response_sticky;
+        session.max_response_size = self.max_response_size;
+        session.response_schema = self.response_schema;
+        session.choices = self.choices;
+        session.voting_closed = self.voting_closed;
+        session.voting_deadline = self.voting_deadline;
+        session.keep_alive = self.keep_alive;
+        match self.anonymous {
+            true => session.make_anonymous(),
+            false => session.anonymous_salt = None,
+        }
+        session.join_code = self.join_code;
+        session.response_filter = self.response_filter;
+        session.results_public = self.results_public;
+        session.banned_users = self.banned_users.into_iter().collect();
+        session.issued_user_ids = self.issued_user_ids.into_iter().collect();
+    }
+}
@@ -0,0 +1,48 @@
+use sha2::{Digest, Sha256};
+
+use crate::{static_files, AppError};
+
+/// Small file that pages of the session can reference, e.g. an image that would make the
+/// page too large as data url. Assets are kept when the page changes.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct SessionAsset {
+    pub data: Vec<u8>,
+    pub content_type: String,
+    /// Hex-encoded sha256 of the data. It's the `ETag` and pages can use it to get a url
+    /// that is cached for long.
+    pub hash: String,
+}
+
+impl SessionAsset {
+    /// The content type is derived from the extension of the name, so that the same name
+    /// is always served the same way.
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        let hash = Sha256::digest(&data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        SessionAsset {
+            data,
+            content_type: static_files::content_type(name).to_string(),
+            hash,
+        }
+    }
+}
+
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Names are used in urls, so only a few characters are allowed, e.g. `logo.png`.
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    let is_valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    match is_valid {
+        true => Ok(()),
+        false => Err(AppError::BadQueryParameters {
+            parameter: Some("name".to_string()),
+        }),
+    }
+}
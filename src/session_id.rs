@@ -1,16 +1,35 @@
 use crate::AppError;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String")]
 pub struct SessionID(pub String);
 
+/// Upper bound for the length of all ids. `Settings` may limit the length further.
+pub const MAX_ID_LENGTH: usize = 100;
+
+/// Ids are used in urls and should not require any escaping there.
+pub fn is_valid_id(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_ID_LENGTH
+        && s.bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-')
+}
+
 impl SessionID {
     pub fn from_string(s: &str) -> Result<SessionID, AppError> {
-        if s.is_empty() {
-            Err(AppError::BadSessionID)
-        } else if s.len() > 100 {
+        if !is_valid_id(s) {
             Err(AppError::BadSessionID)
         } else {
             Ok(SessionID(s.to_string()))
         }
     }
 }
+
+/// Allows using the id directly in query parameters, so that it is validated on extraction.
+impl TryFrom<String> for SessionID {
+    type Error = AppError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        SessionID::from_string(&s)
+    }
+}
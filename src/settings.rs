@@ -1,31 +1,431 @@
 use byte_unit::{Byte, Unit};
-use std::time::Duration;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use rand::RngCore;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    rate_limit::RateLimit,
+    response_filter::{FilterMode, ResponseFilter},
+    security_headers::SecurityHeaders,
+    session_id::MAX_ID_LENGTH,
+};
+
+/// How random session ids created by `/new` look like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionIDStyle {
+    /// E.g. `123456`.
+    Digits,
+    /// E.g. `blue-tiger-42`.
+    Words,
+}
+
+/// What happens to responses of a user that come faster than the minimum interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ResponseThrottle {
+    /// Reject with `429 Too Many Requests`.
+    Reject,
+    /// Replace the previous response, but keep its id and only notify waiting clients once
+    /// the interval is over. Then the newest response gets a new id, so the ids grow by at
+    /// most one per interval and user.
+    Coalesce,
+}
+
+/// Which websites may use the api from the browser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorsPolicy {
+    /// Any website, which is what public instances need, because talks can be hosted
+    /// anywhere.
+    Permissive,
+    /// Only websites with these origins, e.g. `https://talks.example.com`.
+    AllowedOrigins(Vec<String>),
+}
+
+/// Settings that can be changed while the server is running, see `/admin/settings`. Sizes
+/// are given in bytes and durations in seconds when serialized.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tunables {
+    #[serde(with = "bytes")]
+    pub max_response_size: Byte,
+    #[serde(with = "bytes")]
+    pub max_memory_usage: Byte,
+    #[serde(with = "seconds")]
+    pub session_keep_alive_duration: Duration,
+    #[serde(with = "seconds")]
+    pub response_long_poll_duration: Duration,
+    #[serde(with = "seconds")]
+    pub page_update_long_poll_duration: Duration,
+    /// Minimum time between responses of the same user that get a new response id.
+    #[serde(with = "seconds")]
+    pub min_response_interval: Duration,
+    /// Least recently used sessions are removed when there are more sessions.
+    pub max_sessions: usize,
+    /// No new sessions are created when there are that many sessions. This is a hard
+    /// ceiling in addition to the eviction of old sessions.
+    pub max_sessions_total: usize,
+    pub max_sessions_per_ip: usize,
+    /// Maximum number of distinct users that can respond to a page.
+    pub max_users_per_session: usize,
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Tunables {
+            max_response_size: Byte::from_u64_with_unit(4, Unit::KB).unwrap(),
+            max_memory_usage: Byte::from_u64_with_unit(500, Unit::MB).unwrap(),
+            session_keep_alive_duration: Duration::from_secs(24 * 60 * 60),
+            response_long_poll_duration: Duration::from_secs(5),
+            page_update_long_poll_duration: Duration::from_secs(30),
+            min_response_interval: Duration::from_millis(200),
+            max_sessions: 50_000,
+            max_sessions_total: 100_000,
+            max_sessions_per_ip: 1000,
+            max_users_per_session: 10_000,
+        }
+    }
+}
+
+impl Tunables {
+    /// Human readable list of the settings that differ, e.g. for logging.
+    pub fn describe_changes(&self, new: &Tunables) -> Vec<String> {
+        let serde_json::Value::Object(old_values) = serde_json::to_value(self).unwrap() else {
+            return vec![];
+        };
+        let new_values = serde_json::to_value(new).unwrap();
+        old_values
+            .iter()
+            .filter(|(setting, old_value)| new_values[setting.as_str()] != **old_value)
+            .map(|(setting, old_value)| {
+                format!(
+                    "{} from {} to {}",
+                    setting,
+                    old_value,
+                    new_values[setting.as_str()]
+                )
+            })
+            .collect()
+    }
+}
+
+mod bytes {
+    use byte_unit::Byte;
+
+    pub fn serialize<S: serde::Serializer>(value: &Byte, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(value.as_u64())
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Byte, D::Error> {
+        let value: u64 = serde::Deserialize::deserialize(deserializer)?;
+        Ok(Byte::from_u64(value))
+    }
+}
+
+mod seconds {
+    use std::time::Duration;
+
+    pub fn serialize<S: serde::Serializer>(
+        value: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(value.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        let value: f64 = serde::Deserialize::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(value).map_err(serde::de::Error::custom)
+    }
+}
 
 #[derive(Clone)]
 pub struct Settings {
+    /// Shared by all clones of the settings, so that changes are visible everywhere right
+    /// away. Use [`Settings::tunables`] to read them.
+    pub tunables: Arc<RwLock<Tunables>>,
     pub token_timeout: Duration,
-    pub response_long_poll_duration: Duration,
-    pub page_update_long_poll_duration: Duration,
-    pub max_response_size: Byte,
+    /// Key for signing the tokens created by `/new`, see [`crate::AccessToken::new_signed`].
+    pub token_secret: Vec<u8>,
     pub max_page_size: Byte,
+    /// Limits for files that pages can reference, see [`crate::session_assets`].
+    pub max_asset_size: Byte,
+    pub max_assets_per_session: usize,
+    /// Upper bound for the `timeout_ms` that clients can pass to long-polling routes.
+    pub max_long_poll_duration: Duration,
+    /// Long-polls that are in flight at the same time, see [`crate::long_poll`].
+    pub max_long_polls_per_session: usize,
+    pub max_long_polls_total: usize,
     pub cleanup_interval: Duration,
-    pub session_keep_alive_duration: Duration,
-    pub max_memory_usage: Byte,
+    /// How long clients are told that a session expired instead of that it does not exist.
+    pub expired_session_retention: Duration,
+    /// Only that many expired sessions are remembered.
+    pub max_expired_sessions: usize,
+    /// The estimated memory usage is multiplied by this before comparing it with the
+    /// maximum, because the estimate does not take all allocations into account.
+    pub memory_safety_factor: f64,
+    /// The memory usage is tracked incrementally. Once in a while it is counted fully to
+    /// correct any drift.
+    pub memory_recount_interval: Duration,
+    /// Sessions that have been used more recently are not removed when the memory limit
+    /// is reached.
+    pub emergency_retention: Duration,
+    /// Responses that have been received by the presenter are kept at least that long when
+    /// the memory limit is reached.
+    pub received_response_retention: Duration,
+    /// Whether setting the page of a session that does not exist creates it.
+    pub allow_implicit_session_creation: bool,
+    /// Fetching the page counts as usage of the session, so that sessions with a passive
+    /// audience don't expire.
+    pub touch_on_read: bool,
+    /// Put the whole script into pages instead of referencing it. This is useful when the
+    /// audience can't load the script from the root url.
+    pub inline_injection: bool,
+    /// Keep pages gzip-compressed in memory, see [`crate::page_content::PageContent`].
+    pub compress_stored_pages: bool,
+    /// Files in this directory replace the embedded static files with the same name.
+    pub static_dir: Option<PathBuf>,
+    /// Maximum length of ids of new sessions.
+    pub max_session_id_length: usize,
+    pub session_id_style: SessionIDStyle,
+    pub max_user_id_length: usize,
+    /// Only accept responses from user ids that `/join` issued for the session. Otherwise,
+    /// one person could respond many times with made up ids.
+    pub require_server_user_ids: bool,
+    /// Maximum size of messages for the audience in bytes.
+    pub max_message_size: usize,
+    /// Messages for the audience are removed after that time.
+    pub message_lifetime: Duration,
+    /// Number of previous pages per session that the presenter can go back to.
+    pub page_history_len: usize,
+    /// Page updates that follow the previous notification of the audience within this
+    /// time are announced together after it, so that the audience does not reload for
+    /// every update when the page changes many times per second. Zero disables it.
+    pub page_notify_debounce: Duration,
+    /// How often to check whether scheduled pages have to be switched. This is how late
+    /// the audience may get them at most.
+    pub page_schedule_check_interval: Duration,
+    /// Comments are sent this often on `/responses/events`, so that proxies don't close
+    /// the connection when there are no responses for a while.
+    pub event_stream_heartbeat_interval: Duration,
+    /// Audience members count for this long after their last long-poll or ping, see
+    /// [`crate::audience`]. It's longer than the interval of the pings of the injected
+    /// script.
+    pub audience_timeout: Duration,
+    /// Number of events in the audit log of all sessions, see [`crate::audit_log`].
+    pub audit_log_len: usize,
+    /// Denylist for free-text responses of all sessions. It is shared by all clones of the
+    /// settings, so that it can be reloaded, see [`Settings::response_filter`].
+    pub response_filter: Arc<RwLock<Option<ResponseFilter>>>,
+    /// The response filter is loaded from this file again on `SIGHUP`.
+    pub response_filter_path: Option<PathBuf>,
+    pub response_filter_mode: FilterMode,
+    /// Media types that responses may have. Parameters like the charset are ignored.
+    pub allowed_response_content_types: Vec<String>,
+    /// Upper bound for the `limit` of `/responses`. Presenters get the remaining responses
+    /// with the next request.
+    pub max_responses_per_request: usize,
+    /// Maximum number of sessions processed by the cleanup while the state is locked.
+    pub cleanup_batch_size: usize,
+    /// Maximum time spent checking sessions for expiry while the state is locked.
+    pub cleanup_time_budget: Duration,
+    /// How often to check whether digests have to be delivered.
+    pub digest_check_interval: Duration,
+    pub webhook_max_attempts: usize,
+    pub webhook_initial_backoff: Duration,
+    /// Webhooks and digests may post to hosts in private networks or to the server itself.
+    /// Only allow this when everybody who can create sessions is trusted.
+    pub allow_private_webhook_urls: bool,
+    /// Responses that arrive within this time are posted to the webhook together.
+    pub webhook_debounce: Duration,
+    pub webhook_check_interval: Duration,
+    /// Webhooks are disabled after this many deliveries failed in a row.
+    pub webhook_max_failures: usize,
+    /// How often the cleanup task checks the state for internal inconsistencies.
+    pub verify_interval: Duration,
+    pub admin_token: Option<String>,
+    /// Require the main or viewer token for reading responses.
+    pub responses_require_auth: bool,
+    /// Responses are only marked as received by `/ack`, instead of when `/responses` is
+    /// called with a later `start`. That response may not have reached the presenter.
+    pub require_explicit_ack: bool,
+    /// Store [`crate::state::ResponseMetadata`] with responses. It's off by default, because
+    /// the audience may not expect it.
+    pub collect_response_metadata: bool,
+    /// Directory where snapshots of the state are stored. Nothing is persisted without it.
+    pub persist_path: Option<PathBuf>,
+    pub persist_interval: Duration,
+    /// Prepended to all keys when sessions are stored in Redis.
+    pub redis_key_prefix: String,
+    /// Audience members on the same network often share an ip, so this is generous.
+    pub respond_rate_limit: RateLimit,
+    pub new_session_rate_limit: RateLimit,
+    pub set_page_rate_limit: RateLimit,
+    /// Pings only keep sessions alive, so they are limited much more than responses.
+    pub ping_rate_limit: RateLimit,
+    pub response_throttle: ResponseThrottle,
+    /// Use the client ip from `X-Forwarded-For` for rate limiting. This should only be
+    /// enabled when the server runs behind a proxy that sets the header.
+    pub trust_forwarded_for: bool,
+    pub cors: CorsPolicy,
+    pub security_headers: SecurityHeaders,
+    /// Url of the server without the base path.
     pub root_url: String,
+    /// Derive the root url from the `Host` header of each request. The root url setting is
+    /// only used when that's not possible.
+    pub auto_root_url: bool,
+    /// All routes are below this path, e.g. `/polli`. It's empty or starts with a slash.
+    pub base_path: String,
+    /// Use [`Settings::now`] and [`Settings::instant`] instead of `Utc::now()` and
+    /// `Instant::now()`.
+    pub clock: Arc<dyn Clock>,
 }
 
 impl Settings {
     pub fn default(root_url: String) -> Self {
         Settings {
+            tunables: Arc::new(RwLock::new(Tunables::default())),
             token_timeout: Duration::from_secs(60 * 60 * 24),
-            response_long_poll_duration: Duration::from_secs(5),
-            page_update_long_poll_duration: Duration::from_secs(30),
+            token_secret: random_token_secret(),
             max_page_size: Byte::from_u64_with_unit(1, Unit::MB).unwrap(),
-            max_response_size: Byte::from_u64_with_unit(4, Unit::KB).unwrap(),
+            max_asset_size: Byte::from_u64_with_unit(256, Unit::KB).unwrap(),
+            max_assets_per_session: 16,
+            max_long_poll_duration: Duration::from_secs(60),
+            max_long_polls_per_session: 2000,
+            max_long_polls_total: 20_000,
             cleanup_interval: Duration::from_secs(3),
-            session_keep_alive_duration: Duration::from_secs(24 * 60 * 60),
-            max_memory_usage: Byte::from_u64_with_unit(500, Unit::MB).unwrap(),
-            root_url: root_url,
+            expired_session_retention: Duration::from_secs(60 * 60),
+            max_expired_sessions: 1000,
+            memory_safety_factor: 2.0,
+            memory_recount_interval: Duration::from_secs(10 * 60),
+            emergency_retention: Duration::from_secs(30),
+            received_response_retention: Duration::from_secs(30),
+            allow_implicit_session_creation: true,
+            touch_on_read: true,
+            inline_injection: false,
+            compress_stored_pages: false,
+            static_dir: None,
+            max_session_id_length: MAX_ID_LENGTH,
+            session_id_style: SessionIDStyle::Digits,
+            max_user_id_length: MAX_ID_LENGTH,
+            require_server_user_ids: false,
+            max_message_size: 500,
+            message_lifetime: Duration::from_secs(5 * 60),
+            page_history_len: 3,
+            page_notify_debounce: Duration::ZERO,
+            page_schedule_check_interval: Duration::from_millis(250),
+            event_stream_heartbeat_interval: Duration::from_secs(15),
+            audience_timeout: Duration::from_secs(4 * 60),
+            audit_log_len: 1000,
+            response_filter: Arc::new(RwLock::new(None)),
+            response_filter_path: None,
+            response_filter_mode: FilterMode::Reject,
+            allowed_response_content_types: vec![
+                "text/plain".to_string(),
+                "application/json".to_string(),
+            ],
+            max_responses_per_request: 1000,
+            cleanup_batch_size: 1000,
+            cleanup_time_budget: Duration::from_millis(20),
+            digest_check_interval: Duration::from_secs(10),
+            webhook_max_attempts: 5,
+            webhook_initial_backoff: Duration::from_secs(1),
+            allow_private_webhook_urls: false,
+            webhook_debounce: Duration::from_millis(500),
+            webhook_check_interval: Duration::from_millis(100),
+            webhook_max_failures: 5,
+            verify_interval: Duration::from_secs(10 * 60),
+            admin_token: None,
+            responses_require_auth: false,
+            require_explicit_ack: false,
+            collect_response_metadata: false,
+            persist_path: None,
+            persist_interval: Duration::from_secs(30),
+            redis_key_prefix: "polli:".to_string(),
+            respond_rate_limit: RateLimit {
+                burst: 200,
+                per_second: 50.0,
+            },
+            new_session_rate_limit: RateLimit {
+                burst: 20,
+                per_second: 1.0,
+            },
+            set_page_rate_limit: RateLimit {
+                burst: 60,
+                per_second: 10.0,
+            },
+            ping_rate_limit: RateLimit {
+                burst: 30,
+                per_second: 1.0,
+            },
+            trust_forwarded_for: false,
+            cors: CorsPolicy::Permissive,
+            security_headers: SecurityHeaders::default(),
+            response_throttle: ResponseThrottle::Coalesce,
+            root_url,
+            auto_root_url: false,
+            base_path: String::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Url that the paths of the routes are appended to.
+    pub fn base_url(&self) -> String {
+        format!("{}{}", self.root_url.trim_end_matches('/'), self.base_path)
+    }
+
+    /// Cookies are only sent to the routes of the server.
+    pub fn cookie_path(&self) -> String {
+        match self.base_path.as_str() {
+            "" => "/".to_string(),
+            base_path => base_path.to_string(),
         }
     }
+
+    pub fn tunables(&self) -> Tunables {
+        *self.tunables.read()
+    }
+
+    pub fn response_filter(&self) -> Option<ResponseFilter> {
+        self.response_filter.read().clone()
+    }
+
+    /// Duration requested by the client with `timeout_ms`, or the default of the route.
+    pub fn long_poll_duration(&self, default: Duration, timeout_ms: Option<u64>) -> Duration {
+        timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(default)
+            .min(self.max_long_poll_duration)
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Monotonic time, see [`Clock::instant`].
+    pub fn instant(&self) -> Instant {
+        self.clock.instant()
+    }
+}
+
+/// Used when no secret is configured. Tokens then only survive as long as the process.
+pub fn random_token_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// E.g. `polli/` becomes `/polli`, so that it can be put between the root url and the
+/// paths of the routes.
+pub fn normalize_base_path(base_path: &str) -> String {
+    match base_path.trim_matches('/') {
+        "" => String::new(),
+        path => format!("/{}", path),
+    }
 }
@@ -1,37 +1,117 @@
 use actix_cors::Cors;
-use actix_web::http::header::{CacheControl, CacheDirective};
-use actix_web::middleware::DefaultHeaders;
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::middleware::{from_fn, DefaultHeaders};
 use actix_web::{web, App, HttpServer};
 use parking_lot::Mutex;
 use std::net::TcpListener;
-use std::sync::Arc;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{atomic::AtomicUsize, Arc};
 
-use crate::{routes, Settings, SharedState, State};
+use crate::{
+    errors, rate_limit::RateLimiter, request_id, routes, settings::CorsPolicy, storage::Storage,
+    Settings, SharedState, State,
+};
 
+pub enum Listener {
+    Tcp(TcpListener),
+    /// For a reverse proxy on the same host. It never uses TLS.
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// The server listens on all listeners, e.g. for ipv4 and ipv6.
 pub async fn start_server(
-    listener: TcpListener,
+    listeners: Vec<Listener>,
     settings: Settings,
     state: Arc<Mutex<State>>,
+    storage: Arc<dyn Storage>,
+    rate_limiter: Arc<RateLimiter>,
+    tls: Option<rustls::ServerConfig>,
 ) -> std::io::Result<()> {
-    HttpServer::new(move || {
+    // Pages may be sent json-encoded to `/new`, which can make them larger.
+    let max_payload_size = (settings.max_page_size.as_u64() as usize * 2 + 64 * 1024)
+        .max(settings.max_asset_size.as_u64() as usize);
+    let statistics = state.lock().statistics.clone();
+    let long_polls = Arc::new(AtomicUsize::new(0));
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(SharedState {
                 settings: settings.clone(),
                 state: state.clone(),
+                storage: storage.clone(),
+                rate_limiter: rate_limiter.clone(),
+                statistics: statistics.clone(),
+                long_polls: long_polls.clone(),
             }))
+            .app_data(web::PayloadConfig::new(max_payload_size))
+            .app_data(web::QueryConfig::default().error_handler(errors::query_error_handler))
+            .wrap(from_fn(errors::negotiate_error_format))
+            .wrap(from_fn(request_id::assign_request_id))
             .wrap(DefaultHeaders::new().add(CacheControl(vec![CacheDirective::NoCache])))
-            .wrap(Cors::permissive())
-            .service(routes::get_index_route)
-            .service(routes::get_page_route)
-            .service(routes::post_page_route)
-            .service(routes::get_responses_route)
-            .service(routes::post_respond_route)
-            .service(routes::post_init_session_route)
-            .service(routes::get_wait_for_page_route)
+            .wrap(make_cors(&settings.cors))
+            .service(web::scope(&settings.base_path).configure(routes::register_routes))
+            .default_service(web::to(routes::not_found_route))
     })
-    .workers(1)
-    .listen(listener)
-    .unwrap()
-    .run()
-    .await
+    .workers(1);
+    let server =
+        listeners
+            .into_iter()
+            .try_fold(server, |server, listener| match (listener, &tls) {
+                (Listener::Tcp(listener), Some(config)) => {
+                    server.listen_rustls_0_23(listener, config.clone())
+                }
+                (Listener::Tcp(listener), None) => server.listen(listener),
+                #[cfg(unix)]
+                (Listener::Unix(listener), _) => server.listen_uds(listener),
+            });
+    server.unwrap().run().await
+}
+
+/// A socket file that is left over from a previous run is replaced. Other files are not
+/// touched.
+#[cfg(unix)]
+pub fn bind_unix_socket(path: &Path, mode: Option<u32>) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+fn make_cors(policy: &CorsPolicy) -> Cors {
+    match policy {
+        CorsPolicy::Permissive => Cors::permissive(),
+        CorsPolicy::AllowedOrigins(origins) => origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(["GET", "POST", "DELETE"])
+            .allowed_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::ACCEPT,
+                header::IF_MATCH,
+                header::HeaderName::from_static("idempotency-key"),
+            ])
+            .expose_headers([
+                "ETag",
+                "Link",
+                "Retry-After",
+                "X-Request-Id",
+                "X-Polli-Max-Response-Size",
+                "X-Polli-Max-User-ID-Length",
+                "X-Polli-Accepting-Responses",
+                "X-Polli-Lock-First-Response",
+            ])
+            .max_age(60 * 60),
+    }
 }
@@ -1,64 +1,916 @@
+use byte_unit::Byte;
 use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream};
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::Notify;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    sync::{atomic::AtomicUsize, Arc},
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, Notify};
 
-use crate::{AccessToken, SessionID, Settings, UserID};
+use crate::{
+    access_token::TokenHash,
+    audience::Audience,
+    audit_log::{AuditEventKind, AuditLog, PageAccess, RemovalReason},
+    cleanup::{self, CleanupMetrics},
+    digest::Digest,
+    expired_sessions::ExpiredSessions,
+    page,
+    page_content::PageContent,
+    rate_limit::RateLimiter,
+    response_filter::ResponseFilter,
+    response_schema::ResponseSchema,
+    response_webhook::ResponseWebhook,
+    scheduled_page::ScheduledPage,
+    session_assets::SessionAsset,
+    statistics::{self, Statistics},
+    storage::{PageUpdate, Storage, MAX_IDEMPOTENCY_KEYS},
+    user_id, AccessToken, AppError, SessionID, Settings, UserID,
+};
+
+/// Responses that have not been read by a subscriber yet. Streams of slower subscribers end.
+pub const RESPONSE_CHANNEL_CAPACITY: usize = 256;
 
 pub struct SharedState {
     pub settings: Settings,
     pub state: Arc<Mutex<State>>,
+    pub storage: Arc<dyn Storage>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// The same as [`State::statistics`].
+    pub statistics: Arc<Statistics>,
+    /// Long-polls of all sessions that are in flight, see [`crate::long_poll`].
+    pub long_polls: Arc<AtomicUsize>,
+}
+
+/// Can be used from any thread. The state itself is behind a mutex that must not be held
+/// across `.await` points, so the methods here lock it only briefly.
+impl SharedState {
+    /// Sets the page like `POST /page` without any query parameters, i.e. the script is
+    /// injected, the size limit applies and the audience reloads.
+    pub async fn set_page(
+        &self,
+        session_id: &SessionID,
+        access_token: AccessToken,
+        page: String,
+    ) -> Result<(), AppError> {
+        let page = page::prepare_page(&self.settings, page)?;
+        self.storage
+            .set_page(
+                session_id,
+                access_token,
+                page,
+                PageUpdate {
+                    allow_create: self.settings.allow_implicit_session_creation,
+                    notify: true,
+                    skip_unchanged: false,
+                    lock_first_response: None,
+                    lock_sticky: None,
+                    max_response_size: None,
+                    creator_ip: None,
+                    keep_response_schema: false,
+                    choices: None,
+                    voting_deadline: None,
+                    anonymous: false,
+                },
+            )
+            .await?;
+        Ok(())
+    }
 }
 
+#[derive(Default)]
 pub struct State {
+    /// Sessions should be removed with [`State::remove_session`], so that the counts per
+    /// ip and the token index stay up to date.
     pub sessions: HashMap<SessionID, SessionState>,
+    /// Number of sessions created from each ip.
+    pub sessions_per_ip: HashMap<IpAddr, usize>,
+    /// Sessions by the hash of their access token, so that the sessions of a token can be
+    /// found without checking all of them.
+    pub sessions_by_token: HashMap<TokenHash, HashSet<SessionID>>,
+    /// Estimated memory usage of all sessions in bytes. It is updated when data is added
+    /// or removed, so that the cleanup does not have to count everything all the time.
+    pub approx_bytes: u64,
+    /// Sessions that have been removed by the cleanup recently.
+    pub expired_sessions: ExpiredSessions,
+    pub cleanup_metrics: CleanupMetrics,
+    pub audit_log: AuditLog,
+    pub statistics: Arc<Statistics>,
 }
 
+/// Serialized for snapshots, see [`crate::persist`]. Notifiers are created fresh.
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct SessionState {
+    #[serde(skip)]
     pub response_notifier: Arc<Notify>,
+    #[serde(skip)]
     pub page_notifier: Arc<Notify>,
-    pub page: String,
+    /// When the audience has been told to reload the last time, or will be, see
+    /// [`SessionState::notify_page`].
+    #[serde(skip)]
+    pub last_page_notify: Option<Instant>,
+    #[serde(skip)]
+    pub message_notifier: Arc<Notify>,
+    /// Every new response, for applications that embed the server, see
+    /// [`State::subscribe_responses`].
+    #[serde(skip, default = "new_response_sender")]
+    pub response_sender: broadcast::Sender<PublishedResponse>,
+    /// Long-polls of the session that are in flight, see [`crate::long_poll`].
+    #[serde(skip)]
+    pub long_polls: Arc<AtomicUsize>,
+    /// Clients that follow the session, see [`crate::audience`].
+    #[serde(skip)]
+    pub audience: Arc<Mutex<Audience>>,
+    pub page: PageContent,
+    /// Increased whenever the page is set, starting at 1.
+    pub page_version: usize,
+    /// Time when the current page was set.
+    pub page_time: DateTime<Utc>,
+    /// Previous pages, oldest first, so that the presenter can go back to them. At most
+    /// [`Settings::page_history_len`] are kept. The cleanup drops them when the memory
+    /// limit is reached.
+    pub page_history: VecDeque<PageHistoryEntry>,
+    /// Prepared page that replaces the current one when it is committed, so that the
+    /// audience does not see a page that is only partially updated. Setting the page
+    /// directly drops it.
+    pub staged_page: Option<String>,
+    /// Replaces the page at its time, see [`crate::scheduled_page`]. Unlike the staged page,
+    /// it is kept when the page is set directly.
+    pub scheduled_page: Option<ScheduledPage>,
+    /// Files that pages can reference with `/asset`, by name. They are kept when the page
+    /// changes. The cleanup drops them when the memory limit is reached.
+    pub assets: HashMap<String, SessionAsset>,
     pub responses: HashMap<UserID, UserResponse>,
     pub access_token: AccessToken,
+    /// Set if the access token is signed. Signed tokens that have been issued earlier are
+    /// not accepted anymore, e.g. after the token has been rotated.
+    pub token_issued_at: Option<DateTime<Utc>>,
+    /// Token that only allows reading responses.
+    pub viewer_token: Option<AccessToken>,
     pub next_response_id: usize,
+    /// Time when the session was created. Setting a new page does not change it.
+    pub created: DateTime<Utc>,
+    /// Wall-clock time of the last usage. It's only shown to clients and stored, see
+    /// [`SessionState::last_used`].
     pub last_request: DateTime<Utc>,
+    /// Monotonic time of the last usage. Expiry, eviction and takeovers use it, so that
+    /// sessions are not removed when the wall clock jumps. Snapshots only contain
+    /// `last_request`, see [`crate::persist::restore_instants`].
+    #[serde(skip, default = "Instant::now")]
+    pub last_used: Instant,
+    /// Reject responses from users that already responded to the current page.
+    pub lock_first_response: bool,
+    /// Keep `lock_first_response` when the page is updated.
+    pub lock_first_response_sticky: bool,
+    pub digest: Option<Digest>,
+    pub webhook: Option<ResponseWebhook>,
+    /// Per-session limit that is smaller than the global one.
+    pub max_response_size: Option<Byte>,
+    /// Responses that don't match are rejected.
+    pub response_schema: Option<ResponseSchema>,
+    /// Only these responses are accepted for the current page. Replaced whenever the page
+    /// is set.
+    pub choices: Option<Vec<String>>,
+    /// Closed by the presenter until it is opened again or the page changes.
+    pub voting_closed: bool,
+    /// Voting closes automatically at this time. Reset when the page changes.
+    pub voting_deadline: Option<DateTime<Utc>>,
+    /// Per-session keep-alive duration. It can't be longer than the global one.
+    pub keep_alive: Option<Duration>,
+    /// Used to limit the number of sessions per ip.
+    pub creator_ip: Option<IpAddr>,
+    /// Set in anonymous mode. User ids are replaced by a salted hash before responses are
+    /// stored. The salt is never sent to clients and can't be removed from the session.
+    pub anonymous_salt: Option<Vec<u8>>,
+    /// Required for the page and for responding, so that only invited people take part.
+    /// It is never sent to the audience.
+    pub join_code: Option<String>,
+    /// Applies to free-text responses in addition to [`Settings::response_filter`]. It is
+    /// kept when the page changes.
+    pub response_filter: Option<ResponseFilter>,
+    /// Last message for the audience. It is kept when the page changes and removed by the
+    /// cleanup when it's older than [`Settings::message_lifetime`].
+    pub message: Option<AudienceMessage>,
+    /// Ids of messages increase, so that clients can tell which messages they have shown.
+    pub last_message_id: usize,
+    /// Everyone can see the aggregated responses on `/results`, e.g. on a projector. It is
+    /// kept when the page changes.
+    pub results_public: bool,
+    /// Responses of these users are rejected. Bans are kept when the page changes.
+    pub banned_users: HashSet<UserID>,
+    /// User ids that have been issued by `/join`. They stay valid when the page changes.
+    pub issued_user_ids: HashSet<UserID>,
+    /// Responses that were sent with an `Idempotency-Key`, oldest first. Retries only have
+    /// to survive for a short time, so they are not persisted.
+    #[serde(skip)]
+    pub idempotency_keys: VecDeque<IdempotentResponse>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct AudienceMessage {
+    pub id: usize,
+    pub text: String,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PageHistoryEntry {
+    pub version: usize,
+    /// The page with the injected script, like it was stored.
+    pub page: PageContent,
+    pub time: DateTime<Utc>,
 }
 
+/// Id of the response that was stored the first time a key was used by a user.
+pub struct IdempotentResponse {
+    pub user_id: UserID,
+    pub key: String,
+    pub response_id: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct UserResponse {
     pub data: String,
+    /// Media type without parameters, e.g. `application/json`.
+    pub content_type: String,
     pub id: usize,
     pub was_received: bool,
+    /// Wall-clock time when the response arrived, e.g. for exports.
     pub time: DateTime<Utc>,
+    /// Monotonic time when the response arrived. The cleanup uses it for the retention of
+    /// received responses.
+    #[serde(skip, default = "Instant::now")]
+    pub arrived: Instant,
+    /// How often the user changed the response to the current page. Sending the same
+    /// data again does not count.
+    pub revision: u32,
+    /// Only set with [`Settings::collect_response_metadata`].
+    pub metadata: Option<ResponseMetadata>,
+    /// The response replaced others within the minimum interval and keeps the id of the
+    /// first one until the interval is over, see [`crate::ResponseThrottle::Coalesce`].
+    #[serde(skip)]
+    pub coalesced: bool,
+}
+
+/// Details about how a response was sent, e.g. to find out how long the audience took to
+/// answer. They are only part of `/export_session`.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ResponseMetadata {
+    /// Version of the page that the client responded to. It's the current version if the
+    /// client did not send it.
+    pub page_version: usize,
+    /// Time when that page version was set, if it is still known.
+    pub page_time: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+}
+
+pub struct SetPageOptions {
+    /// Create the session if it does not exist yet.
+    pub allow_create: bool,
+    /// Tell waiting audience members to reload the page.
+    pub notify: bool,
+    /// Ip of the client that would create the session. It's also recorded in the
+    /// [`State::audit_log`].
+    pub creator_ip: Option<IpAddr>,
+    /// Keep the response schema of the session for the new page.
+    pub keep_response_schema: bool,
+}
+
+impl State {
+    /// Sets the page of the session, creating the session if allowed. An existing session
+    /// can only be updated with the same access token, unless it has not been used for
+    /// longer than the token timeout.
+    pub fn set_page(
+        &mut self,
+        settings: &Settings,
+        session_id: &SessionID,
+        access_token: AccessToken,
+        page: String,
+        options: SetPageOptions,
+    ) -> Result<&mut SessionState, AppError> {
+        let now = settings.now();
+        let token_issued_at = access_token.signed_issue_time_for(settings, session_id, now);
+        let session_count = self.sessions.len();
+        match self.sessions.entry(session_id.clone()) {
+            Entry::Vacant(entry) => {
+                // A signed token proves that the session existed before, e.g. before a restart.
+                if !options.allow_create && token_issued_at.is_none() {
+                    return Err(AppError::SessionIDDoesNotExist);
+                }
+                if entry.key().0.len() > settings.max_session_id_length {
+                    return Err(AppError::BadSessionID);
+                }
+                if session_count >= settings.tunables().max_sessions_total {
+                    return Err(AppError::SessionLimitReached { limit: "total" });
+                }
+                if let Some(ip) = options.creator_ip {
+                    let count = self.sessions_per_ip.entry(ip).or_default();
+                    if *count >= settings.tunables().max_sessions_per_ip {
+                        return Err(AppError::SessionLimitReached { limit: "per_ip" });
+                    }
+                    *count += 1;
+                }
+                let session_id = entry.key().clone();
+                index_token(&mut self.sessions_by_token, &access_token, &session_id);
+                let page = PageContent::new(settings, page);
+                let session = entry.insert(SessionState::new(
+                    access_token,
+                    page,
+                    now,
+                    settings.instant(),
+                ));
+                session.token_issued_at = token_issued_at;
+                session.creator_ip = options.creator_ip;
+                self.approx_bytes = self
+                    .approx_bytes
+                    .saturating_add(cleanup::count_session_memory_usage(&session_id, session));
+                statistics::add(&self.statistics.sessions_created, 1);
+                self.audit_log.record(
+                    settings,
+                    &session_id,
+                    now,
+                    AuditEventKind::SessionCreated {
+                        ip: options.creator_ip,
+                    },
+                );
+                Ok(session)
+            }
+            Entry::Occupied(entry) => {
+                let old_bytes = cleanup::count_session_memory_usage(entry.key(), entry.get());
+                let session = entry.into_mut();
+                let access;
+                if !session.access_token.constant_time_eq(&access_token) {
+                    let is_newer_signed_token = token_issued_at.is_some_and(|issued_at| {
+                        session
+                            .token_issued_at
+                            .is_none_or(|previous| issued_at > previous)
+                    });
+                    if !is_newer_signed_token
+                        && session.unused_for(settings) < settings.token_timeout
+                    {
+                        return Err(AppError::BadAccessToken);
+                    }
+                    forget_token(
+                        &mut self.sessions_by_token,
+                        &session.access_token,
+                        session_id,
+                    );
+                    index_token(&mut self.sessions_by_token, &access_token, session_id);
+                    if is_newer_signed_token {
+                        session.access_token = access_token;
+                        session.token_issued_at = token_issued_at;
+                        session.update(settings, page, now, options.keep_response_schema);
+                        access = PageAccess::NewerSignedToken;
+                    } else {
+                        // The session is taken over by someone else.
+                        forget_creator(&mut self.sessions_per_ip, session.creator_ip);
+                        if let Some(ip) = options.creator_ip {
+                            *self.sessions_per_ip.entry(ip).or_default() += 1;
+                        }
+                        let page = PageContent::new(settings, page);
+                        *session = SessionState::new(access_token, page, now, settings.instant());
+                        session.token_issued_at = token_issued_at;
+                        session.creator_ip = options.creator_ip;
+                        access = PageAccess::Takeover;
+                    }
+                } else {
+                    session.update(settings, page, now, options.keep_response_schema);
+                    access = PageAccess::TokenMatched;
+                }
+                let new_bytes = cleanup::count_session_memory_usage(session_id, session);
+                self.approx_bytes = self
+                    .approx_bytes
+                    .saturating_sub(old_bytes)
+                    .saturating_add(new_bytes);
+                self.audit_log.record(
+                    settings,
+                    session_id,
+                    now,
+                    AuditEventKind::PageUpdated {
+                        ip: options.creator_ip,
+                        access,
+                    },
+                );
+                if options.notify {
+                    session.notify_page(settings);
+                }
+                Ok(session)
+            }
+        }
+    }
+
+    pub fn remove_session(&mut self, session_id: &SessionID) -> Option<SessionState> {
+        let session = self.sessions.remove(session_id)?;
+        forget_creator(&mut self.sessions_per_ip, session.creator_ip);
+        forget_token(
+            &mut self.sessions_by_token,
+            &session.access_token,
+            session_id,
+        );
+        self.track_memory_usage(cleanup::count_session_memory_usage(session_id, &session), 0);
+        Some(session)
+    }
+
+    /// Removes the session and wakes up everyone who is waiting for it, so that they notice
+    /// that it's gone.
+    pub fn delete_session(&mut self, settings: &Settings, session_id: &SessionID) -> bool {
+        let Some(session) = self.remove_session(session_id) else {
+            return false;
+        };
+        self.audit_log.record(
+            settings,
+            session_id,
+            settings.now(),
+            AuditEventKind::SessionRemoved {
+                reason: RemovalReason::Deleted,
+            },
+        );
+        session.page_notifier.notify_waiters();
+        session.response_notifier.notify_waiters();
+        session.message_notifier.notify_waiters();
+        true
+    }
+
+    /// Removes everything that is stored about the user in all sessions, i.e. responses,
+    /// idempotency keys, bans and issued ids. In anonymous sessions, the id is hashed like
+    /// the stored ids, but the hash itself is accepted as well. Long-polls of sessions that
+    /// changed check again. Returns the number of removed items and changed sessions.
+    pub fn erase_user_data(&mut self, user_id: &UserID) -> (usize, usize) {
+        let mut removed_items = 0;
+        let mut changed_sessions = 0;
+        let mut old_bytes = 0;
+        let mut new_bytes = 0;
+        for (session_id, session) in self.sessions.iter_mut() {
+            let mut user_ids = vec![user_id.clone()];
+            if session.anonymous_salt.is_some() {
+                user_ids.push(session.stored_user_id(user_id));
+            }
+            let session_bytes = cleanup::count_session_memory_usage(session_id, session);
+            let mut removed = 0;
+            for user_id in &user_ids {
+                removed += usize::from(session.responses.remove(user_id).is_some());
+                removed += usize::from(session.banned_users.remove(user_id));
+                removed += usize::from(session.issued_user_ids.remove(user_id));
+            }
+            let keys_before = session.idempotency_keys.len();
+            session
+                .idempotency_keys
+                .retain(|entry| !user_ids.contains(&entry.user_id));
+            removed += keys_before - session.idempotency_keys.len();
+            if removed == 0 {
+                continue;
+            }
+            old_bytes += session_bytes;
+            new_bytes += cleanup::count_session_memory_usage(session_id, session);
+            session.response_notifier.notify_waiters();
+            removed_items += removed;
+            changed_sessions += 1;
+        }
+        self.track_memory_usage(old_bytes, new_bytes);
+        (removed_items, changed_sessions)
+    }
+
+    /// Stream of all responses that the session receives from now on, including responses
+    /// that replace a previous one of the same user. It ends when the session is removed or
+    /// when the subscriber falls more than [`RESPONSE_CHANNEL_CAPACITY`] responses behind.
+    /// Only responses that are stored in memory are published, i.e. not with Redis.
+    pub fn subscribe_responses(
+        &self,
+        session_id: &SessionID,
+    ) -> Result<impl Stream<Item = (UserID, String)> + Send + 'static, AppError> {
+        let Some(session) = self.sessions.get(session_id) else {
+            return Err(AppError::SessionIDDoesNotExist);
+        };
+        let receiver = session.response_sender.subscribe();
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            let response = next_published_response(&mut receiver).await?;
+            Some(((response.user, response.data), receiver))
+        }))
+    }
+
+    /// Removes the session and remembers that it expired. The reason is either
+    /// [`RemovalReason::Expired`] or [`RemovalReason::Evicted`].
+    pub fn expire_session(
+        &mut self,
+        settings: &Settings,
+        session_id: &SessionID,
+        now: DateTime<Utc>,
+        reason: RemovalReason,
+    ) -> Option<SessionState> {
+        let session = self.remove_session(session_id)?;
+        self.expired_sessions
+            .insert(settings, session_id.clone(), now);
+        self.audit_log.record(
+            settings,
+            session_id,
+            now,
+            AuditEventKind::SessionRemoved { reason },
+        );
+        Some(session)
+    }
+
+    /// Error for a session that does not exist (anymore).
+    pub fn session_not_found(&self, settings: &Settings, session_id: &SessionID) -> AppError {
+        if self
+            .expired_sessions
+            .contains(settings, session_id, settings.now())
+        {
+            return AppError::SessionExpired;
+        }
+        AppError::SessionIDDoesNotExist
+    }
+
+    /// Replaces `old_bytes` with `new_bytes` in the estimated memory usage.
+    pub fn track_memory_usage(&mut self, old_bytes: u64, new_bytes: u64) {
+        self.approx_bytes = self
+            .approx_bytes
+            .saturating_sub(old_bytes)
+            .saturating_add(new_bytes);
+    }
+
+    /// Has to be called after sessions have been added or modified without tracking the
+    /// memory usage, e.g. when loading a snapshot.
+    pub fn recount_memory_usage(&mut self) {
+        self.approx_bytes = self
+            .sessions
+            .iter()
+            .fold(0, |total: u64, (session_id, session)| {
+                total.saturating_add(cleanup::count_session_memory_usage(session_id, session))
+            });
+    }
+
+    /// Has to be called after sessions have been added or removed in bulk.
+    pub fn recount_sessions_per_ip(&mut self) {
+        self.sessions_per_ip.clear();
+        for session in self.sessions.values() {
+            if let Some(ip) = session.creator_ip {
+                *self.sessions_per_ip.entry(ip).or_default() += 1;
+            }
+        }
+    }
+
+    /// Has to be called after sessions have been added in bulk.
+    pub fn reindex_tokens(&mut self) {
+        self.sessions_by_token.clear();
+        for (session_id, session) in &self.sessions {
+            index_token(
+                &mut self.sessions_by_token,
+                &session.access_token,
+                session_id,
+            );
+        }
+    }
+
+    /// Has to be called when the access token of a session changes.
+    pub fn replace_token(
+        &mut self,
+        session_id: &SessionID,
+        old_token: &AccessToken,
+        new_token: &AccessToken,
+    ) {
+        forget_token(&mut self.sessions_by_token, old_token, session_id);
+        index_token(&mut self.sessions_by_token, new_token, session_id);
+    }
+
+    /// Sessions whose access token is the given one.
+    pub fn sessions_of_token(&self, access_token: &AccessToken) -> Vec<SessionID> {
+        let Some(session_ids) = self.sessions_by_token.get(&access_token.hash()) else {
+            return vec![];
+        };
+        session_ids
+            .iter()
+            .filter(|session_id| {
+                self.sessions
+                    .get(*session_id)
+                    .is_some_and(|session| session.access_token.constant_time_eq(access_token))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+fn index_token(
+    sessions_by_token: &mut HashMap<TokenHash, HashSet<SessionID>>,
+    access_token: &AccessToken,
+    session_id: &SessionID,
+) {
+    sessions_by_token
+        .entry(access_token.hash())
+        .or_default()
+        .insert(session_id.clone());
+}
+
+fn forget_token(
+    sessions_by_token: &mut HashMap<TokenHash, HashSet<SessionID>>,
+    access_token: &AccessToken,
+    session_id: &SessionID,
+) {
+    if let Entry::Occupied(mut entry) = sessions_by_token.entry(access_token.hash()) {
+        entry.get_mut().remove(session_id);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    }
 }
 
-impl Default for State {
-    fn default() -> Self {
-        State {
-            sessions: HashMap::new(),
+fn forget_creator(sessions_per_ip: &mut HashMap<IpAddr, usize>, ip: Option<IpAddr>) {
+    let Some(ip) = ip else {
+        return;
+    };
+    if let Entry::Occupied(mut entry) = sessions_per_ip.entry(ip) {
+        *entry.get_mut() -= 1;
+        if *entry.get() == 0 {
+            entry.remove();
         }
     }
 }
 
+/// A new or changed response, see [`State::subscribe_responses`].
+#[derive(Clone)]
+pub struct PublishedResponse {
+    pub user: UserID,
+    pub data: String,
+    pub id: usize,
+}
+
+/// Returns `None` when the session has been removed or when the receiver fell behind and
+/// missed responses, so that the subscriber can't silently end up with stale responses.
+pub async fn next_published_response(
+    receiver: &mut broadcast::Receiver<PublishedResponse>,
+) -> Option<PublishedResponse> {
+    receiver.recv().await.ok()
+}
+
+fn new_response_sender() -> broadcast::Sender<PublishedResponse> {
+    broadcast::channel(RESPONSE_CHANNEL_CAPACITY).0
+}
+
 impl SessionState {
-    pub fn new(access_token: AccessToken, page: String) -> SessionState {
+    pub fn new(
+        access_token: AccessToken,
+        page: impl Into<PageContent>,
+        now: DateTime<Utc>,
+        used: Instant,
+    ) -> SessionState {
         SessionState {
             response_notifier: Arc::new(Notify::new()),
             page_notifier: Arc::new(Notify::new()),
-            page: page,
+            last_page_notify: None,
+            message_notifier: Arc::new(Notify::new()),
+            response_sender: new_response_sender(),
+            long_polls: Arc::new(AtomicUsize::new(0)),
+            audience: Arc::default(),
+            page: page.into(),
+            page_version: 1,
+            page_time: now,
+            page_history: VecDeque::new(),
+            staged_page: None,
+            scheduled_page: None,
+            assets: HashMap::new(),
             responses: HashMap::new(),
-            access_token: access_token,
+            access_token,
+            token_issued_at: None,
+            viewer_token: None,
             next_response_id: 0,
-            last_request: Utc::now(),
+            created: now,
+            last_request: now,
+            last_used: used,
+            lock_first_response: false,
+            lock_first_response_sticky: false,
+            digest: None,
+            webhook: None,
+            max_response_size: None,
+            response_schema: None,
+            choices: None,
+            voting_closed: false,
+            voting_deadline: None,
+            keep_alive: None,
+            creator_ip: None,
+            anonymous_salt: None,
+            join_code: None,
+            response_filter: None,
+            message: None,
+            last_message_id: 0,
+            results_public: false,
+            banned_users: HashSet::new(),
+            issued_user_ids: HashSet::new(),
+            idempotency_keys: VecDeque::new(),
         }
     }
 
-    pub fn update(&mut self, page: String) {
-        self.page = page;
+    pub fn update(
+        &mut self,
+        settings: &Settings,
+        page: String,
+        now: DateTime<Utc>,
+        keep_response_schema: bool,
+    ) {
+        let previous_page = std::mem::replace(&mut self.page, PageContent::new(settings, page));
+        if settings.page_history_len == 0 {
+            self.page_history.clear();
+        } else {
+            // The history may be longer when a snapshot of another configuration was loaded.
+            while self.page_history.len() >= settings.page_history_len {
+                self.page_history.pop_front();
+            }
+            self.page_history.push_back(PageHistoryEntry {
+                version: self.page_version,
+                page: previous_page,
+                time: self.page_time,
+            });
+        }
+        self.page_version += 1;
+        self.page_time = now;
+        self.staged_page = None;
         self.responses.clear();
-        self.session_used();
+        self.idempotency_keys.clear();
+        self.choices = None;
+        self.voting_closed = false;
+        self.voting_deadline = None;
+        if !keep_response_schema {
+            self.response_schema = None;
+        }
+        if !self.lock_first_response_sticky {
+            self.lock_first_response = false;
+        }
+        self.session_used(settings);
+    }
+
+    /// Tells the audience to reload. Within [`Settings::page_notify_debounce`] after the
+    /// previous notification, a single notification is sent when the time is up instead,
+    /// so that the audience gets the latest page once.
+    pub fn notify_page(&mut self, settings: &Settings) {
+        let now = settings.instant();
+        let debounce = settings.page_notify_debounce;
+        let Some(last) = self
+            .last_page_notify
+            .filter(|last| !debounce.is_zero() && now < *last + debounce)
+        else {
+            self.last_page_notify = Some(now);
+            self.page_notifier.notify_waiters();
+            return;
+        };
+        if last > now {
+            // The pending notification includes this update already.
+            return;
+        }
+        let due = last + debounce;
+        self.last_page_notify = Some(due);
+        let notifier = self.page_notifier.clone();
+        let delay = due - now;
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            notifier.notify_waiters();
+        });
+    }
+
+    /// Time when the page with the version was set, if it is the current page or still in
+    /// the history.
+    pub fn page_time_of(&self, version: usize) -> Option<DateTime<Utc>> {
+        if version == self.page_version {
+            return Some(self.page_time);
+        }
+        self.page_history
+            .iter()
+            .find(|entry| entry.version == version)
+            .map(|entry| entry.time)
+    }
+
+    /// Keeps the salt if the session is anonymous already, so that the ids of users stay
+    /// the same.
+    pub fn make_anonymous(&mut self) {
+        if self.anonymous_salt.is_none() {
+            self.anonymous_salt = Some(user_id::new_anonymous_salt());
+        }
+    }
+
+    /// Id under which the response of the user is stored.
+    pub fn stored_user_id(&self, user_id: &UserID) -> UserID {
+        match &self.anonymous_salt {
+            Some(salt) => user_id.anonymized(salt),
+            None => user_id.clone(),
+        }
+    }
+
+    /// The message unless it's expired already but not removed by the cleanup yet.
+    pub fn current_message(
+        &self,
+        settings: &Settings,
+        now: DateTime<Utc>,
+    ) -> Option<&AudienceMessage> {
+        self.message
+            .as_ref()
+            .filter(|message| message.time + settings.message_lifetime > now)
+    }
+
+    pub fn idempotent_response(&self, user_id: &UserID, key: &str) -> Option<usize> {
+        self.idempotency_keys
+            .iter()
+            .find(|entry| entry.user_id == *user_id && entry.key == key)
+            .map(|entry| entry.response_id)
     }
 
-    pub fn session_used(&mut self) {
-        self.last_request = Utc::now();
+    /// Forgets the oldest keys when there are too many.
+    pub fn remember_idempotency_key(&mut self, user_id: &UserID, key: String, response_id: usize) {
+        if self.idempotency_keys.len() >= MAX_IDEMPOTENCY_KEYS {
+            self.idempotency_keys.pop_front();
+        }
+        self.idempotency_keys.push_back(IdempotentResponse {
+            user_id: user_id.clone(),
+            key,
+            response_id,
+        });
+    }
+
+    pub fn max_response_size(&self, settings: &Settings) -> Byte {
+        let max_response_size = settings.tunables().max_response_size;
+        match self.max_response_size {
+            None => max_response_size,
+            Some(size) => size.min(max_response_size),
+        }
+    }
+
+    pub fn keep_alive_duration(&self, settings: &Settings) -> Duration {
+        let keep_alive = settings.tunables().session_keep_alive_duration;
+        match self.keep_alive {
+            None => keep_alive,
+            Some(duration) => duration.min(keep_alive),
+        }
+    }
+
+    pub fn check_access_token(&self, access_token: &AccessToken) -> Result<(), AppError> {
+        if !self.access_token.constant_time_eq(access_token) {
+            return Err(AppError::BadAccessToken);
+        }
+        Ok(())
+    }
+
+    /// The main token and the viewer token both allow reading responses.
+    pub fn check_read_access(&self, access_token: &AccessToken) -> Result<(), AppError> {
+        let is_viewer = self
+            .viewer_token
+            .as_ref()
+            .is_some_and(|viewer_token| viewer_token.constant_time_eq(access_token));
+        if self.access_token.constant_time_eq(access_token) || is_viewer {
+            return Ok(());
+        }
+        Err(AppError::BadAccessToken)
+    }
+
+    pub fn session_used(&mut self, settings: &Settings) {
+        self.last_request = settings.now();
+        self.last_used = settings.instant();
+    }
+
+    /// Time since the session has been used, measured with the monotonic clock.
+    pub fn unused_for(&self, settings: &Settings) -> Duration {
+        settings.instant().saturating_duration_since(self.last_used)
+    }
+
+    pub fn accepting_responses(&self, now: DateTime<Utc>) -> bool {
+        !self.voting_closed && self.voting_deadline.is_none_or(|deadline| now < deadline)
+    }
+
+    /// Whether there are at least `min_count` responses with an id of at least `start`.
+    /// Users that changed their response count once.
+    pub fn has_new_responses(&self, start: usize, min_count: usize) -> bool {
+        match min_count {
+            0 => true,
+            // Cheaper, because it does not have to look at every response.
+            1 => self.next_response_id > start,
+            _ => {
+                self.responses
+                    .values()
+                    .filter(|response| response.id >= start)
+                    .count()
+                    >= min_count
+            }
+        }
+    }
+
+    /// Approximate number of clients that follow the session, see [`crate::audience`].
+    pub fn audience_count(&self, settings: &Settings) -> usize {
+        self.audience
+            .lock()
+            .count(settings.instant(), settings.audience_timeout)
+    }
+}
+
+/// Publishes the response to subscribers of the session, if there are any. It takes the
+/// sender instead of the session, so that the responses can be borrowed at the same time.
+pub fn publish_response(
+    sender: &broadcast::Sender<PublishedResponse>,
+    user_id: &UserID,
+    data: &str,
+    id: usize,
+) {
+    if sender.receiver_count() > 0 {
+        let _ = sender.send(PublishedResponse {
+            user: user_id.clone(),
+            data: data.to_string(),
+            id,
+        });
     }
 }
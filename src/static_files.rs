@@ -1,8 +1,120 @@
 use include_dir::include_dir;
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{errors::AppError, request_id, Settings};
 
 static STATIC_FILES: include_dir::Dir = include_dir!("static");
 
-pub fn get(filename: &str) -> &'static str {
+/// Files in the static directory of the settings take precedence over the embedded ones.
+/// They are read again every time, so that they can be changed while the server runs.
+pub fn get(settings: &Settings, filename: &str) -> Cow<'static, str> {
+    if let Some(dir) = &settings.static_dir {
+        if let Some(content) = read_override(dir, filename) {
+            return Cow::Owned(content);
+        }
+    }
+    Cow::Borrowed(embedded(filename))
+}
+
+pub fn embedded(filename: &str) -> &'static str {
     let file = STATIC_FILES.get_file(filename).unwrap();
     file.contents_utf8().unwrap()
 }
+
+/// Like [`get`], but also for binary files and files that may not exist.
+pub fn get_bytes(settings: &Settings, filename: &str) -> Option<Cow<'static, [u8]>> {
+    if let Some(path) = settings
+        .static_dir
+        .as_deref()
+        .and_then(|dir| override_path(dir, filename))
+    {
+        if let Ok(content) = std::fs::read(path) {
+            return Some(Cow::Owned(content));
+        }
+    }
+    STATIC_FILES
+        .get_file(filename)
+        .map(|file| Cow::Borrowed(file.contents()))
+}
+
+pub fn read_override(dir: &Path, filename: &str) -> Option<String> {
+    std::fs::read_to_string(override_path(dir, filename)?).ok()
+}
+
+/// Only files within the directory can be read.
+fn override_path(dir: &Path, filename: &str) -> Option<PathBuf> {
+    let path = Path::new(filename);
+    let is_inside = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    is_inside.then(|| dir.join(path))
+}
+
+/// Content type based on the file extension.
+pub fn content_type(filename: &str) -> &'static str {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Replaces `{{name}}` placeholders in the file with the given values. The values are
+/// not escaped, so they must not contain html.
+pub fn render(
+    settings: &Settings,
+    filename: &str,
+    variables: &[(&str, &str)],
+) -> Result<String, AppError> {
+    substitute(&get(settings, filename), variables).map_err(|message| {
+        request_id::log(format!("Cannot render {}: {}", filename, message));
+        AppError::ServerError
+    })
+}
+
+/// Unknown placeholders are an error, so that typos are noticed.
+pub fn substitute(template: &str, variables: &[(&str, &str)]) -> Result<String, String> {
+    let mut remaining = template;
+    let mut result = String::with_capacity(template.len());
+    while let Some(start) = remaining.find("{{") {
+        result.push_str(&remaining[..start]);
+        let Some(end) = remaining[start..].find("}}") else {
+            return Err("unterminated placeholder".to_string());
+        };
+        let name = remaining[start + 2..start + end].trim();
+        let Some((_, value)) = variables.iter().find(|(variable, _)| *variable == name) else {
+            return Err(format!("unknown placeholder {}", name));
+        };
+        result.push_str(value);
+        remaining = &remaining[start + end + 2..];
+    }
+    result.push_str(remaining);
+    Ok(result)
+}
+
+#[cfg(feature = "test-util")]
+pub fn filenames() -> Vec<&'static str> {
+    STATIC_FILES
+        .files()
+        .filter_map(|file| file.path().to_str())
+        .collect()
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Totals since the server started, see `GET /stats`. They only increase, so that they
+/// can be graphed like other counters. They are shared between [`crate::State`] and
+/// [`crate::SharedState`], so that they can be updated without locking the state.
+#[derive(Default)]
+pub struct Statistics {
+    pub sessions_created: AtomicU64,
+    /// Sessions that have not been used for longer than their keep-alive duration.
+    pub sessions_expired: AtomicU64,
+    /// Sessions that have been removed because there were too many.
+    pub sessions_evicted: AtomicU64,
+    /// Sessions that have been removed because the memory limit has been reached.
+    pub sessions_evicted_for_memory: AtomicU64,
+    pub responses_received: AtomicU64,
+    /// Responses that have been received by everyone already and were removed to free
+    /// memory.
+    pub responses_dropped: AtomicU64,
+}
+
+pub fn add(counter: &AtomicU64, amount: u64) {
+    counter.fetch_add(amount, Ordering::Relaxed);
+}
+
+pub fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use byte_unit::Byte;
+use chrono::{DateTime, Utc};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::{
+    client_config::ClientConfig, page_content::PageContent, state::ResponseMetadata, AccessToken,
+    AppError, SessionID, UserID,
+};
+
+mod memory;
+mod redis;
+
+pub use memory::MemoryStorage;
+pub use redis::RedisStorage;
+
+/// Operations on sessions that are needed by `/page`, `/respond` and `/responses`. They
+/// are abstracted, so that multiple server instances can share sessions by storing them
+/// in Redis. Other routes still only use the in-memory state.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// This counts as usage of the session if [`crate::Settings::touch_on_read`] is set.
+    async fn get_page(&self, session_id: &SessionID) -> Result<StoredPage, AppError>;
+
+    /// Same semantics as [`crate::State::set_page`].
+    async fn set_page(
+        &self,
+        session_id: &SessionID,
+        access_token: AccessToken,
+        page: String,
+        update: PageUpdate,
+    ) -> Result<PageChange, AppError>;
+
+    /// The global limits have been checked already, but per-session limits have not. Returns
+    /// the id of the stored response.
+    async fn insert_response(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+        data: String,
+        content_type: String,
+        conditions: ResponseConditions,
+    ) -> Result<usize, AppError>;
+
+    /// Current response of the user, without counting as usage of the session.
+    async fn get_user_response(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+    ) -> Result<Option<ListedResponse>, AppError>;
+
+    async fn check_read_access(
+        &self,
+        session_id: &SessionID,
+        access_token: &AccessToken,
+    ) -> Result<(), AppError>;
+
+    /// Returns when there may be at least `min_count` responses with an id of at least
+    /// `start` or when the timeout is reached.
+    async fn wait_for_responses(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+        min_count: usize,
+        timeout: Duration,
+    ) -> Result<(), AppError>;
+
+    /// At most `limit` responses with an id of at least `start`, see [`take_responses`].
+    /// This counts as usage of the session.
+    async fn get_responses(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+        limit: usize,
+    ) -> Result<StoredResponses, AppError>;
+
+    /// Deletes everything that is stored about the user in all sessions, see
+    /// [`crate::State::erase_user_data`]. Returns the number of removed items and of the
+    /// sessions they were in.
+    async fn erase_user_data(&self, user_id: &UserID) -> Result<(usize, usize), AppError>;
+
+    /// Fails for options that only the memory storage supports, so that they are not
+    /// silently ignored.
+    fn check_supported(&self, _option: &'static str) -> Result<(), AppError> {
+        Ok(())
+    }
+}
+
+pub struct StoredPage {
+    pub page: PageContent,
+    pub client_config: ClientConfig,
+}
+
+pub struct StoredResponses {
+    pub next_start: usize,
+    /// Sorted by id, i.e. in the order in which they arrived.
+    pub responses: Vec<ListedResponse>,
+    /// Users that have a stored response, including the ones before `start`.
+    pub total_responses: usize,
+    /// See [`crate::SessionState::choices`].
+    pub choices: Option<Vec<String>>,
+}
+
+pub fn invalid_choice(choices: &[String]) -> AppError {
+    AppError::InvalidResponse {
+        message: format!("Response is not one of the choices: {}", choices.join(", ")),
+    }
+}
+
+/// Keeps the `limit` responses with the smallest ids. If some are left out, `next_start`
+/// is the id after the last returned one, so that the next request continues there.
+pub fn take_responses<T>(
+    mut responses: Vec<T>,
+    id: impl Fn(&T) -> usize,
+    start: usize,
+    next_response_id: usize,
+    limit: usize,
+) -> (Vec<T>, usize) {
+    responses.sort_by_key(&id);
+    if responses.len() <= limit {
+        return (responses, next_response_id);
+    }
+    responses.truncate(limit);
+    let next_start = responses.last().map_or(start, |response| id(response) + 1);
+    (responses, next_start)
+}
+
+/// Used for responses that were sent without `Content-Type`.
+pub const DEFAULT_CONTENT_TYPE: &str = "text/plain";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ListedResponse {
+    pub user: UserID,
+    pub data: String,
+    pub content_type: String,
+    pub id: usize,
+    pub time: DateTime<Utc>,
+    /// See [`crate::UserResponse::revision`].
+    #[serde(default)]
+    pub revision: u32,
+}
+
+/// Retries of a response with the same key are not stored again, see
+/// [`ResponseConditions::idempotency_key`].
+pub const MAX_IDEMPOTENCY_KEYS: usize = 256;
+
+#[derive(Default)]
+pub struct ResponseConditions {
+    /// Only replace the response of the user if it has this id. Fails if the user has not
+    /// responded yet.
+    pub if_match: Option<usize>,
+    /// Retries with the same key by the same user get the id of the original response
+    /// instead of storing it again. Only the most recent keys of a session are remembered.
+    pub idempotency_key: Option<String>,
+    /// Stored with the response. Only the memory storage supports it.
+    pub metadata: Option<ResponseMetadata>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageChange {
+    Updated,
+    /// See [`PageUpdate::skip_unchanged`].
+    Unchanged,
+}
+
+pub struct PageUpdate {
+    pub allow_create: bool,
+    pub notify: bool,
+    /// Only count it as usage of the session if the page is the same as the stored one
+    /// and the token matches. The other fields are ignored then.
+    pub skip_unchanged: bool,
+    pub lock_first_response: Option<bool>,
+    pub lock_sticky: Option<bool>,
+    pub max_response_size: Option<Byte>,
+    pub creator_ip: Option<IpAddr>,
+    /// Only the memory storage supports response schemas, Redis rejects it.
+    pub keep_response_schema: bool,
+    /// Replaces the choices of the previous page.
+    pub choices: Option<Vec<String>>,
+    /// Responses are rejected after this time.
+    pub voting_deadline: Option<DateTime<Utc>>,
+    /// Make the session anonymous, see [`crate::SessionState::anonymous_salt`]. Only the
+    /// memory storage supports it, Redis rejects it.
+    pub anonymous: bool,
+}
@@ -0,0 +1,391 @@
+use async_trait::async_trait;
+use byte_unit::Byte;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{
+    self as storage, ListedResponse, PageChange, PageUpdate, ResponseConditions, Storage,
+    StoredPage, StoredResponses,
+};
+use crate::{
+    cleanup::{count_response_memory_usage, count_responses_capacity},
+    client_config::ClientConfig,
+    settings::ResponseThrottle,
+    state::{self, SetPageOptions},
+    AccessToken, AppError, SessionID, Settings, State, UserID, UserResponse,
+};
+
+/// Default storage that only lives in the current process.
+pub struct MemoryStorage {
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryStorage {
+    pub fn new(settings: Settings, state: Arc<Mutex<State>>) -> Self {
+        MemoryStorage { settings, state }
+    }
+
+    fn flush_coalesced_response_later(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+        delay: Duration,
+    ) {
+        let settings = self.settings.clone();
+        let state = self.state.clone();
+        let session_id = session_id.clone();
+        let user_id = user_id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            flush_coalesced_response(&settings, &state, &session_id, &user_id);
+        });
+    }
+}
+
+/// Gives a response that replaced others during the minimum interval a new id, so that
+/// clients that only fetch new responses get it, see [`ResponseThrottle::Coalesce`]. The
+/// next interval starts now.
+fn flush_coalesced_response(
+    settings: &Settings,
+    state: &Mutex<State>,
+    session_id: &SessionID,
+    user_id: &UserID,
+) {
+    let mut state = state.lock();
+    let Some(session) = state.sessions.get_mut(session_id) else {
+        return;
+    };
+    let Some(response) = session.responses.get_mut(user_id) else {
+        return;
+    };
+    if !response.coalesced {
+        return;
+    }
+    response.coalesced = false;
+    response.id = session.next_response_id;
+    response.arrived = settings.instant();
+    session.next_response_id += 1;
+    state::publish_response(
+        &session.response_sender,
+        user_id,
+        &response.data,
+        response.id,
+    );
+    if let Some(webhook) = &mut session.webhook {
+        webhook.response_arrived(settings.instant(), settings.webhook_debounce);
+    }
+    session.response_notifier.notify_waiters();
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get_page(&self, session_id: &SessionID) -> Result<StoredPage, AppError> {
+        let mut state = self.state.lock();
+        let Some(session) = state.sessions.get_mut(session_id) else {
+            return Err(state.session_not_found(&self.settings, session_id));
+        };
+        if self.settings.touch_on_read {
+            session.session_used(&self.settings);
+        }
+        Ok(StoredPage {
+            page: session.page.clone(),
+            client_config: ClientConfig::new(&self.settings, session),
+        })
+    }
+
+    async fn set_page(
+        &self,
+        session_id: &SessionID,
+        access_token: AccessToken,
+        page: String,
+        update: PageUpdate,
+    ) -> Result<PageChange, AppError> {
+        let mut state = self.state.lock();
+        if update.skip_unchanged {
+            if let Some(session) = state.sessions.get_mut(session_id) {
+                if *session.page.text() == page && session.check_access_token(&access_token).is_ok()
+                {
+                    session.session_used(&self.settings);
+                    return Ok(PageChange::Unchanged);
+                }
+            }
+        }
+        let session = state.set_page(
+            &self.settings,
+            session_id,
+            access_token,
+            page,
+            SetPageOptions {
+                allow_create: update.allow_create,
+                notify: update.notify,
+                creator_ip: update.creator_ip,
+                keep_response_schema: update.keep_response_schema,
+            },
+        )?;
+        if let Some(lock) = update.lock_first_response {
+            session.lock_first_response = lock;
+        }
+        if let Some(sticky) = update.lock_sticky {
+            session.lock_first_response_sticky = sticky;
+        }
+        if let Some(size) = update.max_response_size {
+            session.max_response_size = Some(size);
+        }
+        session.choices = update.choices;
+        session.voting_deadline = update.voting_deadline;
+        if update.anonymous {
+            session.make_anonymous();
+        }
+        Ok(PageChange::Updated)
+    }
+
+    async fn insert_response(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+        data: String,
+        content_type: String,
+        conditions: ResponseConditions,
+    ) -> Result<usize, AppError> {
+        let mut state = self.state.lock();
+        let Some(session) = state.sessions.get_mut(session_id) else {
+            return Err(state.session_not_found(&self.settings, session_id));
+        };
+        if let Some(key) = &conditions.idempotency_key {
+            if let Some(response_id) = session.idempotent_response(user_id, key) {
+                return Ok(response_id);
+            }
+        }
+        if session.banned_users.contains(user_id) {
+            return Err(AppError::UserBanned);
+        }
+        let now = self.settings.now();
+        if !session.accepting_responses(now) {
+            return Err(AppError::VotingClosed);
+        }
+        if Byte::from_u64(data.len() as u64) > session.max_response_size(&self.settings) {
+            return Err(AppError::ResponseTooLarge);
+        }
+        if let Some(choices) = &session.choices {
+            if !choices.contains(&data) {
+                return Err(storage::invalid_choice(choices));
+            }
+        }
+        if let Some(expected_id) = conditions.if_match {
+            let current_id = session.responses.get(user_id).map(|response| response.id);
+            if current_id != Some(expected_id) {
+                return Err(AppError::ResponseConflict { current_id });
+            }
+        }
+        if session.lock_first_response {
+            if let Some(locked) = session.responses.get(user_id) {
+                return Err(AppError::ResponseLocked {
+                    response: locked.data.clone(),
+                });
+            }
+        }
+        if !session.responses.contains_key(user_id)
+            && session.responses.len() >= self.settings.tunables().max_users_per_session
+        {
+            return Err(AppError::TooManyUsers);
+        }
+        let arrived = self.settings.instant();
+        if let Some(previous) = session.responses.get_mut(user_id) {
+            let next_allowed = previous.arrived + self.settings.tunables().min_response_interval;
+            if arrived < next_allowed {
+                if self.settings.response_throttle == ResponseThrottle::Reject {
+                    return Err(AppError::TooManyRequests {
+                        retry_after: next_allowed - arrived,
+                    });
+                }
+                // The response is replaced in place and keeps its id until the interval is
+                // over, so that a user who keeps responding does not wake up everyone.
+                let old_bytes = count_response_memory_usage(user_id, previous);
+                if previous.data != data {
+                    previous.revision = previous.revision.saturating_add(1);
+                }
+                previous.data = data;
+                previous.content_type = content_type;
+                previous.time = now;
+                previous.was_received = false;
+                previous.metadata = conditions.metadata;
+                let flush_pending = std::mem::replace(&mut previous.coalesced, true);
+                let response_id = previous.id;
+                let new_bytes = count_response_memory_usage(user_id, previous);
+                if let Some(key) = conditions.idempotency_key {
+                    session.remember_idempotency_key(user_id, key, response_id);
+                }
+                session.session_used(&self.settings);
+                state.track_memory_usage(old_bytes, new_bytes);
+                if !flush_pending {
+                    self.flush_coalesced_response_later(
+                        session_id,
+                        user_id,
+                        next_allowed - arrived,
+                    );
+                }
+                return Ok(response_id);
+            }
+        }
+
+        let response_id = session.next_response_id;
+        session.next_response_id += 1;
+
+        state::publish_response(&session.response_sender, user_id, &data, response_id);
+        let revision = match session.responses.get(user_id) {
+            Some(previous) if previous.data != data => previous.revision.saturating_add(1),
+            Some(previous) => previous.revision,
+            None => 0,
+        };
+        let user_response = UserResponse {
+            data,
+            content_type,
+            id: response_id,
+            was_received: false,
+            time: now,
+            arrived,
+            revision,
+            metadata: conditions.metadata,
+            coalesced: false,
+        };
+        let mut old_bytes = count_responses_capacity(&session.responses);
+        let mut new_bytes = count_response_memory_usage(user_id, &user_response);
+        if let Some(previous) = session.responses.insert(user_id.clone(), user_response) {
+            old_bytes += count_response_memory_usage(user_id, &previous);
+        }
+        new_bytes += count_responses_capacity(&session.responses);
+        if let Some(key) = conditions.idempotency_key {
+            session.remember_idempotency_key(user_id, key, response_id);
+        }
+        if let Some(webhook) = &mut session.webhook {
+            webhook.response_arrived(self.settings.instant(), self.settings.webhook_debounce);
+        }
+        session.session_used(&self.settings);
+        session.response_notifier.notify_waiters();
+        state.track_memory_usage(old_bytes, new_bytes);
+        Ok(response_id)
+    }
+
+    async fn get_user_response(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+    ) -> Result<Option<ListedResponse>, AppError> {
+        let state = self.state.lock();
+        let Some(session) = state.sessions.get(session_id) else {
+            return Err(state.session_not_found(&self.settings, session_id));
+        };
+        Ok(session
+            .responses
+            .get(user_id)
+            .map(|response| ListedResponse {
+                user: user_id.clone(),
+                data: response.data.clone(),
+                content_type: response.content_type.clone(),
+                id: response.id,
+                time: response.time,
+                revision: response.revision,
+            }))
+    }
+
+    async fn check_read_access(
+        &self,
+        session_id: &SessionID,
+        access_token: &AccessToken,
+    ) -> Result<(), AppError> {
+        let state = self.state.lock();
+        match state.sessions.get(session_id) {
+            None => Err(state.session_not_found(&self.settings, session_id)),
+            Some(session) => session.check_read_access(access_token),
+        }
+    }
+
+    async fn wait_for_responses(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+        min_count: usize,
+        timeout: Duration,
+    ) -> Result<(), AppError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        // Notifications can also be for responses that the client has already, e.g. when a
+        // session is deleted, so the wait continues until there are new ones.
+        loop {
+            let notifier;
+            let notified;
+            {
+                let state = self.state.lock();
+                let Some(session) = state.sessions.get(session_id) else {
+                    return Err(state.session_not_found(&self.settings, session_id));
+                };
+                notifier = session.response_notifier.clone();
+                // Created before checking, so that responses arriving right after the check
+                // are not missed.
+                notified = notifier.notified();
+                if session.has_new_responses(start, min_count) || timeout.is_zero() {
+                    return Ok(());
+                }
+            }
+            // Don't wait for notifier while session the mutex is locked!
+            tokio::select! {
+                _ = notified => {},
+                _ = tokio::time::sleep_until(deadline) => return Ok(()),
+            }
+        }
+    }
+
+    async fn get_responses(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+        limit: usize,
+    ) -> Result<StoredResponses, AppError> {
+        let mut state = self.state.lock();
+        let Some(session) = state.sessions.get_mut(session_id) else {
+            return Err(state.session_not_found(&self.settings, session_id));
+        };
+        session.session_used(&self.settings);
+        let total_responses = session.responses.len();
+        let mut selected = vec![];
+        for (user_id, user_response) in session.responses.iter_mut() {
+            if user_response.id < start {
+                if !self.settings.require_explicit_ack {
+                    user_response.was_received = true;
+                }
+                continue;
+            }
+            selected.push((user_id, &*user_response));
+        }
+        // Only the returned responses are cloned. They are serialized after the lock is
+        // released.
+        let (selected, next_start) = storage::take_responses(
+            selected,
+            |(_, response)| response.id,
+            start,
+            session.next_response_id,
+            limit,
+        );
+        Ok(StoredResponses {
+            next_start,
+            total_responses,
+            choices: session.choices.clone(),
+            responses: selected
+                .into_iter()
+                .map(|(user_id, response)| ListedResponse {
+                    user: user_id.clone(),
+                    data: response.data.clone(),
+                    content_type: response.content_type.clone(),
+                    id: response.id,
+                    time: response.time,
+                    revision: response.revision,
+                })
+                .collect(),
+        })
+    }
+
+    async fn erase_user_data(&self, user_id: &UserID) -> Result<(usize, usize), AppError> {
+        Ok(self.state.lock().erase_user_data(user_id))
+    }
+}
@@ -0,0 +1,828 @@
+use async_trait::async_trait;
+use chrono::DateTime;
+use futures_util::StreamExt;
+use parking_lot::Mutex;
+use redis::aio::ConnectionManager;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use super::{
+    self as storage, ListedResponse, PageChange, PageUpdate, ResponseConditions, Storage,
+    StoredPage, StoredResponses, MAX_IDEMPOTENCY_KEYS,
+};
+use crate::{
+    access_token, client_config::ClientConfig, request_id, settings::ResponseThrottle, AccessToken,
+    AppError, SessionID, Settings, UserID,
+};
+
+/// Every session uses two hashes: `<prefix>session:<id>` with the page, token and
+/// options and `<prefix>responses:<id>` with the responses by user. New responses are
+/// published on the channel with the same name as the responses hash, so that
+/// long-polls on all instances return.
+///
+/// Redis expires sessions itself, so they are not touched by the periodic cleanup.
+pub struct RedisStorage {
+    settings: Settings,
+    connection: ConnectionManager,
+    notifiers: Arc<Mutex<HashMap<SessionID, Arc<Notify>>>>,
+}
+
+/// Same semantics as `State::set_page` followed by applying the page options. New sessions
+/// are added to sorted sets of all sessions and of the sessions per ip, scored by when
+/// they expire, so that the session limits can be checked.
+const SET_PAGE_SCRIPT: &str = r#"
+local token = ARGV[1]
+local signed_issued_at = ARGV[6]
+local now = tonumber(ARGV[3])
+-- Sessions that may have expired are either rescored or removed.
+local function count_sessions(set)
+  for _, key in ipairs(redis.call('ZRANGEBYSCORE', set, '-inf', now)) do
+    local ttl = redis.call('PTTL', key)
+    if ttl > 0 then
+      redis.call('ZADD', set, now + ttl, key)
+    else
+      redis.call('ZREM', set, key)
+    end
+  end
+  return redis.call('ZCARD', set)
+end
+local function reset()
+  redis.call('DEL', KEYS[1], KEYS[2], KEYS[3])
+  redis.call('HSET', KEYS[1], 'token', token, 'next_response_id', 0,
+    'lock_first_response', 0, 'lock_sticky', 0)
+  if signed_issued_at ~= '' then
+    redis.call('HSET', KEYS[1], 'token_issued_at', signed_issued_at)
+  end
+end
+if redis.call('EXISTS', KEYS[1]) == 0 then
+  if ARGV[5] ~= '1' and signed_issued_at == '' then
+    return 'not_found'
+  end
+  if count_sessions(KEYS[4]) >= tonumber(ARGV[14]) then
+    return 'limit_total'
+  end
+  if ARGV[16] == '1' and count_sessions(KEYS[5]) >= tonumber(ARGV[15]) then
+    return 'limit_per_ip'
+  end
+  local expires = now + tonumber(ARGV[10]) * 1000
+  redis.call('ZADD', KEYS[4], expires, KEYS[1])
+  if ARGV[16] == '1' then
+    redis.call('ZADD', KEYS[5], expires, KEYS[1])
+    redis.call('EXPIRE', KEYS[5], ARGV[10])
+  end
+  reset()
+elseif redis.call('HGET', KEYS[1], 'token') ~= token then
+  local previous = redis.call('HGET', KEYS[1], 'token_issued_at')
+  local last_request = tonumber(redis.call('HGET', KEYS[1], 'last_request'))
+  if signed_issued_at ~= '' and (not previous or tonumber(signed_issued_at) > tonumber(previous)) then
+    redis.call('HSET', KEYS[1], 'token', token, 'token_issued_at', signed_issued_at)
+  elseif last_request + tonumber(ARGV[4]) > tonumber(ARGV[3]) then
+    return 'bad_token'
+  else
+    reset()
+  end
+end
+if ARGV[12] == '1' and redis.call('HGET', KEYS[1], 'page') == ARGV[2] then
+  redis.call('HSET', KEYS[1], 'last_request', ARGV[3])
+  redis.call('EXPIRE', KEYS[1], ARGV[10])
+  return 'unchanged'
+end
+redis.call('DEL', KEYS[2], KEYS[3])
+if redis.call('HGET', KEYS[1], 'lock_sticky') ~= '1' then
+  redis.call('HSET', KEYS[1], 'lock_first_response', 0)
+end
+redis.call('HSET', KEYS[1], 'page', ARGV[2], 'last_request', ARGV[3])
+if ARGV[7] ~= '' then
+  redis.call('HSET', KEYS[1], 'lock_first_response', ARGV[7])
+end
+if ARGV[8] ~= '' then
+  redis.call('HSET', KEYS[1], 'lock_sticky', ARGV[8])
+end
+if ARGV[9] ~= '' then
+  redis.call('HSET', KEYS[1], 'max_response_size', ARGV[9])
+end
+if ARGV[11] ~= '' then
+  redis.call('HSET', KEYS[1], 'choices', ARGV[11])
+else
+  redis.call('HDEL', KEYS[1], 'choices')
+end
+if ARGV[13] ~= '' then
+  redis.call('HSET', KEYS[1], 'voting_deadline', ARGV[13])
+else
+  redis.call('HDEL', KEYS[1], 'voting_deadline')
+end
+redis.call('EXPIRE', KEYS[1], ARGV[10])
+return 'ok'
+"#;
+
+const INSERT_RESPONSE_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+  return {'not_found'}
+end
+if ARGV[9] ~= '' then
+  local replayed = redis.call('HGET', KEYS[3], ARGV[9])
+  if replayed then
+    return {'ok', replayed}
+  end
+end
+local function remember(id)
+  if ARGV[9] ~= '' then
+    if redis.call('HLEN', KEYS[3]) >= tonumber(ARGV[11]) then
+      redis.call('DEL', KEYS[3])
+    end
+    redis.call('HSET', KEYS[3], ARGV[9], id)
+    redis.call('EXPIRE', KEYS[3], ARGV[5])
+  end
+end
+local voting_deadline = redis.call('HGET', KEYS[1], 'voting_deadline')
+if voting_deadline and tonumber(ARGV[3]) >= tonumber(voting_deadline) then
+  return {'voting_closed'}
+end
+local max_size = tonumber(ARGV[4])
+local override = redis.call('HGET', KEYS[1], 'max_response_size')
+if override and tonumber(override) < max_size then
+  max_size = tonumber(override)
+end
+if string.len(ARGV[2]) > max_size then
+  return {'too_large'}
+end
+local choices = redis.call('HGET', KEYS[1], 'choices')
+if choices then
+  local valid = false
+  for _, choice in ipairs(cjson.decode(choices)) do
+    if choice == ARGV[2] then
+      valid = true
+    end
+  end
+  if not valid then
+    return {'invalid_choice', choices}
+  end
+end
+local previous = redis.call('HGET', KEYS[2], ARGV[1])
+if previous then
+  previous = cjson.decode(previous)
+end
+if ARGV[10] ~= '' then
+  local current_id = previous and tostring(previous.id) or ''
+  if current_id ~= ARGV[10] then
+    return {'conflict', current_id}
+  end
+end
+if previous and redis.call('HGET', KEYS[1], 'lock_first_response') == '1' then
+  return {'locked', previous.data}
+end
+if not previous and redis.call('HLEN', KEYS[2]) >= tonumber(ARGV[8]) then
+  return {'too_many_users'}
+end
+local revision = 0
+if previous then
+  revision = previous.revision or 0
+  if previous.data ~= ARGV[2] then
+    revision = revision + 1
+  end
+end
+local function store(id, started, coalesced)
+  redis.call('HSET', KEYS[2], ARGV[1], cjson.encode({data = ARGV[2], content_type = ARGV[12],
+    id = id, time = ARGV[3], started = started, revision = revision, coalesced = coalesced}))
+  redis.call('HSET', KEYS[1], 'last_request', ARGV[3])
+  redis.call('EXPIRE', KEYS[1], ARGV[5])
+  redis.call('EXPIRE', KEYS[2], ARGV[5])
+  remember(id)
+end
+if previous then
+  local started = previous.started or previous.time
+  local next_allowed = tonumber(started) + tonumber(ARGV[6])
+  if tonumber(ARGV[3]) < next_allowed then
+    local remaining = tostring(next_allowed - tonumber(ARGV[3]))
+    if ARGV[7] == 'reject' then
+      return {'throttled', remaining}
+    end
+    -- The id is kept until the interval is over, see `FLUSH_COALESCED_RESPONSE_SCRIPT`.
+    store(previous.id, started, true)
+    if previous.coalesced then
+      remaining = ''
+    end
+    return {'ok', tostring(previous.id), remaining}
+  end
+end
+local id = redis.call('HINCRBY', KEYS[1], 'next_response_id', 1) - 1
+store(id, ARGV[3], false)
+redis.call('PUBLISH', KEYS[2], id)
+return {'ok', tostring(id)}
+"#;
+
+/// Gives a coalesced response a new id once the minimum interval is over, like
+/// `flush_coalesced_response` of the memory storage. The next interval starts now.
+const FLUSH_COALESCED_RESPONSE_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+  return false
+end
+local stored = redis.call('HGET', KEYS[2], ARGV[1])
+if not stored then
+  return false
+end
+local response = cjson.decode(stored)
+if not response.coalesced then
+  return false
+end
+response.id = redis.call('HINCRBY', KEYS[1], 'next_response_id', 1) - 1
+response.started = ARGV[2]
+response.coalesced = false
+redis.call('HSET', KEYS[2], ARGV[1], cjson.encode(response))
+redis.call('PUBLISH', KEYS[2], response.id)
+return true
+"#;
+
+const GET_RESPONSES_SCRIPT: &str = r#"
+local next_response_id = redis.call('HGET', KEYS[1], 'next_response_id')
+if not next_response_id then
+  return false
+end
+redis.call('HSET', KEYS[1], 'last_request', ARGV[1])
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+redis.call('EXPIRE', KEYS[2], ARGV[2])
+return {
+  next_response_id,
+  redis.call('HGETALL', KEYS[2]),
+  redis.call('HGET', KEYS[1], 'choices') or '',
+}
+"#;
+
+const TOUCH_SESSION_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+  return false
+end
+redis.call('HSET', KEYS[1], 'last_request', ARGV[1])
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+redis.call('EXPIRE', KEYS[2], ARGV[2])
+return true
+"#;
+
+#[derive(serde::Deserialize)]
+struct StoredResponse {
+    data: String,
+    /// Missing in responses that were stored by older versions.
+    #[serde(default = "default_content_type")]
+    content_type: String,
+    id: usize,
+    /// Milliseconds since the epoch.
+    time: String,
+    /// Missing in responses that were stored by older versions.
+    #[serde(default)]
+    revision: u32,
+}
+
+impl StoredResponse {
+    fn into_listed(self, user: UserID, settings: &Settings) -> ListedResponse {
+        let time = self
+            .time
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+            .unwrap_or_else(|| settings.now());
+        ListedResponse {
+            user,
+            data: self.data,
+            content_type: self.content_type,
+            id: self.id,
+            time,
+            revision: self.revision,
+        }
+    }
+}
+
+/// Choices are stored as json array. Sessions without choices have none stored.
+fn parse_choices(choices: &str) -> Option<Vec<String>> {
+    serde_json::from_str(choices).ok()
+}
+
+fn default_content_type() -> String {
+    storage::DEFAULT_CONTENT_TYPE.to_string()
+}
+
+impl RedisStorage {
+    pub async fn connect(url: &str, settings: Settings) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let connection = ConnectionManager::new(client.clone()).await?;
+        let notifiers = Arc::new(Mutex::new(HashMap::new()));
+        let storage = RedisStorage {
+            settings,
+            connection,
+            notifiers: notifiers.clone(),
+        };
+        let pattern = storage.responses_key("*");
+        let prefix_len = pattern.len() - 1;
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) =
+                    forward_notifications(&client, &pattern, prefix_len, &notifiers).await
+                {
+                    println!("Lost Redis subscription: {}", err);
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+        Ok(storage)
+    }
+
+    /// Sorted set of the keys of all sessions, see [`SET_PAGE_SCRIPT`].
+    fn sessions_key(&self) -> String {
+        format!("{}sessions", self.settings.redis_key_prefix)
+    }
+
+    /// Like [`RedisStorage::sessions_key`], but only the sessions created from the ip.
+    fn sessions_by_ip_key(&self, ip: Option<IpAddr>) -> String {
+        let ip = ip.map(|ip| ip.to_string()).unwrap_or_default();
+        format!("{}sessions_by_ip:{}", self.settings.redis_key_prefix, ip)
+    }
+
+    fn session_key(&self, session_id: &str) -> String {
+        format!("{}session:{}", self.settings.redis_key_prefix, session_id)
+    }
+
+    fn responses_key(&self, session_id: &str) -> String {
+        format!("{}responses:{}", self.settings.redis_key_prefix, session_id)
+    }
+
+    /// Maps retried responses to the id of the original response, see
+    /// [`ResponseConditions::idempotency_key`].
+    fn idempotency_key(&self, session_id: &str) -> String {
+        format!(
+            "{}idempotency:{}",
+            self.settings.redis_key_prefix, session_id
+        )
+    }
+
+    fn ttl_seconds(&self) -> u64 {
+        self.settings
+            .tunables()
+            .session_keep_alive_duration
+            .as_secs()
+            .max(1)
+    }
+
+    /// Number of users whose response has an id of at least `start`.
+    async fn count_responses_since(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+    ) -> Result<usize, AppError> {
+        let stored_responses: Vec<String> = redis::cmd("HVALS")
+            .arg(self.responses_key(&session_id.0))
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        Ok(stored_responses
+            .iter()
+            .filter_map(|stored| serde_json::from_str::<StoredResponse>(stored).ok())
+            .filter(|stored| stored.id >= start)
+            .count())
+    }
+
+    fn flush_coalesced_response_later(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+        delay: Duration,
+    ) {
+        let session_key = self.session_key(&session_id.0);
+        let responses_key = self.responses_key(&session_id.0);
+        let user_id = user_id.clone();
+        let settings = self.settings.clone();
+        let mut connection = self.connection.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let result: redis::RedisResult<Option<bool>> =
+                redis::Script::new(FLUSH_COALESCED_RESPONSE_SCRIPT)
+                    .key(session_key)
+                    .key(responses_key)
+                    .arg(&user_id.0)
+                    .arg(settings.now().timestamp_millis())
+                    .invoke_async(&mut connection)
+                    .await;
+            if let Err(err) = result {
+                server_error(err);
+            }
+        });
+    }
+
+    /// Does not create the session if it has been removed in the mean-time.
+    async fn touch_session(&self, session_id: &SessionID) -> Result<(), AppError> {
+        let _: Option<bool> = redis::Script::new(TOUCH_SESSION_SCRIPT)
+            .key(self.session_key(&session_id.0))
+            .key(self.responses_key(&session_id.0))
+            .arg(self.settings.now().timestamp_millis())
+            .arg(self.ttl_seconds())
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        Ok(())
+    }
+}
+
+/// Wakes up long-polls of this instance when a response is inserted by any instance.
+async fn forward_notifications(
+    client: &redis::Client,
+    pattern: &str,
+    prefix_len: usize,
+    notifiers: &Mutex<HashMap<SessionID, Arc<Notify>>>,
+) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.psubscribe(pattern).await?;
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let Some(session_id) = message.get_channel_name().get(prefix_len..) else {
+            continue;
+        };
+        if let Some(notifier) = notifiers.lock().get(&SessionID(session_id.to_string())) {
+            notifier.notify_waiters();
+        }
+    }
+    Ok(())
+}
+
+fn server_error(err: redis::RedisError) -> AppError {
+    request_id::log(format!("Redis error: {}", err));
+    AppError::ServerError
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn get_page(&self, session_id: &SessionID) -> Result<StoredPage, AppError> {
+        let (page, lock_first_response, max_response_size_override, voting_deadline): (
+            Option<String>,
+            Option<String>,
+            Option<u64>,
+            Option<i64>,
+        ) = redis::cmd("HMGET")
+            .arg(self.session_key(&session_id.0))
+            .arg(&[
+                "page",
+                "lock_first_response",
+                "max_response_size",
+                "voting_deadline",
+            ])
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        let Some(page) = page else {
+            return Err(AppError::SessionIDDoesNotExist);
+        };
+        if self.settings.touch_on_read {
+            self.touch_session(session_id).await?;
+        }
+        let max_response_size = self.settings.tunables().max_response_size.as_u64();
+        let voting_deadline = voting_deadline.and_then(DateTime::from_timestamp_millis);
+        Ok(StoredPage {
+            page: page.into(),
+            client_config: ClientConfig {
+                max_response_size: max_response_size
+                    .min(max_response_size_override.unwrap_or(max_response_size)),
+                max_user_id_length: self.settings.max_user_id_length,
+                accepting_responses: voting_deadline
+                    .is_none_or(|deadline| self.settings.now() < deadline),
+                lock_first_response: lock_first_response.as_deref() == Some("1"),
+                voting_deadline,
+            },
+        })
+    }
+
+    async fn set_page(
+        &self,
+        session_id: &SessionID,
+        access_token: AccessToken,
+        page: String,
+        update: PageUpdate,
+    ) -> Result<PageChange, AppError> {
+        if session_id.0.len() > self.settings.max_session_id_length {
+            return Err(AppError::BadSessionID);
+        }
+        if update.anonymous {
+            self.check_supported("anonymous")?;
+        }
+        if update.keep_response_schema {
+            self.check_supported("keep_response_schema")?;
+        }
+        let now = self.settings.now();
+        let signed_issued_at = access_token
+            .signed_issue_time_for(&self.settings, session_id, now)
+            .map(|issued_at| issued_at.timestamp_millis().to_string())
+            .unwrap_or_default();
+        let flag = |value: Option<bool>| match value {
+            None => String::new(),
+            Some(value) => (value as u8).to_string(),
+        };
+        let result: String = redis::Script::new(SET_PAGE_SCRIPT)
+            .key(self.session_key(&session_id.0))
+            .key(self.responses_key(&session_id.0))
+            .key(self.idempotency_key(&session_id.0))
+            .key(self.sessions_key())
+            .key(self.sessions_by_ip_key(update.creator_ip))
+            .arg(&access_token.0)
+            .arg(page)
+            .arg(now.timestamp_millis())
+            .arg(self.settings.token_timeout.as_millis() as u64)
+            .arg(update.allow_create as u8)
+            .arg(signed_issued_at)
+            .arg(flag(update.lock_first_response))
+            .arg(flag(update.lock_sticky))
+            .arg(
+                update
+                    .max_response_size
+                    .map(|size| size.as_u64().to_string())
+                    .unwrap_or_default(),
+            )
+            .arg(self.ttl_seconds())
+            .arg(
+                update
+                    .choices
+                    .map(|choices| serde_json::json!(choices).to_string())
+                    .unwrap_or_default(),
+            )
+            .arg(update.skip_unchanged as u8)
+            .arg(
+                update
+                    .voting_deadline
+                    .map(|deadline| deadline.timestamp_millis().to_string())
+                    .unwrap_or_default(),
+            )
+            .arg(self.settings.tunables().max_sessions_total)
+            .arg(self.settings.tunables().max_sessions_per_ip)
+            .arg(if update.creator_ip.is_some() { "1" } else { "" })
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        match result.as_str() {
+            "ok" => Ok(PageChange::Updated),
+            "unchanged" => Ok(PageChange::Unchanged),
+            "not_found" => Err(AppError::SessionIDDoesNotExist),
+            "bad_token" => Err(AppError::BadAccessToken),
+            "limit_total" => Err(AppError::SessionLimitReached { limit: "total" }),
+            "limit_per_ip" => Err(AppError::SessionLimitReached { limit: "per_ip" }),
+            _ => Err(AppError::ServerError),
+        }
+    }
+
+    async fn insert_response(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+        data: String,
+        content_type: String,
+        conditions: ResponseConditions,
+    ) -> Result<usize, AppError> {
+        // Json, so that the user id and the key can't be confused.
+        let idempotency_field = conditions
+            .idempotency_key
+            .map(|key| serde_json::json!([user_id.0, key]).to_string())
+            .unwrap_or_default();
+        let result: Vec<String> = redis::Script::new(INSERT_RESPONSE_SCRIPT)
+            .key(self.session_key(&session_id.0))
+            .key(self.responses_key(&session_id.0))
+            .key(self.idempotency_key(&session_id.0))
+            .arg(&user_id.0)
+            .arg(data)
+            .arg(self.settings.now().timestamp_millis())
+            .arg(self.settings.tunables().max_response_size.as_u64())
+            .arg(self.ttl_seconds())
+            .arg(self.settings.tunables().min_response_interval.as_millis() as u64)
+            .arg(match self.settings.response_throttle {
+                ResponseThrottle::Reject => "reject",
+                ResponseThrottle::Coalesce => "coalesce",
+            })
+            .arg(self.settings.tunables().max_users_per_session)
+            .arg(idempotency_field)
+            .arg(
+                conditions
+                    .if_match
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+            )
+            .arg(MAX_IDEMPOTENCY_KEYS)
+            .arg(content_type)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        let id_at = |index: usize| result.get(index).and_then(|id| id.parse().ok());
+        match result.first().map(|status| status.as_str()) {
+            Some("ok") => {
+                // Set when the response has been coalesced with the first one in the interval.
+                if let Some(delay_ms) = result.get(2).and_then(|ms| ms.parse().ok()) {
+                    self.flush_coalesced_response_later(
+                        session_id,
+                        user_id,
+                        Duration::from_millis(delay_ms),
+                    );
+                }
+                id_at(1).ok_or(AppError::ServerError)
+            }
+            Some("invalid_choice") => Err(storage::invalid_choice(
+                &parse_choices(result.get(1).map(String::as_str).unwrap_or_default())
+                    .unwrap_or_default(),
+            )),
+            Some("conflict") => Err(AppError::ResponseConflict {
+                current_id: id_at(1),
+            }),
+            Some("not_found") => Err(AppError::SessionIDDoesNotExist),
+            Some("too_large") => Err(AppError::ResponseTooLarge),
+            Some("voting_closed") => Err(AppError::VotingClosed),
+            Some("locked") => Err(AppError::ResponseLocked {
+                response: result.get(1).cloned().unwrap_or_default(),
+            }),
+            Some("too_many_users") => Err(AppError::TooManyUsers),
+            Some("throttled") => Err(AppError::TooManyRequests {
+                retry_after: Duration::from_millis(
+                    result.get(1).and_then(|ms| ms.parse().ok()).unwrap_or(0),
+                ),
+            }),
+            _ => Err(AppError::ServerError),
+        }
+    }
+
+    async fn get_user_response(
+        &self,
+        session_id: &SessionID,
+        user_id: &UserID,
+    ) -> Result<Option<ListedResponse>, AppError> {
+        let (session_exists, stored_response): (bool, Option<String>) = redis::pipe()
+            .exists(self.session_key(&session_id.0))
+            .hget(self.responses_key(&session_id.0), &user_id.0)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        if !session_exists {
+            return Err(AppError::SessionIDDoesNotExist);
+        }
+        Ok(stored_response
+            .and_then(|stored| serde_json::from_str::<StoredResponse>(&stored).ok())
+            .map(|stored| stored.into_listed(user_id.clone(), &self.settings)))
+    }
+
+    async fn check_read_access(
+        &self,
+        session_id: &SessionID,
+        access_token: &AccessToken,
+    ) -> Result<(), AppError> {
+        let token: Option<String> = redis::cmd("HGET")
+            .arg(self.session_key(&session_id.0))
+            .arg("token")
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(server_error)?;
+        match token {
+            None => Err(AppError::SessionIDDoesNotExist),
+            Some(token)
+                if access_token::constant_time_eq(token.as_bytes(), access_token.0.as_bytes()) =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(AppError::BadAccessToken),
+        }
+    }
+
+    async fn wait_for_responses(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+        min_count: usize,
+        timeout: Duration,
+    ) -> Result<(), AppError> {
+        let notifier = self
+            .notifiers
+            .lock()
+            .entry(session_id.clone())
+            .or_default()
+            .clone();
+        let deadline = tokio::time::Instant::now() + timeout;
+        let result = loop {
+            // Created before checking for responses, so that no notification is missed.
+            let notified = notifier.notified();
+            let next_response_id: Option<usize> = match redis::cmd("HGET")
+                .arg(self.session_key(&session_id.0))
+                .arg("next_response_id")
+                .query_async(&mut self.connection.clone())
+                .await
+            {
+                Ok(next_response_id) => next_response_id,
+                Err(err) => break Err(server_error(err)),
+            };
+            let Some(next_response_id) = next_response_id else {
+                break Err(AppError::SessionIDDoesNotExist);
+            };
+            if timeout.is_zero() || min_count == 0 {
+                break Ok(());
+            }
+            if next_response_id > start {
+                if min_count == 1 {
+                    break Ok(());
+                }
+                match self.count_responses_since(session_id, start).await {
+                    Ok(count) if count >= min_count => break Ok(()),
+                    Ok(_) => {}
+                    Err(err) => break Err(err),
+                }
+            }
+            tokio::select! {
+                _ = notified => {},
+                _ = tokio::time::sleep_until(deadline) => break Ok(()),
+            }
+        };
+        let mut notifiers = self.notifiers.lock();
+        // The map and this function hold the only references when nobody else is waiting.
+        if Arc::strong_count(&notifier) <= 2 {
+            notifiers.remove(session_id);
+        }
+        result
+    }
+
+    async fn get_responses(
+        &self,
+        session_id: &SessionID,
+        start: usize,
+        limit: usize,
+    ) -> Result<StoredResponses, AppError> {
+        let result: Option<(usize, HashMap<String, String>, String)> =
+            redis::Script::new(GET_RESPONSES_SCRIPT)
+                .key(self.session_key(&session_id.0))
+                .key(self.responses_key(&session_id.0))
+                .arg(self.settings.now().timestamp_millis())
+                .arg(self.ttl_seconds())
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .map_err(server_error)?;
+        let Some((next_start, stored_responses, choices)) = result else {
+            return Err(AppError::SessionIDDoesNotExist);
+        };
+        let total_responses = stored_responses.len();
+        let mut responses = vec![];
+        for (user_id, stored_response) in stored_responses {
+            let Ok(stored_response) = serde_json::from_str::<StoredResponse>(&stored_response)
+            else {
+                continue;
+            };
+            if stored_response.id < start {
+                continue;
+            }
+            responses.push(stored_response.into_listed(UserID(user_id), &self.settings));
+        }
+        let (responses, next_start) =
+            storage::take_responses(responses, |response| response.id, start, next_start, limit);
+        Ok(StoredResponses {
+            next_start,
+            responses,
+            total_responses,
+            choices: parse_choices(&choices),
+        })
+    }
+
+    /// Only responses are stored per user in Redis. The sessions are found with `SCAN`, so
+    /// that Redis is not blocked when there are many.
+    async fn erase_user_data(&self, user_id: &UserID) -> Result<(usize, usize), AppError> {
+        let mut connection = self.connection.clone();
+        let pattern = self.responses_key("*");
+        let mut cursor: u64 = 0;
+        let mut sessions = 0;
+        loop {
+            let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut connection)
+                .await
+                .map_err(server_error)?;
+            for key in keys {
+                let removed: usize = redis::cmd("HDEL")
+                    .arg(&key)
+                    .arg(&user_id.0)
+                    .query_async(&mut connection)
+                    .await
+                    .map_err(server_error)?;
+                if removed > 0 {
+                    sessions += 1;
+                    // Long-polls return like for a new response, so that the response
+                    // disappears from the results.
+                    let _: usize = redis::cmd("PUBLISH")
+                        .arg(&key)
+                        .arg("erased")
+                        .query_async(&mut connection)
+                        .await
+                        .map_err(server_error)?;
+                }
+            }
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+        Ok((sessions, sessions))
+    }
+
+    fn check_supported(&self, option: &'static str) -> Result<(), AppError> {
+        Err(AppError::NotSupported { option })
+    }
+}
@@ -0,0 +1,35 @@
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Reads the certificate chain and private key from pem files. The errors mention the
+/// file that caused them, because they are shown when the server fails to start.
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, String> {
+    let cert_chain = rustls_pemfile::certs(&mut open(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Cannot parse {}: {}", cert_path.display(), err))?;
+    if cert_chain.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key_path)?)
+        .map_err(|err| format!("Cannot parse {}: {}", key_path.display(), err))?
+        .ok_or_else(|| format!("No private key found in {}", key_path.display()))?;
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(|err| {
+            format!(
+                "Cannot use {} with {}: {}",
+                cert_path.display(),
+                key_path.display(),
+                err
+            )
+        })
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))
+}
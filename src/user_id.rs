@@ -1,16 +1,59 @@
-use crate::AppError;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha2::Sha256;
+
+use crate::{session_id::is_valid_id, AppError};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String")]
 pub struct UserID(pub String);
 
 impl UserID {
     pub fn from_string(s: &str) -> Result<UserID, AppError> {
-        if s.is_empty() {
-            Err(AppError::BadUserID)
-        } else if s.len() > 100 {
+        if !is_valid_id(s) {
             Err(AppError::BadUserID)
         } else {
             Ok(UserID(s.to_string()))
         }
     }
+
+    /// Opaque id that the server issues with `/join`.
+    pub fn new_random() -> UserID {
+        let mut rng = rand::rngs::OsRng;
+        UserID(
+            (0..16)
+                .map(|_| rng.sample(rand::distributions::Alphanumeric) as char)
+                .collect(),
+        )
+    }
+
+    /// Keyed hash of the id, so that the same user gets the same id within a session but the
+    /// original id can't be recovered without the salt.
+    pub fn anonymized(&self, salt: &[u8]) -> UserID {
+        let mut mac = Hmac::<Sha256>::new_from_slice(salt).expect("HMAC accepts keys of any size");
+        mac.update(self.0.as_bytes());
+        UserID(
+            mac.finalize()
+                .into_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        )
+    }
+}
+
+/// Random salt for [`UserID::anonymized`], one per session.
+pub fn new_anonymous_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Allows using the id directly in query parameters, so that it is validated on extraction.
+impl TryFrom<String> for UserID {
+    type Error = AppError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        UserID::from_string(&s)
+    }
 }
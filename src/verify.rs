@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+
+use crate::{SessionID, SessionState, State};
+
+/// Internal invariants of the state. Violations indicate bugs somewhere else.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Invariant {
+    /// All stored responses have an id smaller than `next_response_id`.
+    NextResponseIdAfterResponses,
+    /// No two stored responses have the same id.
+    UniqueResponseIds,
+    /// The digest does not start after the next response.
+    DigestCursorInRange,
+    /// Sessions can't be used in the future.
+    LastRequestNotInFuture,
+    /// The tracked memory usage is the same as when all sessions are counted again. It's
+    /// only checked for the whole state.
+    ApproxBytesMatchesRecount,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Violation {
+    /// Empty for invariants of the whole state.
+    pub session: String,
+    pub invariant: Invariant,
+    pub message: String,
+    pub repaired: bool,
+}
+
+/// Checks all sessions. Violations that can be fixed without losing data are repaired
+/// if requested.
+pub fn verify_state(state: &mut State, now: DateTime<Utc>, repair: bool) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (session_id, session) in state.sessions.iter_mut() {
+        violations.extend(verify_session(session_id, session, now, repair));
+    }
+
+    let approx_bytes = state.approx_bytes;
+    state.recount_memory_usage();
+    if state.approx_bytes != approx_bytes {
+        violations.push(Violation {
+            session: String::new(),
+            invariant: Invariant::ApproxBytesMatchesRecount,
+            message: format!(
+                "Tracked memory usage is {} bytes, but counting again results in {} bytes.",
+                approx_bytes, state.approx_bytes
+            ),
+            repaired: repair,
+        });
+        if !repair {
+            state.approx_bytes = approx_bytes;
+        }
+    }
+    violations
+}
+
+pub fn verify_session(
+    session_id: &SessionID,
+    session: &mut SessionState,
+    now: DateTime<Utc>,
+    repair: bool,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut add_violation = |invariant: Invariant, message: String, repaired: bool| {
+        violations.push(Violation {
+            session: session_id.0.clone(),
+            invariant,
+            message,
+            repaired,
+        })
+    };
+
+    if let Some(max_id) = session.responses.values().map(|response| response.id).max() {
+        if max_id >= session.next_response_id {
+            add_violation(
+                Invariant::NextResponseIdAfterResponses,
+                format!(
+                    "Response id {} is not smaller than next response id {}.",
+                    max_id, session.next_response_id
+                ),
+                repair,
+            );
+            if repair {
+                session.next_response_id = max_id + 1;
+            }
+        }
+    }
+
+    let mut ids = HashSet::new();
+    for response in session.responses.values() {
+        if !ids.insert(response.id) {
+            // Can't be repaired without changing which responses clients have seen.
+            add_violation(
+                Invariant::UniqueResponseIds,
+                format!("Response id {} is used more than once.", response.id),
+                false,
+            );
+        }
+    }
+
+    if let Some(digest) = &mut session.digest {
+        if digest.start > session.next_response_id {
+            add_violation(
+                Invariant::DigestCursorInRange,
+                format!(
+                    "Digest starts at {} but the next response id is {}.",
+                    digest.start, session.next_response_id
+                ),
+                repair,
+            );
+            if repair {
+                digest.start = session.next_response_id;
+            }
+        }
+    }
+
+    if session.last_request > now {
+        add_violation(
+            Invariant::LastRequestNotInFuture,
+            format!("Last request at {} is in the future.", session.last_request),
+            repair,
+        );
+        if repair {
+            session.last_request = now;
+        }
+    }
+
+    violations
+}
@@ -0,0 +1,163 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::Settings;
+
+/// Header with the signature of the body, see [`deliver_signed_with_retry`].
+pub const SIGNATURE_HEADER: &str = "X-Polli-Signature";
+
+/// Posts the json payload to the url. Failed deliveries are retried with exponential
+/// backoff. Returns true when the payload has been delivered.
+pub async fn deliver_with_retry<T: serde::Serialize>(
+    settings: &Settings,
+    url: &str,
+    payload: &T,
+) -> bool {
+    let body = serde_json::to_vec(payload).unwrap();
+    post_with_retry(settings, url, body, None).await
+}
+
+/// Like [`deliver_with_retry`], but the body is signed with the secret, so that the receiver
+/// can check that it comes from the server. The header looks like `sha256=<hex-hmac>`.
+pub async fn deliver_signed_with_retry<T: serde::Serialize>(
+    settings: &Settings,
+    url: &str,
+    payload: &T,
+    secret: &str,
+) -> bool {
+    let body = serde_json::to_vec(payload).unwrap();
+    let signature = format!("sha256={}", sign(secret, &body));
+    post_with_retry(settings, url, body, Some(signature)).await
+}
+
+async fn post_with_retry(
+    settings: &Settings,
+    url: &str,
+    body: Vec<u8>,
+    signature: Option<String>,
+) -> bool {
+    let mut backoff = settings.webhook_initial_backoff;
+    for attempt in 0..settings.webhook_max_attempts {
+        if attempt > 0 {
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+        let client = match client_for(settings, url).await {
+            Ok(client) => client,
+            Err(err) => {
+                println!("Webhook {} refused: {}", url, err);
+                continue;
+            }
+        };
+        let mut request = client
+            .post(url)
+            .timeout(Duration::from_secs(10))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        match request.send().await {
+            Ok(res) if res.status().is_success() => return true,
+            Ok(res) => println!("Webhook {} responded with {}", url, res.status()),
+            Err(err) => println!("Webhook {} failed: {}", url, err),
+        }
+    }
+    false
+}
+
+/// Redirects are not followed, because they could point anywhere. The address of the host
+/// is checked before every attempt, because it may change, and the client only connects to
+/// the checked addresses, so that the host can't resolve to another one in the meantime.
+async fn client_for(settings: &Settings, url: &str) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if !settings.allow_private_webhook_urls {
+        if let Some((host, addresses)) = resolve_public_url(url).await? {
+            builder = builder.resolve_to_addrs(&host, &addresses);
+        }
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+/// Hex encoded HMAC-SHA256 of the body.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn is_valid_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Urls that point to the server itself or the network it runs in could be used to reach
+/// services that are not meant to be public. Hosts that can't be resolved are rejected.
+pub async fn check_public_url(url: &str) -> Result<(), String> {
+    resolve_public_url(url).await.map(|_| ())
+}
+
+/// Like [`check_public_url`], but also returns the host with the checked addresses, unless
+/// the url contains an ip already.
+async fn resolve_public_url(url: &str) -> Result<Option<(String, Vec<SocketAddr>)>, String> {
+    let url = reqwest::Url::parse(url).map_err(|err| err.to_string())?;
+    let Some(host) = url.host_str() else {
+        return Err("missing host".to_string());
+    };
+    // Ipv6 addresses are enclosed in brackets.
+    if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+        return check_public_ip(ip).map(|_| None);
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| format!("cannot resolve {}: {}", host, err))?
+        .collect();
+    for address in &addresses {
+        check_public_ip(address.ip())?;
+    }
+    if addresses.is_empty() {
+        return Err(format!("cannot resolve {}", host));
+    }
+    Ok(Some((host.to_string(), addresses)))
+}
+
+fn check_public_ip(ip: IpAddr) -> Result<(), String> {
+    match is_private_ip(ip) {
+        true => Err(format!("{} is not a public address", ip)),
+        false => Ok(()),
+    }
+}
+
+pub fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Shared address space of carrier-grade NAT.
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_private_ip(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local addresses.
+                || (first & 0xfe00) == 0xfc00
+                // Link local addresses.
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
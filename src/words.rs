@@ -0,0 +1,21 @@
+//! Short and easy to pronounce words for human-friendly session ids.
+
+pub const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "bright", "blue", "calm", "clever", "cool", "crisp", "curly",
+    "daring", "eager", "early", "fancy", "fast", "fuzzy", "gentle", "giant", "golden", "green",
+    "happy", "honest", "jolly", "kind", "lively", "lucky", "mighty", "misty", "modern", "noble",
+    "odd", "orange", "plain", "polite", "proud", "purple", "quick", "quiet", "rapid", "red",
+    "rosy", "royal", "shiny", "silent", "silver", "simple", "sleepy", "smart", "snowy", "soft",
+    "solid", "sunny", "swift", "tall", "tidy", "tiny", "vivid", "warm", "wild", "windy", "wise",
+    "witty", "yellow", "young",
+];
+
+pub const NOUNS: &[&str] = &[
+    "apple", "badger", "bear", "bird", "cactus", "camel", "cloud", "comet", "coral", "crane",
+    "daisy", "dolphin", "dragon", "eagle", "falcon", "fern", "fox", "frog", "garden", "gecko",
+    "glacier", "harbor", "hawk", "island", "koala", "lake", "lemon", "lion", "lotus", "maple",
+    "meadow", "moon", "moose", "nebula", "ocean", "otter", "owl", "panda", "parrot", "pebble",
+    "pepper", "planet", "pony", "rabbit", "raven", "river", "robin", "rocket", "salmon", "seal",
+    "shark", "sparrow", "star", "storm", "tiger", "tulip", "turtle", "valley", "walrus", "whale",
+    "willow", "wolf", "yak", "zebra",
+];
@@ -0,0 +1,209 @@
+const polli_live = (function () {
+  function get_local_user() {
+    let user_id = localStorage.getItem("user_id");
+    if (!user_id) {
+      user_id = Math.random().toString(36).substr(2, 9);
+    }
+    localStorage.setItem("user_id", user_id);
+    return user_id;
+  }
+
+  // The server issues the id once and keeps it in a cookie, so joining again after a reload
+  // returns the same id. Servers that can't issue ids get a locally generated one.
+  let user_promise = null;
+  function get_user() {
+    if (!user_promise) {
+      user_promise = (async () => {
+        const url = `${get_server_url()}/join?session=${get_session_id()}`;
+        try {
+          const res = await fetch(url, { headers: { Accept: "application/json" } });
+          if (res.ok) {
+            return (await res.json()).user;
+          }
+        } catch {}
+        return get_local_user();
+      })();
+    }
+    return user_promise;
+  }
+
+  // Random id of this tab, so that the server can count the audience. It is kept when the
+  // page reloads.
+  function get_client_id() {
+    let client_id = sessionStorage.getItem("client_id");
+    if (!client_id) {
+      client_id = Math.random().toString(36).substr(2, 9);
+      sessionStorage.setItem("client_id", client_id);
+    }
+    return client_id;
+  }
+
+  function get_session_id() {
+    const params = new URLSearchParams(window.location.search);
+    return params.get("session");
+  }
+
+  function auto_reload() {
+    const session = get_session_id();
+    const client = get_client_id();
+    const url = `${get_server_url()}/wait_for_new_page?session=${session}&client=${client}`;
+
+    const handler = async () => {
+      let some_failure = false;
+      try {
+        const res = await fetch(url);
+        if (res.ok) {
+          const text = await res.text();
+          if (text === "reload") {
+            location.reload();
+          }
+        } else {
+          some_failure = true;
+        }
+      } catch {
+        some_failure = true;
+      }
+      setTimeout(handler, some_failure ? 3000 : 0);
+    };
+
+    setTimeout(handler, 0);
+    show_messages();
+    keep_alive();
+    document.addEventListener("visibilitychange", () => {
+      if (document.visibilityState === "visible") {
+        location.reload();
+      }
+    });
+  }
+
+  // Keeps the session alive while the page is visible, even if the presenter does not change
+  // it for a long time. Stops once the server reports that the session has ended.
+  function keep_alive() {
+    const url = `${get_server_url()}/ping?session=${get_session_id()}&client=${get_client_id()}`;
+    const interval = setInterval(async () => {
+      if (document.visibilityState !== "visible") {
+        return;
+      }
+      try {
+        const res = await fetch(url, { method: "POST" });
+        if (res.status === 404 || res.status === 410) {
+          clearInterval(interval);
+          show_toast("The session has ended.");
+        }
+      } catch {}
+    }, 3 * 60 * 1000);
+  }
+
+  // Shows messages of the presenter for a few seconds without changing the page.
+  function show_messages() {
+    const session = get_session_id();
+    let since = 0;
+
+    const handler = async () => {
+      let some_failure = false;
+      try {
+        const url = `${get_server_url()}/message?session=${session}&since=${since}`;
+        const res = await fetch(url);
+        if (res.status === 200) {
+          const message = await res.json();
+          // Messages from before the page has been loaded are shown as well, as long as
+          // they have not expired.
+          since = message.id;
+          show_toast(message.text);
+        } else if (!res.ok) {
+          some_failure = true;
+        }
+      } catch {
+        some_failure = true;
+      }
+      setTimeout(handler, some_failure ? 3000 : 0);
+    };
+
+    setTimeout(handler, 0);
+  }
+
+  function show_toast(text) {
+    const toast = document.createElement("div");
+    toast.textContent = text;
+    toast.style.cssText =
+      "position: fixed; left: 50%; bottom: 2em; transform: translateX(-50%); z-index: 10000;" +
+      "padding: 0.8em 1.2em; border-radius: 0.5em; background: #333; color: #fff;" +
+      "font: 1.2em sans-serif; box-shadow: 0 2px 8px rgba(0, 0, 0, 0.3);";
+    document.body.appendChild(toast);
+    setTimeout(() => toast.remove(), 8000);
+  }
+
+  // Calls the callback with the aggregated responses of the session whenever they change.
+  // It's used by the dashboard, which has the token of the presenter in its url, and by the
+  // public results page, which doesn't need a token.
+  function watch_aggregate(callback) {
+    const token = new URLSearchParams(window.location.search).get("token");
+    const url = `${get_server_url()}/responses/aggregate?session=${get_session_id()}`;
+    const headers = token ? { Authorization: `Bearer ${token}` } : {};
+    let previous = null;
+
+    const handler = async () => {
+      try {
+        const res = await fetch(url, { headers });
+        if (res.ok) {
+          const text = await res.text();
+          if (text !== previous) {
+            previous = text;
+            callback(JSON.parse(text));
+          }
+        }
+      } catch {}
+      setTimeout(handler, 2000);
+    };
+
+    setTimeout(handler, 0);
+  }
+
+  function get_server_url() {
+    // Pages are served from `<base-path>/page`, and the dashboard and results from paths
+    // next to it, where the server may be behind a proxy.
+    const base_path = window.location.pathname.replace(/\/(page|dashboard|results)$/, "");
+    return `${window.location.protocol}//${window.location.host}${base_path}`;
+  }
+
+  // Resolves to null when the response has been stored. Otherwise it resolves to the error
+  // code, e.g. "voting_closed", so that the page can tell the user.
+  async function respond(data_str) {
+    const session = get_session_id();
+    const user = await get_user();
+    const url = `${get_server_url()}/respond?user=${user}&session=${session}`;
+    try {
+      const res = await fetch(url, {
+        method: "POST",
+        body: data_str,
+        headers: { Accept: "application/json" },
+      });
+      if (res.ok) {
+        return null;
+      }
+      return (await res.json()).error;
+    } catch {
+      return "network_error";
+    }
+  }
+
+  // Resolves to the data of the previous response of this user to the current page, or null.
+  async function get_own_response() {
+    const session = get_session_id();
+    const user = await get_user();
+    const url = `${get_server_url()}/respond?user=${user}&session=${session}`;
+    const res = await fetch(url, { headers: { Accept: "application/json" } });
+    if (!res.ok) {
+      return null;
+    }
+    return (await res.json()).data;
+  }
+
+  return {
+    respond,
+    get_own_response,
+    auto_reload,
+    get_session_id,
+    watch_aggregate,
+  };
+})();
@@ -0,0 +1,8143 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use std::net::TcpListener;
+
+use polli_live::{
+    audit_log::{AuditEventKind, PageAccess, RemovalReason},
+    cleanup, cli,
+    client_config::ClientConfig,
+    clock::{self, Clock, SystemClock},
+    commands, config, digest,
+    encoding::Encoding,
+    errors::ErrorBody,
+    expired_sessions::ExpiredSessions,
+    page,
+    page_content::PageContent,
+    page_patch::{self, PageEdit},
+    persist, push,
+    rate_limit::{RateLimit, RateLimitKind, RateLimiter},
+    response_filter::{FilterMode, ResponseFilter},
+    response_schema::ResponseSchema,
+    response_webhook, routes, scheduled_page,
+    security_headers::FrameOptions,
+    session_assets::SessionAsset,
+    settings::{self, CorsPolicy, ResponseThrottle},
+    start_server::Listener,
+    state, static_files, statistics,
+    storage::{MemoryStorage, RedisStorage, Storage},
+    tls,
+    user_id::UserID,
+    verify, webhooks, AccessToken, RetrievedResponses, SessionID, SessionState, Settings, State,
+    UserResponse,
+};
+
+struct TestContext {
+    handle: tokio::task::JoinHandle<()>,
+    url: String,
+    client: reqwest::Client,
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for TestContext {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl TestContext {
+    /// The page without the injected script.
+    async fn request_session_page_text(&self, session_id: &str) -> String {
+        self.request_session_page(session_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+            .replacen(&page::injection_snippet(&self.settings), "", 1)
+    }
+
+    async fn request_session_page(&self, session_id: &str) -> reqwest::Response {
+        self.client
+            .get(format!("{}/page?session={}", &self.url, &session_id))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn request_page_update(
+        &self,
+        session_id: Option<&str>,
+        token: Option<&str>,
+        page: &str,
+    ) -> reqwest::Response {
+        let url = match session_id {
+            None => format!("{}/page", &self.url),
+            Some(session_id) => format!("{}/page?session={}", &self.url, session_id),
+        };
+        let mut builder = self.client.post(&url);
+        if let Some(token) = token {
+            builder = builder.bearer_auth(token);
+        }
+        builder = builder.body(page.to_string());
+        builder.send().await.unwrap()
+    }
+
+    async fn set_page_and_check(&self, session_id: &str, token: &str, page: &str) {
+        let res = self
+            .request_page_update(Some(session_id), Some(token), page)
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(self.request_session_page_text(session_id).await, page);
+    }
+
+    async fn send_reponse(
+        &self,
+        session_id: Option<&str>,
+        user_id: Option<&str>,
+        response_data: &str,
+    ) -> reqwest::Response {
+        let mut url = self.url.clone();
+        url.push_str("/respond?");
+        if let Some(session_id) = session_id {
+            url.push_str(&format!("session={}&", session_id));
+        }
+        if let Some(user_id) = user_id {
+            url.push_str(&format!("user={}", user_id));
+        }
+        self.client
+            .post(url)
+            .body(response_data.to_string())
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn request_responses(
+        &self,
+        session_id: Option<&str>,
+        start: Option<usize>,
+    ) -> reqwest::Response {
+        let mut url = self.url.clone();
+        url.push_str("/responses?");
+        if let Some(session_id) = session_id {
+            url.push_str(&format!("session={}&", session_id));
+        }
+        if let Some(start) = start {
+            url.push_str(&format!("start={}", start));
+        }
+        self.client.get(&url).send().await.unwrap()
+    }
+
+    async fn request_new_session(&self, body: serde_json::Value) -> reqwest::Response {
+        self.request_json(self.client.post(format!("{}/new", self.url)).json(&body))
+            .await
+    }
+
+    async fn request_json(&self, builder: reqwest::RequestBuilder) -> reqwest::Response {
+        builder
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn request_static_page(&self, path: &str) -> reqwest::Response {
+        self.client
+            .get(format!("{}{}", self.url, path))
+            .send()
+            .await
+            .unwrap()
+    }
+}
+
+async fn assert_error_code(res: reqwest::Response, status: reqwest::StatusCode, code: &str) {
+    assert_eq!(res.status(), status);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, code);
+}
+
+fn parse_links(res: &reqwest::Response) -> HashMap<String, String> {
+    let header = res
+        .headers()
+        .get(reqwest::header::LINK)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    header
+        .split(", ")
+        .map(|link| {
+            let (url, rel) = link.split_once("; ").unwrap();
+            let url = url.trim_start_matches('<').trim_end_matches('>');
+            let rel = rel.trim_start_matches("rel=\"").trim_end_matches('"');
+            (rel.to_string(), url.to_string())
+        })
+        .collect()
+}
+
+fn start_test_server(
+    listener: TcpListener,
+    settings: Settings,
+    state: Arc<Mutex<State>>,
+    storage: Arc<dyn Storage>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let rate_limiter = Arc::new(RateLimiter::default());
+        polli_live::start_server::start_server(
+            vec![Listener::Tcp(listener)],
+            settings,
+            state,
+            storage,
+            rate_limiter,
+            None,
+        )
+        .await
+        .expect("failed to start server");
+    })
+}
+
+async fn setup() -> TestContext {
+    setup_with_settings(|_| {}).await
+}
+
+async fn setup_with_settings(modify_settings: impl FnOnce(&mut Settings)) -> TestContext {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to random port");
+    let port = listener.local_addr().unwrap().port();
+    let url = format!("http://127.0.0.1:{}", port);
+
+    let mut settings = Settings::default(url.clone());
+    modify_settings(&mut settings);
+    let state = Arc::new(Mutex::new(State {
+        ..Default::default()
+    }));
+
+    let storage = Arc::new(MemoryStorage::new(settings.clone(), state.clone()));
+    let server = start_test_server(listener, settings.clone(), state.clone(), storage);
+
+    // Wait for server to start.
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    TestContext {
+        handle: server,
+        url,
+        client: reqwest::Client::new(),
+        settings,
+        state,
+    }
+}
+
+#[tokio::test]
+async fn static_index_page() {
+    let ctx = setup().await;
+    let res = ctx.request_static_page("/").await;
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::render(&ctx.settings, "index.html", &[("root_url", &ctx.url)]).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn not_found_session_page() {
+    let ctx = setup().await;
+    let res = ctx.request_session_page("1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn not_found_session_page_json() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_json(ctx.client.get(format!("{}/page?session=1", ctx.url)))
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+}
+
+#[tokio::test]
+async fn not_found_responses_json() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/responses?session=1&start=0", ctx.url)),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+}
+
+#[tokio::test]
+async fn set_page_without_token() {
+    let ctx = setup().await;
+    let res = ctx.request_page_update(Some("1"), None, "test").await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn set_page_and_request() {
+    let ctx = setup().await;
+
+    let page = "my test page";
+
+    let res = ctx
+        .request_page_update(Some("1"), Some("my-test-token"), page)
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx.request_session_page("1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    // The script is injected at the start, because the page has no head.
+    assert_eq!(
+        res.text().await.unwrap(),
+        format!("{}{}", page::injection_snippet(&ctx.settings), page)
+    );
+}
+
+#[tokio::test]
+async fn set_page_twice_with_same_token() {
+    let ctx = setup().await;
+
+    let session = "a";
+    let token = "my-test-token";
+    let page_1 = "page one";
+    let page_2 = "page two";
+
+    let res = ctx
+        .request_page_update(Some(session), Some(token), "page one")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(ctx.request_session_page_text(session).await, page_1);
+
+    let res = ctx
+        .request_page_update(Some(session), Some(token), page_2)
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(ctx.request_session_page_text(session).await, page_2);
+}
+
+#[tokio::test]
+async fn try_update_page_with_other_token() {
+    let ctx = setup().await;
+
+    let session = "b";
+    let token_1 = "my-first-token";
+    let token_2 = "my-second-token";
+    let page_1 = "page 1";
+    let page_2 = "page 2";
+
+    let res = ctx
+        .request_page_update(Some(session), Some(token_1), page_1)
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(ctx.request_session_page_text(session).await, page_1);
+
+    let res = ctx
+        .request_page_update(Some(session), Some(token_2), page_2)
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(ctx.request_session_page_text(session).await, page_1);
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session={}", ctx.url, session))
+                .bearer_auth(token_2)
+                .body(page_2),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+}
+
+#[tokio::test]
+async fn single_response() {
+    let ctx = setup().await;
+
+    let session = "c";
+    let token = "my-test-token";
+    let page = "test page";
+    let user = "me";
+    let response_data = "42";
+
+    ctx.set_page_and_check(session, token, page).await;
+
+    let res = ctx
+        .send_reponse(Some(session), Some(user), response_data)
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx.request_responses(Some(session), Some(0)).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 1);
+    assert_eq!(result.responses_by_user.len(), 1);
+    assert_eq!(
+        result
+            .responses_by_user
+            .get(&UserID::from_string(user).unwrap())
+            .unwrap(),
+        response_data
+    );
+    assert_eq!(result.session, session);
+    assert_eq!(result.total_responses, 1);
+    let age = chrono::Utc::now() - result.server_time;
+    assert!(age.num_seconds().abs() < 60);
+}
+
+#[tokio::test]
+async fn restore_own_response() {
+    let ctx = setup().await;
+    let session = "c";
+    let token = "my-test-token";
+    ctx.set_page_and_check(session, token, "first page").await;
+
+    let own_response = |user: &str| {
+        ctx.request_json(ctx.client.get(format!(
+            "{}/respond?session={}&user={}",
+            ctx.url, session, user
+        )))
+    };
+
+    assert_error_code(
+        own_response("me").await,
+        reqwest::StatusCode::NOT_FOUND,
+        "response_not_found",
+    )
+    .await;
+    ctx.send_reponse(Some(session), Some("me"), "42").await;
+    ctx.send_reponse(Some(session), Some("other"), "7").await;
+
+    let res = own_response("me").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let response: routes::OwnResponse = res.json().await.unwrap();
+    assert_eq!(response.data, "42");
+    assert_eq!(response.id, 0);
+    let response: routes::OwnResponse = own_response("other").await.json().await.unwrap();
+    assert_eq!(response.data, "7");
+    assert_eq!(response.id, 1);
+
+    ctx.set_page_and_check(session, token, "second page").await;
+    assert_error_code(
+        own_response("me").await,
+        reqwest::StatusCode::NOT_FOUND,
+        "response_not_found",
+    )
+    .await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/respond?session=missing&user=me", ctx.url)),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+}
+
+async fn send_conditional_response(
+    ctx: &TestContext,
+    user: &str,
+    data: &str,
+    headers: &[(&str, &str)],
+) -> reqwest::Response {
+    let mut builder = ctx
+        .client
+        .post(format!("{}/respond?session=c&user={}", ctx.url, user))
+        .body(data.to_string());
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    ctx.request_json(builder).await
+}
+
+fn response_etag(res: &reqwest::Response) -> &str {
+    res.headers()
+        .get(reqwest::header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn retry_response_with_idempotency_key() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("c", "my-test-token", "page").await;
+
+    let key = [("Idempotency-Key", "submission-1")];
+    let res = send_conditional_response(&ctx, "me", "first", &key).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(response_etag(&res), "\"0\"");
+
+    // The retry returns the original result and does not store the data again.
+    let res = send_conditional_response(&ctx, "me", "retried", &key).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(response_etag(&res), "\"0\"");
+    let result: routes::RetrievedResponses = ctx
+        .request_responses(Some("c"), Some(0))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(result.next_start, 1);
+    assert_eq!(
+        result.responses_by_user[&UserID::from_string("me").unwrap()],
+        "first"
+    );
+
+    // Keys belong to a user.
+    let res = send_conditional_response(&ctx, "other", "mine", &key).await;
+    assert_eq!(response_etag(&res), "\"1\"");
+
+    let res = send_conditional_response(&ctx, "me", "second", &[("Idempotency-Key", "2")]).await;
+    assert_eq!(response_etag(&res), "\"2\"");
+
+    // Keys are forgotten when the page changes.
+    ctx.set_page_and_check("c", "my-test-token", "new page")
+        .await;
+    let res = send_conditional_response(&ctx, "me", "again", &key).await;
+    assert_eq!(response_etag(&res), "\"3\"");
+
+    let res = send_conditional_response(&ctx, "me", "x", &[("Idempotency-Key", "")]).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn conflicting_responses_from_two_tabs() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("c", "my-test-token", "page").await;
+
+    // There is nothing to match yet.
+    let res = send_conditional_response(&ctx, "me", "a", &[("If-Match", "\"0\"")]).await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "response_conflict");
+    assert_eq!(body.details.unwrap()["current_id"], serde_json::Value::Null);
+
+    let res = send_conditional_response(&ctx, "me", "a", &[]).await;
+    assert_eq!(response_etag(&res), "\"0\"");
+
+    // Both tabs have seen response 0. The first one to answer wins.
+    let res = send_conditional_response(&ctx, "me", "from tab 2", &[("If-Match", "\"0\"")]).await;
+    assert_eq!(response_etag(&res), "\"1\"");
+    let res = send_conditional_response(&ctx, "me", "from tab 1", &[("If-Match", "\"0\"")]).await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "response_conflict");
+    assert_eq!(body.details.unwrap()["current_id"], 1);
+
+    // The query parameter works the same.
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=c&user=me&prev_id=1", ctx.url))
+                .body("from tab 1 after reload"),
+        )
+        .await;
+    assert_eq!(response_etag(&res), "\"2\"");
+    let response: routes::OwnResponse = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/respond?session=c&user=me", ctx.url)),
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response.data, "from tab 1 after reload");
+
+    let res = send_conditional_response(&ctx, "me", "a", &[("If-Match", "latest")]).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn response_content_types() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("c", "my-test-token", "page").await;
+
+    let res = send_conditional_response(
+        &ctx,
+        "json",
+        r#"{"choice": 2}"#,
+        &[("Content-Type", "application/json; charset=utf-8")],
+    )
+    .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some("c"), Some("text"), "hello").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res =
+        send_conditional_response(&ctx, "image", "png", &[("Content-Type", "image/png")]).await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "unsupported_media_type");
+    assert_eq!(body.details.unwrap()["content_type"], "image/png");
+
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=c&start=0&format=list",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let result: routes::RetrievedResponseList = res.json().await.unwrap();
+    let content_types: Vec<(&str, &str)> = result
+        .responses
+        .iter()
+        .map(|r| (r.user.0.as_str(), r.content_type.as_str()))
+        .collect();
+    assert_eq!(
+        content_types,
+        [("json", "application/json"), ("text", "text/plain")]
+    );
+
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=c&start=0&verbose=true",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let result: routes::VerboseRetrievedResponses = res.json().await.unwrap();
+    let json = &result.responses_by_user[&UserID("json".to_string())];
+    assert_eq!(json.content_type, "application/json");
+    assert_eq!(json.data, r#"{"choice": 2}"#);
+
+    let response: routes::OwnResponse = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/respond?session=c&user=json", ctx.url)),
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response.content_type, "application/json");
+}
+
+#[tokio::test]
+async fn configured_response_content_types() {
+    let ctx = setup_with_settings(|settings| {
+        settings.allowed_response_content_types = vec!["application/json".to_string()];
+    })
+    .await;
+    ctx.set_page_and_check("c", "my-test-token", "page").await;
+
+    let res =
+        send_conditional_response(&ctx, "me", "1", &[("Content-Type", "Application/JSON")]).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some("c"), Some("me"), "hello").await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+async fn set_response_schema(ctx: &TestContext, token: &str, schema: &str) -> reqwest::Response {
+    ctx.request_json(
+        ctx.client
+            .post(format!("{}/response_schema?session=c", ctx.url))
+            .bearer_auth(token)
+            .body(schema.to_string()),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn validate_responses_with_schema() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("c", token, "page").await;
+    let schema = r#"{"enum": ["A", "B"]}"#;
+
+    let res = set_response_schema(&ctx, "other-token", schema).await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = set_response_schema(&ctx, token, r#"{"type": 42}"#).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "invalid_response_schema",
+    )
+    .await;
+    let res = set_response_schema(&ctx, token, schema).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let ctx = &ctx;
+    let respond = |data: &'static str| async move {
+        send_conditional_response(ctx, "me", data, &[("Content-Type", "application/json")]).await
+    };
+    assert_eq!(respond("\"A\"").await.status(), reqwest::StatusCode::OK);
+    assert_eq!(respond("\"B\"").await.status(), reqwest::StatusCode::OK);
+    let res = respond("\"C\"").await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "invalid_response");
+    assert!(body.message.contains("\"C\""), "{}", body.message);
+    let res = respond("A").await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_response",
+    )
+    .await;
+
+    // The schema belongs to the page.
+    ctx.set_page_and_check("c", token, "next page").await;
+    assert_eq!(respond("\"C\"").await.status(), reqwest::StatusCode::OK);
+
+    set_response_schema(ctx, token, schema).await;
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/page?session=c&keep_response_schema=true",
+            ctx.url
+        ))
+        .bearer_auth(token)
+        .body("third page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        respond("\"C\"").await.status(),
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    let res = ctx
+        .client
+        .delete(format!("{}/response_schema?session=c", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(respond("\"C\"").await.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn new_session_with_response_schema() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "c",
+            "token": "my-test-token",
+            "response_schema": { "enum": ["A", "B"] },
+        }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some("c"), Some("me"), "\"C\"").await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let res = ctx.send_reponse(Some("c"), Some("me"), "\"A\"").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .request_new_session(serde_json::json!({ "response_schema": { "type": 42 } }))
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "invalid_response_schema",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn new_session_rejects_malformed_body() {
+    let ctx = setup().await;
+    for body in ["{\"session\": ", "[1, 2]", "{\"ttl_seconds\": \"long\"}"] {
+        let res = ctx
+            .request_json(ctx.client.post(format!("{}/new", ctx.url)).body(body))
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+        let error: ErrorBody = res.json().await.unwrap();
+        assert_eq!(error.error, "bad_query_parameters");
+        assert_eq!(error.details.unwrap()["parameter"], "body");
+    }
+    assert!(ctx.state.lock().sessions.is_empty());
+
+    // Without a body, a random session is created as before.
+    for body in ["", " \n"] {
+        let res = ctx
+            .client
+            .post(format!("{}/new", ctx.url))
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn filter_free_text_responses() {
+    let ctx = setup_with_settings(|settings| {
+        *settings.response_filter.write() =
+            Some(ResponseFilter::new("darn", FilterMode::Mask).unwrap());
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("f", token, "page").await;
+    let respond = |data: &'static str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/respond?session=f&user=me", ctx.url))
+                .body(data),
+        )
+    };
+    let set_filter = |mode: &'static str, patterns: &'static str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!(
+                    "{}/response_filter?session=f&mode={}",
+                    ctx.url, mode
+                ))
+                .bearer_auth(token)
+                .body(patterns),
+        )
+    };
+    let stored_response = || async {
+        request_user_ids(&ctx, "f")
+            .await
+            .into_keys()
+            .collect::<Vec<_>>()
+    };
+
+    // The filter of the server masks.
+    assert_eq!(respond("darn it").await.status(), reqwest::StatusCode::OK);
+    assert_eq!(stored_response().await, vec!["**** it"]);
+
+    // The filter of the session rejects in addition.
+    let res = set_filter("reject", "/(/").await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "invalid_response_filter",
+    )
+    .await;
+    let res = set_filter("reject", "spam\n/sp[a4]m/").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = respond("this is sp4m").await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_response",
+    )
+    .await;
+    assert_eq!(respond("no spa").await.status(), reqwest::StatusCode::OK);
+
+    assert_eq!(
+        set_filter("mask", "spam").await.status(),
+        reqwest::StatusCode::OK
+    );
+    assert_eq!(
+        respond("spam, darn").await.status(),
+        reqwest::StatusCode::OK
+    );
+    assert_eq!(stored_response().await, vec!["****, ****"]);
+
+    // Fixed choices are not filtered.
+    let res = ctx
+        .client
+        .post(format!("{}/page?session=f&choices=spam,eggs", ctx.url))
+        .bearer_auth(token)
+        .body("next page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(respond("spam").await.status(), reqwest::StatusCode::OK);
+    assert_eq!(stored_response().await, vec!["spam"]);
+
+    let res = ctx
+        .client
+        .delete(format!("{}/response_filter?session=f", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn broadcast_message_to_audience() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.max_message_size = 20;
+    })
+    .await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("m", token, "page").await;
+    let post_message = |token: &str, text: &'static str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/message?session=m", ctx.url))
+                .bearer_auth(token)
+                .body(text),
+        )
+    };
+    let poll = |since: usize, timeout_ms: u64| {
+        ctx.client
+            .get(format!(
+                "{}/message?session=m&since={}&timeout_ms={}",
+                ctx.url, since, timeout_ms
+            ))
+            .send()
+    };
+
+    let res = post_message("other-token", "hello").await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = post_message(token, "this message is too long").await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::PAYLOAD_TOO_LARGE,
+        "message_too_large",
+    )
+    .await;
+    assert_eq!(
+        poll(0, 0).await.unwrap().status(),
+        reqwest::StatusCode::NO_CONTENT
+    );
+
+    let res = post_message(token, "Audio ok?").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = poll(0, 0).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let message: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(message["text"], "Audio ok?");
+    assert_eq!(message["id"], 1);
+
+    // The message is only received once, the next poll waits for a new one.
+    let res = poll(1, 100).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+    let waiting = poll(1, 10_000);
+    let post_later = async {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        post_message(token, "Thanks!").await
+    };
+    let (res, _) = tokio::join!(waiting, post_later);
+    let message: serde_json::Value = res.unwrap().json().await.unwrap();
+    assert_eq!(message["text"], "Thanks!");
+    assert_eq!(message["id"], 2);
+
+    // Messages expire.
+    clock.advance(ctx.settings.message_lifetime);
+    assert_eq!(
+        poll(0, 0).await.unwrap().status(),
+        reqwest::StatusCode::NO_CONTENT
+    );
+    let mut state = ctx.state.lock();
+    cleanup::cleanup_once(&ctx.settings, &mut state, clock.now());
+    assert!(state.sessions[&SessionID::from_string("m").unwrap()]
+        .message
+        .is_none());
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn ban_and_unban_user() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("b", token, "page").await;
+    ctx.send_reponse(Some("b"), Some("spammer"), "spam").await;
+    ctx.send_reponse(Some("b"), Some("other"), "fine").await;
+    let ban = |method: reqwest::Method, token: &str| {
+        ctx.client
+            .request(method, format!("{}/ban?session=b&user=spammer", ctx.url))
+            .bearer_auth(token)
+            .send()
+    };
+
+    let res = ban(reqwest::Method::POST, "other-token").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = ban(reqwest::Method::POST, token).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let ids = request_user_ids(&ctx, "b").await;
+    assert_eq!(ids.keys().collect::<Vec<_>>(), vec!["fine"]);
+    {
+        let state = ctx.state.lock();
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=b&user=spammer", ctx.url))
+                .body("more spam"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "user_banned").await;
+
+    // Bans are kept for the next page.
+    ctx.set_page_and_check("b", token, "next page").await;
+    let res = ctx.send_reponse(Some("b"), Some("spammer"), "spam").await;
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let banned: routes::BannedUsers = ctx
+        .client
+        .get(format!("{}/ban?session=b", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(banned.users, vec![UserID::from_string("spammer").unwrap()]);
+
+    let res = ban(reqwest::Method::DELETE, token).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some("b"), Some("spammer"), "sorry").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let state = ctx.state.lock();
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn join_code_protects_page_and_responses() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "k",
+            "token": token,
+            "page": "secret page",
+            "join_code": "1234",
+        }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let get_page = |query: &str, cookie: Option<&str>| {
+        let mut builder = ctx
+            .client
+            .get(format!("{}/page?session=k{}", ctx.url, query));
+        if let Some(cookie) = cookie {
+            builder = builder.header(reqwest::header::COOKIE, cookie.to_string());
+        }
+        builder.send()
+    };
+    let respond = |query: &str, cookie: Option<&str>| {
+        let mut builder = ctx
+            .client
+            .post(format!("{}/respond?session=k&user=me{}", ctx.url, query))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .body("A");
+        if let Some(cookie) = cookie {
+            builder = builder.header(reqwest::header::COOKIE, cookie.to_string());
+        }
+        builder.send()
+    };
+
+    // Browsers get a page to enter the code.
+    let res = get_page("", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let body = res.text().await.unwrap();
+    assert!(body.contains("name=\"code\""));
+    assert!(!body.contains("1234"));
+    let res = get_page("&code=0000", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let res = get_page("&code=1234", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let cookie = header_value(&res, "Set-Cookie")
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    assert_eq!(cookie, "polli_code_k=1234");
+    assert!(res.text().await.unwrap().contains("secret page"));
+    let res = get_page("", Some(&cookie)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    for (query, cookie) in [
+        ("", None),
+        ("&code=0000", None),
+        ("", Some("polli_code_k=0")),
+    ] {
+        let res = respond(query, cookie).await.unwrap();
+        assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "join_code_required").await;
+    }
+    let res = respond("&code=1234", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = respond("", Some(&cookie)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // Rotating the code locks out everyone with the old one.
+    let res = ctx
+        .client
+        .post(format!("{}/join_code?session=k", ctx.url))
+        .bearer_auth("other-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = ctx
+        .client
+        .post(format!("{}/join_code?session=k", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    let code: routes::JoinCode = res.json().await.unwrap();
+    assert_eq!(code.join_code.len(), 6);
+    let res = get_page("", Some(&cookie)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let res = respond(&format!("&code={}", code.join_code), None)
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .client
+        .delete(format!("{}/join_code?session=k", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        get_page("", None).await.unwrap().status(),
+        reqwest::StatusCode::OK
+    );
+    assert_eq!(
+        respond("", None).await.unwrap().status(),
+        reqwest::StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn respond_with_server_user_ids() {
+    let ctx = setup_with_settings(|settings| settings.require_server_user_ids = true).await;
+    ctx.set_page_and_check("j", "my-test-token", "page").await;
+    let join = |cookie: Option<String>| {
+        let mut builder = ctx.client.get(format!("{}/join?session=j", ctx.url));
+        if let Some(cookie) = cookie {
+            builder = builder.header(reqwest::header::COOKIE, cookie);
+        }
+        builder.send()
+    };
+
+    let res = join(None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let cookie = header_value(&res, "Set-Cookie");
+    assert!(cookie.contains("HttpOnly"));
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let joined: routes::JoinedUser = res.json().await.unwrap();
+    assert_eq!(cookie, format!("polli_user_j={}", joined.user.0));
+
+    let res = ctx.send_reponse(Some("j"), Some(&joined.user.0), "A").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=j&user=made-up", ctx.url))
+                .body("B"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "unknown_user_id").await;
+
+    // The cookie keeps the id, also when the page changes.
+    ctx.set_page_and_check("j", "my-test-token", "next page")
+        .await;
+    let rejoined: routes::JoinedUser = join(Some(cookie)).await.unwrap().json().await.unwrap();
+    assert_eq!(rejoined.user, joined.user);
+    let other: routes::JoinedUser = join(None).await.unwrap().json().await.unwrap();
+    assert_ne!(other.user, joined.user);
+    let res = ctx.send_reponse(Some("j"), Some(&other.user.0), "B").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+async fn request_user_ids(ctx: &TestContext, session: &str) -> HashMap<String, String> {
+    let result: routes::RetrievedResponses = ctx
+        .request_responses(Some(session), Some(0))
+        .await
+        .json()
+        .await
+        .unwrap();
+    result
+        .responses_by_user
+        .into_iter()
+        .map(|(user, data)| (data, user.0))
+        .collect()
+}
+
+#[tokio::test]
+async fn anonymous_sessions_hash_user_ids() {
+    let ctx = setup().await;
+    for session in ["a1", "a2"] {
+        let res = ctx
+            .request_new_session(serde_json::json!({
+                "session": session,
+                "token": "my-test-token",
+                "anonymous": true,
+            }))
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    for (session, user, data) in [
+        ("a1", "alice", "first"),
+        ("a1", "alice", "A1"),
+        ("a1", "bob", "B1"),
+        ("a2", "alice", "A2"),
+    ] {
+        let res = ctx.send_reponse(Some(session), Some(user), data).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    let ids1 = request_user_ids(&ctx, "a1").await;
+    let ids2 = request_user_ids(&ctx, "a2").await;
+    // The second response of alice replaced the first one.
+    assert_eq!(ids1.len(), 2);
+    assert!(!ids1.contains_key("first"));
+    assert_ne!(ids1["A1"], "alice");
+    assert_ne!(ids1["A1"], ids1["B1"]);
+    assert_ne!(ids1["A1"], ids2["A2"]);
+
+    // The users can still restore their own response.
+    let res = ctx
+        .client
+        .get(format!("{}/respond?session=a1&user=alice", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    let own: routes::OwnResponse = res.json().await.unwrap();
+    assert_eq!(own.data, "A1");
+
+    // Existing sessions become anonymous with the page.
+    ctx.set_page_and_check("p", "my-test-token", "page").await;
+    ctx.send_reponse(Some("p"), Some("alice"), "raw").await;
+    assert_eq!(request_user_ids(&ctx, "p").await["raw"], "alice");
+    let res = ctx
+        .client
+        .post(format!("{}/page?session=p&anonymous=true", ctx.url))
+        .bearer_auth("my-test-token")
+        .body("next page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    ctx.send_reponse(Some("p"), Some("alice"), "hashed").await;
+    let ids = request_user_ids(&ctx, "p").await;
+    assert_ne!(ids["hashed"], "alice");
+    assert_ne!(ids["hashed"], ids1["A1"]);
+}
+
+async fn request_aggregate(ctx: &TestContext, session: &str) -> routes::AggregatedResponses {
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses/aggregate?session={}",
+            ctx.url, session
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    res.json().await.unwrap()
+}
+
+fn aggregate_counts(result: &routes::AggregatedResponses) -> Vec<(&str, usize)> {
+    result
+        .counts
+        .iter()
+        .map(|count| (count.response.as_str(), count.count))
+        .collect()
+}
+
+#[tokio::test]
+async fn count_revised_responses() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("rv", "my-test-token", "page").await;
+    for (user, data) in [("a", "1"), ("a", "2"), ("a", "2"), ("b", "1"), ("b", "1")] {
+        let res = ctx.send_reponse(Some("rv"), Some(user), data).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=rv&start=0&verbose=true",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let verbose: routes::VerboseRetrievedResponses = res.json().await.unwrap();
+    assert_eq!(
+        verbose.responses_by_user[&UserID("a".to_string())].revision,
+        1
+    );
+    assert_eq!(
+        verbose.responses_by_user[&UserID("b".to_string())].revision,
+        0
+    );
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=rv&start=0&format=list",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let list: routes::RetrievedResponseList = res.json().await.unwrap();
+    let revisions: Vec<(&str, u32)> = list
+        .responses
+        .iter()
+        .map(|response| (response.user.0.as_str(), response.revision))
+        .collect();
+    assert_eq!(revisions, vec![("a", 1), ("b", 0)]);
+    assert_eq!(request_aggregate(&ctx, "rv").await.revised_count, 1);
+
+    // Revisions start again with the next page.
+    ctx.set_page_and_check("rv", "my-test-token", "next page")
+        .await;
+    ctx.send_reponse(Some("rv"), Some("a"), "3").await;
+    assert_eq!(request_aggregate(&ctx, "rv").await.revised_count, 0);
+}
+
+async fn export_response_metadata(collect: bool) -> Option<serde_json::Value> {
+    let ctx = setup_with_settings(|settings| settings.collect_response_metadata = collect).await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("md", token, "first").await;
+    ctx.set_page_and_check("md", token, "second").await;
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/respond?session=md&user=a&page_version=1",
+            ctx.url
+        ))
+        .header(reqwest::header::USER_AGENT, "test-agent")
+        .body("yes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    {
+        let state = ctx.state.lock();
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+    // Metadata is only part of the export.
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=md&start=0&format=list",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert!(!res.text().await.unwrap().contains("test-agent"));
+
+    let res = ctx
+        .client
+        .get(format!("{}/export_session?session=md", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    let archive: serde_json::Value = res.json().await.unwrap();
+    archive["responses"][0].get("metadata").cloned()
+}
+
+#[tokio::test]
+async fn collect_response_metadata_when_enabled() {
+    assert_eq!(export_response_metadata(false).await, None);
+    let metadata = export_response_metadata(true).await.unwrap();
+    assert_eq!(metadata["page_version"], 1);
+    assert!(metadata["page_time"].is_string());
+    assert_eq!(metadata["user_agent"], "test-agent");
+}
+
+#[tokio::test]
+async fn export_and_import_session() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("x", token, "<html><body>poll</body></html>")
+        .await;
+    for (user, data) in [("a", "1"), ("b", "2"), ("a", "3")] {
+        ctx.send_reponse(Some("x"), Some(user), data).await;
+        // Avoid that responses of the same user are coalesced.
+        tokio::time::sleep(ctx.settings.tunables().min_response_interval).await;
+    }
+    let request_responses = || async {
+        let res = ctx.request_responses(Some("x"), Some(0)).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let responses: RetrievedResponses = res.json().await.unwrap();
+        (responses.next_start, responses.responses_by_user)
+    };
+    let responses_before = request_responses().await;
+    let page_before = ctx.request_session_page_text("x").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/export_session?session=x", ctx.url))
+                .bearer_auth("my-other-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = ctx
+        .client
+        .get(format!("{}/export_session?session=x", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let archive = res.text().await.unwrap();
+    assert!(!archive.contains(token));
+    assert!(!archive.contains("polli_live.js"));
+
+    *ctx.state.lock() = State::default();
+    let import = |token: &'static str, archive: String| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/import_session", ctx.url))
+                .bearer_auth(token)
+                .body(archive),
+        )
+    };
+    let res = import(token, archive.clone()).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(request_responses().await, responses_before);
+    assert_eq!(responses_before.0, 3);
+    assert_eq!(ctx.request_session_page_text("x").await, page_before);
+    {
+        let state = ctx.state.lock();
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    // The session belongs to the importing token now.
+    let res = import("my-other-token", archive.clone()).await;
+    assert_error_code(res, reqwest::StatusCode::CONFLICT, "session_taken").await;
+    let res = import(
+        token,
+        archive.replacen("\"version\":1", "\"version\":1000", 1),
+    )
+    .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "invalid_archive").await;
+    let res = import(token, "not json".to_string()).await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "invalid_archive").await;
+}
+
+#[tokio::test]
+async fn list_and_delete_own_sessions() {
+    let ctx = setup().await;
+    let (token_a, token_b) = ("my-test-token-a", "my-test-token-b");
+    ctx.set_page_and_check("a1", token_a, "page").await;
+    ctx.set_page_and_check("a2", token_a, "page").await;
+    ctx.set_page_and_check("b1", token_b, "page").await;
+    ctx.send_reponse(Some("a2"), Some("u"), "yes").await;
+    let my_sessions = |method: reqwest::Method, token: &str| {
+        ctx.request_json(
+            ctx.client
+                .request(method, format!("{}/my_sessions", ctx.url))
+                .bearer_auth(token),
+        )
+    };
+    let list = |token: &'static str| async move {
+        let res = my_sessions(reqwest::Method::GET, token).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let mut sessions = res.json::<routes::OwnedSessions>().await.unwrap().sessions;
+        sessions.sort_by(|a, b| a.session.0.cmp(&b.session.0));
+        sessions
+            .into_iter()
+            .map(|session| (session.session.0, session.responses))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        list(token_a).await,
+        vec![("a1".to_string(), 0), ("a2".to_string(), 1)]
+    );
+    assert_eq!(list(token_b).await, vec![("b1".to_string(), 0)]);
+    assert_eq!(list("my-other-token").await, vec![]);
+
+    let res = my_sessions(reqwest::Method::DELETE, token_a).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        res.json::<routes::DeletedSessions>().await.unwrap().deleted,
+        2
+    );
+    assert_eq!(list(token_a).await, vec![]);
+    assert_eq!(list(token_b).await, vec![("b1".to_string(), 0)]);
+    let state = ctx.state.lock();
+    assert_eq!(state.sessions.len(), 1);
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    assert_eq!(state.sessions_by_token.len(), 1);
+}
+
+#[tokio::test]
+async fn own_sessions_follow_token_changes() {
+    let ctx = setup().await;
+    let old_token = AccessToken::from_string("my-test-token").unwrap();
+    ctx.set_page_and_check("r", &old_token.0, "page").await;
+
+    let res = ctx
+        .client
+        .post(format!("{}/rotate_token?session=r", ctx.url))
+        .bearer_auth(&old_token.0)
+        .send()
+        .await
+        .unwrap();
+    let new_token = AccessToken(res.json::<routes::RotatedToken>().await.unwrap().token);
+    let state = ctx.state.lock();
+    assert!(state.sessions_of_token(&old_token).is_empty());
+    assert_eq!(
+        state.sessions_of_token(&new_token),
+        vec![SessionID::from_string("r").unwrap()]
+    );
+
+    // The index is not part of snapshots, so it's rebuilt when they are loaded.
+    let restored = persist::state_from_snapshot(&persist::state_to_snapshot(&state)).unwrap();
+    assert_eq!(
+        restored.sessions_of_token(&new_token),
+        vec![SessionID::from_string("r").unwrap()]
+    );
+}
+
+#[test]
+fn compare_tokens_in_constant_time() {
+    let token = AccessToken::from_string("my-test-token").unwrap();
+    assert!(token.constant_time_eq(&AccessToken::from_string("my-test-token").unwrap()));
+    assert!(!token.constant_time_eq(&AccessToken::from_string("my-test-tokem").unwrap()));
+    assert!(!token.constant_time_eq(&AccessToken::from_string("my-test-token2").unwrap()));
+}
+
+#[tokio::test]
+async fn session_info_for_public_and_presenter() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| settings.clock = clock.clone()).await;
+    let token = "my-test-token";
+    let created = clock.now();
+    ctx.set_page_and_check("i", token, "page").await;
+    clock.advance(std::time::Duration::from_secs(5 * 60));
+    ctx.send_reponse(Some("i"), Some("a"), "yes").await;
+    clock.advance(std::time::Duration::from_secs(60));
+    let request_info = |token: Option<&str>| {
+        let mut builder = ctx
+            .client
+            .get(format!("{}/session_info?session=i", ctx.url));
+        if let Some(token) = token {
+            builder = builder.bearer_auth(token);
+        }
+        ctx.request_json(builder)
+    };
+
+    let res = request_info(None).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let text = res.text().await.unwrap();
+    assert!(!text.contains(token));
+    assert!(!text.contains("presenter"));
+    let info: routes::SessionInfo = serde_json::from_str(&text).unwrap();
+    assert_eq!(info.session, "i");
+    assert_eq!(info.created, created);
+    assert_eq!(info.responses, 1);
+    let session_id = SessionID::from_string("i").unwrap();
+    assert_eq!(
+        info.page_bytes,
+        ctx.state.lock().sessions[&session_id].page.len()
+    );
+    let keep_alive = ctx.settings.tunables().session_keep_alive_duration;
+    assert_eq!(info.expires_in_seconds, keep_alive.as_secs() - 60);
+    assert_eq!(info.expires_at, info.last_request + keep_alive);
+
+    let res = request_info(Some("other-token")).await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = request_info(Some(token)).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let text = res.text().await.unwrap();
+    assert!(!text.contains(token));
+    let info: routes::SessionInfo = serde_json::from_str(&text).unwrap();
+    let presenter = info.presenter.unwrap();
+    assert_eq!(
+        presenter.approx_bytes,
+        cleanup::count_session_memory_usage(&session_id, &ctx.state.lock().sessions[&session_id])
+    );
+    assert!(!presenter.anonymous);
+    assert!(!presenter.results_public);
+}
+
+#[tokio::test]
+async fn commit_staged_page() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("st", token, "old").await;
+    ctx.send_reponse(Some("st"), Some("a"), "yes").await;
+    let stage = |page: &str| {
+        ctx.client
+            .post(format!("{}/page/stage?session=st", ctx.url))
+            .bearer_auth(token)
+            .body(page.to_string())
+            .send()
+    };
+    let commit = || {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/page/commit?session=st", ctx.url))
+                .bearer_auth(token),
+        )
+    };
+
+    let res = commit().await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "staged_page_not_found").await;
+    let res = stage("new").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(ctx.request_session_page_text("st").await, "old");
+    {
+        let state = ctx.state.lock();
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    let wait = ctx
+        .client
+        .get(format!("{}/wait_for_new_page?session=st", ctx.url))
+        .send();
+    let (wait, res) = tokio::join!(wait, async {
+        // Give the audience time to start waiting.
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        commit().await
+    });
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(wait.unwrap().text().await.unwrap(), "reload");
+    assert_eq!(ctx.request_session_page_text("st").await, "new");
+    {
+        let state = ctx.state.lock();
+        let session = &state.sessions[&SessionID::from_string("st").unwrap()];
+        assert!(session.responses.is_empty());
+        assert!(session.staged_page.is_none());
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    // Setting the page directly drops the staged page.
+    stage("staged").await.unwrap();
+    ctx.set_page_and_check("st", token, "direct").await;
+    let res = commit().await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "staged_page_not_found").await;
+    assert_eq!(ctx.request_session_page_text("st").await, "direct");
+    let state = ctx.state.lock();
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn switch_to_scheduled_page() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| settings.clock = clock.clone()).await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("q", token, "question 1").await;
+    ctx.send_reponse(Some("q"), Some("a"), "yes").await;
+    let at = clock.now() + chrono::Duration::seconds(10);
+    let schedule = |page: &str| {
+        ctx.client
+            .post(format!("{}/page/schedule", ctx.url))
+            .query(&[("session", "q"), ("at", &at.to_rfc3339())])
+            .bearer_auth(token)
+            .body(page.to_string())
+            .send()
+    };
+    let switch_due_pages =
+        || scheduled_page::switch_due_pages(&ctx.settings, &mut ctx.state.lock(), clock.now());
+
+    let res = schedule("question 2").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .client
+        .get(format!("{}/session_info?session=q", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    let info: routes::SessionInfo = res.json().await.unwrap();
+    assert_eq!(info.presenter.unwrap().scheduled_page_at, Some(at));
+
+    clock.advance(std::time::Duration::from_secs(9));
+    assert_eq!(switch_due_pages(), 0);
+    assert_eq!(ctx.request_session_page_text("q").await, "question 1");
+
+    clock.advance(std::time::Duration::from_secs(1));
+    assert_eq!(switch_due_pages(), 1);
+    assert_eq!(ctx.request_session_page_text("q").await, "question 2");
+    {
+        let state = ctx.state.lock();
+        let session = &state.sessions[&SessionID::from_string("q").unwrap()];
+        assert!(session.responses.is_empty());
+        assert!(session.scheduled_page.is_none());
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    // Cancelled pages are not switched to.
+    schedule("question 3").await.unwrap();
+    let res = ctx
+        .client
+        .delete(format!("{}/page/schedule?session=q", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(switch_due_pages(), 0);
+    assert_eq!(ctx.request_session_page_text("q").await, "question 2");
+    let state = ctx.state.lock();
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn roll_back_to_previous_page() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("h", token, "first").await;
+    ctx.set_page_and_check("h", token, "second").await;
+    ctx.set_page_and_check("h", token, "third").await;
+    ctx.send_reponse(Some("h"), Some("a"), "yes").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/page_history?session=h", ctx.url))
+                .bearer_auth(token),
+        )
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let history: routes::PageHistory = res.json().await.unwrap();
+    assert_eq!(history.current_version, 3);
+    let versions: Vec<usize> = history.versions.iter().map(|v| v.version).collect();
+    assert_eq!(versions, vec![3, 2, 1]);
+    let snippet_len = page::injection_snippet(&ctx.settings).len();
+    assert_eq!(history.versions[2].bytes, "first".len() + snippet_len);
+
+    let rollback = |version: &str, token: &str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/page_rollback", ctx.url))
+                .query(&[("session", "h"), ("version", version)])
+                .bearer_auth(token),
+        )
+    };
+    let res = rollback("1", "other-token").await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = rollback("7", token).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::NOT_FOUND,
+        "page_version_not_found",
+    )
+    .await;
+
+    let wait = ctx
+        .client
+        .get(format!("{}/wait_for_new_page?session=h", ctx.url))
+        .send();
+    let (wait, res) = tokio::join!(wait, async {
+        // Give the audience time to start waiting.
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        rollback("1", token).await
+    });
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(wait.unwrap().text().await.unwrap(), "reload");
+    assert_eq!(ctx.request_session_page_text("h").await, "first");
+
+    // The rollback is a new version, so it can be undone as well.
+    let state = ctx.state.lock();
+    let session = &state.sessions[&SessionID::from_string("h").unwrap()];
+    assert!(session.responses.is_empty());
+    assert_eq!(session.page_version, 4);
+    let versions: Vec<usize> = session.page_history.iter().map(|e| e.version).collect();
+    assert_eq!(versions, vec![1, 2, 3]);
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn presenter_dashboard() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("d", token, "page").await;
+    ctx.send_reponse(Some("d"), Some("a"), "yes").await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/dashboard", ctx.url))
+                .query(&[("session", "d"), ("token", "other-token")]),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    let res = ctx
+        .client
+        .get(format!("{}/dashboard", ctx.url))
+        .query(&[("session", "d"), ("token", token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(header_value(&res, "content-type").starts_with("text/html"));
+    assert_eq!(header_value(&res, "cache-control"), "no-store");
+    let html = res.text().await.unwrap();
+    assert!(html.contains("<span id=\"session-id\">d</span>"));
+    assert!(html.contains(&format!("{}/page?session=d", ctx.url)));
+    assert!(html.contains("<span id=\"response-count\">1</span>"));
+    assert!(html.contains(&page::injection_snippet(&ctx.settings)));
+}
+
+#[tokio::test]
+async fn public_results_page() {
+    let ctx = setup_with_settings(|settings| settings.responses_require_auth = true).await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("r", token, "page").await;
+    ctx.send_reponse(Some("r"), Some("a"), "yes").await;
+    let request_results =
+        || ctx.request_json(ctx.client.get(format!("{}/results?session=r", ctx.url)));
+    let set_public = |method: reqwest::Method| {
+        ctx.client
+            .request(method, format!("{}/results/public?session=r", ctx.url))
+            .bearer_auth(token)
+            .send()
+    };
+
+    let res = request_results().await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "results_not_public").await;
+    let res = ctx
+        .client
+        .get(format!("{}/responses/aggregate?session=r", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let res = set_public(reqwest::Method::POST).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .client
+        .get(format!("{}/results?session=r", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let html = res.text().await.unwrap();
+    assert!(html.contains("Results of r"));
+    assert!(html.contains(&page::injection_snippet(&ctx.settings)));
+    // The page reads the aggregate without the token.
+    let aggregate = request_aggregate(&ctx, "r").await;
+    assert_eq!(aggregate_counts(&aggregate), vec![("yes", 1)]);
+
+    let res = set_public(reqwest::Method::DELETE).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = request_results().await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "results_not_public").await;
+}
+
+#[tokio::test]
+async fn restrict_responses_to_choices() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    let set_page = |query: &'static str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/page?session=c{}", ctx.url, query))
+                .bearer_auth(token)
+                .body("page"),
+        )
+    };
+    assert_eq!(
+        set_page("&choices=A,B,C").await.status(),
+        reqwest::StatusCode::OK
+    );
+
+    for (user, data) in [("u1", "A"), ("u2", "A"), ("u3", "C")] {
+        let res = ctx.send_reponse(Some("c"), Some(user), data).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=c&user=u4", ctx.url))
+                .body("D"),
+        )
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_response",
+    )
+    .await;
+
+    // Unchosen options are included, so that charts have stable axes.
+    let result = request_aggregate(&ctx, "c").await;
+    assert_eq!(result.session, "c");
+    assert_eq!(result.total_responses, 3);
+    assert_eq!(aggregate_counts(&result), [("A", 2), ("B", 0), ("C", 1)]);
+
+    // A json array allows choices that contain commas.
+    assert_eq!(
+        set_page("&choices=%5B%22yes%2C%20really%22%2C%22no%22%5D")
+            .await
+            .status(),
+        reqwest::StatusCode::OK
+    );
+    let res = ctx.send_reponse(Some("c"), Some("u1"), "A").await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let result = request_aggregate(&ctx, "c").await;
+    assert_eq!(aggregate_counts(&result), [("yes, really", 0), ("no", 0)]);
+
+    // Setting the page without choices clears them. It has to be forced, because the page
+    // itself is the same.
+    assert_eq!(
+        set_page("&force=true").await.status(),
+        reqwest::StatusCode::OK
+    );
+    for (user, data) in [("u1", "D"), ("u2", "E"), ("u3", "E")] {
+        let res = ctx.send_reponse(Some("c"), Some(user), data).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let result = request_aggregate(&ctx, "c").await;
+    assert_eq!(aggregate_counts(&result), [("E", 2), ("D", 1)]);
+
+    let res = set_page("&choices=%5Bbroken").await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn lock_first_response() {
+    let ctx = setup().await;
+
+    let session = "d";
+    let token = "my-test-token";
+    let user = "me";
+
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/page?session={}&lock_first_response=true",
+            ctx.url, session
+        ))
+        .bearer_auth(token)
+        .body("page 1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx.send_reponse(Some(session), Some(user), "first").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!(
+                    "{}/respond?session={}&user={}",
+                    ctx.url, session, user
+                ))
+                .body("second"),
+        )
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "response_locked");
+    assert_eq!(body.details.unwrap()["response"], "first");
+
+    let res = ctx.request_responses(Some(session), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 1);
+
+    // The lock is reset when the page changes.
+    ctx.set_page_and_check(session, token, "page 2").await;
+    let res = ctx.send_reponse(Some(session), Some(user), "first").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some(session), Some(user), "second").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn lock_first_response_sticky() {
+    let ctx = setup().await;
+
+    let session = "e";
+    let token = "my-test-token";
+    let user = "me";
+
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/page?session={}&lock_first_response=true&lock_sticky=true",
+            ctx.url, session
+        ))
+        .bearer_auth(token)
+        .body("page 1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    ctx.set_page_and_check(session, token, "page 2").await;
+    let res = ctx.send_reponse(Some(session), Some(user), "first").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some(session), Some(user), "second").await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn malformed_query_parameters() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("f", "my-test-token", "page").await;
+
+    for (url, parameter) in [
+        (format!("{}/responses?session=f", ctx.url), Some("start")),
+        (format!("{}/responses?start=0", ctx.url), Some("session")),
+        (format!("{}/responses?session=f&start=abc", ctx.url), None),
+        (format!("{}/page", ctx.url), Some("session")),
+        (format!("{}/wait_for_new_page", ctx.url), Some("session")),
+    ] {
+        let res = ctx.request_json(ctx.client.get(url)).await;
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: ErrorBody = res.json().await.unwrap();
+        assert_eq!(body.error, "bad_query_parameters");
+        assert_eq!(
+            body.details.map(|details| details["parameter"].clone()),
+            parameter.map(|parameter| parameter.into())
+        );
+    }
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=f", ctx.url))
+                .body("42"),
+        )
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn malformed_authorization() {
+    let ctx = setup().await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=g", ctx.url))
+                .body("page"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=g", ctx.url))
+                .header(reqwest::header::AUTHORIZATION, "Basic abc")
+                .body("page"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=g", ctx.url))
+                .bearer_auth("short")
+                .body("page"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+}
+
+#[test]
+fn deserialize_session_id() {
+    let session_id: SessionID = serde_json::from_str("\"1234\"").unwrap();
+    assert_eq!(session_id.0, "1234");
+    assert!(serde_json::from_str::<SessionID>("\"\"").is_err());
+    assert!(serde_json::from_str::<SessionID>(&format!("\"{}\"", "a".repeat(101))).is_err());
+    assert!(serde_json::from_str::<SessionID>("42").is_err());
+}
+
+#[test]
+fn deserialize_user_id() {
+    let user_id: UserID = serde_json::from_str("\"me\"").unwrap();
+    assert_eq!(user_id.0, "me");
+    assert!(serde_json::from_str::<UserID>("\"\"").is_err());
+    assert!(serde_json::from_str::<UserID>(&format!("\"{}\"", "a".repeat(101))).is_err());
+}
+
+#[tokio::test]
+async fn invalid_ids_in_query() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("h", "my-test-token", "page").await;
+
+    let res = ctx
+        .request_json(ctx.client.get(format!("{}/page?session=", ctx.url)))
+        .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "bad_session_id").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/responses?session=&start=0", ctx.url)),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "bad_session_id").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!(
+                    "{}/respond?session=h&user={}",
+                    ctx.url,
+                    "a".repeat(101)
+                ))
+                .body("42"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "bad_user_id").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=", ctx.url))
+                .bearer_auth("my-test-token")
+                .body("page"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "bad_session_id").await;
+}
+
+#[tokio::test]
+async fn page_links() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("i", "my-test-token", "page").await;
+
+    let res = ctx.request_session_page("i").await;
+    let links = parse_links(&res);
+    assert_eq!(links["page"], format!("{}/page?session=i", ctx.url));
+    assert_eq!(
+        links["respond"],
+        format!("{}/respond?session=i&user={{user}}", ctx.url)
+    );
+    assert_eq!(
+        links["wait_for_new_page"],
+        format!("{}/wait_for_new_page?session=i", ctx.url)
+    );
+    let res = ctx
+        .client
+        .get(&links["client_config"])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn responses_next_link() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("j", "my-test-token", "page").await;
+    ctx.send_reponse(Some("j"), Some("a"), "1").await;
+
+    let res = ctx.request_responses(Some("j"), Some(0)).await;
+    let links = parse_links(&res);
+    assert_eq!(
+        links["next"],
+        format!("{}/responses?session=j&start=1", ctx.url)
+    );
+
+    ctx.send_reponse(Some("j"), Some("b"), "2").await;
+    let res = ctx.client.get(&links["next"]).send().await.unwrap();
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 2);
+    assert_eq!(result.responses_by_user.len(), 1);
+    assert!(result
+        .responses_by_user
+        .contains_key(&UserID::from_string("b").unwrap()));
+}
+
+#[tokio::test]
+async fn responses_export_links() {
+    let ctx = setup_with_settings(|settings| settings.max_responses_per_request = 2).await;
+    ctx.set_page_and_check("j", "my-test-token", "page").await;
+    ctx.send_reponse(Some("j"), Some("a"), "1").await;
+    ctx.send_reponse(Some("j"), Some("b"), "say \"hi\", then\nleave")
+        .await;
+    ctx.send_reponse(Some("j"), Some("c"), "3").await;
+    ctx.send_reponse(Some("j"), Some("d"), "4").await;
+
+    let res = ctx.request_responses(Some("j"), Some(0)).await;
+    let links = parse_links(&res);
+    assert_eq!(links["results"], format!("{}/results?session=j", ctx.url));
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 2);
+
+    // The exports contain all responses, not only as many as one request of `/responses`.
+    let res = ctx.client.get(&links["export_csv"]).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        header_value(&res, "Content-Type"),
+        "text/csv; charset=utf-8"
+    );
+    let csv = res.text().await.unwrap();
+    let rows: Vec<&str> = csv.split("\r\n").collect();
+    assert_eq!(rows.len(), 6);
+    assert_eq!(rows[0], "id,user,time,content_type,data");
+    assert!(rows[1].starts_with("0,a,"));
+    assert!(rows[1].ends_with(",text/plain,1"));
+    assert!(rows[2].ends_with(",\"say \"\"hi\"\", then\nleave\""));
+    assert!(rows[4].starts_with("3,d,"));
+    assert_eq!(rows[5], "");
+
+    let res = ctx
+        .client
+        .get(&links["export_ndjson"])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let text = res.text().await.unwrap();
+    let lines: Vec<routes::StreamedLine> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 5);
+    let routes::StreamedLine::Summary(summary) = &lines[4] else {
+        panic!("the last line is the summary");
+    };
+    assert_eq!(summary.next_start, 4);
+}
+
+#[tokio::test]
+async fn new_session_links() {
+    let ctx = setup().await;
+    let res = ctx
+        .client
+        .post(format!("{}/new", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let links = parse_links(&res);
+    let res = ctx.client.get(&links["page"]).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.client.get(&links["qr"]).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(header_value(&res, "Content-Type"), "image/svg+xml");
+    assert!(res.text().await.unwrap().contains("<svg"));
+}
+
+/// Creates a state with many small sessions. Every second session was last used
+/// `old_age` ago, the others are fresh.
+fn make_state_with_many_sessions(
+    count: usize,
+    now: chrono::DateTime<chrono::Utc>,
+    old_age: chrono::Duration,
+) -> State {
+    let mut state = State::default();
+    for i in 0..count {
+        let mut session = SessionState::new(
+            AccessToken::from_string("my-test-token").unwrap(),
+            "page".to_string(),
+            now,
+            clock::instant_at(&SystemClock, now),
+        );
+        session.last_request = if i % 2 == 0 { now - old_age } else { now };
+        session.last_used = clock::instant_at(&SystemClock, session.last_request);
+        state
+            .sessions
+            .insert(SessionID::from_string(&i.to_string()).unwrap(), session);
+    }
+    state
+}
+
+#[test]
+fn incremental_session_expiry_converges() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.cleanup_batch_size = 1000;
+    settings.cleanup_time_budget = std::time::Duration::from_secs(10);
+    let mut state = make_state_with_many_sessions(
+        20_000,
+        now,
+        chrono::Duration::from_std(settings.tunables().session_keep_alive_duration).unwrap(),
+    );
+
+    let mut cursor = cleanup::CleanupCursor::default();
+    let mut ticks = 0;
+    while !cleanup::expire_sessions_incrementally(&settings, &mut state, &mut cursor, now) {
+        ticks += 1;
+        assert!(ticks < 20);
+    }
+    assert_eq!(ticks, 19);
+    assert_eq!(state.sessions.len(), 10_000);
+    assert_eq!(state.cleanup_metrics.last_pass_sessions_scanned, 20_000);
+    assert_eq!(state.cleanup_metrics.completed_passes, 1);
+}
+
+#[test]
+fn incremental_session_expiry_respects_time_budget() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.cleanup_batch_size = usize::MAX;
+    settings.cleanup_time_budget = std::time::Duration::from_micros(200);
+    let mut state = make_state_with_many_sessions(
+        50_000,
+        now,
+        chrono::Duration::from_std(settings.tunables().session_keep_alive_duration).unwrap(),
+    );
+
+    let mut cursor = cleanup::CleanupCursor::default();
+    let mut ticks = 0;
+    while !cleanup::expire_sessions_incrementally(&settings, &mut state, &mut cursor, now) {
+        ticks += 1;
+        // Generous tolerance because the initial collection of all session ids is not
+        // interruptible.
+        assert!(state.cleanup_metrics.last_tick_duration < std::time::Duration::from_millis(100));
+    }
+    assert!(ticks > 0);
+    assert_eq!(state.sessions.len(), 25_000);
+    assert_eq!(state.cleanup_metrics.last_pass_sessions_scanned, 50_000);
+}
+
+#[tokio::test]
+async fn evict_least_recently_used_sessions() {
+    let now = chrono::Utc::now();
+    let settings = Settings::default("".to_string());
+    settings.tunables.write().max_sessions = 15_000;
+    let state = Mutex::new(make_state_with_many_sessions(
+        20_000,
+        now,
+        chrono::Duration::minutes(1),
+    ));
+
+    cleanup::evict_least_recently_used_sessions(&settings, &state, chrono::Utc::now()).await;
+    {
+        let state = state.lock();
+        assert_eq!(state.sessions.len(), 15_000);
+        assert_eq!(state.cleanup_metrics.evicted_sessions, 5_000);
+        // Only old sessions have been evicted.
+        let fresh_count = state
+            .sessions
+            .values()
+            .filter(|session| session.last_request == now)
+            .count();
+        assert_eq!(fresh_count, 10_000);
+    }
+
+    cleanup::evict_least_recently_used_sessions(&settings, &state, chrono::Utc::now()).await;
+    assert_eq!(state.lock().sessions.len(), 15_000);
+}
+
+#[test]
+fn id_character_set() {
+    let valid = ["1234", "blue-tiger-42", "a_b", "ABCxyz", &"a".repeat(100)];
+    let invalid = [
+        "",
+        "a&b",
+        "a=b",
+        "a b",
+        "a\nb",
+        "a%26b",
+        "a/b",
+        "caf\u{e9}",
+        "\u{1F600}",
+        "a\u{0}b",
+        "a\tb",
+        &"a".repeat(101),
+    ];
+    for id in valid {
+        assert!(SessionID::from_string(id).is_ok(), "{:?}", id);
+        assert!(UserID::from_string(id).is_ok(), "{:?}", id);
+    }
+    for id in invalid {
+        assert!(SessionID::from_string(id).is_err(), "{:?}", id);
+        assert!(UserID::from_string(id).is_err(), "{:?}", id);
+    }
+}
+
+#[tokio::test]
+async fn reject_user_id_with_ampersand() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("k", "my-test-token", "page").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=k&user=a%26b", ctx.url))
+                .body("42"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "bad_user_id").await;
+
+    let res = ctx.request_responses(Some("k"), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert!(result.responses_by_user.is_empty());
+}
+
+#[tokio::test]
+async fn new_session_id_is_valid() {
+    let ctx = setup().await;
+    let res = ctx
+        .client
+        .post(format!("{}/new", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    assert!(SessionID::from_string(session).is_ok());
+    let res = ctx.request_session_page(session).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[test]
+fn parse_digest_interval() {
+    assert_eq!(
+        digest::parse_interval("15m"),
+        Some(std::time::Duration::from_secs(15 * 60))
+    );
+    assert_eq!(
+        digest::parse_interval("2h"),
+        Some(std::time::Duration::from_secs(2 * 60 * 60))
+    );
+    assert_eq!(
+        digest::parse_interval("90s"),
+        Some(std::time::Duration::from_secs(90))
+    );
+    for interval in [
+        "",
+        "15",
+        "m",
+        "10s",
+        "25h",
+        "15x",
+        "-5m",
+        "99999999999999999999h",
+    ] {
+        assert_eq!(digest::parse_interval(interval), None, "{:?}", interval);
+    }
+}
+
+#[test]
+fn digest_only_delivers_non_empty_intervals() {
+    let t0 = std::time::Instant::now();
+    let interval = std::time::Duration::from_secs(15 * 60);
+    let mut state = State::default();
+    let session_id = SessionID::from_string("digest").unwrap();
+    let mut session = SessionState::new(
+        AccessToken::from_string("my-test-token").unwrap(),
+        "page".to_string(),
+        chrono::Utc::now(),
+        std::time::Instant::now(),
+    );
+    session.digest = Some(digest::Digest::new(
+        "http://127.0.0.1:1/digest".to_string(),
+        interval,
+        t0,
+        0,
+    ));
+    state.sessions.insert(session_id.clone(), session);
+
+    // Not due yet.
+    assert!(digest::collect_due_digests(&mut state, t0).is_empty());
+    // First interval without responses.
+    let t1 = t0 + interval;
+    assert!(digest::collect_due_digests(&mut state, t1).is_empty());
+
+    let session = state.sessions.get_mut(&session_id).unwrap();
+    for (i, user) in ["a", "b"].iter().enumerate() {
+        session.responses.insert(
+            UserID::from_string(user).unwrap(),
+            UserResponse {
+                data: i.to_string(),
+                content_type: "text/plain".to_string(),
+                id: i,
+                was_received: false,
+                time: chrono::Utc::now(),
+                arrived: std::time::Instant::now(),
+                revision: 0,
+                metadata: None,
+                coalesced: false,
+            },
+        );
+        session.next_response_id += 1;
+    }
+
+    // Second interval with responses.
+    let t2 = t1 + interval;
+    assert!(
+        digest::collect_due_digests(&mut state, t2 - std::time::Duration::from_secs(1)).is_empty()
+    );
+    let deliveries = digest::collect_due_digests(&mut state, t2);
+    assert_eq!(deliveries.len(), 1);
+    let payload = &deliveries[0].payload;
+    assert_eq!(payload.session, "digest");
+    assert_eq!(payload.start, 0);
+    assert_eq!(payload.end, 2);
+    assert_eq!(payload.responses_by_user.len(), 2);
+    assert_eq!(
+        payload.responses_by_user[&UserID::from_string("b").unwrap()],
+        "1"
+    );
+
+    // Delivered responses are not sent again.
+    assert!(digest::collect_due_digests(&mut state, t2 + interval).is_empty());
+}
+
+/// Posts received by a second server, with the signature header and the body.
+type ReceivedPosts = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+/// Server that records all posts and responds with the given status.
+async fn start_webhook_receiver(
+    status: Arc<std::sync::atomic::AtomicU16>,
+) -> (String, ReceivedPosts, tokio::task::JoinHandle<()>) {
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "http://127.0.0.1:{}/hook",
+        listener.local_addr().unwrap().port()
+    );
+    let received: ReceivedPosts = Arc::default();
+    let received_clone = received.clone();
+    let server = HttpServer::new(move || {
+        let received = received_clone.clone();
+        let status = status.clone();
+        App::new().route(
+            "/hook",
+            web::post().to(move |req: HttpRequest, body: web::Bytes| {
+                let received = received.clone();
+                let status = status.load(std::sync::atomic::Ordering::SeqCst);
+                async move {
+                    let signature = req
+                        .headers()
+                        .get(webhooks::SIGNATURE_HEADER)
+                        .map(|value| value.to_str().unwrap().to_string())
+                        .unwrap_or_default();
+                    received.lock().push((signature, body.to_vec()));
+                    HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap())
+                        .finish()
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    let handle = tokio::spawn(async move {
+        server.await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    (url, received, handle)
+}
+
+async fn wait_until(mut condition: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    }
+    panic!("Condition not met in time");
+}
+
+#[tokio::test]
+async fn deliver_responses_to_webhook() {
+    let ctx = setup_with_settings(|settings| {
+        settings.allow_private_webhook_urls = true;
+        settings.webhook_debounce = std::time::Duration::from_millis(100);
+        settings.webhook_check_interval = std::time::Duration::from_millis(10);
+        settings.webhook_initial_backoff = std::time::Duration::from_millis(10);
+        settings.webhook_max_attempts = 2;
+        settings.webhook_max_failures = 2;
+    })
+    .await;
+    let status = Arc::new(std::sync::atomic::AtomicU16::new(200));
+    let (hook_url, received, receiver) = start_webhook_receiver(status.clone()).await;
+    let delivery = tokio::spawn(response_webhook::do_periodic_webhook_delivery(
+        ctx.settings.clone(),
+        ctx.state.clone(),
+    ));
+    let token = "my-test-token";
+    ctx.set_page_and_check("w", token, "page").await;
+    let session_id = SessionID::from_string("w").unwrap();
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!(
+                    "{}/webhook?session=w&url=ftp://example.com",
+                    ctx.url
+                ))
+                .bearer_auth(token),
+        )
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/webhook?session=w&url={}", ctx.url, hook_url))
+                .bearer_auth(token),
+        )
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let secret = res.json::<routes::WebhookSecret>().await.unwrap().secret;
+
+    // Both responses arrive within the debounce duration.
+    ctx.send_reponse(Some("w"), Some("a"), "1").await;
+    ctx.send_reponse(Some("w"), Some("b"), "2").await;
+    wait_until(|| !received.lock().is_empty()).await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    {
+        let received = received.lock();
+        assert_eq!(received.len(), 1);
+        let (signature, body) = &received[0];
+        assert_eq!(
+            *signature,
+            format!("sha256={}", webhooks::sign(&secret, body))
+        );
+        let payload: RetrievedResponses = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload.session, "w");
+        assert_eq!(payload.next_start, 2);
+        assert_eq!(payload.total_responses, 2);
+        assert_eq!(
+            payload.responses_by_user[&UserID::from_string("b").unwrap()],
+            "2"
+        );
+    }
+
+    // Only the new response is delivered.
+    ctx.send_reponse(Some("w"), Some("c"), "3").await;
+    wait_until(|| received.lock().len() == 2).await;
+    let payload: RetrievedResponses = serde_json::from_slice(&received.lock()[1].1).unwrap();
+    assert_eq!(payload.responses_by_user.len(), 1);
+    assert_eq!(payload.next_start, 3);
+
+    // The webhook is disabled after the deliveries failed twice.
+    status.store(500, std::sync::atomic::Ordering::SeqCst);
+    ctx.send_reponse(Some("w"), Some("d"), "4").await;
+    wait_until(|| ctx.state.lock().sessions[&session_id].webhook.is_none()).await;
+    // Two attempts for each of the two deliveries.
+    assert_eq!(received.lock().len(), 6);
+
+    delivery.abort();
+    receiver.abort();
+}
+
+#[tokio::test]
+async fn webhook_rejects_private_urls() {
+    let ctx = setup().await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("w", token, "page").await;
+    for url in [
+        "http://127.0.0.1:1/hook",
+        "http://[::1]/hook",
+        "http://10.0.0.1/hook",
+    ] {
+        let res = ctx
+            .request_json(
+                ctx.client
+                    .post(format!("{}/webhook", ctx.url))
+                    .query(&[("session", "w"), ("url", url)])
+                    .bearer_auth(token),
+            )
+            .await;
+        assert_error_code(
+            res,
+            reqwest::StatusCode::BAD_REQUEST,
+            "bad_query_parameters",
+        )
+        .await;
+    }
+
+    for ip in [
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "fd00::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(webhooks::is_private_ip(ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in ["1.1.1.1", "2606:4700:4700::1111"] {
+        assert!(!webhooks::is_private_ip(ip.parse().unwrap()), "{}", ip);
+    }
+}
+
+#[tokio::test]
+async fn webhooks_dont_follow_redirects() {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    let status = Arc::new(std::sync::atomic::AtomicU16::new(200));
+    let (hook_url, received, receiver) = start_webhook_receiver(status).await;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let redirect_url = format!(
+        "http://127.0.0.1:{}/",
+        listener.local_addr().unwrap().port()
+    );
+    let redirect = HttpServer::new(move || {
+        let hook_url = hook_url.clone();
+        App::new().default_service(web::to(move || {
+            let hook_url = hook_url.clone();
+            async move {
+                HttpResponse::TemporaryRedirect()
+                    .insert_header(("Location", hook_url))
+                    .finish()
+            }
+        }))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    let redirect = tokio::spawn(async move { redirect.await.unwrap() });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let mut settings = Settings::default("http://127.0.0.1".to_string());
+    settings.allow_private_webhook_urls = true;
+    settings.webhook_max_attempts = 1;
+    let delivered =
+        webhooks::deliver_with_retry(&settings, &redirect_url, &serde_json::json!({})).await;
+    assert!(!delivered);
+    assert!(received.lock().is_empty());
+    redirect.abort();
+    receiver.abort();
+}
+
+#[tokio::test]
+async fn enable_digest() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("l", "my-test-token", "page").await;
+
+    let digest_url = |interval: &str, url: &str| {
+        format!(
+            "{}/digest?session=l&interval={}&url={}",
+            ctx.url, interval, url
+        )
+    };
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(digest_url("15m", "http%3A%2F%2Fexample.com"))
+                .bearer_auth("other-test-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(digest_url("5s", "http%3A%2F%2Fexample.com"))
+                .bearer_auth("my-test-token"),
+        )
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(digest_url("15m", "ftp%3A%2F%2Fexample.com"))
+                .bearer_auth("my-test-token"),
+        )
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+
+    let res = ctx
+        .client
+        .post(digest_url("15m", "http%3A%2F%2Fexample.com"))
+        .bearer_auth("my-test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .client
+        .delete(format!("{}/digest?session=l", ctx.url))
+        .bearer_auth("my-test-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn implicit_session_creation() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("m", "my-test-token", "page").await;
+    assert!(ctx
+        .state
+        .lock()
+        .sessions
+        .contains_key(&SessionID::from_string("m").unwrap()));
+}
+
+#[tokio::test]
+async fn no_implicit_session_creation() {
+    let ctx =
+        setup_with_settings(|settings| settings.allow_implicit_session_creation = false).await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=1234", ctx.url))
+                .bearer_auth("my-test-token")
+                .body("page"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+
+    // Sessions created with /new can still be updated.
+    let res = ctx
+        .client
+        .post(format!("{}/new", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    let token = body["token"].as_str().unwrap();
+    ctx.set_page_and_check(session, token, "page").await;
+}
+
+fn header_value<'a>(res: &'a reqwest::Response, name: &str) -> &'a str {
+    res.headers().get(name).unwrap().to_str().unwrap()
+}
+
+#[tokio::test]
+async fn client_config_reflects_session_overrides() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("n", "my-test-token", "page").await;
+
+    let res = ctx.request_session_page("n").await;
+    assert_eq!(header_value(&res, "X-Polli-Max-Response-Size"), "4000");
+    assert_eq!(header_value(&res, "X-Polli-Accepting-Responses"), "true");
+    assert_eq!(header_value(&res, "X-Polli-Lock-First-Response"), "false");
+
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/page?session=n&max_response_size=10&lock_first_response=true",
+            ctx.url
+        ))
+        .bearer_auth("my-test-token")
+        .body("page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx.request_session_page("n").await;
+    assert_eq!(header_value(&res, "X-Polli-Max-Response-Size"), "10");
+    assert_eq!(header_value(&res, "X-Polli-Lock-First-Response"), "true");
+
+    let res = ctx
+        .client
+        .get(format!("{}/client_config?session=n", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    let config: ClientConfig = res.json().await.unwrap();
+    assert_eq!(
+        config,
+        ClientConfig {
+            max_response_size: 10,
+            max_user_id_length: 100,
+            accepting_responses: true,
+            lock_first_response: true,
+            voting_deadline: None,
+        }
+    );
+
+    let res = ctx.send_reponse(Some("n"), Some("me"), "01234567890").await;
+    assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    let res = ctx.send_reponse(Some("n"), Some("me"), "0123456789").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn session_override_cannot_raise_limit() {
+    let ctx = setup().await;
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/page?session=o&max_response_size=1000000",
+            ctx.url
+        ))
+        .bearer_auth("my-test-token")
+        .body("page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.request_session_page("o").await;
+    assert_eq!(header_value(&res, "X-Polli-Max-Response-Size"), "4000");
+}
+
+#[tokio::test]
+async fn new_session_with_free_desired_id() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "free",
+            "token": "my-test-token",
+            "strict": true,
+        }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["session"], "free");
+    assert_eq!(body["token"], "my-test-token");
+}
+
+#[tokio::test]
+async fn new_session_with_desired_id_taken_by_self() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("mine", "my-test-token", "my page")
+        .await;
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "mine",
+            "token": "my-test-token",
+            "strict": true,
+        }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["session"], "mine");
+    // The existing session is reused as is.
+    assert_eq!(ctx.request_session_page_text("mine").await, "my page");
+}
+
+#[tokio::test]
+async fn new_session_with_desired_id_taken_by_other() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("taken", "other-test-token", "other page")
+        .await;
+
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "taken",
+            "token": "my-test-token",
+            "strict": true,
+        }))
+        .await;
+    assert_error_code(res, reqwest::StatusCode::CONFLICT, "session_taken").await;
+
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "taken",
+            "token": "my-test-token",
+        }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_ne!(body["session"], "taken");
+    assert_ne!(body["token"], "my-test-token");
+    assert_eq!(ctx.request_session_page_text("taken").await, "other page");
+}
+
+/// A session with two responses that satisfies all invariants.
+fn make_verify_fixture() -> (SessionID, SessionState) {
+    let mut session = SessionState::new(
+        AccessToken::from_string("my-test-token").unwrap(),
+        "page".to_string(),
+        chrono::Utc::now(),
+        std::time::Instant::now(),
+    );
+    for (id, user) in ["a", "b"].iter().enumerate() {
+        session.responses.insert(
+            UserID::from_string(user).unwrap(),
+            UserResponse {
+                data: "42".to_string(),
+                content_type: "text/plain".to_string(),
+                id,
+                was_received: false,
+                time: chrono::Utc::now(),
+                arrived: std::time::Instant::now(),
+                revision: 0,
+                metadata: None,
+                coalesced: false,
+            },
+        );
+    }
+    session.next_response_id = 2;
+    (SessionID::from_string("verify").unwrap(), session)
+}
+
+fn verify_fixture(
+    session_id: &SessionID,
+    session: &mut SessionState,
+    repair: bool,
+) -> Vec<verify::Invariant> {
+    verify::verify_session(session_id, session, chrono::Utc::now(), repair)
+        .iter()
+        .map(|violation| violation.invariant)
+        .collect()
+}
+
+#[test]
+fn verify_valid_fixture() {
+    let (session_id, mut session) = make_verify_fixture();
+    assert!(verify_fixture(&session_id, &mut session, false).is_empty());
+}
+
+#[test]
+fn verify_next_response_id() {
+    let (session_id, mut session) = make_verify_fixture();
+    session.next_response_id = 1;
+    assert_eq!(
+        verify_fixture(&session_id, &mut session, true),
+        [verify::Invariant::NextResponseIdAfterResponses]
+    );
+    assert_eq!(session.next_response_id, 2);
+    assert!(verify_fixture(&session_id, &mut session, false).is_empty());
+}
+
+#[test]
+fn verify_unique_response_ids() {
+    let (session_id, mut session) = make_verify_fixture();
+    for response in session.responses.values_mut() {
+        response.id = 0;
+    }
+    assert_eq!(
+        verify_fixture(&session_id, &mut session, true),
+        [verify::Invariant::UniqueResponseIds]
+    );
+    // Not repairable.
+    assert_eq!(
+        verify_fixture(&session_id, &mut session, false),
+        [verify::Invariant::UniqueResponseIds]
+    );
+}
+
+#[test]
+fn verify_digest_cursor() {
+    let (session_id, mut session) = make_verify_fixture();
+    session.digest = Some(digest::Digest::new(
+        "http://127.0.0.1:1".to_string(),
+        digest::MIN_DIGEST_INTERVAL,
+        std::time::Instant::now(),
+        5,
+    ));
+    assert_eq!(
+        verify_fixture(&session_id, &mut session, true),
+        [verify::Invariant::DigestCursorInRange]
+    );
+    assert_eq!(session.digest.as_ref().unwrap().start, 2);
+    assert!(verify_fixture(&session_id, &mut session, false).is_empty());
+}
+
+#[test]
+fn verify_last_request() {
+    let (session_id, mut session) = make_verify_fixture();
+    session.last_request = chrono::Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(
+        verify_fixture(&session_id, &mut session, false),
+        [verify::Invariant::LastRequestNotInFuture]
+    );
+    assert_eq!(
+        verify_fixture(&session_id, &mut session, true),
+        [verify::Invariant::LastRequestNotInFuture]
+    );
+    assert!(verify_fixture(&session_id, &mut session, false).is_empty());
+}
+
+#[test]
+fn verify_approx_bytes() {
+    let (session_id, session) = make_verify_fixture();
+    let mut state = State::default();
+    state.sessions.insert(session_id, session);
+    state.recount_memory_usage();
+    let correct_bytes = state.approx_bytes;
+    let invariants = |state: &mut State, repair: bool| -> Vec<verify::Invariant> {
+        verify::verify_state(state, chrono::Utc::now(), repair)
+            .iter()
+            .map(|violation| violation.invariant)
+            .collect()
+    };
+    assert!(invariants(&mut state, false).is_empty());
+
+    state.approx_bytes = correct_bytes + 1000;
+    assert_eq!(
+        invariants(&mut state, false),
+        [verify::Invariant::ApproxBytesMatchesRecount]
+    );
+    assert_eq!(state.approx_bytes, correct_bytes + 1000);
+    assert_eq!(
+        invariants(&mut state, true),
+        [verify::Invariant::ApproxBytesMatchesRecount]
+    );
+    assert_eq!(state.approx_bytes, correct_bytes);
+    assert!(invariants(&mut state, false).is_empty());
+}
+
+#[tokio::test]
+async fn admin_verify_disabled_without_admin_token() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/admin/verify", ctx.url))
+                .bearer_auth("admin-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "admin_disabled").await;
+}
+
+#[tokio::test]
+async fn admin_verify() {
+    let ctx =
+        setup_with_settings(|settings| settings.admin_token = Some("admin-token".to_string()))
+            .await;
+    ctx.set_page_and_check("p", "my-test-token", "page").await;
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/admin/verify", ctx.url))
+                .bearer_auth("wrong-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    ctx.state
+        .lock()
+        .sessions
+        .get_mut(&SessionID::from_string("p").unwrap())
+        .unwrap()
+        .last_request += chrono::Duration::hours(1);
+
+    for (repair, expected_violations) in [(false, 1), (true, 1), (false, 0)] {
+        let res = ctx
+            .client
+            .post(format!(
+                "{}/admin/verify?session=p&repair={}",
+                ctx.url, repair
+            ))
+            .bearer_auth("admin-token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let result: routes::VerifyResult = res.json().await.unwrap();
+        assert_eq!(result.violations.len(), expected_violations);
+    }
+}
+
+fn assert_word_session_id(session: &str) {
+    let parts: Vec<&str> = session.split('-').collect();
+    assert_eq!(parts.len(), 3, "{}", session);
+    assert!(polli_live::words::ADJECTIVES.contains(&parts[0]));
+    assert!(polli_live::words::NOUNS.contains(&parts[1]));
+    assert!(parts[2].parse::<u32>().unwrap() < 100);
+}
+
+#[tokio::test]
+async fn new_session_with_word_id() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_new_session(serde_json::json!({ "style": "words" }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    assert_word_session_id(session);
+
+    let res = ctx.request_session_page(session).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    ctx.set_page_and_check(session, body["token"].as_str().unwrap(), "page")
+        .await;
+}
+
+#[tokio::test]
+async fn new_session_with_default_word_id() {
+    let ctx = setup_with_settings(|settings| {
+        settings.session_id_style = polli_live::settings::SessionIDStyle::Words
+    })
+    .await;
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_word_session_id(body["session"].as_str().unwrap());
+
+    let res = ctx
+        .request_new_session(serde_json::json!({ "style": "digits" }))
+        .await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    assert_eq!(session.len(), 6);
+    assert!(session.bytes().all(|c| c.is_ascii_digit()));
+}
+
+#[tokio::test]
+async fn new_session_with_initial_page() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_new_session(serde_json::json!({ "page": "my first page" }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    assert_eq!(
+        ctx.request_session_page_text(session).await,
+        "my first page"
+    );
+
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "page": "x".repeat(ctx.settings.max_page_size.as_u64() as usize + 1),
+        }))
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::PAYLOAD_TOO_LARGE,
+        "page_too_large",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn set_large_page() {
+    let ctx = setup().await;
+    // The limit applies to the page including the injected script.
+    let max_page_size =
+        ctx.settings.max_page_size.as_u64() as usize - page::injection_snippet(&ctx.settings).len();
+    let page = "x".repeat(max_page_size);
+    ctx.set_page_and_check("large", "my-test-token", &page)
+        .await;
+
+    let page = "x".repeat(max_page_size + 1);
+    let res = ctx
+        .request_page_update(Some("large"), Some("my-test-token"), &page)
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn new_session_with_ttl() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| settings.clock = clock.clone()).await;
+    let res = ctx
+        .request_new_session(serde_json::json!({
+            "session": "short-lived",
+            "token": "my-test-token",
+            "ttl_seconds": 1,
+        }))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let other = ctx.request_new_session(serde_json::json!({})).await;
+    assert_eq!(other.status(), reqwest::StatusCode::OK);
+
+    clock.advance(std::time::Duration::from_secs(2));
+    let mut state = ctx.state.lock();
+    cleanup::expire_sessions_incrementally(
+        &ctx.settings,
+        &mut state,
+        &mut cleanup::CleanupCursor::default(),
+        clock.now(),
+    );
+    assert!(!state
+        .sessions
+        .contains_key(&SessionID::from_string("short-lived").unwrap()));
+    assert_eq!(state.sessions.len(), 1);
+}
+
+#[tokio::test]
+async fn rotate_token() {
+    let ctx = setup().await;
+    let old_token = "my-test-token";
+    ctx.set_page_and_check("q", old_token, "page 1").await;
+
+    let rotate = |token: &str| {
+        ctx.client
+            .post(format!("{}/rotate_token?session=q", ctx.url))
+            .bearer_auth(token)
+    };
+
+    let res = ctx.request_json(rotate("wrong-test-token")).await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    let res = rotate(old_token).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let new_token = res.json::<routes::RotatedToken>().await.unwrap().token;
+    assert_ne!(new_token, old_token);
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=q", ctx.url))
+                .bearer_auth(old_token)
+                .body("page 2"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    assert_eq!(ctx.request_session_page_text("q").await, "page 1");
+
+    ctx.set_page_and_check("q", &new_token, "page 3").await;
+
+    // The old token can't be used to rotate again either.
+    let res = rotate(old_token).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rotate_token_of_nonexistent_session() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/rotate_token?session=unknown", ctx.url))
+                .bearer_auth("my-test-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+}
+
+#[tokio::test]
+async fn viewer_token() {
+    let ctx = setup_with_settings(|s| s.responses_require_auth = true).await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("v", token, "page 1").await;
+    let res = ctx.send_reponse(Some("v"), Some("me"), "42").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .client
+        .post(format!("{}/viewer_token?session=v", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let viewer_token = res.json::<routes::ViewerToken>().await.unwrap().token;
+    assert_ne!(viewer_token, token);
+
+    let get_responses = |token: Option<&str>| {
+        let builder = ctx
+            .client
+            .get(format!("{}/responses?session=v&start=0", ctx.url));
+        match token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    };
+
+    // Both tokens can read responses, but nobody else can.
+    for token in [token, viewer_token.as_str()] {
+        let res = get_responses(Some(token)).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        let result: routes::RetrievedResponses = res.json().await.unwrap();
+        assert_eq!(result.responses_by_user.len(), 1);
+    }
+    let res = ctx.request_json(get_responses(None)).await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = ctx
+        .request_json(get_responses(Some("wrong-test-token")))
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+
+    // Only the main token can change the page.
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=v", ctx.url))
+                .bearer_auth(&viewer_token)
+                .body("page 2"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    assert_eq!(ctx.request_session_page_text("v").await, "page 1");
+    ctx.set_page_and_check("v", token, "page 3").await;
+
+    // The viewer token can't create further viewer tokens.
+    let res = ctx
+        .client
+        .post(format!("{}/viewer_token?session=v", ctx.url))
+        .bearer_auth(&viewer_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn responses_without_required_auth() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("w", "my-test-token", "page").await;
+    ctx.send_reponse(Some("w"), Some("me"), "42").await;
+    let res = ctx.request_responses(Some("w"), Some(0)).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[test]
+fn signed_token_derivation() {
+    let secret = b"test-secret";
+    let session = SessionID::from_string("abc").unwrap();
+    let now = chrono::Utc::now();
+    let max_age = std::time::Duration::from_secs(60);
+    let token = AccessToken::new_signed(secret, &session, now);
+    assert!(AccessToken::from_string(&token.0).is_ok());
+
+    // Only the millisecond precision is kept.
+    let issued_at = token.signed_issue_time(secret, &session, now, max_age);
+    assert_eq!(
+        issued_at.unwrap().timestamp_millis(),
+        now.timestamp_millis()
+    );
+
+    let other_session = SessionID::from_string("abd").unwrap();
+    assert!(token
+        .signed_issue_time(secret, &other_session, now, max_age)
+        .is_none());
+    assert!(token
+        .signed_issue_time(b"other-secret", &session, now, max_age)
+        .is_none());
+    let later = now + chrono::Duration::seconds(61);
+    assert!(token
+        .signed_issue_time(secret, &session, later, max_age)
+        .is_none());
+
+    // Changing the issue time invalidates the signature.
+    let (_, signature) = token.0.split_once('.').unwrap();
+    let tampered = AccessToken(format!("{}.{}", now.timestamp_millis() + 1, signature));
+    assert!(tampered
+        .signed_issue_time(secret, &session, now, max_age)
+        .is_none());
+    for bad in ["not-a-signed-token", "123.abc", "123.zz", ""] {
+        assert!(AccessToken(bad.to_string())
+            .signed_issue_time(secret, &session, now, max_age)
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn signed_token_survives_restart() {
+    let ctx = setup_with_settings(|s| s.allow_implicit_session_creation = false).await;
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    let token = body["token"].as_str().unwrap();
+
+    // Simulate a restart that forgets all sessions.
+    ctx.state.lock().sessions.clear();
+    ctx.set_page_and_check(session, token, "page 1").await;
+
+    // Someone else claimed the session id after the restart.
+    ctx.state.lock().sessions.clear();
+    ctx.state
+        .lock()
+        .set_page(
+            &ctx.settings,
+            &SessionID::from_string(session).unwrap(),
+            AccessToken::from_string("other-test-token").unwrap(),
+            "other page".to_string(),
+            polli_live::state::SetPageOptions {
+                allow_create: true,
+                notify: false,
+                creator_ip: None,
+                keep_response_schema: false,
+            },
+        )
+        .unwrap();
+    ctx.set_page_and_check(session, token, "page 2").await;
+    let res = ctx
+        .request_page_update(Some(session), Some("other-test-token"), "page 3")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // A signed token is only valid for the session it has been created for.
+    let res = ctx
+        .request_page_update(Some("other-session"), Some(token), "page")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn rotated_signed_token_is_not_accepted_after_restart() {
+    let ctx = setup().await;
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    let old_token = body["token"].as_str().unwrap();
+
+    let res = ctx
+        .client
+        .post(format!("{}/rotate_token?session={}", ctx.url, session))
+        .bearer_auth(old_token)
+        .send()
+        .await
+        .unwrap();
+    let new_token = res.json::<routes::RotatedToken>().await.unwrap().token;
+
+    let res = ctx
+        .request_page_update(Some(session), Some(old_token), "page 1")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    ctx.state.lock().sessions.clear();
+    ctx.set_page_and_check(session, &new_token, "page 2").await;
+}
+
+fn make_persist_fixture() -> State {
+    let (session_id, mut session) = make_verify_fixture();
+    session.viewer_token = Some(AccessToken::from_string("my-viewer-token").unwrap());
+    session.token_issued_at = Some(chrono::Utc::now());
+    session.lock_first_response = true;
+    session.max_response_size = Some(byte_unit::Byte::from_u64(100));
+    session.keep_alive = Some(std::time::Duration::from_secs(60));
+    session.response_schema =
+        Some(ResponseSchema::new(serde_json::json!({ "enum": ["A", "B"] })).unwrap());
+    session.digest = Some(digest::Digest::new(
+        "http://example.com/hook".to_string(),
+        std::time::Duration::from_secs(60),
+        std::time::Instant::now(),
+        1,
+    ));
+    let mut state = State::default();
+    state.sessions.insert(session_id, session);
+    state.sessions.insert(
+        SessionID::from_string("other").unwrap(),
+        SessionState::new(
+            AccessToken::from_string("other-test-token").unwrap(),
+            "other page".to_string(),
+            chrono::Utc::now(),
+            std::time::Instant::now(),
+        ),
+    );
+    state
+}
+
+#[test]
+fn persist_round_trip() {
+    let state = make_persist_fixture();
+    let mut restored = persist::state_from_snapshot(&persist::state_to_snapshot(&state)).unwrap();
+    assert_eq!(restored.sessions.len(), state.sessions.len());
+    for (session_id, session) in &state.sessions {
+        let restored_session = restored.sessions.get(session_id).unwrap();
+        assert_eq!(restored_session.page, session.page);
+        assert_eq!(restored_session.access_token, session.access_token);
+        assert_eq!(restored_session.token_issued_at, session.token_issued_at);
+        assert_eq!(restored_session.viewer_token, session.viewer_token);
+        assert_eq!(restored_session.next_response_id, session.next_response_id);
+        assert_eq!(restored_session.last_request, session.last_request);
+        assert_eq!(
+            restored_session.lock_first_response,
+            session.lock_first_response
+        );
+        assert_eq!(
+            restored_session.max_response_size,
+            session.max_response_size
+        );
+        assert_eq!(restored_session.keep_alive, session.keep_alive);
+        assert_eq!(
+            restored_session
+                .response_schema
+                .as_ref()
+                .map(|s| s.schema()),
+            session.response_schema.as_ref().map(|s| s.schema())
+        );
+        if let Some(schema) = &restored_session.response_schema {
+            assert!(schema.validate("\"C\"").is_err());
+        }
+        assert_eq!(
+            restored_session.digest.as_ref().map(|d| (&d.url, d.start)),
+            session.digest.as_ref().map(|d| (&d.url, d.start))
+        );
+        assert_eq!(restored_session.responses.len(), session.responses.len());
+        for (user_id, response) in &session.responses {
+            let restored_response = restored_session.responses.get(user_id).unwrap();
+            assert_eq!(restored_response.data, response.data);
+            assert_eq!(restored_response.id, response.id);
+            assert_eq!(restored_response.time, response.time);
+        }
+    }
+    assert!(verify::verify_state(&mut restored, chrono::Utc::now(), false).is_empty());
+}
+
+#[test]
+fn persist_restores_monotonic_times() {
+    let clock = MockClock::new();
+    let mut settings = Settings::default("".to_string());
+    settings.clock = clock.clone();
+    let keep_alive = settings.tunables().session_keep_alive_duration;
+    let mut state = make_persist_fixture();
+    for (session_id, session) in &mut state.sessions {
+        session.keep_alive = None;
+        session.last_request = match session_id.0.as_str() {
+            "other" => clock.now() - chrono::Duration::from_std(keep_alive).unwrap(),
+            _ => clock.now() - chrono::Duration::seconds(20),
+        };
+        for response in session.responses.values_mut() {
+            response.time = clock.now() - chrono::Duration::seconds(30);
+        }
+    }
+    let mut restored = persist::state_from_snapshot(&persist::state_to_snapshot(&state)).unwrap();
+
+    clock.advance(std::time::Duration::from_secs(60));
+    persist::restore_instants(&settings, &mut restored);
+    // The other session expired while the server was stopped.
+    let other_id = SessionID::from_string("other").unwrap();
+    assert_eq!(restored.sessions.len(), 1);
+    assert!(restored
+        .expired_sessions
+        .contains(&settings, &other_id, clock.now()));
+    let session = restored.sessions.values().next().unwrap();
+    assert_eq!(
+        session.unused_for(&settings),
+        std::time::Duration::from_secs(80)
+    );
+    assert!(!session.responses.is_empty());
+    for response in session.responses.values() {
+        assert_eq!(
+            clock.instant() - response.arrived,
+            std::time::Duration::from_secs(90)
+        );
+    }
+}
+
+#[test]
+fn persist_rejects_bad_snapshots() {
+    assert!(matches!(
+        persist::state_from_snapshot(b"{\"version\": 1, \"sessions\": "),
+        Err(persist::SnapshotError::Parse(_))
+    ));
+    assert!(matches!(
+        persist::state_from_snapshot(b"{\"version\": 1000, \"sessions\": {}}"),
+        Err(persist::SnapshotError::VersionMismatch { found: 1000 })
+    ));
+}
+
+#[tokio::test]
+async fn persist_snapshot_in_batches() {
+    let state = make_persist_fixture();
+    let expected: serde_json::Value =
+        serde_json::from_slice(&persist::state_to_snapshot(&state)).unwrap();
+    let state = Mutex::new(state);
+    for batch_size in [1, 1000] {
+        let mut settings = Settings::default("http://127.0.0.1".to_string());
+        settings.cleanup_batch_size = batch_size;
+        let data = persist::state_to_snapshot_in_batches(&settings, &state).await;
+        let snapshot: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(snapshot, expected);
+    }
+    let data = persist::state_to_snapshot_in_batches(
+        &Settings::default("http://127.0.0.1".to_string()),
+        &Mutex::new(State::default()),
+    )
+    .await;
+    assert!(persist::state_from_snapshot(&data)
+        .unwrap()
+        .sessions
+        .is_empty());
+}
+
+#[tokio::test]
+async fn persist_save_and_load() {
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    assert!(persist::load_state(&dir).sessions.is_empty());
+
+    let state = Mutex::new(make_persist_fixture());
+    let settings = Settings::default("http://127.0.0.1".to_string());
+    persist::save_state(&settings, &dir, &state).await.unwrap();
+    let restored = persist::load_state(&dir);
+    assert_eq!(restored.sessions.len(), 2);
+
+    // Corrupt snapshots are skipped.
+    std::fs::write(persist::snapshot_file_path(&dir), "not json").unwrap();
+    assert!(persist::load_state(&dir).sessions.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Redis tests are ignored by default, because they need a Redis server. Run them with
+/// `POLLI_TEST_REDIS_URL=redis://127.0.0.1:6379 cargo test -- --ignored redis`. All
+/// instances created with the same prefix share sessions.
+async fn setup_with_redis(prefix: &str) -> TestContext {
+    setup_with_redis_and_settings(prefix, |_| {}).await
+}
+
+async fn setup_with_redis_and_settings(
+    prefix: &str,
+    modify_settings: impl FnOnce(&mut Settings),
+) -> TestContext {
+    let redis_url = std::env::var("POLLI_TEST_REDIS_URL")
+        .expect("POLLI_TEST_REDIS_URL has to be set for the Redis tests");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to random port");
+    let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let mut settings = Settings::default(url.clone());
+    settings.redis_key_prefix = prefix.to_string();
+    modify_settings(&mut settings);
+    let state = Arc::new(Mutex::new(State::default()));
+    let storage = Arc::new(
+        RedisStorage::connect(&redis_url, settings.clone())
+            .await
+            .unwrap(),
+    );
+    let server = start_test_server(listener, settings.clone(), state.clone(), storage);
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    TestContext {
+        handle: server,
+        url,
+        client: reqwest::Client::new(),
+        settings,
+        state,
+    }
+}
+
+fn make_redis_test_prefix() -> String {
+    format!("polli-test-{}:", rand::random::<u64>())
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_set_page_and_respond() {
+    let ctx = setup_with_redis(&make_redis_test_prefix()).await;
+    ctx.set_page_and_check("r", "my-test-token", "page 1").await;
+    let res = ctx
+        .request_page_update(Some("r"), Some("other-test-token"), "page 2")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let res = ctx.send_reponse(Some("r"), Some("me"), "42").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some("unknown"), Some("me"), "42").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let res = ctx.request_responses(Some("r"), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 1);
+    assert_eq!(
+        result.responses_by_user[&UserID::from_string("me").unwrap()],
+        "42"
+    );
+
+    // Setting the page again clears the responses.
+    ctx.set_page_and_check("r", "my-test-token", "page 3").await;
+    let res = ctx.send_reponse(Some("r"), Some("you"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.request_responses(Some("r"), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 2);
+    assert_eq!(result.responses_by_user.len(), 1);
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_conditional_responses() {
+    let ctx = setup_with_redis(&make_redis_test_prefix()).await;
+    ctx.set_page_and_check("c", "my-test-token", "page").await;
+
+    let key = [("Idempotency-Key", "submission-1")];
+    let res = send_conditional_response(&ctx, "me", "first", &key).await;
+    assert_eq!(response_etag(&res), "\"0\"");
+    let res = send_conditional_response(&ctx, "me", "retried", &key).await;
+    assert_eq!(response_etag(&res), "\"0\"");
+
+    let res = send_conditional_response(&ctx, "me", "old", &[("If-Match", "\"5\"")]).await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.details.unwrap()["current_id"], 0);
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_lock_first_response() {
+    let ctx = setup_with_redis(&make_redis_test_prefix()).await;
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/page?session=l&lock_first_response=true&max_response_size=3",
+            ctx.url
+        ))
+        .bearer_auth("my-test-token")
+        .body("page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.request_session_page("l").await;
+    assert_eq!(header_value(&res, "X-Polli-Lock-First-Response"), "true");
+    assert_eq!(header_value(&res, "X-Polli-Max-Response-Size"), "3");
+
+    let res = ctx.send_reponse(Some("l"), Some("me"), "1234").await;
+    assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    let res = ctx.send_reponse(Some("l"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=l&user=me", ctx.url))
+                .body("2"),
+        )
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.details.unwrap()["response"], "1");
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_voting_deadline_and_session_limits() {
+    let ctx = setup_with_redis(&make_redis_test_prefix()).await;
+    let set_page = |session: &str, query: &str| {
+        ctx.client
+            .post(format!("{}/page?session={}&{}", ctx.url, session, query))
+            .bearer_auth("my-test-token")
+            .body("page")
+            .send()
+    };
+    let res = set_page("d", "duration_ms=1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=d&user=me", ctx.url))
+                .body("1"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "voting_closed").await;
+    let res = ctx.request_session_page("d").await;
+    assert_eq!(header_value(&res, "X-Polli-Accepting-Responses"), "false");
+
+    // Setting the page again removes the deadline.
+    ctx.set_page_and_check("d", "my-test-token", "page 2").await;
+    let res = ctx.send_reponse(Some("d"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    ctx.settings.tunables.write().max_sessions_per_ip = 2;
+    let res = set_page("e", "").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page?session=f", ctx.url))
+                .bearer_auth("my-test-token")
+                .body("page"),
+        )
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "session_limit_reached");
+    assert_eq!(body.details.unwrap()["limit"], "per_ip");
+
+    ctx.settings.tunables.write().max_sessions_per_ip = 10;
+    ctx.settings.tunables.write().max_sessions_total = 2;
+    let res = set_page("f", "").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    // Existing sessions can still be updated.
+    let res = set_page("e", "force=true").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_rejects_memory_only_options() {
+    let ctx = setup_with_redis(&make_redis_test_prefix()).await;
+    for option in ["anonymous", "keep_response_schema"] {
+        let res = ctx
+            .request_json(
+                ctx.client
+                    .post(format!("{}/page?session=a&{}=true", ctx.url, option))
+                    .bearer_auth("my-test-token")
+                    .body("page"),
+            )
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::NOT_IMPLEMENTED);
+        let body: ErrorBody = res.json().await.unwrap();
+        assert_eq!(body.error, "not_supported");
+        assert_eq!(body.details.unwrap()["option"], option);
+    }
+    // The session has not been created with the ignored option.
+    let res = ctx.request_session_page("a").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/new", ctx.url))
+                .body(r#"{"anonymous": true}"#),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_IMPLEMENTED, "not_supported").await;
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_erase_user_data() {
+    let ctx = setup_with_redis_and_settings(&make_redis_test_prefix(), |settings| {
+        settings.admin_token = Some("admin-token".to_string())
+    })
+    .await;
+    for session in ["e1", "e2"] {
+        ctx.set_page_and_check(session, "my-test-token", "page")
+            .await;
+        ctx.send_reponse(Some(session), Some("erase-me"), "yes")
+            .await;
+    }
+    ctx.send_reponse(Some("e1"), Some("keep"), "yes").await;
+
+    let res = ctx
+        .client
+        .delete(format!("{}/admin/user_data?user=erase-me", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let erased: routes::ErasedUserData = res.json().await.unwrap();
+    assert_eq!(erased.removed, 2);
+    assert_eq!(erased.sessions, 2);
+    let res = ctx.request_responses(Some("e1"), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    let users: Vec<&str> = result
+        .responses_by_user
+        .keys()
+        .map(|user| user.0.as_str())
+        .collect();
+    assert_eq!(users, vec!["keep"]);
+}
+
+#[tokio::test]
+#[ignore = "requires a Redis server, see setup_with_redis"]
+async fn redis_sessions_are_shared_between_instances() {
+    let prefix = make_redis_test_prefix();
+    let ctx_a = setup_with_redis(&prefix).await;
+    let ctx_b = setup_with_redis(&prefix).await;
+    ctx_a.set_page_and_check("s", "my-test-token", "page").await;
+    assert_eq!(ctx_b.request_session_page_text("s").await, "page");
+
+    // A long-poll on one instance returns early when the other one receives a response.
+    let start_time = std::time::Instant::now();
+    let (res, _) = tokio::join!(ctx_a.request_responses(Some("s"), Some(0)), async {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        ctx_b.send_reponse(Some("s"), Some("me"), "42").await
+    });
+    assert!(start_time.elapsed() < ctx_a.settings.tunables().response_long_poll_duration);
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.responses_by_user.len(), 1);
+}
+
+#[test]
+fn rate_limiter_token_bucket() {
+    let rate_limiter = RateLimiter::default();
+    let limit = RateLimit {
+        burst: 2,
+        per_second: 4.0,
+    };
+    let ip = "10.0.0.1".parse().unwrap();
+    let other_ip = "10.0.0.2".parse().unwrap();
+    let start = std::time::Instant::now();
+    let check = |ip, offset_ms| {
+        rate_limiter.check(
+            RateLimitKind::Respond,
+            limit,
+            ip,
+            start + std::time::Duration::from_millis(offset_ms),
+        )
+    };
+
+    assert!(check(ip, 0).is_ok());
+    assert!(check(ip, 0).is_ok());
+    assert_eq!(check(ip, 0), Err(std::time::Duration::from_millis(250)));
+    // Other clients and other kinds of requests have their own buckets.
+    assert!(check(other_ip, 0).is_ok());
+    assert!(rate_limiter
+        .check(RateLimitKind::NewSession, limit, ip, start)
+        .is_ok());
+
+    // One token is refilled every 250ms.
+    assert!(check(ip, 250).is_ok());
+    assert!(check(ip, 250).is_err());
+    assert!(check(ip, 1000).is_ok());
+    assert!(check(ip, 1000).is_ok());
+    assert!(check(ip, 1000).is_err());
+
+    // Buckets that have been refilled completely are forgotten.
+    assert_eq!(rate_limiter.bucket_count(), 3);
+    rate_limiter.remove_full_buckets(start + std::time::Duration::from_millis(300));
+    assert_eq!(rate_limiter.bucket_count(), 1);
+    rate_limiter.remove_full_buckets(start + std::time::Duration::from_secs(10));
+    assert_eq!(rate_limiter.bucket_count(), 0);
+}
+
+#[tokio::test]
+async fn rate_limit_responses() {
+    let ctx = setup_with_settings(|s| {
+        s.respond_rate_limit = RateLimit {
+            burst: 3,
+            per_second: 10.0,
+        }
+    })
+    .await;
+    ctx.set_page_and_check("r", "my-test-token", "page").await;
+    for _ in 0..3 {
+        let res = ctx.send_reponse(Some("r"), Some("me"), "42").await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=r&user=me", ctx.url))
+                .body("42"),
+        )
+        .await;
+    assert_eq!(header_value(&res, "Retry-After"), "1");
+    assert_error_code(
+        res,
+        reqwest::StatusCode::TOO_MANY_REQUESTS,
+        "too_many_requests",
+    )
+    .await;
+
+    // Other routes are limited separately.
+    ctx.set_page_and_check("r", "my-test-token", "page 2").await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    let res = ctx.send_reponse(Some("r"), Some("me"), "42").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rate_limit_new_sessions() {
+    let ctx = setup_with_settings(|s| {
+        s.new_session_rate_limit = RateLimit {
+            burst: 1,
+            per_second: 10.0,
+        }
+    })
+    .await;
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::TOO_MANY_REQUESTS,
+        "too_many_requests",
+    )
+    .await;
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rate_limit_forwarded_for() {
+    let new_session = |ctx: &TestContext, ip: &str| {
+        ctx.client
+            .post(format!("{}/new", ctx.url))
+            .header("X-Forwarded-For", ip)
+            .send()
+    };
+    let limit = RateLimit {
+        burst: 1,
+        per_second: 0.01,
+    };
+
+    // Without a trusted proxy, the header is ignored.
+    let ctx = setup_with_settings(|s| s.new_session_rate_limit = limit).await;
+    let res = new_session(&ctx, "10.0.0.1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = new_session(&ctx, "10.0.0.2").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    let ctx = setup_with_settings(|s| {
+        s.new_session_rate_limit = limit;
+        s.trust_forwarded_for = true;
+    })
+    .await;
+    let res = new_session(&ctx, "10.0.0.1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = new_session(&ctx, "10.0.0.2").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = new_session(&ctx, "10.0.0.1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    // Entries that the client added itself in front of the one from the proxy don't help.
+    let res = new_session(&ctx, "10.0.0.3, 10.0.0.1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let res = new_session(&ctx, "10.0.0.1, 10.0.0.4").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn coalesce_rapid_responses_of_one_user() {
+    let ctx = setup_with_settings(|settings| {
+        let mut tunables = settings.tunables.write();
+        tunables.min_response_interval = std::time::Duration::from_secs(1);
+        tunables.response_long_poll_duration = std::time::Duration::from_secs(30);
+    })
+    .await;
+    ctx.set_page_and_check("t", "my-test-token", "page").await;
+    let me = UserID::from_string("me").unwrap();
+    let res = ctx.send_reponse(Some("t"), Some("me"), "0").await;
+    assert_eq!(header_value(&res, "ETag"), "\"0\"");
+
+    // Someone who saw the first response waits for the next one.
+    let poll = ctx
+        .client
+        .get(format!("{}/responses?session=t&start=1", ctx.url));
+    let poll = tokio::spawn(async move { poll.send().await.unwrap() });
+    for i in 1..20 {
+        let res = ctx
+            .send_reponse(Some("t"), Some("me"), &i.to_string())
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(header_value(&res, "ETag"), "\"0\"");
+    }
+
+    // The newest response replaced the others without a new id or waking anyone up.
+    let res = ctx.request_responses(Some("t"), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.responses_by_user[&me], "19");
+    assert_eq!(result.next_start, 1);
+    assert!(!poll.is_finished());
+
+    // When the interval is over, it gets a single new id.
+    let result: routes::RetrievedResponses = poll.await.unwrap().json().await.unwrap();
+    assert_eq!(result.responses_by_user[&me], "19");
+    assert_eq!(result.next_start, 2);
+    assert_eq!(
+        ctx.state.lock().sessions[&SessionID::from_string("t").unwrap()].next_response_id,
+        2
+    );
+}
+
+#[tokio::test]
+async fn reject_rapid_responses_of_one_user() {
+    let ctx = setup_with_settings(|s| {
+        s.tunables.write().min_response_interval = std::time::Duration::from_secs(60);
+        s.response_throttle = ResponseThrottle::Reject;
+    })
+    .await;
+    ctx.set_page_and_check("t", "my-test-token", "page").await;
+    let res = ctx.send_reponse(Some("t"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=t&user=me", ctx.url))
+                .body("2"),
+        )
+        .await;
+    assert_eq!(header_value(&res, "Retry-After"), "60");
+    assert_error_code(
+        res,
+        reqwest::StatusCode::TOO_MANY_REQUESTS,
+        "too_many_requests",
+    )
+    .await;
+
+    // Other users are not affected.
+    let res = ctx.send_reponse(Some("t"), Some("you"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.request_responses(Some("t"), Some(0)).await;
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 2);
+    assert_eq!(
+        result.responses_by_user[&UserID::from_string("me").unwrap()],
+        "1"
+    );
+}
+
+#[tokio::test]
+async fn max_sessions_total() {
+    let ctx = setup_with_settings(|s| s.tunables.write().max_sessions_total = 2).await;
+    ctx.set_page_and_check("a", "my-test-token", "page").await;
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "session_limit_reached");
+    assert_eq!(body.details.unwrap()["limit"], "total");
+    let res = ctx
+        .request_page_update(Some("b"), Some("my-test-token"), "page")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    // Existing sessions can still be updated.
+    ctx.set_page_and_check("a", "my-test-token", "page 2").await;
+
+    ctx.state
+        .lock()
+        .remove_session(&SessionID::from_string("a").unwrap());
+    ctx.set_page_and_check("b", "my-test-token", "page").await;
+}
+
+#[tokio::test]
+async fn max_sessions_per_ip() {
+    let ctx = setup_with_settings(|s| {
+        s.tunables.write().max_sessions_per_ip = 2;
+        s.trust_forwarded_for = true;
+    })
+    .await;
+    let new_session = |ip: &str| {
+        ctx.client
+            .post(format!("{}/new", ctx.url))
+            .header("X-Forwarded-For", ip)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+    };
+    for _ in 0..2 {
+        let res = new_session("10.0.0.1").await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = new_session("10.0.0.1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.details.unwrap()["limit"], "per_ip");
+    let res = new_session("10.0.0.2").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // Expired sessions don't count anymore.
+    {
+        let mut state = ctx.state.lock();
+        for session in state.sessions.values_mut() {
+            session.last_used -= ctx.settings.tunables().session_keep_alive_duration;
+        }
+        let mut cursor = cleanup::CleanupCursor::default();
+        while !cleanup::expire_sessions_incrementally(
+            &ctx.settings,
+            &mut state,
+            &mut cursor,
+            chrono::Utc::now(),
+        ) {}
+        assert!(state.sessions.is_empty());
+        assert!(state.sessions_per_ip.is_empty());
+    }
+    let res = new_session("10.0.0.1").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn sessions_per_ip_are_counted_on_eviction() {
+    let settings = Settings::default("".to_string());
+    settings.tunables.write().max_sessions = 1;
+    let mut state = State::default();
+    for (session, ip) in [("a", "10.0.0.1"), ("b", "10.0.0.1"), ("c", "10.0.0.2")] {
+        state
+            .set_page(
+                &settings,
+                &SessionID::from_string(session).unwrap(),
+                AccessToken::from_string("my-test-token").unwrap(),
+                "page".to_string(),
+                polli_live::state::SetPageOptions {
+                    allow_create: true,
+                    notify: false,
+                    creator_ip: Some(ip.parse().unwrap()),
+                    keep_response_schema: false,
+                },
+            )
+            .unwrap();
+    }
+    assert_eq!(state.sessions_per_ip[&"10.0.0.1".parse().unwrap()], 2);
+    let state = Mutex::new(state);
+    cleanup::evict_least_recently_used_sessions(&settings, &state, chrono::Utc::now()).await;
+    let mut state = state.into_inner();
+    assert_eq!(state.sessions.len(), 1);
+    assert_eq!(state.sessions_per_ip.values().sum::<usize>(), 1);
+    let counted = state.sessions_per_ip.clone();
+    state.recount_sessions_per_ip();
+    assert_eq!(counted, state.sessions_per_ip);
+}
+
+#[tokio::test]
+async fn max_users_per_session() {
+    let ctx = setup_with_settings(|s| s.tunables.write().max_users_per_session = 2).await;
+    ctx.set_page_and_check("u", "my-test-token", "page").await;
+    for user in ["a", "b"] {
+        let res = ctx.send_reponse(Some("u"), Some(user), "1").await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=u&user=c", ctx.url))
+                .body("1"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "too_many_users").await;
+
+    // Existing users can still update their response.
+    let res = ctx.send_reponse(Some("u"), Some("a"), "2").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // The limit applies per page.
+    ctx.set_page_and_check("u", "my-test-token", "page 2").await;
+    let res = ctx.send_reponse(Some("u"), Some("c"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn cleanup_does_not_hold_lock_for_long() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.cleanup_batch_size = 100;
+    // Make all steps of the cleanup run.
+    settings.tunables.write().max_sessions = 2000;
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(1);
+    let mut state = make_state_with_many_sessions(5000, now, chrono::Duration::days(2));
+    for session in state.sessions.values_mut() {
+        for i in 0..20 {
+            session.responses.insert(
+                UserID::from_string(&i.to_string()).unwrap(),
+                UserResponse {
+                    data: "some response".to_string(),
+                    content_type: "text/plain".to_string(),
+                    id: i,
+                    was_received: false,
+                    time: now,
+                    arrived: std::time::Instant::now(),
+                    revision: 0,
+                    metadata: None,
+                    coalesced: false,
+                },
+            );
+        }
+    }
+    let state = Arc::new(Mutex::new(state));
+
+    // Measures how long it takes to get the lock while the cleanup is running.
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let probe = {
+        let state = state.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut longest_wait = std::time::Duration::ZERO;
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                let start = std::time::Instant::now();
+                drop(state.lock());
+                longest_wait = longest_wait.max(start.elapsed());
+                std::thread::sleep(std::time::Duration::from_micros(50));
+            }
+            longest_wait
+        })
+    };
+
+    let mut cursor = cleanup::CleanupCursor::default();
+    cleanup::cleanup_tick(&settings, &*state, &mut cursor, now).await;
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    let longest_wait = probe.join().unwrap();
+
+    // Old sessions have been expired and some of the others have been evicted.
+    assert_eq!(state.lock().sessions.len(), 2000);
+    assert!(
+        longest_wait < std::time::Duration::from_millis(50),
+        "{:?}",
+        longest_wait
+    );
+}
+
+#[test]
+fn memory_safety_factor_saturates() {
+    let max = byte_unit::Byte::from_u64(u64::MAX);
+    assert_eq!(cleanup::apply_safety_factor(u64::MAX, 2.0), max);
+    assert_eq!(cleanup::apply_safety_factor(u64::MAX / 2 + 1, 2.0), max);
+    assert_eq!(cleanup::apply_safety_factor(u64::MAX, f64::INFINITY), max);
+    assert_eq!(
+        cleanup::apply_safety_factor(100, 1.5),
+        byte_unit::Byte::from_u64(150)
+    );
+    assert_eq!(
+        cleanup::apply_safety_factor(100, -1.0),
+        byte_unit::Byte::from_u64(0)
+    );
+}
+
+#[tokio::test]
+async fn memory_usage_is_recorded_with_safety_factor() {
+    let mut settings = Settings::default("".to_string());
+    settings.memory_safety_factor = 3.0;
+    let (session_id, session) = make_verify_fixture();
+    let session_usage = cleanup::count_session_memory_usage(&session_id, &session);
+    let mut state = State::default();
+    state.sessions.insert(session_id, session);
+    state.recount_memory_usage();
+    let state = Mutex::new(state);
+
+    let mut cursor = cleanup::CleanupCursor::default();
+    cleanup::cleanup_tick(&settings, &state, &mut cursor, chrono::Utc::now()).await;
+    let metrics = state.lock().cleanup_metrics.clone();
+    assert!(metrics.memory_usage > session_usage);
+    assert_eq!(
+        metrics.memory_usage_with_safety_buffer,
+        metrics.memory_usage * 3
+    );
+}
+
+fn count_memory_usage_fully(state: &State) -> u64 {
+    state
+        .sessions
+        .iter()
+        .map(|(session_id, session)| cleanup::count_session_memory_usage(session_id, session))
+        .sum()
+}
+
+#[tokio::test]
+async fn memory_usage_is_tracked_incrementally() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::from_secs(60);
+    })
+    .await;
+    ctx.set_page_and_check("a", "my-test-token-a", "page a")
+        .await;
+    ctx.set_page_and_check("b", "my-test-token-b", "page b")
+        .await;
+    for i in 0..50 {
+        let user = format!("user{}", i);
+        let res = ctx.send_reponse(Some("a"), Some(&user), "response").await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    // Coalesced with the previous response.
+    let res = ctx
+        .send_reponse(Some("a"), Some("user0"), "a much longer response")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx.send_reponse(Some("b"), Some("user0"), "response").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    ctx.set_page_and_check("b", "my-test-token-b", "a new page for b")
+        .await;
+    let res = ctx
+        .client
+        .post(format!("{}/viewer_token?session=a", ctx.url))
+        .bearer_auth("my-test-token-a")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = ctx
+        .client
+        .post(format!("{}/rotate_token?session=a", ctx.url))
+        .bearer_auth("my-test-token-a")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let mut state = ctx.state.lock();
+    let full_count = count_memory_usage_fully(&state);
+    assert!(full_count > 0);
+    assert!(state.approx_bytes.abs_diff(full_count) <= full_count / 100);
+
+    state.remove_session(&SessionID::from_string("b").unwrap());
+    let full_count = count_memory_usage_fully(&state);
+    assert!(state.approx_bytes.abs_diff(full_count) <= full_count / 100);
+    state.remove_session(&SessionID::from_string("a").unwrap());
+    assert_eq!(state.approx_bytes, 0);
+}
+
+#[tokio::test]
+async fn memory_recount_corrects_drift() {
+    let settings = Settings::default("".to_string());
+    let (session_id, session) = make_verify_fixture();
+    let mut state = State::default();
+    state.sessions.insert(session_id, session);
+    state.approx_bytes = 12345;
+    let state = Mutex::new(state);
+
+    cleanup::recount_memory_usage_in_batches(&settings, &state).await;
+    let state = state.lock();
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn memory_pressure_evicts_least_recently_used_sessions() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.cleanup_batch_size = 10;
+    settings.memory_safety_factor = 1.0;
+    let mut state = State::default();
+    for i in 0..100 {
+        let mut session = SessionState::new(
+            AccessToken::from_string("my-test-token").unwrap(),
+            "x".repeat(10_000),
+            chrono::Utc::now(),
+            std::time::Instant::now(),
+        );
+        // Every fourth session is fresh, the others have been unused for different times.
+        session.last_request = if i % 4 == 0 {
+            now
+        } else {
+            now - chrono::Duration::minutes(i)
+        };
+        session.last_used = clock::instant_at(&SystemClock, session.last_request);
+        state
+            .sessions
+            .insert(SessionID::from_string(&i.to_string()).unwrap(), session);
+    }
+    state.recount_memory_usage();
+    // Only about half of the sessions fit.
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(state.approx_bytes / 2);
+    let state = Mutex::new(state);
+
+    cleanup::evict_sessions_for_memory(&settings, &state, now).await;
+    let state = state.lock();
+    let remaining: Vec<usize> = state
+        .sessions
+        .keys()
+        .map(|session_id| session_id.0.parse().unwrap())
+        .collect();
+    // Fresh sessions survive.
+    for i in (0..100).step_by(4) {
+        assert!(remaining.contains(&i), "{}", i);
+    }
+    // Older sessions are evicted first and no more than necessary.
+    let oldest_remaining = remaining.iter().filter(|i| *i % 4 != 0).max().unwrap();
+    for i in 0..100 {
+        if i % 4 != 0 && i > *oldest_remaining {
+            assert!(!remaining.contains(&i), "{}", i);
+        }
+    }
+    assert!((45..=50).contains(&remaining.len()), "{}", remaining.len());
+    assert_eq!(
+        state.cleanup_metrics.evicted_sessions_for_memory,
+        100 - remaining.len()
+    );
+    assert_eq!(
+        statistics::get(&state.statistics.sessions_evicted_for_memory),
+        (100 - remaining.len()) as u64
+    );
+}
+
+#[tokio::test]
+async fn memory_pressure_drops_page_history_before_sessions() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.memory_safety_factor = 1.0;
+    settings.tunables.write().session_keep_alive_duration = std::time::Duration::from_secs(3600);
+    let mut state = State::default();
+    for i in 0..10 {
+        let mut session = SessionState::new(
+            AccessToken::from_string("my-test-token").unwrap(),
+            "page".to_string(),
+            now,
+            clock::instant_at(&SystemClock, now),
+        );
+        for _ in 0..4 {
+            session.update(&settings, "x".repeat(10_000), now, false);
+        }
+        session.last_request = now - chrono::Duration::minutes(10);
+        session.last_used = clock::instant_at(&SystemClock, session.last_request);
+        state
+            .sessions
+            .insert(SessionID::from_string(&i.to_string()).unwrap(), session);
+    }
+    state.recount_memory_usage();
+    // The sessions fit, but not with their history.
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(state.approx_bytes);
+
+    cleanup::cleanup_once(&settings, &mut state, now);
+    assert_eq!(state.sessions.len(), 10);
+    assert!(state
+        .sessions
+        .values()
+        .all(|session| session.page_history.is_empty()));
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[test]
+fn memory_pressure_drops_assets_before_sessions() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.memory_safety_factor = 1.0;
+    settings.tunables.write().session_keep_alive_duration = std::time::Duration::from_secs(3600);
+    let mut state = State::default();
+    for i in 0..10 {
+        let mut session = SessionState::new(
+            AccessToken::from_string("my-test-token").unwrap(),
+            "page".to_string(),
+            now,
+            clock::instant_at(&SystemClock, now),
+        );
+        session.assets.insert(
+            "image.png".to_string(),
+            SessionAsset::new("image.png", vec![0; 10_000]),
+        );
+        session.last_request = now - chrono::Duration::minutes(10);
+        session.last_used = clock::instant_at(&SystemClock, session.last_request);
+        state
+            .sessions
+            .insert(SessionID::from_string(&i.to_string()).unwrap(), session);
+    }
+    state.recount_memory_usage();
+    assert!(state.approx_bytes > 10 * 10_000);
+    // The sessions fit, but not with their assets.
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(state.approx_bytes);
+
+    cleanup::cleanup_once(&settings, &mut state, now);
+    assert_eq!(state.sessions.len(), 10);
+    assert!(state
+        .sessions
+        .values()
+        .all(|session| session.assets.is_empty()));
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn memory_pressure_keeps_sessions_within_grace_period() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(1);
+    let mut state = make_state_with_many_sessions(100, now, chrono::Duration::seconds(10));
+    state.recount_memory_usage();
+    let state = Mutex::new(state);
+
+    cleanup::evict_sessions_for_memory(&settings, &state, now).await;
+    assert_eq!(state.lock().sessions.len(), 100);
+
+    settings.emergency_retention = std::time::Duration::from_secs(5);
+    cleanup::evict_sessions_for_memory(&settings, &state, now).await;
+    assert_eq!(state.lock().sessions.len(), 50);
+}
+
+fn make_retention_test_session(now: chrono::DateTime<chrono::Utc>) -> SessionState {
+    SessionState::new(
+        AccessToken::from_string("my-test-token").unwrap(),
+        "page".to_string(),
+        now,
+        clock::instant_at(&SystemClock, now),
+    )
+}
+
+#[test]
+fn received_responses_are_kept_for_retention_time() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(1);
+    settings.received_response_retention = std::time::Duration::from_secs(60);
+    settings.emergency_retention = std::time::Duration::from_secs(24 * 60 * 60);
+
+    let mut session = make_retention_test_session(now);
+    for (i, age) in [59, 60, 61].into_iter().enumerate() {
+        session.responses.insert(
+            UserID::from_string(&age.to_string()).unwrap(),
+            UserResponse {
+                data: "response".to_string(),
+                content_type: "text/plain".to_string(),
+                id: i,
+                was_received: true,
+                time: now - chrono::Duration::seconds(age),
+                arrived: clock::instant_at(&SystemClock, now - chrono::Duration::seconds(age)),
+                revision: 0,
+                metadata: None,
+                coalesced: false,
+            },
+        );
+    }
+    let mut state = State::default();
+    state
+        .sessions
+        .insert(SessionID::from_string("s").unwrap(), session);
+    state.recount_memory_usage();
+
+    cleanup::cleanup_once(&settings, &mut state, now);
+    let responses = &state.sessions[&SessionID::from_string("s").unwrap()].responses;
+    assert_eq!(responses.len(), 1);
+    assert!(responses.contains_key(&UserID::from_string("59").unwrap()));
+    assert_eq!(statistics::get(&state.statistics.responses_dropped), 2);
+}
+
+#[test]
+fn emergency_retention_keeps_recently_used_sessions() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(1);
+    settings.emergency_retention = std::time::Duration::from_secs(10);
+
+    let mut state = State::default();
+    for age in [9, 10, 11] {
+        state.sessions.insert(
+            SessionID::from_string(&age.to_string()).unwrap(),
+            make_retention_test_session(now - chrono::Duration::seconds(age)),
+        );
+    }
+    state.recount_memory_usage();
+
+    cleanup::cleanup_once(&settings, &mut state, now);
+    assert_eq!(state.sessions.len(), 1);
+    assert!(state
+        .sessions
+        .contains_key(&SessionID::from_string("9").unwrap()));
+}
+
+#[test]
+fn cleanup_once_expires_sessions_after_keep_alive() {
+    let now = chrono::Utc::now();
+    let settings = Settings::default("".to_string());
+    let keep_alive =
+        chrono::Duration::from_std(settings.tunables().session_keep_alive_duration).unwrap();
+
+    let mut state = State::default();
+    for (name, age) in [
+        ("fresh", keep_alive - chrono::Duration::seconds(1)),
+        ("expired", keep_alive),
+    ] {
+        state.sessions.insert(
+            SessionID::from_string(name).unwrap(),
+            make_retention_test_session(now - age),
+        );
+    }
+    state.recount_memory_usage();
+
+    cleanup::cleanup_once(&settings, &mut state, now);
+    assert_eq!(state.sessions.len(), 1);
+    assert!(state
+        .sessions
+        .contains_key(&SessionID::from_string("fresh").unwrap()));
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn pings_keep_sessions_alive() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.tunables.write().session_keep_alive_duration = std::time::Duration::from_secs(60);
+    })
+    .await;
+    let ctx = &ctx;
+    let token = "my-test-token";
+    ctx.set_page_and_check("pinged", token, "page").await;
+    ctx.set_page_and_check("idle", token, "page").await;
+    let ping = |session: &str| {
+        ctx.client
+            .post(format!("{}/ping", ctx.url))
+            .query(&[("session", session)])
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+    };
+    let cleanup = || cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+
+    for _ in 0..3 {
+        clock.advance(std::time::Duration::from_secs(40));
+        let res = ping("pinged").await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        cleanup();
+    }
+    {
+        let state = ctx.state.lock();
+        assert!(state
+            .sessions
+            .contains_key(&SessionID::from_string("pinged").unwrap()));
+        assert!(!state
+            .sessions
+            .contains_key(&SessionID::from_string("idle").unwrap()));
+    }
+    assert_error_code(
+        ping("idle").await.unwrap(),
+        reqwest::StatusCode::GONE,
+        "session_expired",
+    )
+    .await;
+    assert_error_code(
+        ping("unknown").await.unwrap(),
+        reqwest::StatusCode::NOT_FOUND,
+        "session_not_found",
+    )
+    .await;
+
+    // Without pings, the session expires as well.
+    clock.advance(std::time::Duration::from_secs(60));
+    cleanup();
+    assert!(ctx.state.lock().sessions.is_empty());
+}
+
+#[tokio::test]
+async fn audience_is_counted() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.tunables.write().page_update_long_poll_duration =
+            std::time::Duration::from_secs(30);
+    })
+    .await;
+    let ctx = &ctx;
+    ctx.set_page_and_check("a", "my-test-token", "page").await;
+    let wait = |client: &str| {
+        let builder = ctx
+            .client
+            .get(format!("{}/wait_for_new_page", ctx.url))
+            .query(&[("session", "a"), ("client", client)]);
+        tokio::spawn(async move { builder.send().await.unwrap() })
+    };
+    let count = || {
+        ctx.state.lock().sessions[&SessionID::from_string("a").unwrap()]
+            .audience_count(&ctx.settings)
+    };
+    let request_count = || async {
+        let res = ctx
+            .client
+            .get(format!("{}/audience_count?session=a", ctx.url))
+            .send()
+            .await
+            .unwrap();
+        res.json::<routes::AudienceCount>().await.unwrap().audience
+    };
+
+    // The same client is only counted once.
+    let waits = ["x", "y", "z", "z"].map(wait);
+    wait_until(|| {
+        ctx.state.lock().sessions[&SessionID::from_string("a").unwrap()]
+            .long_polls
+            .load(std::sync::atomic::Ordering::Relaxed)
+            == 4
+    })
+    .await;
+    assert_eq!(request_count().await, 3);
+    let res = ctx
+        .client
+        .post(format!("{}/ping?session=a&client=w", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(request_count().await, 4);
+
+    // Waiting clients stay counted, however long they wait.
+    clock.advance(ctx.settings.audience_timeout);
+    assert_eq!(count(), 3);
+
+    ctx.set_page_and_check("a", "my-test-token", "new page")
+        .await;
+    for wait in waits {
+        assert_eq!(wait.await.unwrap().text().await.unwrap(), "reload");
+    }
+    let res = ctx
+        .client
+        .get(format!("{}/session_info?session=a", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    let info: routes::SessionInfo = res.json().await.unwrap();
+    assert_eq!(info.audience, 3);
+
+    // Clients that don't come back drop out.
+    clock.advance(ctx.settings.audience_timeout - std::time::Duration::from_secs(1));
+    assert_eq!(count(), 3);
+    clock.advance(std::time::Duration::from_secs(1));
+    assert_eq!(request_count().await, 0);
+
+    let res = ctx
+        .request_json(ctx.client.get(format!(
+            "{}/wait_for_new_page?session=a&timeout_ms=0&client=",
+            ctx.url
+        )))
+        .await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn wall_clock_jumps_dont_affect_session_liveness() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.token_timeout = std::time::Duration::from_secs(10 * 60);
+        settings.tunables.write().session_keep_alive_duration =
+            std::time::Duration::from_secs(60 * 60);
+    })
+    .await;
+    let ctx = &ctx;
+    ctx.set_page_and_check("a", "my-test-token", "page").await;
+    ctx.set_page_and_check("b", "my-test-token", "page").await;
+    let cleanup = || cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    let session_count = || ctx.state.lock().sessions.len();
+
+    // The clock of the host was a day behind and is corrected. The sessions have just been
+    // used, so they are kept.
+    clock.jump(chrono::Duration::days(1));
+    cleanup();
+    assert_eq!(session_count(), 2);
+    let res = ctx
+        .client
+        .get(format!("{}/session_info?session=a", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    let info: routes::SessionInfo = res.json().await.unwrap();
+    assert_eq!(info.expires_in_seconds, 60 * 60);
+
+    // Now the clock jumps back by two days, so that the last requests are in the future.
+    // Unused sessions can still be taken over and expire in time.
+    clock.jump(chrono::Duration::days(-2));
+    clock.advance(std::time::Duration::from_secs(10 * 60));
+    let res = ctx
+        .request_page_update(Some("b"), Some("other-token"), "taken over")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    clock.advance(std::time::Duration::from_secs(50 * 60));
+    cleanup();
+    assert_eq!(session_count(), 1);
+    clock.advance(std::time::Duration::from_secs(10 * 60));
+    cleanup();
+    assert_eq!(session_count(), 0);
+}
+
+#[tokio::test]
+async fn wall_clock_jumps_dont_affect_response_throttle() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.response_throttle = ResponseThrottle::Reject;
+        settings.tunables.write().min_response_interval = std::time::Duration::from_secs(1);
+    })
+    .await;
+    ctx.set_page_and_check("t", "my-test-token", "page").await;
+    let res = ctx.send_reponse(Some("t"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // Jumping forward does not end the interval early.
+    clock.jump(chrono::Duration::hours(1));
+    let res = ctx.send_reponse(Some("t"), Some("me"), "2").await;
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    // Jumping back does not make the user wait for an hour.
+    clock.jump(chrono::Duration::hours(-2));
+    clock.advance(std::time::Duration::from_secs(1));
+    let res = ctx.send_reponse(Some("t"), Some("me"), "3").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+/// Clock that only moves forward when told to. The wall clock can also jump on its own.
+struct MockClock(Mutex<(chrono::DateTime<chrono::Utc>, std::time::Instant)>);
+
+impl MockClock {
+    fn new() -> Arc<Self> {
+        Arc::new(MockClock(Mutex::new((
+            chrono::Utc::now(),
+            std::time::Instant::now(),
+        ))))
+    }
+
+    fn advance(&self, duration: std::time::Duration) {
+        let mut times = self.0.lock();
+        times.0 += chrono::Duration::from_std(duration).unwrap();
+        times.1 += duration;
+    }
+
+    /// Only changes the wall clock, e.g. like a time synchronization does.
+    fn jump(&self, offset: chrono::Duration) {
+        self.0.lock().0 += offset;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.0.lock().0
+    }
+
+    fn instant(&self) -> std::time::Instant {
+        self.0.lock().1
+    }
+}
+
+async fn request_client_config(ctx: &TestContext, session: &str) -> ClientConfig {
+    ctx.client
+        .get(format!("{}/client_config?session={}", ctx.url, session))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn close_and_reopen_voting() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("v", token, "page").await;
+    let voting = |action: &str, token: &str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/voting/{}?session=v", ctx.url, action))
+                .bearer_auth(token),
+        )
+    };
+
+    let res = voting("close", "other-token").await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    assert_eq!(
+        voting("close", token).await.status(),
+        reqwest::StatusCode::OK
+    );
+    let config = request_client_config(&ctx, "v").await;
+    assert!(!config.accepting_responses);
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=v&user=me", ctx.url))
+                .body("1"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "voting_closed").await;
+
+    assert_eq!(
+        voting("open", token).await.status(),
+        reqwest::StatusCode::OK
+    );
+    assert!(request_client_config(&ctx, "v").await.accepting_responses);
+    let res = ctx.send_reponse(Some("v"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // A new page opens the voting again.
+    voting("close", token).await;
+    ctx.set_page_and_check("v", token, "next page").await;
+    let res = ctx.send_reponse(Some("v"), Some("me"), "2").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn voting_deadline_expires() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("v", token, "page").await;
+    let res = ctx
+        .client
+        .post(format!(
+            "{}/voting/close?session=v&after_seconds=30",
+            ctx.url
+        ))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let config = request_client_config(&ctx, "v").await;
+    assert!(config.accepting_responses);
+    assert_eq!(
+        config.voting_deadline,
+        Some(clock.now() + chrono::Duration::seconds(30))
+    );
+    clock.advance(std::time::Duration::from_secs(29));
+    let res = ctx.send_reponse(Some("v"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    clock.advance(std::time::Duration::from_secs(1));
+    let res = ctx.send_reponse(Some("v"), Some("me"), "2").await;
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    assert!(!request_client_config(&ctx, "v").await.accepting_responses);
+
+    // The deadline can also be given with the page.
+    let deadline = clock.now() + chrono::Duration::seconds(10);
+    let res = ctx
+        .client
+        .post(format!("{}/page?session=v", ctx.url))
+        .query(&[("deadline", deadline.to_rfc3339())])
+        .bearer_auth(token)
+        .body("next page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        request_client_config(&ctx, "v").await.voting_deadline,
+        Some(deadline)
+    );
+    let res = ctx.send_reponse(Some("v"), Some("me"), "3").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    clock.advance(std::time::Duration::from_secs(10));
+    let res = ctx.send_reponse(Some("v"), Some("me"), "4").await;
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn countdown_with_page_duration() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    let token = "my-test-token";
+    let post_page = |query: &[(&str, &str)], page: &str| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/page?session=c", ctx.url))
+                .query(query)
+                .bearer_auth(token)
+                .body(page.to_string()),
+        )
+    };
+    let request_time = || async {
+        let res = ctx
+            .client
+            .get(format!("{}/session_time?session=c", ctx.url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(header_value(&res, "cache-control"), "no-store");
+        res.json::<routes::SessionTime>().await.unwrap()
+    };
+
+    let res = post_page(&[("duration_ms", "20000")], "question").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    clock.advance(std::time::Duration::from_millis(19_500));
+    let time = request_time().await;
+    assert_eq!(time.server_time, clock.now());
+    assert_eq!(time.remaining_ms, Some(500));
+    assert!(time.accepting_responses);
+    let res = ctx.send_reponse(Some("c"), Some("me"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    clock.advance(std::time::Duration::from_millis(500));
+    let time = request_time().await;
+    assert_eq!(time.remaining_ms, Some(0));
+    assert!(!time.accepting_responses);
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/respond?session=c&user=me", ctx.url))
+                .body("2"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "voting_closed").await;
+
+    // The deadline is removed with the page.
+    ctx.set_page_and_check("c", token, "break").await;
+    let time = request_time().await;
+    assert_eq!(time.deadline, None);
+    assert_eq!(time.remaining_ms, None);
+
+    let deadline = clock.now().to_rfc3339();
+    let res = post_page(&[("duration_ms", "1000"), ("deadline", &deadline)], "page").await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+
+    // The duration of a scheduled page starts when it is switched to.
+    let at = clock.now() + chrono::Duration::seconds(60);
+    let res = ctx
+        .client
+        .post(format!("{}/page/schedule", ctx.url))
+        .query(&[
+            ("session", "c"),
+            ("at", &at.to_rfc3339()),
+            ("duration_ms", "10000"),
+        ])
+        .bearer_auth(token)
+        .body("next question")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(request_time().await.deadline, None);
+    clock.advance(std::time::Duration::from_secs(60));
+    scheduled_page::switch_due_pages(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    let time = request_time().await;
+    assert_eq!(time.deadline, Some(at + chrono::Duration::seconds(10)));
+    assert_eq!(time.remaining_ms, Some(10_000));
+}
+
+#[tokio::test]
+async fn stale_session_can_be_taken_over_after_token_timeout() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| settings.clock = clock.clone()).await;
+    let second = std::time::Duration::from_secs(1);
+    ctx.set_page_and_check("s", "my-test-token", "first page")
+        .await;
+
+    clock.advance(ctx.settings.token_timeout - second);
+    let res = ctx
+        .request_page_update(Some("s"), Some("other-test-token"), "second page")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    clock.advance(second);
+    ctx.set_page_and_check("s", "other-test-token", "second page")
+        .await;
+    let res = ctx
+        .request_page_update(Some("s"), Some("my-test-token"), "third page")
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn session_expires_after_keep_alive_duration() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| settings.clock = clock.clone()).await;
+    let second = std::time::Duration::from_secs(1);
+    ctx.set_page_and_check("s", "my-test-token", "page").await;
+
+    clock.advance(ctx.settings.tunables().session_keep_alive_duration - second);
+    cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    assert_eq!(ctx.request_session_page_text("s").await, "page");
+
+    // Responses count as usage of the session.
+    let res = ctx.send_reponse(Some("s"), Some("u"), "1").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    clock.advance(second);
+    cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    assert_eq!(ctx.request_session_page_text("s").await, "page");
+
+    clock.advance(ctx.settings.tunables().session_keep_alive_duration);
+    cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    let res = ctx.request_session_page("s").await;
+    assert_eq!(res.status(), reqwest::StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn stats_count_expired_sessions() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.tunables.write().session_keep_alive_duration = std::time::Duration::from_secs(10);
+    })
+    .await;
+    let request_stats = || async {
+        let res = ctx
+            .client
+            .get(format!("{}/stats", ctx.url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        res.json::<routes::Stats>().await.unwrap()
+    };
+    ctx.set_page_and_check("s1", "my-test-token", "page").await;
+    ctx.set_page_and_check("s2", "my-test-token", "page").await;
+    let res = ctx.send_reponse(Some("s1"), Some("u"), "yes").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let stats = request_stats().await;
+    assert_eq!(stats.sessions_created, 2);
+    assert_eq!(stats.responses_received, 1);
+    assert_eq!(stats.sessions_expired, 0);
+    assert_eq!(stats.memory_usage, ctx.state.lock().approx_bytes);
+    assert!(stats.memory_usage > 0);
+
+    clock.advance(std::time::Duration::from_secs(5));
+    ctx.set_page_and_check("s2", "my-test-token", "page").await;
+    clock.advance(std::time::Duration::from_secs(5));
+    cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    let stats = request_stats().await;
+    assert_eq!(stats.sessions_expired, 1);
+    assert_eq!(stats.sessions_evicted, 0);
+
+    clock.advance(std::time::Duration::from_secs(5));
+    cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    let stats = request_stats().await;
+    assert_eq!(stats.sessions_created, 2);
+    assert_eq!(stats.sessions_expired, 2);
+    assert_eq!(stats.memory_usage, 0);
+}
+
+async fn fetch_page_while_time_passes(touch_on_read: bool) -> reqwest::StatusCode {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.tunables.write().session_keep_alive_duration = std::time::Duration::from_secs(10);
+        settings.tunables.write().page_update_long_poll_duration = std::time::Duration::ZERO;
+        settings.touch_on_read = touch_on_read;
+    })
+    .await;
+    ctx.set_page_and_check("s", "my-test-token", "page").await;
+    for i in 0..4 {
+        clock.advance(std::time::Duration::from_secs(6));
+        if i % 2 == 0 {
+            ctx.request_session_page("s").await;
+        } else {
+            ctx.client
+                .get(format!("{}/wait_for_new_page?session=s", ctx.url))
+                .send()
+                .await
+                .unwrap();
+        }
+        cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+    }
+    ctx.request_session_page("s").await.status()
+}
+
+#[tokio::test]
+async fn fetching_page_keeps_session_alive() {
+    assert_eq!(
+        fetch_page_while_time_passes(true).await,
+        reqwest::StatusCode::OK
+    );
+    assert_eq!(
+        fetch_page_while_time_passes(false).await,
+        reqwest::StatusCode::GONE
+    );
+}
+
+#[tokio::test]
+async fn expired_sessions_are_gone() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| settings.clock = clock.clone()).await;
+    ctx.set_page_and_check("s", "my-test-token", "page").await;
+    clock.advance(ctx.settings.tunables().session_keep_alive_duration);
+    cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), clock.now());
+
+    let res = ctx.request_session_page("s").await;
+    assert_eq!(res.status(), reqwest::StatusCode::GONE);
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::get(&ctx.settings, "expired_session_page.html")
+    );
+    let res = ctx
+        .client
+        .get(format!("{}/responses?session=s&start=0", ctx.url))
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(res, reqwest::StatusCode::GONE, "session_expired").await;
+    let res = ctx
+        .client
+        .post(format!("{}/respond?session=s&user=u", ctx.url))
+        .header(reqwest::header::ACCEPT, "application/json")
+        .body("1")
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(res, reqwest::StatusCode::GONE, "session_expired").await;
+
+    // Sessions that never existed are still not found.
+    let res = ctx.request_session_page("never").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::render(
+            &ctx.settings,
+            "empty_session_page.html",
+            &[("session", "never")]
+        )
+        .unwrap()
+    );
+    let res = ctx
+        .client
+        .get(format!("{}/responses?session=never&start=0", ctx.url))
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+
+    // Expired sessions are only remembered for some time.
+    clock.advance(ctx.settings.expired_session_retention);
+    let res = ctx.request_session_page("s").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn expired_sessions_are_bounded() {
+    let now = chrono::Utc::now();
+    let mut settings = Settings::default("".to_string());
+    settings.max_expired_sessions = 3;
+    let mut expired = ExpiredSessions::default();
+    for i in 0..5 {
+        expired.insert(
+            &settings,
+            SessionID::from_string(&i.to_string()).unwrap(),
+            now,
+        );
+    }
+    assert_eq!(expired.count(), 3);
+    for i in 0..5 {
+        let session_id = SessionID::from_string(&i.to_string()).unwrap();
+        assert_eq!(expired.contains(&settings, &session_id, now), i >= 2);
+    }
+
+    // Expiring the same session again does not drop it early.
+    expired.insert(&settings, SessionID::from_string("2").unwrap(), now);
+    expired.insert(&settings, SessionID::from_string("5").unwrap(), now);
+    assert_eq!(expired.count(), 3);
+    assert!(expired.contains(&settings, &SessionID::from_string("2").unwrap(), now));
+
+    let later = now + chrono::Duration::from_std(settings.expired_session_retention).unwrap();
+    expired.insert(&settings, SessionID::from_string("6").unwrap(), later);
+    assert_eq!(expired.count(), 1);
+}
+
+#[test]
+fn script_injection_positions() {
+    let inject = |page: &str| page::inject_script(page.to_string(), "<script/>");
+    assert_eq!(
+        inject("<html><head><title>t</title></head><body>b</body></html>"),
+        "<html><head><title>t</title><script/></head><body>b</body></html>"
+    );
+    assert_eq!(
+        inject("<HTML><HEAD></HEAD><BODY>b</BODY></HTML>"),
+        "<HTML><HEAD><script/></HEAD><BODY>b</BODY></HTML>"
+    );
+    assert_eq!(
+        inject("<html><Body class=\"dark\">b</Body></html>"),
+        "<html><Body class=\"dark\"><script/>b</Body></html>"
+    );
+    assert_eq!(inject("<body>b</body>"), "<body><script/>b</body>");
+    assert_eq!(
+        inject("<!DOCTYPE html><p>b</p>"),
+        "<!DOCTYPE html><script/><p>b</p>"
+    );
+    assert_eq!(inject("<p>b</p>"), "<script/><p>b</p>");
+    assert_eq!(inject(""), "<script/>");
+    assert_eq!(
+        inject("<bodyguard>b</bodyguard>"),
+        "<script/><bodyguard>b</bodyguard>"
+    );
+    // The placeholder has priority.
+    assert_eq!(
+        inject("<head></head><body><!-- polli-live --><p>b</p></body>"),
+        "<head></head><body><script/><p>b</p></body>"
+    );
+    // Non-ascii characters before the tag don't shift the position.
+    assert_eq!(
+        inject("<title>Grüße</title></head>"),
+        "<title>Grüße</title><script/></head>"
+    );
+}
+
+#[test]
+fn page_size_limit_includes_injected_script() {
+    let mut settings = Settings::default("".to_string());
+    let script_len = page::injection_snippet(&settings).len();
+    settings.max_page_size = byte_unit::Byte::from_u64(script_len as u64 + 10);
+    let page = page::prepare_page(&settings, "x".repeat(10)).unwrap();
+    assert_eq!(page.len(), script_len + 10);
+    assert!(matches!(
+        page::prepare_page(&settings, "x".repeat(11)),
+        Err(polli_live::AppError::PageTooLarge { .. })
+    ));
+}
+
+#[test]
+fn check_page_reports_injection_point_and_size() {
+    let mut settings = Settings::default("".to_string());
+    let check = page::check_page(&settings, "<html>\n<head>\n  <title>t</title>\n  </head>");
+    assert_eq!(check.injection_point, page::InjectionPoint::EndOfHead);
+    assert_eq!((check.line, check.column), (4, 3));
+    assert!(check.result.is_ok());
+
+    let check = page::check_page(&settings, "<p>Grüße</p><!-- polli-live -->");
+    assert_eq!(check.injection_point, page::InjectionPoint::Placeholder);
+    assert_eq!((check.line, check.column), (1, 13));
+
+    let page = "<body>b</body>";
+    let check = page::check_page(&settings, page);
+    assert_eq!(check.injection_point, page::InjectionPoint::StartOfBody);
+    assert_eq!(
+        check.size as usize,
+        page.len() + page::injection_snippet(&settings).len()
+    );
+    settings.max_page_size = byte_unit::Byte::from_u64(check.size - 1);
+    let check = page::check_page(&settings, page);
+    assert!(matches!(
+        check.result,
+        Err(polli_live::AppError::PageTooLarge { .. })
+    ));
+    let description = commands::describe_page_check("talk.html", &check);
+    assert!(description.starts_with("talk.html would be rejected"));
+    assert!(description.contains("at the start of the body (line 1, column 7)"));
+}
+
+#[test]
+fn generate_token_is_accepted_by_server_with_same_secret() {
+    let mut settings = Settings::default("".to_string());
+    settings.token_secret = b"shared secret".to_vec();
+    let now = settings.now();
+    let token = commands::generate_token("shared secret", "talk-1", now).unwrap();
+    let session_id = SessionID::from_string("talk-1").unwrap();
+    assert!(token
+        .signed_issue_time_for(&settings, &session_id, now)
+        .is_some());
+    let other_session = SessionID::from_string("talk-2").unwrap();
+    assert!(token
+        .signed_issue_time_for(&settings, &other_session, now)
+        .is_none());
+    assert!(commands::generate_token("shared secret", "not valid", now).is_err());
+}
+
+#[test]
+fn cli_serves_without_subcommand() {
+    use clap::Parser;
+    let cli = cli::Cli::try_parse_from(["polli-live", "--port", "1234"]).unwrap();
+    assert!(cli.command.is_none());
+    assert_eq!(cli.serve.port, 1234);
+    let cli = cli::Cli::try_parse_from(["polli-live", "serve", "--port", "1234"]).unwrap();
+    assert!(matches!(cli.command, Some(cli::Command::Serve(args)) if args.port == 1234));
+    let cli = cli::Cli::try_parse_from(["polli-live", "check-page", "talk.html"]).unwrap();
+    assert!(matches!(cli.command, Some(cli::Command::CheckPage { .. })));
+    assert!(cli::Cli::try_parse_from(["polli-live", "--port", "1", "generate-token"]).is_err());
+}
+
+#[tokio::test]
+async fn page_size_limit_boundary() {
+    let ctx = setup_with_settings(|settings| {
+        settings.max_page_size = byte_unit::Byte::from_u64(5000);
+    })
+    .await;
+    let max_size = ctx.settings.max_page_size.as_u64() as usize;
+    let script_len = page::injection_snippet(&ctx.settings).len();
+    for (prefix, suffix) in [("<html><head>", "</head></html>"), ("<p>", "</p>")] {
+        let fill = max_size - script_len - prefix.len() - suffix.len();
+        let page = format!("{}{}{}", prefix, "x".repeat(fill), suffix);
+        ctx.set_page_and_check("s", "my-test-token", &page).await;
+
+        let page = format!("{}{}{}", prefix, "x".repeat(fill + 1), suffix);
+        let res = ctx
+            .client
+            .post(format!("{}/page?session=s", ctx.url))
+            .bearer_auth("my-test-token")
+            .header(reqwest::header::ACCEPT, "application/json")
+            .body(page)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        let body: ErrorBody = res.json().await.unwrap();
+        assert_eq!(body.error, "page_too_large");
+        assert_eq!(
+            body.details,
+            Some(serde_json::json!({ "size": max_size + 1, "max_size": max_size }))
+        );
+    }
+}
+
+#[tokio::test]
+async fn injected_script_is_cacheable() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("s", "my-test-token", "<head></head>")
+        .await;
+    let page = ctx.request_session_page("s").await.text().await.unwrap();
+    let script_url = format!("{}/polli_live.js?v={}", ctx.url, env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        page,
+        format!("<head><script src=\"{}\"></script>\n</head>", script_url)
+    );
+
+    let res = ctx.client.get(&script_url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        header_value(&res, "Cache-Control"),
+        "public, max-age=604800"
+    );
+    assert_eq!(header_value(&res, "Content-Type"), "text/javascript");
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::embedded("polli_live.js")
+    );
+
+    // Other routes are still not cached.
+    let res = ctx.request_session_page("s").await;
+    assert_eq!(header_value(&res, "Cache-Control"), "no-cache");
+}
+
+#[tokio::test]
+async fn injected_script_can_be_inlined() {
+    let ctx = setup_with_settings(|settings| settings.inline_injection = true).await;
+    ctx.set_page_and_check("s", "my-test-token", "<head></head>")
+        .await;
+    let page = ctx.request_session_page("s").await.text().await.unwrap();
+    assert_eq!(
+        page,
+        format!(
+            "<head><script>\n{}</script>\n</head>",
+            static_files::embedded("polli_live.js")
+        )
+    );
+}
+
+#[test]
+fn static_files_only_use_known_placeholders() {
+    let filenames = static_files::filenames();
+    assert!(filenames.contains(&"index.html"));
+    for filename in filenames {
+        let result = static_files::substitute(
+            static_files::embedded(filename),
+            &[
+                ("root_url", "http://example.com"),
+                ("session", "123"),
+                ("response_count", "0"),
+            ],
+        );
+        assert!(result.is_ok(), "{}: {:?}", filename, result);
+    }
+}
+
+#[test]
+fn static_file_substitution() {
+    let variables = [("session", "123"), ("root_url", "http://example.com")];
+    assert_eq!(
+        static_files::substitute(
+            "<a href=\"{{root_url}}/page?session={{ session }}\">",
+            &variables
+        ),
+        Ok("<a href=\"http://example.com/page?session=123\">".to_string())
+    );
+    assert_eq!(
+        static_files::substitute("no placeholders", &variables),
+        Ok("no placeholders".to_string())
+    );
+    assert!(static_files::substitute("{{sesion}}", &variables).is_err());
+    assert!(static_files::substitute("{{session", &variables).is_err());
+}
+
+#[tokio::test]
+async fn static_pages_contain_runtime_values() {
+    let ctx = setup().await;
+    let index = ctx
+        .client
+        .get(&ctx.url)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(index.contains(&format!("`{}/page?session=", ctx.url)));
+
+    let res = ctx.request_session_page("unknown").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    assert!(res
+        .text()
+        .await
+        .unwrap()
+        .starts_with("Session unknown does not exist (yet)."));
+
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    let session = body["session"].as_str().unwrap();
+    assert!(ctx
+        .request_session_page_text(session)
+        .await
+        .contains(&format!("Session {} has just been created.", session)));
+}
+
+fn make_static_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(dir.join("static")).unwrap();
+    std::fs::write(dir.join("static/index.html"), "custom index {{root_url}}").unwrap();
+    std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn static_dir_overrides_embedded_files() {
+    let dir = make_static_dir();
+    let static_dir = dir.join("static");
+    let ctx = setup_with_settings(|settings| settings.static_dir = Some(static_dir)).await;
+
+    // Present in the override directory.
+    let res = ctx.request_static_page("/").await;
+    assert_eq!(
+        res.text().await.unwrap(),
+        format!("custom index {}", ctx.url)
+    );
+    // Missing in the override directory.
+    let res = ctx.request_static_page("/polli_live.js").await;
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::embedded("polli_live.js")
+    );
+    // Changes are picked up without restarting.
+    std::fs::write(dir.join("static/index.html"), "changed").unwrap();
+    let res = ctx.request_static_page("/").await;
+    assert_eq!(res.text().await.unwrap(), "changed");
+}
+
+#[test]
+fn static_dir_rejects_path_traversal() {
+    let dir = make_static_dir();
+    let static_dir = dir.join("static");
+    assert_eq!(
+        static_files::read_override(&static_dir, "index.html"),
+        Some("custom index {{root_url}}".to_string())
+    );
+    assert_eq!(
+        static_files::read_override(&static_dir, "missing.html"),
+        None
+    );
+    assert_eq!(
+        static_files::read_override(&static_dir, "../secret.txt"),
+        None
+    );
+    assert_eq!(
+        static_files::read_override(&static_dir, "sub/../../secret.txt"),
+        None
+    );
+    let absolute = dir.join("secret.txt");
+    assert_eq!(
+        static_files::read_override(&static_dir, absolute.to_str().unwrap()),
+        None
+    );
+}
+
+#[tokio::test]
+async fn static_route_sets_content_type_and_allows_caching() {
+    let dir = make_static_dir();
+    std::fs::write(dir.join("static/style.css"), "body {}").unwrap();
+    std::fs::write(dir.join("static/logo.png"), [0x89, b'P', b'N', b'G', 0xff]).unwrap();
+    let static_dir = dir.join("static");
+    let ctx = setup_with_settings(|settings| settings.static_dir = Some(static_dir)).await;
+
+    let res = ctx.request_static_page("/static/style.css").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        header_value(&res, "Content-Type"),
+        "text/css; charset=utf-8"
+    );
+    assert!(header_value(&res, "Cache-Control").contains("max-age"));
+    assert_eq!(res.text().await.unwrap(), "body {}");
+
+    let res = ctx.request_static_page("/static/logo.png").await;
+    assert_eq!(header_value(&res, "Content-Type"), "image/png");
+    assert_eq!(
+        res.bytes().await.unwrap().as_ref(),
+        &[0x89, b'P', b'N', b'G', 0xff]
+    );
+
+    // Embedded files are served as well.
+    let res = ctx.request_static_page("/static/polli_live.js").await;
+    assert_eq!(
+        header_value(&res, "Content-Type"),
+        "text/javascript; charset=utf-8"
+    );
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::embedded("polli_live.js")
+    );
+}
+
+#[test]
+fn static_content_types_depend_on_extension() {
+    assert_eq!(
+        static_files::content_type("a.html"),
+        "text/html; charset=utf-8"
+    );
+    assert_eq!(static_files::content_type("fonts/a.WOFF2"), "font/woff2");
+    assert_eq!(static_files::content_type("a.svg"), "image/svg+xml");
+    assert_eq!(static_files::content_type("a"), "application/octet-stream");
+}
+
+#[tokio::test]
+async fn static_route_rejects_unknown_files() {
+    let dir = make_static_dir();
+    let static_dir = dir.join("static");
+    let ctx = setup_with_settings(|settings| settings.static_dir = Some(static_dir)).await;
+
+    let res = ctx.request_static_page("/static/missing.css").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    // The separator is encoded, so that the client does not normalize the path.
+    let res = ctx.request_static_page("/static/..%2Fsecret.txt").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unknown_routes_return_not_found_page() {
+    let ctx = setup().await;
+
+    let res = ctx.request_static_page("/respnd").await;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    assert!(header_value(&res, "Content-Type").starts_with("text/html"));
+    assert_eq!(
+        res.text().await.unwrap(),
+        static_files::render(
+            &ctx.settings,
+            "not_found_page.html",
+            &[("root_url", &ctx.url)]
+        )
+        .unwrap()
+    );
+
+    let res = ctx
+        .client
+        .post(format!("{}/pages", ctx.url))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "not_found").await;
+}
+
+#[tokio::test]
+async fn responses_have_request_id() {
+    let ctx = setup().await;
+
+    let res = ctx.request_static_page("/").await;
+    let generated = header_value(&res, "X-Request-Id").to_string();
+    assert!(!generated.is_empty());
+    let res = ctx.request_static_page("/").await;
+    assert_ne!(header_value(&res, "X-Request-Id"), generated);
+
+    let res = ctx
+        .client
+        .get(format!("{}/page?session=missing", ctx.url))
+        .header("Accept", "application/json")
+        .header("X-Request-Id", "proxy-id-123")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(header_value(&res, "X-Request-Id"), "proxy-id-123");
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.request_id.as_deref(), Some("proxy-id-123"));
+
+    // Ids that could mess up the logs are replaced.
+    let res = ctx
+        .client
+        .get(format!("{}/", ctx.url))
+        .header("X-Request-Id", "a b")
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(header_value(&res, "X-Request-Id"), "a b");
+}
+
+/// Writes a self-signed certificate for `127.0.0.1` and returns the certificate pem.
+fn write_tls_files(dir: &std::path::Path) -> String {
+    let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    let cert_pem = certified.cert.pem();
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("cert.pem"), &cert_pem).unwrap();
+    std::fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem()).unwrap();
+    cert_pem
+}
+
+#[tokio::test]
+async fn serve_https() {
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    let cert_pem = write_tls_files(&dir);
+    let tls = tls::load_server_config(&dir.join("cert.pem"), &dir.join("key.pem")).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "https://127.0.0.1:{}",
+        listener.local_addr().unwrap().port()
+    );
+    let settings = Settings::default(url.clone());
+    let state = Arc::new(Mutex::new(State::default()));
+    let storage = Arc::new(MemoryStorage::new(settings.clone(), state.clone()));
+    let rate_limiter = Arc::new(RateLimiter::default());
+    tokio::spawn(polli_live::start_server::start_server(
+        vec![Listener::Tcp(listener)],
+        settings,
+        state,
+        storage,
+        rate_limiter,
+        Some(tls),
+    ));
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(cert_pem.as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let res = client.get(format!("{}/", url)).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.text().await.unwrap().contains(&url));
+}
+
+#[test]
+fn tls_config_errors() {
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    write_tls_files(&dir.join("a"));
+    write_tls_files(&dir.join("b"));
+
+    let err = tls::load_server_config(&dir.join("a/cert.pem"), &dir.join("b/key.pem"));
+    assert!(err.unwrap_err().starts_with("Cannot use"));
+    let err = tls::load_server_config(&dir.join("a/missing.pem"), &dir.join("a/key.pem"));
+    assert!(err.unwrap_err().starts_with("Cannot read"));
+    // The files are swapped.
+    let err = tls::load_server_config(&dir.join("a/key.pem"), &dir.join("a/cert.pem"));
+    assert!(err.unwrap_err().starts_with("No certificate found"));
+}
+
+async fn send_preflight(ctx: &TestContext, origin: &str) -> reqwest::Response {
+    ctx.client
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/page?session=1", ctx.url),
+        )
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "authorization")
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn cors_is_permissive_by_default() {
+    let ctx = setup().await;
+    let res = send_preflight(&ctx, "https://talks.example.com").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        header_value(&res, "Access-Control-Allow-Origin"),
+        "https://talks.example.com"
+    );
+}
+
+#[tokio::test]
+async fn cors_only_allows_configured_origins() {
+    let ctx = setup_with_settings(|settings| {
+        settings.cors = CorsPolicy::AllowedOrigins(vec![
+            "https://talks.example.com".to_string(),
+            "https://other.example.com".to_string(),
+        ])
+    })
+    .await;
+
+    let res = send_preflight(&ctx, "https://other.example.com").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        header_value(&res, "Access-Control-Allow-Origin"),
+        "https://other.example.com"
+    );
+
+    let res = send_preflight(&ctx, "https://evil.example.com").await;
+    assert!(res.headers().get("Access-Control-Allow-Origin").is_none());
+
+    // Requests that need no preflight are not allowed either.
+    let res = ctx
+        .client
+        .get(format!("{}/", ctx.url))
+        .header("Origin", "https://evil.example.com")
+        .send()
+        .await
+        .unwrap();
+    assert!(res.headers().get("Access-Control-Allow-Origin").is_none());
+}
+
+#[tokio::test]
+async fn security_headers_only_on_html_responses() {
+    let ctx = setup_with_settings(|settings| {
+        settings.security_headers.content_security_policy = Some("default-src 'self'".into());
+        settings.security_headers.frame_options = Some(FrameOptions::Deny);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+
+    for path in ["/", "/page?session=1", "/page?session=2"] {
+        let res = ctx.request_static_page(path).await;
+        assert_eq!(
+            header_value(&res, "X-Content-Type-Options"),
+            "nosniff",
+            "{path}"
+        );
+        assert_eq!(
+            header_value(&res, "Content-Security-Policy"),
+            "default-src 'self'"
+        );
+        assert_eq!(header_value(&res, "X-Frame-Options"), "DENY");
+        assert!(res.headers().contains_key("Referrer-Policy"));
+    }
+    for path in ["/client_config?session=1", "/responses?session=1&start=0"] {
+        let res = ctx.request_static_page(path).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert!(
+            !res.headers().contains_key("Content-Security-Policy"),
+            "{path}"
+        );
+        assert!(!res.headers().contains_key("X-Frame-Options"), "{path}");
+    }
+}
+
+#[tokio::test]
+async fn security_headers_can_be_disabled() {
+    let ctx = setup_with_settings(|settings| {
+        settings.security_headers.enabled = false;
+        settings.security_headers.frame_options = Some(FrameOptions::Deny);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+
+    let res = ctx.request_static_page("/page?session=1").await;
+    assert!(!res.headers().contains_key("X-Content-Type-Options"));
+    assert!(!res.headers().contains_key("X-Frame-Options"));
+    assert!(!res.headers().contains_key("Referrer-Policy"));
+}
+
+#[tokio::test]
+async fn serve_below_base_path() {
+    let mut ctx = setup_with_settings(|settings| {
+        settings.base_path = "/polli".to_string();
+        settings.admin_token = Some("admin-token".to_string());
+        settings.tunables.write().page_update_long_poll_duration =
+            std::time::Duration::from_millis(100);
+    })
+    .await;
+    let root_url = std::mem::replace(&mut ctx.url, ctx.settings.base_url());
+    assert_eq!(ctx.url, format!("{}/polli", root_url));
+
+    let res = ctx.request_static_page("/").await;
+    assert!(res
+        .text()
+        .await
+        .unwrap()
+        .contains(&format!("{}/page", ctx.url)));
+    let res = ctx.request_new_session(serde_json::json!({})).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(header_value(&res, "Link").contains(&format!("<{}/page?session=", ctx.url)));
+
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let page = ctx.request_session_page("1").await.text().await.unwrap();
+    assert!(page.contains(&format!("src=\"{}/polli_live.js", ctx.url)));
+    assert_eq!(
+        ctx.send_reponse(Some("1"), Some("a"), "yes").await.status(),
+        reqwest::StatusCode::OK
+    );
+    assert_eq!(
+        ctx.request_responses(Some("1"), Some(0)).await.status(),
+        reqwest::StatusCode::OK
+    );
+
+    for path in [
+        "/polli_live.js",
+        "/static/polli_live.js",
+        "/client_config?session=1",
+        "/wait_for_new_page?session=1",
+    ] {
+        let res = ctx.request_static_page(path).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK, "{path}");
+    }
+    for path in [
+        "/viewer_token?session=1",
+        "/digest?session=1&interval=15m&url=http%3A%2F%2Fexample.com",
+        "/rotate_token?session=1",
+    ] {
+        let res = ctx
+            .client
+            .post(format!("{}{}", ctx.url, path))
+            .bearer_auth("my-test-token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK, "{path}");
+    }
+    let res = ctx
+        .client
+        .delete(format!("{}/digest?session=1", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = ctx
+        .client
+        .post(format!("{}/admin/verify", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // Nothing is served outside of the base path.
+    let res = ctx
+        .client
+        .get(format!("{}/page?session=1", root_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn normalize_base_path() {
+    assert_eq!(settings::normalize_base_path(""), "");
+    assert_eq!(settings::normalize_base_path("/"), "");
+    assert_eq!(settings::normalize_base_path("polli/"), "/polli");
+    assert_eq!(settings::normalize_base_path("/a/b"), "/a/b");
+}
+
+async fn request_new_session_with_headers(
+    ctx: &TestContext,
+    headers: &[(&str, &str)],
+) -> reqwest::Response {
+    let mut builder = ctx.client.post(format!("{}/new", ctx.url));
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    builder.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn auto_root_url_uses_host_header() {
+    let mut ctx = setup_with_settings(|settings| {
+        settings.auto_root_url = true;
+        settings.base_path = "/polli".to_string();
+    })
+    .await;
+    ctx.url = ctx.settings.base_url();
+
+    let res = request_new_session_with_headers(&ctx, &[("Host", "polli.example.com")]).await;
+    assert!(header_value(&res, "Link").starts_with("<http://polli.example.com/polli/page?session="));
+
+    // Forwarded headers are ignored without a trusted proxy.
+    let res = request_new_session_with_headers(
+        &ctx,
+        &[
+            ("Host", "polli.example.com"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "evil.example.com"),
+        ],
+    )
+    .await;
+    assert!(header_value(&res, "Link").starts_with("<http://polli.example.com/polli/"));
+
+    // Hosts that could inject html fall back to the configured root url.
+    let res = ctx
+        .client
+        .get(format!("{}/", ctx.url))
+        .header("Host", "a\"><script>")
+        .send()
+        .await
+        .unwrap();
+    let text = res.text().await.unwrap();
+    assert!(!text.contains("a\"><script>"));
+    assert!(text.contains(&format!("{}/page?session=", ctx.url)));
+
+    // Stored pages don't depend on the host of the presenter.
+    assert_eq!(
+        page::injection_snippet(&ctx.settings),
+        format!(
+            "<script src=\"/polli/polli_live.js?v={}\"></script>\n",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+}
+
+#[tokio::test]
+async fn auto_root_url_with_trusted_proxy() {
+    let ctx = setup_with_settings(|settings| {
+        settings.auto_root_url = true;
+        settings.trust_forwarded_for = true;
+    })
+    .await;
+
+    let res = request_new_session_with_headers(
+        &ctx,
+        &[
+            ("Host", "internal:9000"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "polli.example.com"),
+        ],
+    )
+    .await;
+    assert!(header_value(&res, "Link").starts_with("<https://polli.example.com/page?session="));
+
+    // Only known schemes are used.
+    let res = request_new_session_with_headers(
+        &ctx,
+        &[
+            ("Host", "polli.example.com"),
+            ("X-Forwarded-Proto", "javascript"),
+        ],
+    )
+    .await;
+    assert!(header_value(&res, "Link").starts_with("<http://polli.example.com/page?session="));
+}
+
+#[tokio::test]
+async fn listen_on_multiple_addresses() {
+    let listeners = vec![
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+        TcpListener::bind("[::1]:0").unwrap(),
+    ];
+    let urls: Vec<String> = listeners
+        .iter()
+        .map(|listener| format!("http://{}", listener.local_addr().unwrap()))
+        .collect();
+    let listeners = listeners.into_iter().map(Listener::Tcp).collect();
+    let settings = Settings::default(urls[0].clone());
+    let state = Arc::new(Mutex::new(State::default()));
+    let storage = Arc::new(MemoryStorage::new(settings.clone(), state.clone()));
+    let rate_limiter = Arc::new(RateLimiter::default());
+    tokio::spawn(polli_live::start_server::start_server(
+        listeners,
+        settings,
+        state,
+        storage,
+        rate_limiter,
+        None,
+    ));
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    for url in &urls {
+        let res = client.get(format!("{}/", url)).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK, "{url}");
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn listen_on_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("polli.sock");
+    // Left over from a previous run.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let unix_listener = polli_live::start_server::bind_unix_socket(&path, Some(0o660)).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o660);
+    let tcp_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", tcp_listener.local_addr().unwrap());
+    let settings = Settings::default(url.clone());
+    let state = Arc::new(Mutex::new(State::default()));
+    let storage = Arc::new(MemoryStorage::new(settings.clone(), state.clone()));
+    tokio::spawn(polli_live::start_server::start_server(
+        vec![Listener::Tcp(tcp_listener), Listener::Unix(unix_listener)],
+        settings,
+        state,
+        storage,
+        Arc::new(RateLimiter::default()),
+        None,
+    ));
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let res = reqwest::get(format!("{}/", url)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+}
+
+#[tokio::test]
+async fn admin_sessions_require_admin_token() {
+    let ctx = setup().await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/admin/sessions", ctx.url))
+                .bearer_auth("admin-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "admin_disabled").await;
+
+    let ctx =
+        setup_with_settings(|settings| settings.admin_token = Some("admin-token".to_string()))
+            .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    for builder in [
+        ctx.client.get(format!("{}/admin/sessions", ctx.url)),
+        ctx.client.delete(format!("{}/admin/sessions/1", ctx.url)),
+    ] {
+        let res = ctx.request_json(builder.bearer_auth("my-test-token")).await;
+        assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    }
+    assert!(ctx
+        .state
+        .lock()
+        .sessions
+        .contains_key(&SessionID("1".into())));
+}
+
+#[tokio::test]
+async fn admin_list_sessions() {
+    let ctx =
+        setup_with_settings(|settings| settings.admin_token = Some("admin-token".to_string()))
+            .await;
+    ctx.set_page_and_check("small", "my-test-token", "page")
+        .await;
+    ctx.set_page_and_check("large", "my-test-token", &"x".repeat(1000))
+        .await;
+    ctx.send_reponse(Some("large"), Some("a"), "yes").await;
+
+    let res = ctx
+        .client
+        .get(format!("{}/admin/sessions", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    let text = res.text().await.unwrap();
+    assert!(!text.contains("my-test-token"));
+    let list: routes::SessionList = serde_json::from_str(&text).unwrap();
+    assert_eq!(list.total, 2);
+    assert_eq!(list.sessions[0].session.0, "large");
+    assert_eq!(list.sessions[0].responses, 1);
+    assert!(list.sessions[0].page_bytes > 1000);
+    assert!(list.sessions[0].approx_bytes > list.sessions[1].approx_bytes);
+    assert_eq!(list.sessions[1].session.0, "small");
+
+    let res = ctx
+        .client
+        .get(format!("{}/admin/sessions?limit=1", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    let list: routes::SessionList = res.json().await.unwrap();
+    assert_eq!(list.total, 2);
+    assert_eq!(list.sessions.len(), 1);
+    assert_eq!(list.sessions[0].session.0, "large");
+    assert_eq!(list.next_start, Some(1));
+
+    let res = ctx
+        .client
+        .get(format!("{}/admin/sessions?limit=1&start=1", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    let list: routes::SessionList = res.json().await.unwrap();
+    assert_eq!(list.total, 2);
+    assert_eq!(list.sessions.len(), 1);
+    assert_eq!(list.sessions[0].session.0, "small");
+    assert_eq!(list.next_start, None);
+}
+
+#[tokio::test]
+async fn admin_delete_session() {
+    let ctx = setup_with_settings(|settings| {
+        settings.admin_token = Some("admin-token".to_string());
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::from_secs(10);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+
+    let waiting = ctx.request_responses(Some("1"), Some(0));
+    let delete = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        ctx.client
+            .delete(format!("{}/admin/sessions/1", ctx.url))
+            .bearer_auth("admin-token")
+            .send()
+            .await
+            .unwrap()
+    };
+    let start = std::time::Instant::now();
+    let (waiting, deleted) = tokio::join!(waiting, delete);
+    assert_eq!(deleted.status(), reqwest::StatusCode::OK);
+    // The long-poll returns right away instead of waiting for the timeout.
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(waiting.status(), reqwest::StatusCode::NOT_FOUND);
+    assert!(!ctx
+        .state
+        .lock()
+        .sessions
+        .contains_key(&SessionID("1".into())));
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .delete(format!("{}/admin/sessions/1", ctx.url))
+                .bearer_auth("admin-token"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+}
+
+#[tokio::test]
+async fn admin_erases_user_data() {
+    let ctx =
+        setup_with_settings(|settings| settings.admin_token = Some("admin-token".to_string()))
+            .await;
+    let token = "my-test-token";
+    ctx.set_page_and_check("e1", token, "page").await;
+    ctx.set_page_and_check("e2", token, "page").await;
+    for session in ["e3", "e4"] {
+        let res = ctx
+            .request_new_session(serde_json::json!({
+                "session": session,
+                "token": token,
+                "anonymous": true,
+            }))
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = ctx
+        .client
+        .post(format!("{}/respond?session=e1&user=erase-me", ctx.url))
+        .header("Idempotency-Key", "k1")
+        .body("yes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    for (session, user) in [
+        ("e1", "keep"),
+        ("e2", "erase-me"),
+        ("e3", "erase-me"),
+        ("e4", "other"),
+    ] {
+        let res = ctx.send_reponse(Some(session), Some(user), "yes").await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = ctx
+        .client
+        .post(format!("{}/ban?session=e2&user=erase-me", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let erase = |user: String| {
+        ctx.client
+            .delete(format!("{}/admin/user_data", ctx.url))
+            .query(&[("user", user)])
+            .bearer_auth("admin-token")
+            .send()
+    };
+
+    let res = ctx
+        .client
+        .delete(format!("{}/admin/user_data?user=erase-me", ctx.url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let res = erase("erase-me".to_string()).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let erased: routes::ErasedUserData = res.json().await.unwrap();
+    // The response and idempotency key in e1, the ban in e2 and the hashed response in e3.
+    assert_eq!(erased.removed, 4);
+    assert_eq!(erased.sessions, 3);
+    {
+        let state = ctx.state.lock();
+        for session in state.sessions.values() {
+            assert!(session.banned_users.is_empty());
+            assert!(session.idempotency_keys.is_empty());
+        }
+        let e1 = &state.sessions[&SessionID::from_string("e1").unwrap()];
+        let users: Vec<&str> = e1.responses.keys().map(|user| user.0.as_str()).collect();
+        assert_eq!(users, vec!["keep"]);
+        assert!(state.sessions[&SessionID::from_string("e3").unwrap()]
+            .responses
+            .is_empty());
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    // Hashed ids of anonymous sessions are accepted as well.
+    let hashed = request_user_ids(&ctx, "e4").await.remove("yes").unwrap();
+    let erased: routes::ErasedUserData = erase(hashed).await.unwrap().json().await.unwrap();
+    assert_eq!(erased.removed, 1);
+    assert_eq!(erased.sessions, 1);
+    let erased: routes::ErasedUserData = erase("nobody".to_string())
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(erased.removed, 0);
+}
+
+#[tokio::test]
+async fn audit_log_records_session_events() {
+    let clock = MockClock::new();
+    let ctx = setup_with_settings(|settings| {
+        settings.clock = clock.clone();
+        settings.admin_token = Some("admin-token".to_string());
+    })
+    .await;
+    ctx.set_page_and_check("a", "first-test-token", "secret page 1")
+        .await;
+    ctx.set_page_and_check("a", "first-test-token", "secret page 2")
+        .await;
+    ctx.set_page_and_check("b", "first-test-token", "other page")
+        .await;
+    let res = ctx
+        .client
+        .post(format!("{}/rotate_token?session=a", ctx.url))
+        .bearer_auth("first-test-token")
+        .send()
+        .await
+        .unwrap();
+    let rotated_token = res.json::<routes::RotatedToken>().await.unwrap().token;
+    clock.advance(ctx.settings.token_timeout);
+    ctx.set_page_and_check("a", "second-test-token", "secret page 3")
+        .await;
+    let res = ctx
+        .client
+        .delete(format!("{}/admin/sessions/a", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let request_audit = |token: &str| {
+        ctx.client
+            .get(format!("{}/admin/audit?session=a", ctx.url))
+            .bearer_auth(token)
+            .send()
+    };
+    let res = request_audit("second-test-token").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let res = request_audit("admin-token").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let text = res.text().await.unwrap();
+    for secret in [
+        "secret page",
+        "first-test-token",
+        "second-test-token",
+        &rotated_token,
+    ] {
+        assert!(!text.contains(secret));
+    }
+    let events = serde_json::from_str::<routes::AuditEvents>(&text)
+        .unwrap()
+        .events;
+    let ip = Some("127.0.0.1".parse().unwrap());
+    let kinds: Vec<AuditEventKind> = events.into_iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            AuditEventKind::SessionCreated { ip },
+            AuditEventKind::PageUpdated {
+                ip,
+                access: PageAccess::TokenMatched,
+            },
+            AuditEventKind::TokenRotated { ip },
+            AuditEventKind::PageUpdated {
+                ip,
+                access: PageAccess::Takeover,
+            },
+            AuditEventKind::SessionRemoved {
+                reason: RemovalReason::Deleted,
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn audit_log_keeps_only_the_last_events() {
+    let ctx = setup_with_settings(|settings| settings.audit_log_len = 3).await;
+    for i in 0..4 {
+        ctx.set_page_and_check("a", "my-test-token", &format!("page {}", i))
+            .await;
+    }
+    ctx.set_page_and_check("b", "my-test-token", "page").await;
+    let state = ctx.state.lock();
+    let events = state
+        .audit_log
+        .session_events(&SessionID::from_string("a").unwrap());
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|event| matches!(
+        event.kind,
+        AuditEventKind::PageUpdated {
+            access: PageAccess::TokenMatched,
+            ..
+        }
+    )));
+}
+
+async fn patch_admin_settings(ctx: &TestContext, body: serde_json::Value) -> reqwest::Response {
+    ctx.request_json(
+        ctx.client
+            .patch(format!("{}/admin/settings", ctx.url))
+            .bearer_auth("admin-token")
+            .json(&body),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn admin_settings_change_limits_live() {
+    let ctx =
+        setup_with_settings(|settings| settings.admin_token = Some("admin-token".to_string()))
+            .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let response = "x".repeat(5000);
+    let res = ctx.send_reponse(Some("1"), Some("a"), &response).await;
+    assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+    let res = patch_admin_settings(
+        &ctx,
+        serde_json::json!({"max_response_size": 8000, "session_keep_alive_duration": 60.0}),
+    )
+    .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let tunables: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(tunables["max_response_size"], 8000);
+    assert_eq!(tunables["max_sessions"], 50_000);
+    assert_eq!(
+        ctx.settings.tunables().session_keep_alive_duration,
+        std::time::Duration::from_secs(60)
+    );
+
+    let res = ctx.send_reponse(Some("1"), Some("a"), &response).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .client
+        .get(format!("{}/admin/settings", ctx.url))
+        .bearer_auth("admin-token")
+        .send()
+        .await
+        .unwrap();
+    let tunables: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(tunables["max_response_size"], 8000);
+}
+
+#[tokio::test]
+async fn admin_settings_reject_invalid_changes() {
+    let ctx =
+        setup_with_settings(|settings| settings.admin_token = Some("admin-token".to_string()))
+            .await;
+    let before = ctx.settings.tunables();
+
+    for body in [
+        serde_json::json!({"max_response_size": 8000, "token_secret": "x"}),
+        serde_json::json!({"max_response_size": 8000, "max_sessions": -1}),
+        serde_json::json!({"max_response_size": 8000, "min_response_interval": "1s"}),
+    ] {
+        let res = patch_admin_settings(&ctx, body).await;
+        assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "invalid_setting").await;
+    }
+    assert_eq!(ctx.settings.tunables(), before);
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .patch(format!("{}/admin/settings", ctx.url))
+                .bearer_auth("my-test-token")
+                .json(&serde_json::json!({"max_response_size": 8000})),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    assert_eq!(ctx.settings.tunables(), before);
+}
+
+#[test]
+fn reload_config_file() {
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("polli.toml");
+    let settings = Settings::default("".to_string());
+    let defaults = settings.tunables();
+
+    std::fs::write(
+        &path,
+        "max_response_size = 8000\nsession_keep_alive_duration = 60\n",
+    )
+    .unwrap();
+    config::reload(&settings, &path, &defaults).unwrap();
+    assert_eq!(settings.tunables().max_response_size.as_u64(), 8000);
+    assert_eq!(
+        settings.tunables().session_keep_alive_duration,
+        std::time::Duration::from_secs(60)
+    );
+    assert_eq!(settings.tunables().max_sessions, defaults.max_sessions);
+
+    // Invalid files are rejected as a whole.
+    for content in [
+        "max_response_size = 100\nport = 80\n",
+        "max_response_size = 100\nmax_sessions = -1\n",
+        "max_response_size = ",
+    ] {
+        std::fs::write(&path, content).unwrap();
+        assert!(config::reload(&settings, &path, &defaults).is_err());
+        assert_eq!(settings.tunables().max_response_size.as_u64(), 8000);
+    }
+
+    // Settings that are removed from the file get their previous value again.
+    std::fs::write(&path, "max_response_size = 8000\n").unwrap();
+    config::reload(&settings, &path, &defaults).unwrap();
+    assert_eq!(
+        settings.tunables().session_keep_alive_duration,
+        defaults.session_keep_alive_duration
+    );
+}
+
+#[test]
+fn reload_response_filter_file() {
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("filter.txt");
+    let mut settings = Settings::default("".to_string());
+    settings.response_filter_path = Some(path.clone());
+    settings.response_filter_mode = FilterMode::Mask;
+    let apply = |data: &str| settings.response_filter().unwrap().apply(data.to_string());
+
+    std::fs::write(&path, "# Comments are ignored.\ndarn\n/sp[a4]m/\n").unwrap();
+    config::reload_response_filter(&settings).unwrap();
+    assert_eq!(apply("Darn, sp4m!").unwrap(), "****, ****!");
+    assert_eq!(apply("darned").unwrap(), "darned");
+
+    std::fs::write(&path, "heck\n").unwrap();
+    config::reload_response_filter(&settings).unwrap();
+    assert_eq!(apply("darn heck").unwrap(), "darn ****");
+
+    // Invalid files keep the previous filter.
+    std::fs::write(&path, "/(/\n").unwrap();
+    assert!(config::reload_response_filter(&settings).is_err());
+    assert_eq!(apply("heck").unwrap(), "****");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Method and path of all routes, read from the route attributes in the source.
+fn registered_routes() -> Vec<(String, String)> {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/routes");
+    let mut routes = vec![];
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let source = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        for line in source.lines() {
+            let Some(attribute) = line.trim().strip_prefix("#[") else {
+                continue;
+            };
+            let Some((method, rest)) = attribute.split_once("(\"") else {
+                continue;
+            };
+            if !["get", "post", "patch", "delete"].contains(&method) {
+                continue;
+            }
+            let path = rest.split('"').next().unwrap();
+            // Patterns like `{filename:.*}` are documented as `{filename}`.
+            let path = path.replace(":.*}", "}");
+            routes.push((method.to_string(), path));
+        }
+    }
+    routes
+}
+
+#[tokio::test]
+async fn openapi_documents_all_routes() {
+    let ctx = setup().await;
+    let res = ctx.request_static_page("/openapi.json").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let document: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(document["openapi"], "3.0.3");
+    assert_eq!(document["servers"][0]["url"], ctx.url);
+
+    let routes = registered_routes();
+    assert!(routes.len() > 10);
+    for (method, path) in routes {
+        assert!(
+            document["paths"][&path][&method].is_object(),
+            "{method} {path} is not documented"
+        );
+    }
+}
+
+#[tokio::test]
+async fn push_file_sets_page() {
+    let ctx = setup_with_settings(|settings| {
+        settings.max_page_size = byte_unit::Byte::from_u64(1000);
+    })
+    .await;
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("talk.html");
+    std::fs::write(&file, "first page").unwrap();
+    let mut target = push::PushTarget {
+        server_url: ctx.url.clone(),
+        session: "pushed".to_string(),
+        token: "my-test-token".to_string(),
+    };
+    let status = push::push_file(&ctx.client, &target, &file).await.unwrap();
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(ctx.request_session_page_text("pushed").await, "first page");
+
+    std::fs::write(&file, "x".repeat(1000)).unwrap();
+    let err = push::push_file(&ctx.client, &target, &file)
+        .await
+        .unwrap_err();
+    assert!(err.starts_with("The page is too large"), "{}", err);
+    assert!(err.contains("at most 1000 allowed"), "{}", err);
+
+    target.token = "other-test-token".to_string();
+    std::fs::write(&file, "second page").unwrap();
+    let err = push::push_file(&ctx.client, &target, &file)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        "The token does not belong to this session. (401 Unauthorized)"
+    );
+
+    let err = push::push_file(&ctx.client, &target, &dir.join("missing.html"))
+        .await
+        .unwrap_err();
+    assert!(err.starts_with("Cannot read"), "{}", err);
+}
+
+#[tokio::test]
+async fn push_watch_pushes_changes() {
+    let ctx = setup().await;
+    let dir = std::env::temp_dir().join(format!("polli-live-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("talk.html");
+    std::fs::write(&file, "first page").unwrap();
+    let target = push::PushTarget {
+        server_url: ctx.url.clone(),
+        session: "watched".to_string(),
+        token: "my-test-token".to_string(),
+    };
+    let client = ctx.client.clone();
+    let watched_file = file.clone();
+    let handle =
+        tokio::spawn(async move { push::watch_and_push(&client, &target, &watched_file).await });
+
+    let wait_for_page = |expected: &'static str| {
+        let ctx = &ctx;
+        async move {
+            for _ in 0..50 {
+                let res = ctx.request_session_page("watched").await;
+                if res.status() == reqwest::StatusCode::OK
+                    && res.text().await.unwrap().contains(expected)
+                {
+                    return;
+                }
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            }
+            panic!("Page was not updated to {}", expected);
+        }
+    };
+    wait_for_page("first page").await;
+    std::fs::write(&file, "second page").unwrap();
+    wait_for_page("second page").await;
+    handle.abort();
+}
+
+#[test]
+fn push_describes_non_json_errors() {
+    assert_eq!(
+        push::describe_error(reqwest::StatusCode::BAD_GATEWAY, "<html>proxy</html>"),
+        "Server responded with 502 Bad Gateway"
+    );
+}
+
+#[tokio::test]
+async fn embed_routes_in_own_app() {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let mut settings = Settings::default(url.clone());
+    settings.base_path = "/polls".to_string();
+    let state = Arc::new(Mutex::new(State::default()));
+    let shared_state = web::Data::new(polli_live::SharedState {
+        settings: settings.clone(),
+        storage: Arc::new(MemoryStorage::new(settings.clone(), state.clone())),
+        state: state.clone(),
+        rate_limiter: Arc::new(RateLimiter::default()),
+        statistics: state.lock().statistics.clone(),
+        long_polls: Default::default(),
+    });
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(shared_state.clone())
+            .route(
+                "/",
+                web::get().to(|| async { HttpResponse::Ok().body("my tool") }),
+            )
+            .service(web::scope("/polls").configure(routes::register_routes))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    let handle = tokio::spawn(async move {
+        server.await.unwrap();
+    });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let res = client.get(format!("{}/", url)).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "my tool");
+    let res = client
+        .post(format!("{}/polls/page?session=embedded", url))
+        .bearer_auth("my-test-token")
+        .body("embedded page")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let session_id = SessionID::from_string("embedded").unwrap();
+    assert!(state.lock().sessions.contains_key(&session_id));
+    handle.abort();
+}
+
+fn make_shared_state(ctx: &TestContext) -> polli_live::SharedState {
+    polli_live::SharedState {
+        settings: ctx.settings.clone(),
+        state: ctx.state.clone(),
+        storage: Arc::new(MemoryStorage::new(ctx.settings.clone(), ctx.state.clone())),
+        rate_limiter: Arc::new(RateLimiter::default()),
+        statistics: ctx.state.lock().statistics.clone(),
+        long_polls: Default::default(),
+    }
+}
+
+#[tokio::test]
+async fn subscribe_responses_while_clients_respond() {
+    use futures_util::StreamExt;
+
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    let session_id = SessionID::from_string("1").unwrap();
+    assert!(ctx.state.lock().subscribe_responses(&session_id).is_err());
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+
+    let responses = ctx.state.lock().subscribe_responses(&session_id).unwrap();
+    let mut responses = Box::pin(responses);
+    for (user, data) in [("a", "first"), ("b", "second"), ("a", "third")] {
+        let res = ctx.send_reponse(Some("1"), Some(user), data).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    for (user, data) in [("a", "first"), ("b", "second"), ("a", "third")] {
+        let (user_id, response) = responses.next().await.unwrap();
+        assert_eq!((user_id.0.as_str(), response.as_str()), (user, data));
+    }
+
+    // Rejected responses are not published.
+    let res = ctx
+        .send_reponse(Some("1"), Some("c"), &"x".repeat(5000))
+        .await;
+    assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+    assert!(ctx.state.lock().delete_session(&ctx.settings, &session_id));
+    assert!(responses.next().await.is_none());
+}
+
+#[tokio::test]
+async fn response_subscriptions_end_when_falling_behind() {
+    use futures_util::StreamExt;
+
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+        settings.respond_rate_limit = RateLimit {
+            burst: 1000,
+            per_second: 1000.0,
+        };
+    })
+    .await;
+    let session_id = SessionID::from_string("1").unwrap();
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let responses = ctx.state.lock().subscribe_responses(&session_id).unwrap();
+    let mut responses = Box::pin(responses);
+    for i in 0..=state::RESPONSE_CHANNEL_CAPACITY {
+        let res = ctx.send_reponse(Some("1"), Some("a"), &i.to_string()).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    // Instead of continuing with later responses, so that the subscriber notices.
+    assert!(responses.next().await.is_none());
+}
+
+#[tokio::test]
+async fn set_page_from_embedding_application() {
+    let ctx = setup_with_settings(|settings| {
+        settings.max_page_size = byte_unit::Byte::from_u64(1000);
+    })
+    .await;
+    let shared_state = make_shared_state(&ctx);
+    let session_id = SessionID::from_string("embedded").unwrap();
+    let token = AccessToken::from_string("my-test-token").unwrap();
+    shared_state
+        .set_page(&session_id, token.clone(), "embedded page".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        ctx.request_session_page_text("embedded").await,
+        "embedded page"
+    );
+
+    assert!(matches!(
+        shared_state
+            .set_page(&session_id, token, "x".repeat(1000))
+            .await,
+        Err(polli_live::AppError::PageTooLarge { .. })
+    ));
+    let other_token = AccessToken::from_string("other-test-token").unwrap();
+    assert!(matches!(
+        shared_state
+            .set_page(&session_id, other_token, "other page".to_string())
+            .await,
+        Err(polli_live::AppError::BadAccessToken)
+    ));
+    assert_eq!(
+        ctx.request_session_page_text("embedded").await,
+        "embedded page"
+    );
+}
+
+#[tokio::test]
+async fn responses_list_format_is_ordered() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    for (user, data) in [
+        ("c", "first"),
+        ("a", "second"),
+        ("b", "third"),
+        ("c", "fourth"),
+    ] {
+        let res = ctx.send_reponse(Some("1"), Some(user), data).await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=1&start=0&format=list",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let result: routes::RetrievedResponseList = res.json().await.unwrap();
+    assert_eq!(result.next_start, 4);
+    let listed: Vec<(&str, &str, usize)> = result
+        .responses
+        .iter()
+        .map(|response| {
+            (
+                response.user.0.as_str(),
+                response.data.as_str(),
+                response.id,
+            )
+        })
+        .collect();
+    // The first response of `c` has been replaced.
+    assert_eq!(
+        listed,
+        [("a", "second", 1), ("b", "third", 2), ("c", "fourth", 3)]
+    );
+    assert!(result.responses.windows(2).all(|w| w[0].time <= w[1].time));
+
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=1&start=2&format=list",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let result: routes::RetrievedResponseList = res.json().await.unwrap();
+    assert_eq!(result.responses.len(), 2);
+    assert_eq!(result.responses[0].user.0, "b");
+}
+
+#[tokio::test]
+async fn responses_verbose_map_format() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    ctx.send_reponse(Some("1"), Some("a"), "first").await;
+    ctx.send_reponse(Some("1"), Some("b"), "second").await;
+
+    let url = format!("{}/responses?session=1&start=0", ctx.url);
+    let result: routes::RetrievedResponses = ctx
+        .client
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(result.responses_by_user[&UserID("b".to_string())], "second");
+
+    let result: routes::VerboseRetrievedResponses = ctx
+        .client
+        .get(format!("{}&verbose=true", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let response = &result.responses_by_user[&UserID("b".to_string())];
+    assert_eq!((response.data.as_str(), response.id), ("second", 1));
+
+    let res = ctx
+        .client
+        .get(format!("{}&format=table", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn responses_in_pages() {
+    let ctx = setup_with_settings(|settings| {
+        settings.max_responses_per_request = 40;
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let user_count = 150;
+    for i in 0..user_count {
+        let res = ctx
+            .send_reponse(Some("1"), Some(&format!("user{}", i)), &i.to_string())
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    let mut all_responses = HashMap::new();
+    let mut start = 0;
+    let mut page_sizes = vec![];
+    loop {
+        let res = ctx
+            .client
+            .get(format!(
+                "{}/responses?session=1&start={}&limit=50&format=list",
+                ctx.url, start
+            ))
+            .send()
+            .await
+            .unwrap();
+        let result: routes::RetrievedResponseList = res.json().await.unwrap();
+        let ids: Vec<usize> = result.responses.iter().map(|r| r.id).collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|id| *id >= start && *id < result.next_start));
+        assert_eq!(result.session, "1");
+        assert_eq!(result.total_responses, user_count);
+        page_sizes.push(result.responses.len());
+        for response in result.responses {
+            all_responses.insert(response.user.0, response.data);
+        }
+        start = result.next_start;
+        if start == user_count {
+            break;
+        }
+    }
+    // The server limit is smaller than the requested one.
+    assert_eq!(page_sizes, [40, 40, 40, 30]);
+    assert_eq!(all_responses.len(), user_count);
+    for i in 0..user_count {
+        assert_eq!(all_responses[&format!("user{}", i)], i.to_string());
+    }
+
+    // The map format is limited in the same way.
+    let res = ctx
+        .client
+        .get(format!("{}/responses?session=1&start=100&limit=3", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 103);
+    assert_eq!(result.responses_by_user.len(), 3);
+    // The total includes the responses before `start` and after the limit.
+    assert_eq!(result.total_responses, user_count);
+    assert_eq!(
+        result.responses_by_user[&UserID("user102".to_string())],
+        "102"
+    );
+}
+
+#[tokio::test]
+async fn long_poll_waits_for_min_count_responses() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::from_secs(30);
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let poll = ctx.client.get(format!(
+        "{}/responses?session=1&start=0&min_count=3",
+        ctx.url
+    ));
+    let poll = tokio::spawn(async move { poll.send().await.unwrap() });
+    let still_waiting = || async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        !poll.is_finished()
+    };
+
+    ctx.send_reponse(Some("1"), Some("a"), "1").await;
+    ctx.send_reponse(Some("1"), Some("b"), "2").await;
+    assert!(still_waiting().await);
+    // Changed responses count once.
+    ctx.send_reponse(Some("1"), Some("a"), "3").await;
+    assert!(still_waiting().await);
+
+    ctx.send_reponse(Some("1"), Some("c"), "4").await;
+    let result: routes::RetrievedResponses = poll.await.unwrap().json().await.unwrap();
+    assert_eq!(result.responses_by_user.len(), 3);
+    assert_eq!(result.next_start, 4);
+
+    // Responses before `start` don't count.
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/responses?session=1&start=2&min_count=3&timeout_ms=200",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.responses_by_user.len(), 2);
+}
+
+#[tokio::test]
+async fn unacknowledged_responses_survive_memory_pressure() {
+    let ctx = setup_with_settings(|settings| {
+        settings.require_explicit_ack = true;
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::ZERO;
+        settings.received_response_retention = std::time::Duration::ZERO;
+        settings.emergency_retention = std::time::Duration::from_secs(24 * 60 * 60);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    ctx.send_reponse(Some("1"), Some("a"), "first").await;
+    ctx.send_reponse(Some("1"), Some("b"), "second").await;
+    // Polling with a later start does not mark the responses as received.
+    ctx.request_responses(Some("1"), Some(2)).await;
+
+    ctx.settings.tunables.write().max_memory_usage = byte_unit::Byte::from_u64(1);
+    let session_id = SessionID::from_string("1").unwrap();
+    let cleanup = || {
+        let now = ctx.settings.now() + chrono::Duration::seconds(1);
+        cleanup::cleanup_once(&ctx.settings, &mut ctx.state.lock(), now);
+        ctx.state.lock().sessions[&session_id].responses.len()
+    };
+    assert_eq!(cleanup(), 2);
+
+    let ack = |token: &str| {
+        ctx.client
+            .post(format!("{}/ack?session=1&upto=1", ctx.url))
+            .bearer_auth(token)
+            .send()
+    };
+    let res = ack("other-test-token").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(cleanup(), 2);
+
+    let res = ack("my-test-token").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(cleanup(), 1);
+    assert!(ctx.state.lock().sessions[&session_id]
+        .responses
+        .contains_key(&UserID("b".to_string())));
+}
+
+#[tokio::test]
+async fn polling_marks_responses_without_explicit_ack() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    ctx.send_reponse(Some("1"), Some("a"), "first").await;
+    ctx.request_responses(Some("1"), Some(1)).await;
+    let session_id = SessionID::from_string("1").unwrap();
+    assert!(
+        ctx.state.lock().sessions[&session_id].responses[&UserID("a".to_string())].was_received
+    );
+}
+
+#[tokio::test]
+async fn long_poll_returns_when_data_arrives_mid_wait() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::from_secs(3);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+
+    let started = std::time::Instant::now();
+    let poll = ctx.request_responses(Some("1"), Some(0));
+    let respond = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        ctx.send_reponse(Some("1"), Some("a"), "late").await;
+    };
+    let (res, _) = tokio::join!(poll, respond);
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 1);
+    assert_eq!(result.responses_by_user[&UserID("a".to_string())], "late");
+}
+
+#[tokio::test]
+async fn long_poll_ignores_irrelevant_notification() {
+    let long_poll_duration = std::time::Duration::from_millis(1500);
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = long_poll_duration;
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    ctx.send_reponse(Some("1"), Some("a"), "known").await;
+
+    let started = std::time::Instant::now();
+    let poll = ctx.request_responses(Some("1"), Some(1));
+    let notify = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        let session_id = SessionID::from_string("1").unwrap();
+        ctx.state.lock().sessions[&session_id]
+            .response_notifier
+            .notify_waiters();
+    };
+    let (res, _) = tokio::join!(poll, notify);
+    assert!(started.elapsed() >= long_poll_duration);
+    let result: routes::RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(result.next_start, 1);
+    assert!(result.responses_by_user.is_empty());
+}
+
+#[tokio::test]
+async fn long_poll_timeout_override() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::from_secs(10);
+        settings.tunables.write().page_update_long_poll_duration =
+            std::time::Duration::from_secs(10);
+        settings.max_long_poll_duration = std::time::Duration::from_millis(500);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let ctx = &ctx;
+    let get = |path: &str| {
+        let url = format!("{}{}", ctx.url, path);
+        async move {
+            let started = std::time::Instant::now();
+            let res = ctx.client.get(url).send().await.unwrap();
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            (started.elapsed(), res.text().await.unwrap())
+        }
+    };
+
+    let (elapsed, body) = get("/responses?session=1&start=0&timeout_ms=0").await;
+    assert!(elapsed < std::time::Duration::from_millis(200));
+    assert!(body.contains("\"next_start\":0"));
+    let (elapsed, body) = get("/wait_for_new_page?session=1&timeout_ms=0").await;
+    assert!(elapsed < std::time::Duration::from_millis(200));
+    assert_eq!(body, "wait");
+
+    // Longer timeouts and the default are clamped to the maximum.
+    for path in [
+        "/responses?session=1&start=0&timeout_ms=60000",
+        "/responses?session=1&start=0",
+        "/wait_for_new_page?session=1&timeout_ms=60000",
+    ] {
+        let (elapsed, _) = get(path).await;
+        assert!(elapsed >= std::time::Duration::from_millis(500), "{}", path);
+        assert!(elapsed < std::time::Duration::from_secs(3), "{}", path);
+    }
+
+    let (elapsed, _) = get("/responses?session=1&start=0&timeout_ms=200").await;
+    assert!(elapsed >= std::time::Duration::from_millis(200));
+    assert!(elapsed < std::time::Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn concurrent_long_polls_are_limited() {
+    let ctx = setup_with_settings(|settings| {
+        settings.max_long_polls_per_session = 2;
+        settings.max_long_polls_total = 3;
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::from_secs(30);
+    })
+    .await;
+    ctx.set_page_and_check("a", "my-test-token", "page").await;
+    ctx.set_page_and_check("b", "my-test-token", "page").await;
+    let ctx = &ctx;
+    let long_poll = |path: &str| {
+        let builder = ctx
+            .client
+            .get(format!("{}{}", ctx.url, path))
+            .header(reqwest::header::ACCEPT, "application/json");
+        tokio::spawn(async move { builder.send().await.unwrap() })
+    };
+    let total = || {
+        let state = ctx.state.lock();
+        ["a", "b"]
+            .iter()
+            .map(|session| {
+                state.sessions[&SessionID::from_string(session).unwrap()]
+                    .long_polls
+                    .load(std::sync::atomic::Ordering::Relaxed)
+            })
+            .sum::<usize>()
+    };
+    let assert_rejected = |path: &'static str, limit: &'static str| async move {
+        let started = std::time::Instant::now();
+        let res = ctx
+            .request_json(ctx.client.get(format!("{}{}", ctx.url, path)))
+            .await;
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        let body: ErrorBody = res.json().await.unwrap();
+        assert_eq!(body.error, "long_poll_limit_reached");
+        assert_eq!(body.details.unwrap()["limit"], limit);
+    };
+
+    let _first = long_poll("/wait_for_new_page?session=a");
+    let _second = long_poll("/responses?session=a&start=0");
+    wait_until(|| total() == 2).await;
+    assert_rejected("/wait_for_new_page?session=a", "per_session").await;
+    assert_rejected("/message?session=a", "per_session").await;
+    // Requests that don't wait are not limited.
+    let res = ctx
+        .client
+        .get(format!(
+            "{}/wait_for_new_page?session=a&timeout_ms=0",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let third = long_poll("/wait_for_new_page?session=b");
+    wait_until(|| total() == 3).await;
+    assert_rejected("/wait_for_new_page?session=b", "total").await;
+
+    // Waiting clients get the new page as usual.
+    ctx.set_page_and_check("b", "my-test-token", "new page")
+        .await;
+    let res = third.await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.text().await.unwrap(), "reload");
+    wait_until(|| total() == 2).await;
+
+    // Actix only drops the handler of a cancelled request when the long-poll ends, so
+    // its slot is free again at the latest after the timeout.
+    let cancelled = long_poll("/wait_for_new_page?session=b&timeout_ms=300");
+    wait_until(|| total() == 3).await;
+    cancelled.abort();
+    wait_until(|| total() == 2).await;
+}
+
+#[tokio::test]
+async fn page_notifications_are_debounced() {
+    let ctx = setup_with_settings(|settings| {
+        settings.page_notify_debounce = std::time::Duration::from_millis(300);
+    })
+    .await;
+    ctx.set_page_and_check("d", "my-test-token", "page 0").await;
+    let waiter = {
+        let client = ctx.client.clone();
+        let url = format!("{}/wait_for_new_page?session=d&timeout_ms=1000", ctx.url);
+        tokio::spawn(async move {
+            let mut reloads = vec![];
+            loop {
+                let body = client.get(&url).send().await.unwrap().text().await.unwrap();
+                if body != "reload" {
+                    return reloads;
+                }
+                reloads.push(std::time::Instant::now());
+            }
+        })
+    };
+    let is_waiting = || {
+        ctx.state.lock().sessions[&SessionID::from_string("d").unwrap()]
+            .long_polls
+            .load(std::sync::atomic::Ordering::Relaxed)
+            == 1
+    };
+    wait_until(is_waiting).await;
+
+    for i in 1..=10 {
+        ctx.set_page_and_check("d", "my-test-token", &format!("page {}", i))
+            .await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+    }
+    let last_update = std::time::Instant::now();
+
+    // The first update is announced right away and all others together afterwards.
+    let reloads = waiter.await.unwrap();
+    assert_eq!(reloads.len(), 2);
+    assert!(reloads[0] < last_update);
+    assert!(reloads[1] > last_update);
+    assert_eq!(ctx.request_session_page_text("d").await, "page 10");
+}
+
+#[tokio::test]
+async fn unchanged_page_is_not_updated() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("u", "my-test-token", "page").await;
+    let res = ctx.send_reponse(Some("u"), Some("a"), "yes").await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let wait = || {
+        ctx.client
+            .get(format!(
+                "{}/wait_for_new_page?session=u&timeout_ms=300",
+                ctx.url
+            ))
+            .send()
+    };
+    let response_count = || {
+        ctx.state.lock().sessions[&SessionID::from_string("u").unwrap()]
+            .responses
+            .len()
+    };
+    let set_page = |query: &str| {
+        ctx.client
+            .post(format!("{}/page?session=u{}", ctx.url, query))
+            .bearer_auth("my-test-token")
+            .body("page")
+            .send()
+    };
+
+    let (waited, res) = tokio::join!(wait(), async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        set_page("").await.unwrap()
+    });
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.text().await.unwrap(), "Page unchanged.");
+    assert_eq!(waited.unwrap().text().await.unwrap(), "wait");
+    assert_eq!(response_count(), 1);
+
+    let (waited, res) = tokio::join!(wait(), async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        set_page("&force=true").await.unwrap()
+    });
+    assert_eq!(res.text().await.unwrap(), "Page updated.");
+    assert_eq!(waited.unwrap().text().await.unwrap(), "reload");
+    assert_eq!(response_count(), 0);
+}
+
+#[tokio::test]
+async fn batch_responses_wait_for_any_session() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::from_secs(3);
+    })
+    .await;
+    ctx.set_page_and_check("room1", "my-test-token", "page")
+        .await;
+    ctx.set_page_and_check("room2", "my-test-token", "page")
+        .await;
+    ctx.send_reponse(Some("room1"), Some("a"), "old").await;
+
+    let started = std::time::Instant::now();
+    let poll = ctx
+        .client
+        .get(format!(
+            "{}/responses/batch?sessions=room1,room2,missing&start=1&start=0",
+            ctx.url
+        ))
+        .send();
+    let respond = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        ctx.send_reponse(Some("room2"), Some("b"), "new").await;
+    };
+    let (res, _) = tokio::join!(poll, respond);
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    let result: HashMap<String, routes::BatchEntry> = res.unwrap().json().await.unwrap();
+    assert_eq!(result.len(), 3);
+    let routes::BatchEntry::Responses(room1) = &result["room1"] else {
+        panic!("room1 should have responses");
+    };
+    assert_eq!(room1.next_start, 1);
+    assert!(room1.responses_by_user.is_empty());
+    assert_eq!(room1.session, "room1");
+    assert_eq!(room1.total_responses, 1);
+    let routes::BatchEntry::Responses(room2) = &result["room2"] else {
+        panic!("room2 should have responses");
+    };
+    assert_eq!(room2.next_start, 1);
+    assert_eq!(room2.responses_by_user[&UserID("b".to_string())], "new");
+    let routes::BatchEntry::Error(missing) = &result["missing"] else {
+        panic!("missing should have an error");
+    };
+    assert_eq!(missing.error, "session_not_found");
+
+    // The start ids can also be passed in the body.
+    let res = ctx
+        .client
+        .get(format!("{}/responses/batch?sessions=room1,room2", ctx.url))
+        .body(r#"{"room2": 1}"#)
+        .send()
+        .await
+        .unwrap();
+    let result: HashMap<String, routes::BatchEntry> = res.json().await.unwrap();
+    let routes::BatchEntry::Responses(room1) = &result["room1"] else {
+        panic!("room1 should have responses");
+    };
+    assert_eq!(room1.responses_by_user[&UserID("a".to_string())], "old");
+    let routes::BatchEntry::Responses(room2) = &result["room2"] else {
+        panic!("room2 should have responses");
+    };
+    assert!(room2.responses_by_user.is_empty());
+}
+
+#[tokio::test]
+async fn batch_responses_bad_parameters() {
+    let ctx = setup().await;
+    for query in [
+        "",
+        "sessions=",
+        "sessions=a&start=x",
+        "sessions=a&start=1&start=2",
+    ] {
+        let res = ctx
+            .client
+            .get(format!("{}/responses/batch?{}", ctx.url, query))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+fn edit(start: usize, end: usize, text: &str) -> PageEdit {
+    PageEdit {
+        start,
+        end,
+        text: text.to_string(),
+    }
+}
+
+#[test]
+fn page_edits_are_applied_in_order_of_their_offsets() {
+    let edits = [edit(6, 11, "there"), edit(0, 0, ">"), edit(5, 5, ",")];
+    assert_eq!(
+        page_patch::apply_edits("Hello world", &edits).unwrap(),
+        ">Hello, there"
+    );
+    // Touching edits don't overlap.
+    let edits = [edit(0, 5, "Bye"), edit(5, 6, "-")];
+    assert_eq!(
+        page_patch::apply_edits("Hello world", &edits).unwrap(),
+        "Bye-world"
+    );
+}
+
+#[test]
+fn invalid_page_edits_are_rejected() {
+    let page = "Hällo world";
+    for edits in [
+        vec![edit(0, 5, "a"), edit(4, 6, "b")],
+        vec![edit(2, 2, "a"), edit(0, 6, "b")],
+        vec![edit(0, 13, "a")],
+        vec![edit(20, 20, "a")],
+        vec![edit(5, 3, "a")],
+        // Inside of the two bytes of `ä`.
+        vec![edit(2, 2, "a")],
+    ] {
+        let err = page_patch::apply_edits(page, &edits).unwrap_err();
+        assert_eq!(err.code(), "invalid_patch");
+    }
+}
+
+#[tokio::test]
+async fn page_can_be_patched() {
+    let ctx = setup().await;
+    let original = "<html><head></head><body>Hello world</body></html>";
+    ctx.set_page_and_check("u", "my-test-token", original).await;
+    let patch = |body: serde_json::Value| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/page/patch?session=u", ctx.url))
+                .bearer_auth("my-test-token")
+                .json(&body),
+        )
+    };
+
+    let res = patch(serde_json::json!({
+        "base_version": 1,
+        "edits": [{ "start": 31, "end": 36, "text": "there" }],
+    }))
+    .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let patched: routes::PatchedPage = res.json().await.unwrap();
+    assert_eq!(patched.version, 2);
+    assert_eq!(
+        ctx.request_session_page_text("u").await,
+        "<html><head></head><body>Hello there</body></html>"
+    );
+
+    // The patch is based on the replaced version.
+    let res = patch(serde_json::json!({
+        "base_version": 1,
+        "edits": [{ "start": 25, "end": 25, "text": "!" }],
+    }))
+    .await;
+    assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+    let body: ErrorBody = res.json().await.unwrap();
+    assert_eq!(body.error, "page_version_conflict");
+    assert_eq!(body.details.unwrap()["current_version"], 2);
+
+    let res = patch(serde_json::json!({
+        "base_version": 2,
+        "edits": [{ "start": 0, "end": 1000, "text": "" }],
+    }))
+    .await;
+    assert_error_code(res, reqwest::StatusCode::BAD_REQUEST, "invalid_patch").await;
+    let res = ctx
+        .request_json(
+            ctx.client
+                .post(format!("{}/page/patch?session=u", ctx.url))
+                .bearer_auth("other-token")
+                .body("{\"base_version\": 2, \"edits\": []}"),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    assert_eq!(
+        ctx.request_session_page_text("u").await,
+        "<html><head></head><body>Hello there</body></html>"
+    );
+}
+
+#[tokio::test]
+async fn session_assets_can_be_referenced_by_pages() {
+    let ctx = setup_with_settings(|settings| {
+        settings.max_asset_size = byte_unit::Byte::from_u64(100);
+        settings.max_assets_per_session = 2;
+    })
+    .await;
+    let page = "<img src=\"asset?session=u&name=logo.png\">";
+    ctx.set_page_and_check("u", "my-test-token", page).await;
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+    let upload = |name: &str, token: &str, data: Vec<u8>| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/asset?session=u&name={}", ctx.url, name))
+                .bearer_auth(token)
+                .body(data),
+        )
+    };
+    let get = |query: &str| {
+        ctx.client
+            .get(format!("{}/asset?session=u{}", ctx.url, query))
+            .send()
+    };
+
+    let res = upload("logo.png", "my-test-token", png.clone()).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let stored: routes::StoredAsset = res.json().await.unwrap();
+
+    let res = get("&name=logo.png").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert_eq!(res.headers()["etag"], format!("\"{}\"", stored.hash));
+    assert_eq!(res.headers()["cache-control"], "no-cache");
+    assert_eq!(res.bytes().await.unwrap(), png);
+
+    let res = get(&format!("&name=logo.png&v={}", stored.hash))
+        .await
+        .unwrap();
+    assert!(res.headers()["cache-control"]
+        .to_str()
+        .unwrap()
+        .contains("immutable"));
+    let res = ctx
+        .client
+        .get(format!("{}/asset?session=u&name=logo.png", ctx.url))
+        .header(
+            reqwest::header::IF_NONE_MATCH,
+            format!("\"{}\"", stored.hash),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+    // Assets are kept when the page changes.
+    ctx.set_page_and_check("u", "my-test-token", "new page")
+        .await;
+    assert_eq!(
+        get("&name=logo.png").await.unwrap().status(),
+        reqwest::StatusCode::OK
+    );
+
+    let res = upload("other.png", "other-token", png.clone()).await;
+    assert_error_code(res, reqwest::StatusCode::UNAUTHORIZED, "bad_access_token").await;
+    let res = upload("../page", "my-test-token", png.clone()).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::BAD_REQUEST,
+        "bad_query_parameters",
+    )
+    .await;
+    let res = upload("large.png", "my-test-token", vec![0; 101]).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::PAYLOAD_TOO_LARGE,
+        "asset_too_large",
+    )
+    .await;
+    let res = upload("second.png", "my-test-token", png.clone()).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = upload("third.png", "my-test-token", png.clone()).await;
+    assert_error_code(res, reqwest::StatusCode::FORBIDDEN, "too_many_assets").await;
+    // Replacing an asset does not count as another one.
+    let res = upload("second.png", "my-test-token", vec![1; 100]).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/asset?session=u&name=third.png", ctx.url)),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "asset_not_found").await;
+    let state = ctx.state.lock();
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[test]
+fn compressed_pages_round_trip() {
+    let mut settings = Settings::default("".to_string());
+    settings.compress_stored_pages = true;
+    let page = format!("<ul>{}</ul>", "<li>Äpfel</li>".repeat(1000));
+    let content = PageContent::new(&settings, page.clone());
+    assert!(content.gzip().is_some());
+    assert_eq!(content.text(), page);
+    assert_eq!(content.len(), page.len());
+    assert!(content.stored_len() * 10 < page.len());
+    // Snapshots contain the plain page.
+    let json = serde_json::to_string(&content).unwrap();
+    assert_eq!(json, serde_json::to_string(&page).unwrap());
+    let restored: PageContent = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.text(), page);
+
+    // Compression would make it larger.
+    let content = PageContent::new(&settings, "page".to_string());
+    assert_eq!(content, PageContent::Plain("page".to_string()));
+    settings.compress_stored_pages = false;
+    let content = PageContent::new(&settings, page.clone());
+    assert_eq!(content, PageContent::Plain(page));
+}
+
+#[tokio::test]
+async fn stored_pages_can_be_compressed() {
+    let ctx = setup_with_settings(|settings| {
+        settings.compress_stored_pages = true;
+    })
+    .await;
+    let page = format!("<ul>{}</ul>", "<li>choice</li>".repeat(1000));
+    ctx.set_page_and_check("u", "my-test-token", &page).await;
+    let session_id = SessionID::from_string("u").unwrap();
+    {
+        let state = ctx.state.lock();
+        let session = &state.sessions[&session_id];
+        assert!(session.page.gzip().is_some());
+        assert!(session.page.stored_len() * 10 < page.len());
+        assert!(state.approx_bytes < page.len() as u64);
+        assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+    }
+
+    let res = ctx
+        .client
+        .get(format!("{}/page?session=u", ctx.url))
+        .header(reqwest::header::ACCEPT_ENCODING, "gzip, deflate")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["content-encoding"], "gzip");
+    let mut served = String::new();
+    std::io::Read::read_to_string(
+        &mut flate2::read::GzDecoder::new(&res.bytes().await.unwrap()[..]),
+        &mut served,
+    )
+    .unwrap();
+    assert_eq!(
+        served.replacen(&page::injection_snippet(&ctx.settings), "", 1),
+        page
+    );
+
+    // Previous pages are compressed as well.
+    ctx.set_page_and_check("u", "my-test-token", "new page")
+        .await;
+    let state = ctx.state.lock();
+    let session = &state.sessions[&session_id];
+    assert!(session.page_history[0].page.gzip().is_some());
+    assert_eq!(state.approx_bytes, count_memory_usage_fully(&state));
+}
+
+#[tokio::test]
+async fn responses_can_be_exchanged_as_msgpack_and_cbor() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().response_long_poll_duration = std::time::Duration::ZERO;
+    })
+    .await;
+    ctx.set_page_and_check("u", "my-test-token", "page").await;
+    let respond = |user: &str, encoding: Encoding, data: Vec<u8>| {
+        ctx.request_json(
+            ctx.client
+                .post(format!("{}/respond?session=u&user={}", ctx.url, user))
+                .header(reqwest::header::CONTENT_TYPE, encoding.media_type())
+                .body(data),
+        )
+    };
+    let value = serde_json::json!({ "buzzer": 3, "pressed": true });
+    let res = respond(
+        "a",
+        Encoding::MessagePack,
+        rmp_serde::to_vec(&value).unwrap(),
+    )
+    .await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let mut cbor = vec![];
+    ciborium::into_writer(&value, &mut cbor).unwrap();
+    let res = respond("b", Encoding::Cbor, cbor).await;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = respond("c", Encoding::MessagePack, vec![0xc1]).await;
+    assert_error_code(
+        res,
+        reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_response",
+    )
+    .await;
+
+    let get = |path: &str, accept: &str| {
+        ctx.client
+            .get(format!("{}{}", ctx.url, path))
+            .header(reqwest::header::ACCEPT, accept)
+            .send()
+    };
+    let res = get("/responses?session=u&start=0", "application/msgpack")
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["content-type"], "application/msgpack");
+    let responses: RetrievedResponses = Encoding::MessagePack
+        .decode(&res.bytes().await.unwrap())
+        .unwrap();
+    assert_eq!(responses.total_responses, 2);
+    for user in ["a", "b"] {
+        let data = &responses.responses_by_user[&UserID(user.to_string())];
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(data).unwrap(),
+            value
+        );
+    }
+    let res = get(
+        "/responses?session=u&start=0&format=list",
+        "application/cbor;q=0.9, application/json;q=0.5",
+    )
+    .await
+    .unwrap();
+    assert_eq!(res.headers()["content-type"], "application/cbor");
+    let list: routes::RetrievedResponseList =
+        Encoding::Cbor.decode(&res.bytes().await.unwrap()).unwrap();
+    assert!(list
+        .responses
+        .iter()
+        .all(|response| response.content_type == "application/json"));
+
+    let res = get("/responses/aggregate?session=u", "application/cbor")
+        .await
+        .unwrap();
+    let aggregate: routes::AggregatedResponses =
+        Encoding::Cbor.decode(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!(aggregate.counts.len(), 1);
+    assert_eq!(aggregate.counts[0].count, 2);
+    let res = get("/responses/batch?sessions=u,missing", "application/msgpack")
+        .await
+        .unwrap();
+    let batch: HashMap<String, routes::BatchEntry> = Encoding::MessagePack
+        .decode(&res.bytes().await.unwrap())
+        .unwrap();
+    assert!(matches!(batch["u"], routes::BatchEntry::Responses(_)));
+    assert!(matches!(batch["missing"], routes::BatchEntry::Error(_)));
+
+    // Unknown types fall back to json.
+    let res = get("/responses?session=u&start=0", "text/html")
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["content-type"], "application/json");
+    let responses: RetrievedResponses = res.json().await.unwrap();
+    assert_eq!(responses.total_responses, 2);
+}
+
+#[tokio::test]
+async fn responses_can_be_streamed_as_lines() {
+    let ctx = setup().await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let user_count = 150;
+    for i in 0..user_count {
+        let res = ctx
+            .send_reponse(
+                Some("1"),
+                Some(&format!("user{}", i)),
+                &format!("line\n{}", i),
+            )
+            .await;
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+
+    let mut res = ctx
+        .client
+        .get(format!("{}/responses/stream?session=1&start=20", ctx.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/x-ndjson");
+    let mut buffer = vec![];
+    let mut lines = vec![];
+    while let Some(chunk) = res.chunk().await.unwrap() {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            lines.push(serde_json::from_slice::<routes::StreamedLine>(&line).unwrap());
+        }
+    }
+    assert!(buffer.is_empty());
+    assert_eq!(lines.len(), user_count - 20 + 1);
+    for (i, line) in lines[..user_count - 20].iter().enumerate() {
+        let routes::StreamedLine::Response(response) = line else {
+            panic!("Expected a response");
+        };
+        assert_eq!(response.id, i + 20);
+        assert_eq!(response.user.0, format!("user{}", i + 20));
+        // Newlines in responses are escaped by the json encoding.
+        assert_eq!(response.data, format!("line\n{}", i + 20));
+    }
+    let Some(routes::StreamedLine::Summary(summary)) = lines.last() else {
+        panic!("Expected the summary");
+    };
+    assert_eq!(summary.next_start, user_count);
+    assert_eq!(summary.total_responses, user_count);
+
+    let text = ctx
+        .client
+        .get(format!(
+            "{}/responses/stream?session=1&start=0&limit=2",
+            ctx.url
+        ))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(text.lines().count(), 3);
+    assert!(text.ends_with("{\"next_start\":2,\"total_responses\":150}\n"));
+
+    let res = ctx
+        .request_json(
+            ctx.client
+                .get(format!("{}/responses/stream?session=2&start=0", ctx.url)),
+        )
+        .await;
+    assert_error_code(res, reqwest::StatusCode::NOT_FOUND, "session_not_found").await;
+}
+
+/// Reads server-sent events until the given number of events (not comments) arrived.
+async fn read_events(res: &mut reqwest::Response, count: usize) -> Vec<String> {
+    let mut buffer = String::new();
+    let mut events = vec![];
+    while events.len() < count {
+        let chunk = res.chunk().await.unwrap().unwrap();
+        buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        while let Some(end) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..end + 2).collect();
+            if !event.starts_with(':') {
+                events.push(event);
+            }
+        }
+    }
+    events
+}
+
+fn event_field<'a>(event: &'a str, field: &str) -> &'a str {
+    event
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{}: ", field)))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn responses_are_sent_as_server_sent_events() {
+    let ctx = setup_with_settings(|settings| {
+        settings.tunables.write().min_response_interval = std::time::Duration::ZERO;
+        settings.event_stream_heartbeat_interval = std::time::Duration::from_millis(50);
+    })
+    .await;
+    ctx.set_page_and_check("1", "my-test-token", "page").await;
+    let connect = |last_event_id: Option<usize>| {
+        let mut builder = ctx
+            .client
+            .get(format!("{}/responses/events?session=1", ctx.url));
+        if let Some(id) = last_event_id {
+            builder = builder.header("Last-Event-ID", id.to_string());
+        }
+        builder.send()
+    };
+    let mut res = connect(None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/event-stream");
+    // Wait for a heartbeat, so that the stream is known to be subscribed.
+    let chunk = res.chunk().await.unwrap().unwrap();
+    assert_eq!(&chunk[..], b": heartbeat\n\n");
+
+    ctx.send_reponse(Some("1"), Some("a"), "first").await;
+    ctx.send_reponse(Some("1"), Some("b"), "second").await;
+    let events = read_events(&mut res, 2).await;
+    let ids: Vec<usize> = events
+        .iter()
+        .map(|event| event_field(event, "id").parse().unwrap())
+        .collect();
+    assert!(ids[0] < ids[1]);
+    for (event, (user, data)) in events.iter().zip([("a", "first"), ("b", "second")]) {
+        assert_eq!(event_field(event, "event"), "response");
+        let response: routes::StreamedResponse =
+            serde_json::from_str(event_field(event, "data")).unwrap();
+        assert_eq!(
+            (response.user.0.as_str(), response.data.as_str()),
+            (user, data)
+        );
+        assert_eq!(response.id.to_string(), event_field(event, "id"));
+    }
+
+    // Reconnecting sends the missed responses first.
+    ctx.send_reponse(Some("1"), Some("a"), "third").await;
+    let mut res = connect(Some(ids[1])).await.unwrap();
+    let events = read_events(&mut res, 1).await;
+    let response: routes::StreamedResponse =
+        serde_json::from_str(event_field(&events[0], "data")).unwrap();
+    assert_eq!(response.data, "third");
+    assert!(response.id > ids[1]);
+
+    // The stream ends with the session.
+    ctx.state
+        .lock()
+        .delete_session(&ctx.settings, &SessionID::from_string("1").unwrap());
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while res.chunk().await.unwrap().is_some() {}
+    })
+    .await
+    .unwrap();
+}